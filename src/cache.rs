@@ -1,87 +1,116 @@
-use std::{fmt};
+use std::{fmt, sync::Arc};
 
-#[derive(Debug, Clone)]
-pub struct CacheError {
-    message: String,
+use async_trait::async_trait;
+use serde::de::DeserializeOwned;
+
+use crate::config::RateLimit;
+use crate::types;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CacheError {
+    // the cache server cannot be reached or the command failed
+    Unavailable(String),
+    // the key or the requested entry does not exist
+    NotFound(String),
+    // the cached value cannot be parsed
+    InvalidData(String),
 }
 
 impl fmt::Display for CacheError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Cache error: {}", self.message)
+        match self {
+            CacheError::Unavailable(message) => write!(f, "Cache error: cache is unavailable: {}", message),
+            CacheError::NotFound(message) => write!(f, "Cache error: {} is not found", message),
+            CacheError::InvalidData(message) => write!(f, "Cache error: invalid data: {}", message),
+        }
     }
 }
 
-// fn get_folder(chain: &str) -> String {
-//     Config::init();
-//     let config = Config::current();
-//     if chain == "KSM" {
-//         config.new_cache_folder.clone()
-//     } else {
-//         config.new_cache_folder_polkadot.clone()
-//     }
-// }
-
-// pub fn get_validators(chain: &str) -> Vec<types::ValidatorInfo> {
-//     let path = Path::new(get_folder(chain).as_str()).join("validDetailAll.json");
-//     let data = fs::read_to_string(path).expect("Unable to read the cache file");
-//     let json: Option<types::ValidatorDetailAll> =
-//         serde_json::from_str(data.as_str()).expect("JSON was not well-formatted");
-
-//     json.unwrap().valid
-// }
-
-// pub fn get_1kv_info_simple(chain: &str) -> types::ValidatorDetail1kv {
-//     let path = Path::new(get_folder(chain).as_str()).join("onekv.json");
-//     let data = fs::read_to_string(path).expect("Unable to read the cache file");
-//     let json: Option<types::ValidatorDetail1kv> =
-//         serde_json::from_str(data.as_str()).expect("JSON was not well-formatted");
-
-//     json.unwrap()
-// }
-
-// pub fn get_1kv_info_detail(chain: &str) -> types::ValidatorDetail1kv {
-//     let path = Path::new(get_folder(chain).as_str()).join("onekv.json");
-//     let data = fs::read_to_string(path.clone()).expect("Unable to read the cache file");
-//     let metadata = fs::metadata(path.clone());
-//     let json_option: Option<types::ValidatorDetail1kv> =
-//         serde_json::from_str(data.as_str()).expect("JSON was not well-formatted");
-//     let mut json = json_option.unwrap();
-//     if let Ok(metadata) = metadata {
-//         if let Ok(modified_time) = metadata.modified() {
-//             let timestamp = modified_time
-//             .duration_since(UNIX_EPOCH)
-//             .expect("Time went backwards").as_secs();
-//             json.modified_time = Some(timestamp);
-//         }
-//     }
-//     json
-// }
-
-// pub fn get_nominators(chain: &str) -> Vec<types::NominatorNomination> {
-//     let path = Path::new(get_folder(chain).as_str()).join("nominators.json");
-//     let data = fs::read_to_string(path).expect("Unable to read the cache file");
-//     let json: Option<Vec<types::NominatorNomination>> =
-//         serde_json::from_str(data.as_str()).expect("JSON was not well-formatted");
-//         json.unwrap()
-// }
-
-// pub fn get_nominator(chain: &str, stash: String) -> Result<types::NominatorNomination, CacheError> {
-//     let nominators = get_nominators(chain);
-//     for nominator in nominators {
-//         if nominator.account_id == stash {
-//             return Ok(nominator);
-//         }
-//     }
-//     Err(CacheError {
-//         message: "Cannot find stash in nominator cache".to_string(),
-//     })
-// }
-
-// pub fn get_1kv_nominators(chain: &str) -> types::OneKvNominators {
-//     let path = Path::new(get_folder(chain).as_str()).join("onekvNominators.json");
-//     let data = fs::read_to_string(path).expect("Unable to read the cache file");
-//     let json: Option<types::OneKvNominators> =
-//         serde_json::from_str(data.as_str()).expect("JSON was not well-formatted");
-
-//     json.unwrap()
-// }
+impl std::error::Error for CacheError {}
+
+pub type Cache = Arc<dyn CacheBackend>;
+
+async fn get_json<T: DeserializeOwned, C: CacheBackend + ?Sized>(cache: &C, key: &str) -> Result<T, CacheError> {
+    let data = cache.get(key).await?.ok_or_else(|| CacheError::NotFound(key.to_string()))?;
+    serde_json::from_str(data.as_str()).map_err(|e| CacheError::InvalidData(e.to_string()))
+}
+
+// Values are stored under the keys chain-data-collector writes to Redis, e.g. `KSMvalidDetailAll`,
+// as JSON strings. Backends only implement `get` and `set`; the getters are shared.
+#[async_trait]
+pub trait CacheBackend: Send + Sync {
+    async fn get(&self, key: &str) -> Result<Option<String>, CacheError>;
+
+    async fn set(&self, key: &str, value: String) -> Result<(), CacheError>;
+
+    // takes a token from the rate limit bucket at `key`, atomically for the instances sharing the backend; returns the
+    // wait in milliseconds before the next token when the bucket is empty, see `rate_limit::Bucket`
+    async fn take_token(&self, key: &str, limit: &RateLimit, now_ms: u64) -> Result<Option<u64>, CacheError>;
+
+    // whether the backend is reachable, for the readiness check
+    async fn ping(&self) -> Result<(), CacheError> {
+        Ok(())
+    }
+
+    async fn get_validators(&self, chain: &str) -> Result<Vec<types::ValidatorInfo>, CacheError> {
+        match get_json::<types::ValidatorDetailAll, _>(self, &format!("{}validDetailAll", chain)).await {
+            Ok(data) => Ok(data.valid),
+            Err(CacheError::NotFound(_)) => Ok(vec![]),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn get_1kv_info_simple(&self, chain: &str) -> Result<types::ValidatorDetail1kv, CacheError> {
+        get_json(self, &format!("{}onekv", chain)).await
+    }
+
+    async fn get_1kv_info_detail(&self, chain: &str) -> Result<types::ValidatorDetail1kv, CacheError> {
+        let mut data: types::ValidatorDetail1kv = get_json(self, &format!("{}onekv", chain)).await?;
+        let timestamp = self.get(&format!("{}onekv_timestamp", chain)).await?;
+        for v in data.valid.iter_mut() {
+            let mut valid = true;
+            for validity in &v.validity {
+                if !validity.valid {
+                    valid = false;
+                }
+            }
+            if valid {
+                v.valid = Some(true);
+            }
+        }
+        let modified_time = timestamp.unwrap_or_else(|| "0".to_string()).parse::<u64>().ok();
+        data.modified_time = modified_time;
+        Ok(data)
+    }
+
+    async fn get_nominators(&self, chain: &str) -> Result<Vec<types::NominatorNomination>, CacheError> {
+        match get_json(self, &format!("{}nominators", chain)).await {
+            Err(CacheError::NotFound(_)) => Ok(vec![]),
+            result => result,
+        }
+    }
+
+    async fn get_nominator(&self, chain: &str, stash: String) -> Result<types::NominatorNomination, CacheError> {
+        let nominators = self.get_nominators(chain).await?;
+        for nominator in nominators {
+            if nominator.account_id == stash {
+                return Ok(nominator)
+            }
+        }
+        Err(CacheError::NotFound(format!("nominator {}", stash)))
+    }
+
+    async fn get_1kv_nominators(&self, chain: &str) -> Result<types::OneKvNominators, CacheError> {
+        get_json(self, &format!("{}onekvNominators", chain)).await
+    }
+
+    async fn cache_current_era(&self, chain: &str, era: u32) -> Result<(), CacheError> {
+        self.set(&format!("{}Era", chain), era.to_string()).await
+    }
+
+    async fn get_current_era(&self, chain: &str) -> Result<u32, CacheError> {
+        let key = format!("{}Era", chain);
+        let era = self.get(&key).await?.ok_or_else(|| CacheError::NotFound(key.clone()))?;
+        era.parse::<u32>().map_err(|e| CacheError::InvalidData(e.to_string()))
+    }
+}
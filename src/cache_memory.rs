@@ -0,0 +1,94 @@
+use std::{collections::HashMap, fs, io, path::Path, sync::{Mutex, RwLock}, time::UNIX_EPOCH};
+
+use async_trait::async_trait;
+
+use crate::cache::{CacheBackend, CacheError};
+use crate::config::RateLimit;
+use crate::rate_limit::{self, Bucket};
+
+// cache files written by chain-data-collector and the keys they are served under
+const CACHE_FILES: [(&str, &str); 4] = [
+  ("validDetailAll.json", "validDetailAll"),
+  ("onekv.json", "onekv"),
+  ("nominators.json", "nominators"),
+  ("onekvNominators.json", "onekvNominators"),
+];
+
+// the full buckets are dropped once there are this many
+const MAX_BUCKETS: usize = 10_000;
+
+// In-process cache for single-node deployments and tests.
+#[derive(Default)]
+pub struct MemoryCache {
+  entries: RwLock<HashMap<String, String>>,
+  // rate limit buckets and when they are full again
+  buckets: Mutex<HashMap<String, (Bucket, u64)>>,
+}
+
+impl MemoryCache {
+  pub fn new() -> Self {
+    Default::default()
+  }
+
+  // loads the cache files of a chain from a chain-data-collector cache folder, missing files are skipped
+  pub fn load_folder(&self, chain: &str, folder: &str) -> io::Result<()> {
+    let mut entries = self.entries.write().unwrap();
+    for (file, key) in CACHE_FILES {
+      let path = Path::new(folder).join(file);
+      if !path.exists() {
+        continue;
+      }
+      entries.insert(format!("{}{}", chain, key), fs::read_to_string(&path)?);
+      if key == "onekv" {
+        let modified = fs::metadata(&path)?.modified()?;
+        let timestamp = modified.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        entries.insert(format!("{}onekv_timestamp", chain), timestamp.to_string());
+      }
+    }
+    Ok(())
+  }
+}
+
+#[async_trait]
+impl CacheBackend for MemoryCache {
+  async fn get(&self, key: &str) -> Result<Option<String>, CacheError> {
+    Ok(self.entries.read().unwrap().get(key).cloned())
+  }
+
+  async fn set(&self, key: &str, value: String) -> Result<(), CacheError> {
+    self.entries.write().unwrap().insert(key.to_string(), value);
+    Ok(())
+  }
+
+  async fn take_token(&self, key: &str, limit: &RateLimit, now_ms: u64) -> Result<Option<u64>, CacheError> {
+    let mut buckets = self.buckets.lock().unwrap();
+    if buckets.len() >= MAX_BUCKETS {
+      buckets.retain(|_, (_, full_ms)| *full_ms > now_ms);
+    }
+    let bucket = buckets.get(key).filter(|(_, full_ms)| *full_ms > now_ms).map(|(bucket, _)| *bucket);
+    let (bucket, wait) = Bucket::take(bucket, limit, now_ms);
+    buckets.insert(key.to_string(), (bucket, now_ms + rate_limit::refill_ms(limit)));
+    Ok(wait)
+  }
+}
+
+#[tokio::test]
+async fn test_memory_cache() {
+  let cache = MemoryCache::new();
+  assert!(matches!(cache.get_current_era("KSM").await, Err(CacheError::NotFound(_))));
+  cache.cache_current_era("KSM", 2500).await.unwrap();
+  assert_eq!(Ok(2500), cache.get_current_era("KSM").await);
+  assert_eq!(0, cache.get_validators("KSM").await.unwrap().len());
+  cache.set("KSMnominators", r#"[{"accountId": "Dqp3fU11G4t8pDkvH7Km2At1fiP2od5xhdh3nD2SFoTYx7D", "targets": [], "balance": {"lockedBalance": "0x0", "freeBalance": "0x0"}}]"#.to_string()).await.unwrap();
+  let nominator = cache.get_nominator("KSM", "Dqp3fU11G4t8pDkvH7Km2At1fiP2od5xhdh3nD2SFoTYx7D".to_string()).await;
+  assert!(nominator.is_ok());
+  assert!(matches!(cache.get_nominator("KSM", "unknown".to_string()).await, Err(CacheError::NotFound(_))));
+  cache.set("KSMonekv", "not json".to_string()).await.unwrap();
+  assert!(matches!(cache.get_1kv_info_detail("KSM").await, Err(CacheError::InvalidData(_))));
+
+  let limit = RateLimit { burst: 1, per_minute: 60 };
+  assert_eq!(Ok(None), cache.take_token("rateLimit:write:1.2.3.4", &limit, 0).await);
+  assert_eq!(Ok(Some(1_000)), cache.take_token("rateLimit:write:1.2.3.4", &limit, 0).await);
+  assert_eq!(Ok(None), cache.take_token("rateLimit:write:5.6.7.8", &limit, 0).await);
+  assert_eq!(Ok(None), cache.take_token("rateLimit:write:1.2.3.4", &limit, 1_000).await);
+}
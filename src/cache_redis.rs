@@ -1,117 +1,154 @@
-use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 extern crate redis;
-use redis::{Commands, RedisError};
+use async_trait::async_trait;
+use tracing::{Instrument, error, info};
+use redis::{AsyncCommands, RedisError, aio::ConnectionManager};
 
-use crate::{config::Config, types};
+use crate::cache::{CacheBackend, CacheError};
+use crate::config::RateLimit;
+use crate::rate_limit;
 
-#[derive(Debug, Clone)]
-pub struct CacheError {
-    message: String,
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+// after a failed connection the commands fail at once for this long, then the next one starts a new attempt
+const RETRY_AFTER: Duration = Duration::from_secs(5);
+
+// `rate_limit::Bucket::take` on a hash with the tokens and the time they were counted, which expires once the bucket
+// is full again. The time is given by the instance, the clocks of the instances are expected to be in sync.
+const TAKE_TOKEN: &str = r"
+local burst = tonumber(ARGV[1])
+local per_ms = tonumber(ARGV[2]) / 60000
+local now = tonumber(ARGV[3])
+local bucket = redis.call('HMGET', KEYS[1], 'tokens', 'updated')
+local tokens = burst
+if bucket[1] and bucket[2] then
+  tokens = math.min(burst, tonumber(bucket[1]) + math.max(0, now - tonumber(bucket[2])) * per_ms)
+end
+local wait = -1
+if tokens >= 1 then
+  tokens = tokens - 1
+else
+  wait = math.ceil((1 - tokens) / per_ms)
+end
+redis.call('HMSET', KEYS[1], 'tokens', tostring(tokens), 'updated', ARGV[3])
+redis.call('PEXPIRE', KEYS[1], ARGV[4])
+return wait
+";
+
+lazy_static! {
+  static ref TAKE_TOKEN_SCRIPT: redis::Script = redis::Script::new(TAKE_TOKEN);
 }
 
-impl fmt::Display for CacheError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Cache error: {}", self.message)
-    }
+impl From<RedisError> for CacheError {
+  fn from(err: RedisError) -> CacheError {
+    CacheError::Unavailable(err.to_string())
+  }
 }
 
-#[derive(Debug, Clone)]
-pub struct Cache {
+// A multiplexed connection shared by all clones. It is opened at startup and, if Redis was not reachable then, reopened
+// in the background; the connection manager reconnects by itself afterwards. The commands never wait for a connection
+// attempt, they fail with `CacheError::Unavailable` until one succeeds.
+#[derive(Clone)]
+pub struct RedisCache {
+  url: String,
+  state: Arc<Mutex<ConnectionState>>,
 }
 
-impl Cache {
-  pub fn connect(&self) -> Result<redis::Connection, RedisError> {
-    Config::init();
-    let config = Config::current();
-    let client = redis::Client::open(format!("redis://{}:{}/", config.redis, config.redis_port));
-    match client {
-        Ok(client) => {
-          let con = client.get_connection()?;
-          Ok(con)
-        },
-        Err(e) => {Err(e)},
-    }
+#[derive(Default)]
+struct ConnectionState {
+  manager: Option<ConnectionManager>,
+  failed_at: Option<Instant>,
+  connecting: bool,
+}
+
+impl RedisCache {
+  pub async fn new(redis: &str, port: u16) -> Self {
+    let cache = RedisCache {
+      url: format!("redis://{}:{}/", redis, port),
+      state: Arc::new(Mutex::new(ConnectionState { connecting: true, ..Default::default() })),
+    };
+    cache.connect().await;
+    cache
   }
-  
-  pub fn get_validators(&self, chain: &str) -> Vec<types::ValidatorInfo> {
-    let result: Result<String, RedisError> = self.connect().unwrap().get(format!("{}validDetailAll", chain));
+
+  async fn connect(&self) {
+    let result = match redis::Client::open(self.url.as_str()) {
+      Ok(client) => tokio::time::timeout(CONNECT_TIMEOUT, ConnectionManager::new(client)).await
+        .unwrap_or_else(|_| Err(RedisError::from((redis::ErrorKind::IoError, "timed out connecting")))),
+      Err(e) => Err(e),
+    };
+    let mut state = self.state.lock().unwrap();
+    state.connecting = false;
     match result {
-        Ok(data) => {
-          let json: Option<types::ValidatorDetailAll> =
-            serde_json::from_str(data.as_str()).expect("JSON was not well-formatted");
-          json.unwrap().valid
-        },
-        Err(_) => {vec![]},
+      Ok(manager) => {
+        info!("connected to {}", self.url);
+        state.manager = Some(manager);
+        state.failed_at = None;
+      },
+      Err(e) => {
+        error!("cannot connect to {}: {}", self.url, e);
+        state.failed_at = Some(Instant::now());
+      },
     }
   }
-  
-  pub fn get_1kv_info_simple(&self, chain: &str) -> types::ValidatorDetail1kv {
-    let result: Result<String, RedisError> = self.connect().unwrap().get(format!("{}onekv", chain));
-    let json: Option<types::ValidatorDetail1kv> =
-    serde_json::from_str(result.unwrap().as_str()).expect("JSON was not well-formatted");
-    json.unwrap()
-  }
-  
-  pub fn get_1kv_info_detail(&self, chain: &str) -> types::ValidatorDetail1kv {
-    let result: Result<String, RedisError> = self.connect().unwrap().get(format!("{}onekv", chain));
-    let timestamp_result: Result<String, RedisError> = self.connect().unwrap().get(format!("{}onekv_timestamp", chain));
-    let json: Option<types::ValidatorDetail1kv> =
-    serde_json::from_str(result.unwrap().as_str()).expect("JSON was not well-formatted");
-    let mut data = json.unwrap();
-    for mut v in data.valid.iter_mut() {
-      let mut valid = true;
-      for validity in &v.validity {
-          if !validity.valid {
-            valid = false;
-          }
-      }
-      if valid {
-        v.valid = Some(true);
-      }
+
+  fn connection(&self) -> Result<ConnectionManager, CacheError> {
+    let mut state = self.state.lock().unwrap();
+    if let Some(manager) = state.manager.as_ref() {
+      return Ok(manager.clone());
     }
-    let modified_time = timestamp_result.unwrap_or_else(|_| "0".to_string()).parse::<u64>().ok();
-    data.modified_time = modified_time;
-    data
-  }
-  
-  pub fn get_nominators(&self, chain: &str) -> Vec<types::NominatorNomination> {
-    let result: Result<String, RedisError> = self.connect().unwrap().get(format!("{}nominators", chain));
-    match result {
-        Ok(data) => {
-          let json: Option<Vec<types::NominatorNomination>> =
-            serde_json::from_str(data.as_str()).expect("JSON was not well-formatted");
-          json.unwrap()
-        },
-        Err(_) => {vec![]},
+    let retry = !state.connecting && state.failed_at.is_none_or(|at| at.elapsed() >= RETRY_AFTER);
+    if retry {
+      state.connecting = true;
+      let cache = self.clone();
+      tokio::spawn(async move { cache.connect().await });
     }
+    Err(CacheError::Unavailable(format!("not connected to {}", self.url)))
   }
-  
-  pub fn get_nominator(&self, chain: &str, stash: String) -> Result<types::NominatorNomination, CacheError> {
-      let nominators = self.get_nominators(chain);
-      for nominator in nominators {
-          if nominator.account_id == stash {
-              return Ok(nominator)
-          }
-      }
-      Err(CacheError {
-          message: "Cannot find stash in nominator cache".to_string(),
-      })
+}
+
+#[async_trait]
+impl CacheBackend for RedisCache {
+  async fn get(&self, key: &str) -> Result<Option<String>, CacheError> {
+    let result = async {
+      Ok(self.connection()?.get(key).await?)
+    }.instrument(tracing::debug_span!("redis", command = "GET", key)).await;
+    crate::metrics::record_cache_read(key, &result);
+    result
   }
-  
-  pub fn get_1kv_nominators(&self, chain: &str) -> types::OneKvNominators {
-    let result: Result<String, RedisError> = self.connect().unwrap().get(format!("{}onekvNominators", chain));
-    let json: Option<types::OneKvNominators> =
-    serde_json::from_str(result.unwrap().as_str()).expect("JSON was not well-formatted");
-    json.unwrap()
-  
+
+  async fn set(&self, key: &str, value: String) -> Result<(), CacheError> {
+    async {
+      self.connection()?.set::<&str, String, ()>(key, value).await?;
+      Ok(())
+    }.instrument(tracing::debug_span!("redis", command = "SET", key)).await
   }
 
-  pub fn cache_current_era(&self, chain: &str, era: u32) {
-    self.connect().unwrap().set::<String, u32, u32>(format!("{}Era", chain), era);
+  async fn take_token(&self, key: &str, limit: &RateLimit, now_ms: u64) -> Result<Option<u64>, CacheError> {
+    let wait: i64 = async {
+      TAKE_TOKEN_SCRIPT.key(key).arg(limit.burst).arg(limit.per_minute).arg(now_ms).arg(rate_limit::refill_ms(limit))
+        .invoke_async(&mut self.connection()?).await.map_err(CacheError::from)
+    }.instrument(tracing::debug_span!("redis", command = "EVALSHA", key)).await?;
+    Ok((wait >= 0).then_some(wait as u64))
   }
 
-  pub fn get_current_era(&self, chain: &str) -> u32 {
-    let result: Result<u32, RedisError> = self.connect().unwrap().get(format!("{}Era", chain));
-    result.unwrap_or(0)
+  async fn ping(&self) -> Result<(), CacheError> {
+    redis::cmd("PING").query_async::<_, String>(&mut self.connection()?).await?;
+    Ok(())
   }
 }
+
+#[tokio::test]
+async fn test_unreachable_redis() {
+  // nothing listens on port 1, getters report the outage instead of panicking
+  let cache = RedisCache::new("127.0.0.1", 1).await;
+  assert!(matches!(cache.get_current_era("KSM").await, Err(CacheError::Unavailable(_))));
+  assert!(matches!(cache.get_validators("KSM").await, Err(CacheError::Unavailable(_))));
+  let limit = RateLimit { burst: 1, per_minute: 1 };
+  assert!(matches!(cache.take_token("rateLimit:write:1.2.3.4", &limit, 0).await, Err(CacheError::Unavailable(_))));
+  // the failure is remembered, the commands do not start another attempt until it is old enough
+  assert!(!cache.state.lock().unwrap().connecting);
+  cache.state.lock().unwrap().failed_at = Some(Instant::now() - RETRY_AFTER);
+  assert!(matches!(cache.ping().await, Err(CacheError::Unavailable(_))));
+  assert!(cache.state.lock().unwrap().connecting);
+}
@@ -0,0 +1,146 @@
+use blake2::{Blake2b512, Digest};
+use serde::{Deserialize, Serialize};
+use std::sync::OnceLock;
+
+use crate::config::Config;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Chain {
+    pub ticker: String,
+    pub ss58_prefix: u16,
+    pub decimals: u32,
+    pub db_name: String,
+    pub src_network: String,
+    pub era_length_hours: u32,
+    #[serde(default)]
+    pub one_kv: bool,
+    // nominators rewarded per validator, more oversubscribe it
+    #[serde(default = "default_max_nominators_rewarded")]
+    pub max_nominators_rewarded: u32,
+    // validators a nominator can nominate
+    #[serde(default = "default_max_nominations")]
+    pub max_nominations: u32,
+    // chain-data-collector cache folder, loaded by the in-memory cache backend
+    #[serde(default)]
+    pub cache_folder: Option<String>,
+}
+
+fn default_max_nominators_rewarded() -> u32 {
+    256
+}
+
+fn default_max_nominations() -> u32 {
+    16
+}
+
+static REGISTRY: OnceLock<Vec<Chain>> = OnceLock::new();
+
+pub fn init(chains: Vec<Chain>) {
+    let _ = REGISTRY.set(chains);
+}
+
+pub fn all() -> &'static [Chain] {
+    REGISTRY.get().map(|c| c.as_slice()).unwrap_or(&[])
+}
+
+pub fn find_by_address(address: &str) -> Option<&'static Chain> {
+    let prefix = ss58_prefix(address)?;
+    all().iter().find(|c| c.ss58_prefix == prefix)
+}
+
+// builds the chain list from the pre-registry config keys so old config.json files keep working
+pub fn from_legacy_config(config: &Config) -> Vec<Chain> {
+    let mut chains = vec![
+        Chain {
+            ticker: "KSM".to_string(),
+            ss58_prefix: 2,
+            decimals: 12,
+            db_name: config.kusama_db_name.clone(),
+            src_network: "Kusama".to_string(),
+            era_length_hours: 6,
+            one_kv: true,
+            max_nominators_rewarded: default_max_nominators_rewarded(),
+            max_nominations: 24,
+            cache_folder: Some(config.new_cache_folder.clone()),
+        },
+        Chain {
+            ticker: "DOT".to_string(),
+            ss58_prefix: 0,
+            decimals: 10,
+            db_name: config.polkadot_db_name.clone(),
+            src_network: "Polkadot".to_string(),
+            era_length_hours: 24,
+            one_kv: true,
+            max_nominators_rewarded: default_max_nominators_rewarded(),
+            max_nominations: default_max_nominations(),
+            cache_folder: Some(config.new_cache_folder_polkadot.clone()),
+        },
+    ];
+    if config.support_westend {
+        chains.push(Chain {
+            ticker: "WND".to_string(),
+            ss58_prefix: 42,
+            decimals: 12,
+            db_name: config.westend_db_name.clone(),
+            src_network: "Westend".to_string(),
+            era_length_hours: 6,
+            one_kv: false,
+            max_nominators_rewarded: default_max_nominators_rewarded(),
+            max_nominations: default_max_nominations(),
+            cache_folder: None,
+        });
+    }
+    chains
+}
+
+pub fn ss58_prefix(address: &str) -> Option<u16> {
+    let data = bs58::decode(address).into_vec().ok()?;
+    match data.first()? {
+        0..=63 => Some(data[0] as u16),
+        64..=127 => {
+            if data.len() < 2 {
+                return None;
+            }
+            let lower = ((data[0] & 0b0011_1111) << 2) | (data[1] >> 6);
+            let upper = data[1] & 0b0011_1111;
+            Some(lower as u16 | ((upper as u16) << 8))
+        }
+        _ => None,
+    }
+}
+
+// returns the prefix and the 32-byte account id of an address after verifying its checksum
+pub fn ss58_decode(address: &str) -> Option<(u16, [u8; 32])> {
+    let data = bs58::decode(address).into_vec().ok()?;
+    let prefix = ss58_prefix(address)?;
+    let prefix_len = if prefix < 64 { 1 } else { 2 };
+    if data.len() != prefix_len + 32 + 2 {
+        return None;
+    }
+    let mut hasher = Blake2b512::new();
+    hasher.update(b"SS58PRE");
+    hasher.update(&data[..prefix_len + 32]);
+    if hasher.finalize()[..2] != data[prefix_len + 32..] {
+        return None;
+    }
+    let mut account_id = [0u8; 32];
+    account_id.copy_from_slice(&data[prefix_len..prefix_len + 32]);
+    Some((prefix, account_id))
+}
+
+#[test]
+fn test_ss58_decode() {
+    let (prefix, account_id) = ss58_decode("5GrwvaEF5zXb26Fz9rcQpDWS57CtERHpNehXCPcNoHGKutQY").unwrap();
+    assert_eq!(42, prefix);
+    assert_eq!("d43593c715fdd31c61141abd04a99fd6822c8558854ccde39a5684e7a56da27d", hex::encode(account_id));
+    // last character changed, the checksum no longer matches
+    assert_eq!(None, ss58_decode("5GrwvaEF5zXb26Fz9rcQpDWS57CtERHpNehXCPcNoHGKutQZ"));
+}
+
+#[test]
+fn test_ss58_prefix() {
+    assert_eq!(Some(0), ss58_prefix("15Uv8ppUZVb8dM2uDf8rLnNPo4QdK9mHrJSUn6fqAhAtDZKu"));
+    assert_eq!(Some(2), ss58_prefix("Dqp3fU11G4t8pDkvH7Km2At1fiP2od5xhdh3nD2SFoTYx7D"));
+    assert_eq!(Some(42), ss58_prefix("5GrwvaEF5zXb26Fz9rcQpDWS57CtERHpNehXCPcNoHGKutQY"));
+    assert_eq!(None, ss58_prefix("not-base58-0OIl"));
+}
@@ -0,0 +1,260 @@
+use std::env;
+
+use chrono::NaiveDateTime;
+use clap::{Parser, Subcommand, ValueEnum};
+
+use crate::chain::{self, Chain};
+use crate::config::Config;
+use crate::db::{Database, DatabaseError};
+use crate::db::migrations::{self, Target};
+use crate::db::repo::get_nominator_events;
+use crate::referer;
+use crate::staking_rewards_collector::{StakingRewardsAddress, StakingRewardsCollector, to_csv};
+
+// The support commands run against the databases and the cache of the configuration, print their result to stdout
+// and log to stderr. They exit with 1 on failure.
+
+#[derive(Parser)]
+#[command(name = "cryptolab-backend", version, about = "CryptoLab web server and support commands")]
+pub struct Cli {
+  /// Configuration file, ./config/config.json or $CRYPTOLAB_CONFIG by default
+  #[arg(long, global = true)]
+  pub config: Option<String>,
+  #[command(subcommand)]
+  pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+  /// Start the web server (default)
+  Serve,
+  /// Validate the configuration and print every problem
+  CheckConfig,
+  /// Apply the pending migrations of the databases and check their indexes
+  Migrate {
+    /// Only list the pending migrations and the missing indexes
+    #[arg(long)]
+    check: bool,
+  },
+  /// Print the cached era of a chain and the active era of its database
+  Era {
+    /// Ticker of the chain, e.g. KSM
+    chain: String,
+  },
+  /// Compute a staking rewards report from the chain database and print it
+  Rewards {
+    stash: String,
+    /// First day, 2020-01-01 by default
+    #[arg(long, default_value = "2020-01-01")]
+    start: String,
+    /// Last day, today by default
+    #[arg(long)]
+    end: Option<String>,
+    #[arg(long, value_enum, default_value_t = ReportFormat::Csv)]
+    format: ReportFormat,
+    #[arg(long, default_value = "USD")]
+    currency: String,
+    /// Balance at the first day, for the annualized return
+    #[arg(long, default_value_t = 0.0)]
+    start_balance: f64,
+    /// Leave the prices out of the report
+    #[arg(long)]
+    no_prices: bool,
+  },
+  /// Generate or decode the ref key of a validator
+  Refkey {
+    #[command(subcommand)]
+    action: RefKeyAction,
+  },
+  /// Print the staking events of a nominator as JSON
+  ExportEvents {
+    stash: String,
+    /// 84 eras before the active one by default
+    #[arg(long)]
+    from_era: Option<u32>,
+    /// The active era by default
+    #[arg(long)]
+    to_era: Option<u32>,
+  },
+}
+
+#[derive(Subcommand)]
+pub enum RefKeyAction {
+  /// Print the ref key of a stash, a new one when it has none
+  Gen { stash: String },
+  /// Print the stash and the creation time of a ref key
+  Decode { ref_key: String },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum ReportFormat {
+  Csv,
+  Json,
+}
+
+impl Command {
+  // whether the configuration and the chains are initialized before `run`
+  pub fn needs_config(&self) -> bool {
+    !matches!(self, Command::CheckConfig | Command::Refkey { action: RefKeyAction::Decode { .. } })
+  }
+}
+
+// validates the configuration at `path` without installing it
+fn check_config(path: &str) -> Result<(), String> {
+  let config = Config::load(path).map_err(|e| e.to_string())?;
+  for warning in &config.warnings {
+    println!("warning: {}", warning);
+  }
+  let tickers: Vec<String> = config.chains().into_iter().map(|chain| chain.ticker).collect();
+  println!("configuration {} is valid, chains: {}", path, tickers.join(", "));
+  Ok(())
+}
+
+// runs a support command, see `Command::needs_config`
+pub async fn run(command: Command, config_path: &str) -> Result<(), String> {
+  match command {
+    Command::Serve => Ok(()),
+    Command::CheckConfig => check_config(config_path),
+    Command::Migrate { check } => migrate(check).await,
+    Command::Era { chain } => era(&chain).await,
+    Command::Rewards { stash, start, end, format, currency, start_balance, no_prices } => {
+      let end = end.unwrap_or_else(|| chrono::Utc::now().format("%Y-%m-%d").to_string());
+      rewards(&stash, start, end, format, currency, start_balance, !no_prices).await
+    },
+    Command::Refkey { action: RefKeyAction::Gen { stash } } => gen_ref_key(&stash).await,
+    Command::Refkey { action: RefKeyAction::Decode { ref_key } } => decode_ref_key(&ref_key),
+    Command::ExportEvents { stash, from_era, to_era } => export_events(&stash, from_era, to_era).await,
+  }
+}
+
+async fn connect(db_name: &str) -> Result<Database, String> {
+  let config = Config::current();
+  let mongo_ip = env::var("MONGO_IP_ADDR").unwrap_or_else(|_| config.db_address.clone());
+  let mut db = Database::new(mongo_ip, config.db_port, db_name);
+  db.connect().await.map_err(|e| format!("failed to connect to the {} database: {}", db_name, e))?;
+  Ok(db)
+}
+
+fn find_chain(ticker: &str) -> Result<&'static Chain, String> {
+  chain::all().iter().find(|chain| chain.ticker.eq_ignore_ascii_case(ticker)).ok_or_else(|| {
+    let tickers: Vec<&str> = chain::all().iter().map(|chain| chain.ticker.as_str()).collect();
+    format!("unknown chain {}, the configured chains are {}", ticker, tickers.join(", "))
+  })
+}
+
+fn find_stash_chain(stash: &str) -> Result<&'static Chain, String> {
+  chain::find_by_address(stash).ok_or_else(|| format!("{} does not belong to a configured chain", stash))
+}
+
+// the users database, then the database of each chain
+async fn migrate(check: bool) -> Result<(), String> {
+  let mut databases = vec![("users".to_string(), Config::current().users_db_name.clone(), Target::Users)];
+  for chain in chain::all() {
+    databases.push((chain.ticker.clone(), chain.db_name.clone(), Target::Chain));
+  }
+  let mut problems = 0;
+  for (name, db_name, target) in databases {
+    let db = connect(&db_name).await?;
+    let failed = |e: DatabaseError| format!("{}: {}", name, e);
+    if check {
+      for migration in migrations::pending(&db, target).await.map_err(failed)? {
+        println!("{}: migration {} is pending: {}", name, migration.version, migration.description);
+        problems += 1;
+      }
+    } else {
+      for (migration, result) in migrations::migrate(&db, target).await.map_err(failed)? {
+        match result {
+          Ok(()) => println!("{}: migration {} applied: {}", name, migration.version, migration.description),
+          Err(e) => {
+            println!("{}: migration {} failed: {}", name, migration.version, e);
+            problems += 1;
+          },
+        }
+      }
+    }
+    for index in migrations::missing_indexes(&db, target).await.map_err(failed)? {
+      println!("{}: index {} of {} is missing", name, index.name, index.collection);
+      problems += 1;
+    }
+  }
+  if problems > 0 {
+    return Err(format!("{} migrations or indexes are missing, run migrate", problems));
+  }
+  println!("the databases are up to date");
+  Ok(())
+}
+
+async fn era(ticker: &str) -> Result<(), String> {
+  let chain = find_chain(ticker)?;
+  let cached = match Config::current().cache_backend.as_deref() {
+    Some("memory") => "none, the memory cache backend is only in the server process".to_string(),
+    _ => match crate::create_cache().await.get_current_era(&chain.ticker).await {
+      Ok(era) => era.to_string(),
+      Err(e) => e.to_string(),
+    },
+  };
+  let db = connect(&chain.db_name).await?;
+  let active = db.get_chain_info().await.map_err(|e| e.to_string())?.active_era;
+  println!("{} cached era: {}", chain.ticker, cached);
+  println!("{} database era: {}", chain.ticker, active);
+  Ok(())
+}
+
+async fn rewards(stash: &str, start: String, end: String, format: ReportFormat, currency: String, start_balance: f64,
+  price_data: bool) -> Result<(), String> {
+  let chain = find_stash_chain(stash)?;
+  let address = StakingRewardsAddress::new("".to_string(), stash.to_string(), start_balance, chain.src_network.clone());
+  let request = StakingRewardsCollector::new(start, end, currency, price_data, vec![address]).map_err(|e| e.to_string())?;
+  let db = connect(&chain.db_name).await?;
+  let rewards = request.collect(&db).await.map_err(|e| e.to_string())?;
+  match format {
+    ReportFormat::Csv => print!("{}", to_csv(&rewards)),
+    ReportFormat::Json => println!("{}", serde_json::to_string_pretty(&rewards).map_err(|e| e.to_string())?),
+  }
+  Ok(())
+}
+
+// the same key as `GET /api/v1/refKey/stash/{stash}/{chain}`, a new key is not stored
+async fn gen_ref_key(stash: &str) -> Result<(), String> {
+  find_stash_chain(stash)?;
+  let users_db = connect(&Config::current().users_db_name).await?;
+  let ref_key = users_db.get_validator_ref_key(stash).await.unwrap_or_else(|_| referer::gen_ref_key(stash));
+  println!("{}", ref_key);
+  Ok(())
+}
+
+fn decode_ref_key(ref_key: &str) -> Result<(), String> {
+  let options = referer::decrypt_ref_key(ref_key).map_err(|e| e.to_string())?;
+  println!("stash: {}", options.stash);
+  println!("created: {}", NaiveDateTime::from_timestamp(options.timestamp as i64, 0).format("%Y-%m-%d %H:%M:%S UTC"));
+  Ok(())
+}
+
+async fn export_events(stash: &str, from_era: Option<u32>, to_era: Option<u32>) -> Result<(), String> {
+  let chain = find_stash_chain(stash)?;
+  let db = connect(&chain.db_name).await?;
+  let users_db = connect(&Config::current().users_db_name).await?;
+  let era = db.get_chain_info().await.map_err(|e| e.to_string())?.active_era;
+  let to_era = to_era.unwrap_or(era);
+  let from_era = from_era.unwrap_or_else(|| era.saturating_sub(84));
+  let events = get_nominator_events(&db, &users_db, stash, from_era, to_era).await.map_err(|e| e.to_string())?;
+  println!("{}", serde_json::to_string_pretty(&events).map_err(|e| e.to_string())?);
+  Ok(())
+}
+
+#[test]
+fn test_parse() {
+  let cli = Cli::try_parse_from(["cryptolab-backend", "rewards", "1abc", "--start", "2021-01-01", "--format", "json",
+    "--config", "config.toml"]).unwrap();
+  assert_eq!(Some("config.toml".to_string()), cli.config);
+  match cli.command {
+    Some(Command::Rewards { stash, start, end, format: ReportFormat::Json, .. }) => {
+      assert_eq!(("1abc", "2021-01-01", None), (stash.as_str(), start.as_str(), end));
+    },
+    _ => panic!("not the rewards command"),
+  }
+  assert!(Cli::try_parse_from(["cryptolab-backend"]).unwrap().command.is_none());
+  assert!(matches!(Cli::try_parse_from(["cryptolab-backend", "refkey", "decode", "abc"]).unwrap().command,
+    Some(Command::Refkey { action: RefKeyAction::Decode { .. } })));
+  assert!(Cli::try_parse_from(["cryptolab-backend", "era"]).is_err());
+}
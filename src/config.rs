@@ -1,15 +1,76 @@
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::HashSet;
 use std::env;
-use std::error::Error;
-use std::fs::File;
-use std::io::BufReader;
+use std::fmt;
+use std::fs;
+use std::net::IpAddr;
 use std::sync::{Arc, RwLock};
-#[derive(Serialize, Deserialize, Debug, Default)]
+
+use crate::chain::{self, Chain};
+
+// The configuration is layered: the file (JSON, or TOML when its name ends with .toml), then the legacy variables of
+// `LEGACY_ENV`, then the variables starting with `ENV_PREFIX`, e.g. `CRYPTOLAB_PORT=8080`, or
+// `CRYPTOLAB_EMAIL__SMTP_PASSWORD=...` for a key of a section. Values of the variables are read as JSON, and as
+// strings when they are not JSON of the type of the key.
+
+// file read when neither `--config` nor `CRYPTOLAB_CONFIG` is given
+pub const DEFAULT_PATH: &str = "./config/config.json";
+pub const PATH_VARIABLE: &str = "CRYPTOLAB_CONFIG";
+pub const ENV_PREFIX: &str = "CRYPTOLAB_";
+
+// variables of the first deployments; a key of a section is only set when the file has the section
+const LEGACY_ENV: &[(&str, &str)] = &[
+    ("KUSAMA_DB_NAME", "kusama_db_name"),
+    ("POLKADOT_DB_NAME", "polkadot_db_name"),
+    ("WESTEND_DB_NAME", "westend_db_name"),
+    ("USERS_DB_NAME", "users_db_name"),
+    ("PORT", "port"),
+    ("REDIS", "redis"),
+    ("REDIS_PORT", "redis_port"),
+    ("SERVE_WWW", "serve_www"),
+    ("CORS_URL", "cors_url"),
+    ("DB_ADDRESS", "db_address"),
+    ("DB_PORT", "db_port"),
+    ("DB_HAS_CREDENTIAL", "db_has_credential"),
+    ("DB_USERNAME", "db_username"),
+    ("DB_PASSWORD", "db_password"),
+    ("DB_HAS_TLS", "db_has_tls"),
+    ("DB_CA_FILE", "db_ca_file"),
+    ("DB_CERT_KEY_FILE", "db_cert_key_file"),
+    ("SUPPORT_WESTEND", "support_westend"),
+    ("ADMIN_TOKEN", "admin_token"),
+    ("SMTP_PASSWORD", "email.smtp_password"),
+    ("EMAIL_TOKEN_SECRET", "email.token_secret"),
+];
+
+// keys of earlier versions which are ignored with a warning, with the reason
+const RETIRED_KEYS: &[(&str, &str)] = &[
+    ("staking_rewards_collector_dir", "the rewards reports are computed in-process"),
+    ("signature_verifier", "the signatures are verified in-process"),
+];
+
+// keys applied by `Config::reload`, a change of the others needs a restart
+pub const HOT_KEYS: &[&str] = &[
+    "cors_url",
+    "log_level",
+    "admin_token",
+    "rewards_job_ttl_hours",
+    "stream_poll_secs",
+    "rate_limit",
+];
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
 pub struct Config {
     pub db_address: String,
     pub db_port: u16,
+    #[serde(default)]
+    pub chains: Vec<Chain>,
+    #[serde(default)]
     pub kusama_db_name: String,
+    #[serde(default)]
     pub polkadot_db_name: String,
+    #[serde(default)]
     pub westend_db_name: String,
     pub users_db_name: String,
     pub port: u16,
@@ -21,23 +82,165 @@ pub struct Config {
     pub db_ca_file: Option<String>,
     pub db_cert_key_file: Option<String>,
 
+    #[serde(default)]
     pub new_cache_folder: String,
+    #[serde(default)]
     pub new_cache_folder_polkadot: String,
 
+    // "redis" (default) or "memory"
+    pub cache_backend: Option<String>,
+
+    #[serde(default)]
     pub redis: String,
+    #[serde(default)]
     pub redis_port: u16,
 
-    pub staking_rewards_collector_dir: String,
     pub serve_www: Option<bool>,
+    pub rewards_job_ttl_hours: Option<u32>,
+    // rewards jobs computed at the same time, 4 by default
+    pub rewards_job_workers: Option<u32>,
+    // deliveries of a webhook payload before it is retried with the next run of the scheduler
+    pub webhook_attempts: Option<u32>,
+    // wait before the second delivery, doubled before each following one
+    pub webhook_backoff_secs: Option<u64>,
 
+    #[serde(default)]
     pub support_westend: bool,
-    pub signature_verifier: String,
+
+    // newsletter confirmations and digests are only logged when absent
+    pub email: Option<EmailConfig>,
+
+    // bearer token of the admin routes, which are closed when absent
+    pub admin_token: Option<String>,
+
+    // how often the event streams are refreshed, 30 seconds by default
+    pub stream_poll_secs: Option<u64>,
+    // open event streams of all the chains, 1000 by default; more are refused until some close
+    pub max_streams: Option<u32>,
+
+    // `RUST_LOG` directives, "info" by default; `RUST_LOG` wins over it
+    pub log_level: Option<String>,
+    // "json" (default) or "text"
+    pub log_format: Option<String>,
+
+    // whether the pending migrations are applied at startup, true by default; the indexes are checked either way
+    pub migrate_on_start: Option<bool>,
+
+    // limits of the requests of a client, see `rate_limit`; the default limits apply when absent
+    pub rate_limit: Option<RateLimitConfig>,
+
+    // what is accepted but should be changed, e.g. a retired key
+    #[serde(skip)]
+    pub warnings: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct EmailConfig {
+    // "smtp", "file" or "log"
+    pub transport: String,
+    pub smtp_host: Option<String>,
+    pub smtp_port: Option<u16>,
+    // "starttls" (default), "tls" or "none"
+    pub smtp_tls: Option<String>,
+    pub smtp_username: Option<String>,
+    pub smtp_password: Option<String>,
+    // folder the file transport writes .eml files to
+    pub file_folder: Option<String>,
+    // sender of the emails, e.g. "CryptoLab <newsletter@cryptolab.network>"
+    pub from: String,
+    // public address of this server, the links of the emails point to it
+    pub base_url: String,
+    // key of the confirmation and unsubscribe tokens
+    pub token_secret: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct RateLimitConfig {
+    // true by default
+    pub enabled: Option<bool>,
+    // reverse proxies whose X-Forwarded-For header gives the address of the client
+    #[serde(default)]
+    pub trusted_proxies: Vec<IpAddr>,
+    // the rewards collector routes, which run a Node process
+    pub collector: Option<RateLimit>,
+    // the POST, PUT and DELETE routes
+    pub write: Option<RateLimit>,
+    // the other routes, which are not limited by default
+    pub read: Option<RateLimit>,
+}
+
+// a token bucket: `burst` requests at once, then `per_minute`
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    pub burst: u32,
+    pub per_minute: u32,
+}
+
+// every problem of a configuration, not only the first one
+#[derive(Debug)]
+pub struct ConfigError {
+    pub path: String,
+    pub problems: Vec<String>,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid configuration {}", self.path)?;
+        for problem in &self.problems {
+            write!(f, "\n  - {}", problem)?;
+        }
+        Ok(())
+    }
+}
+
+// the changed keys of a reloaded configuration
+#[derive(Debug, Default, PartialEq)]
+pub struct Reloaded {
+    // hot keys, now current
+    pub applied: Vec<String>,
+    // keys which need a restart, their previous value is kept
+    pub ignored: Vec<String>,
+}
+
+#[derive(Clone, Copy)]
+enum Format {
+    Json,
+    Toml,
+}
+
+// the `--config` argument, then `CRYPTOLAB_CONFIG`, then `DEFAULT_PATH`
+pub fn path(argument: Option<&str>) -> String {
+    argument.map(String::from)
+        .or_else(|| env::var(PATH_VARIABLE).ok())
+        .unwrap_or_else(|| DEFAULT_PATH.to_string())
 }
 
 impl Config {
-    pub fn init() {
-        let config = read_config("./config/config.json".to_string()).unwrap();
+    pub fn init(path: &str) -> Result<(), ConfigError> {
+        Config::load(path)?.make_current();
+        Ok(())
+    }
+    // reads the file at `path` with the overrides of the environment
+    pub fn load(path: &str) -> Result<Config, ConfigError> {
+        let error = |problems| ConfigError { path: path.to_string(), problems };
+        let text = fs::read_to_string(path).map_err(|e| error(vec![e.to_string()]))?;
+        let format = if path.ends_with(".toml") { Format::Toml } else { Format::Json };
+        let env: Vec<(String, String)> = env::vars().collect();
+        Config::parse(&text, format, &env).map_err(error)
+    }
+    // reads the file at `path` again and makes the changes of the `HOT_KEYS` current; nothing changes when the file
+    // is invalid
+    pub fn reload(path: &str) -> Result<Reloaded, ConfigError> {
+        let (config, reloaded) = Config::current().merge_hot_keys(Config::load(path)?);
         config.make_current();
+        Ok(reloaded)
+    }
+    pub fn chains(&self) -> Vec<Chain> {
+        if self.chains.is_empty() {
+            chain::from_legacy_config(self)
+        } else {
+            self.chains.clone()
+        }
     }
     pub fn current() -> Arc<Config> {
         CURRENT_CONFIG.read().unwrap().clone()
@@ -46,78 +249,412 @@ impl Config {
         let mut c = CURRENT_CONFIG.write().unwrap();
         *c = Arc::new(self);
     }
+
+    fn parse(text: &str, format: Format, env: &[(String, String)]) -> Result<Config, Vec<String>> {
+        let value = match format {
+            Format::Json => serde_json::from_str::<Value>(text).map_err(|e| e.to_string()),
+            Format::Toml => toml::from_str::<toml::Value>(text)
+                .map_err(|e| e.to_string())
+                .and_then(|value| serde_json::to_value(value).map_err(|e| e.to_string())),
+        };
+        let mut object = match value.map_err(|e| vec![e])? {
+            Value::Object(object) => object,
+            _ => return Err(vec!["the configuration is not a table of keys".to_string()]),
+        };
+        for (variable, key) in LEGACY_ENV {
+            if let Some((_, raw)) = env.iter().find(|(name, _)| name == variable) {
+                set_key(&mut object, key, raw, false);
+            }
+        }
+        for (name, raw) in env {
+            match name.strip_prefix(ENV_PREFIX) {
+                Some(key) if name != PATH_VARIABLE => set_key(&mut object, &key.to_lowercase().replace("__", "."), raw, true),
+                _ => {},
+            }
+        }
+
+        // the invalid keys are replaced by their default to check the others
+        let defaults = to_object(&Config::default());
+        let mut problems = vec![];
+        let mut warnings = vec![];
+        let mut invalid = HashSet::new();
+        for key in object.keys().filter(|key| !defaults.contains_key(*key)) {
+            match RETIRED_KEYS.iter().find(|(retired, _)| retired == key) {
+                Some((_, reason)) => warnings.push(format!("{}: ignored, {}", key, reason)),
+                None => problems.push(format!("{}: unknown key", key)),
+            }
+        }
+        for (key, default) in &defaults {
+            let problem = match object.get(key) {
+                Some(value) => fits(&defaults, key, value).err(),
+                None => fits(&defaults, key, &Value::Null).err().and(Some("missing".to_string())),
+            };
+            if let Some(problem) = problem {
+                problems.push(format!("{}: {}", key, problem));
+                invalid.insert(key.as_str());
+                object.insert(key.clone(), default.clone());
+            }
+        }
+        object.retain(|key, _| defaults.contains_key(key));
+        let mut config: Config = serde_json::from_value(Value::Object(object)).map_err(|e| vec![e.to_string()])?;
+
+        for chain in config.chains.iter_mut() {
+            let variable = format!("{}_DB_NAME", chain.ticker);
+            if let Some((_, db_name)) = env.iter().find(|(name, _)| *name == variable) {
+                chain.db_name = db_name.clone();
+            }
+        }
+        config.db_ca_file = config.db_ca_file.filter(|file| !file.is_empty());
+        config.db_cert_key_file = config.db_cert_key_file.filter(|file| !file.is_empty());
+        config.warnings = warnings;
+
+        for (key, problem) in config.validate() {
+            if !key.split('.').next().is_some_and(|key| invalid.contains(key)) {
+                problems.push(format!("{}: {}", key, problem));
+            }
+        }
+        if problems.is_empty() {
+            Ok(config)
+        } else {
+            Err(problems)
+        }
+    }
+
+    // the problems of the values, by key
+    fn validate(&self) -> Vec<(String, String)> {
+        let mut problems = vec![];
+        let mut problem = |key: &str, message: String| problems.push((key.to_string(), message));
+        for (key, value) in [("db_address", &self.db_address), ("users_db_name", &self.users_db_name)] {
+            if value.is_empty() {
+                problem(key, "must not be empty".to_string());
+            }
+        }
+        for (key, port) in [("port", self.port), ("db_port", self.db_port)] {
+            if port == 0 {
+                problem(key, "must not be 0".to_string());
+            }
+        }
+        let chains = self.chains();
+        if chains.is_empty() {
+            problem("chains", "no chain is configured".to_string());
+        }
+        let mut tickers = HashSet::new();
+        for chain in &chains {
+            if !tickers.insert(chain.ticker.as_str()) {
+                problem("chains", format!("{} is configured twice", chain.ticker));
+            }
+            if chain.db_name.is_empty() {
+                problem("chains", format!("the db_name of {} is empty", chain.ticker));
+            }
+            if chain.era_length_hours == 0 {
+                problem("chains", format!("the era_length_hours of {} must not be 0", chain.ticker));
+            }
+        }
+        if self.db_has_credential {
+            for (key, value) in [("db_username", &self.db_username), ("db_password", &self.db_password)] {
+                if value.as_deref().is_none_or(str::is_empty) {
+                    problem(key, "missing, db_has_credential is set".to_string());
+                }
+            }
+        }
+        match self.cache_backend.as_deref().unwrap_or("redis") {
+            "redis" if self.redis.is_empty() => problem("redis", "missing, the cache backend is redis".to_string()),
+            "redis" | "memory" => {},
+            backend => problem("cache_backend", format!("unknown backend {}, expected redis or memory", backend)),
+        }
+        for origin in &self.cors_url {
+            let host = origin.strip_prefix("https://").or_else(|| origin.strip_prefix("http://"));
+            if host.is_none_or(|host| host.is_empty() || host.contains('/')) {
+                problem("cors_url", format!("{} is not an origin, e.g. https://cryptolab.network", origin));
+            }
+        }
+        if let Some(level) = &self.log_level {
+            if let Err(e) = tracing_subscriber::EnvFilter::try_new(level) {
+                problem("log_level", format!("{}: {}", level, e));
+            }
+        }
+        if let Some(format) = self.log_format.as_deref().filter(|format| !["json", "text"].contains(format)) {
+            problem("log_format", format!("unknown format {}, expected json or text", format));
+        }
+        let counts = [
+            ("webhook_attempts", self.webhook_attempts.map(u64::from)),
+            ("rewards_job_workers", self.rewards_job_workers.map(u64::from)),
+            ("stream_poll_secs", self.stream_poll_secs),
+            ("max_streams", self.max_streams.map(u64::from)),
+        ];
+        for (key, value) in counts {
+            if value == Some(0) {
+                problem(key, "must not be 0".to_string());
+            }
+        }
+        if let Some(rate_limit) = &self.rate_limit {
+            let classes = [("collector", rate_limit.collector), ("write", rate_limit.write), ("read", rate_limit.read)];
+            for (class, limit) in classes {
+                if limit.is_some_and(|limit| limit.burst == 0 || limit.per_minute == 0) {
+                    problem(&format!("rate_limit.{}", class), "burst and per_minute must not be 0".to_string());
+                }
+            }
+        }
+        if let Some(email) = &self.email {
+            match email.transport.as_str() {
+                "smtp" if email.smtp_host.is_none() => problem("email.smtp_host", "missing, the transport is smtp".to_string()),
+                "smtp" | "file" | "log" => {},
+                transport => problem("email.transport", format!("unknown transport {}, expected smtp, file or log", transport)),
+            }
+            if let Some(tls) = email.smtp_tls.as_deref().filter(|tls| !["starttls", "tls", "none"].contains(tls)) {
+                problem("email.smtp_tls", format!("unknown value {}, expected starttls, tls or none", tls));
+            }
+            if let Err(e) = email.from.parse::<lettre::message::Mailbox>() {
+                problem("email.from", format!("{} is not an address: {}", email.from, e));
+            }
+            if !email.base_url.starts_with("http://") && !email.base_url.starts_with("https://") {
+                problem("email.base_url", format!("{} is not an http(s) address", email.base_url));
+            }
+            if email.token_secret.is_empty() {
+                problem("email.token_secret", "must not be empty".to_string());
+            }
+        }
+        problems
+    }
+
+    // takes the changed `HOT_KEYS` of `new`
+    fn merge_hot_keys(&self, new: Config) -> (Config, Reloaded) {
+        let mut merged = to_object(self);
+        let mut reloaded = Reloaded::default();
+        for (key, value) in to_object(&new) {
+            if merged.get(&key) == Some(&value) {
+                continue;
+            }
+            if HOT_KEYS.contains(&key.as_str()) {
+                merged.insert(key.clone(), value);
+                reloaded.applied.push(key);
+            } else {
+                reloaded.ignored.push(key);
+            }
+        }
+        let mut config: Config = serde_json::from_value(Value::Object(merged)).expect("a serialized config is valid");
+        config.warnings = new.warnings;
+        (config, reloaded)
+    }
 }
 
 lazy_static! {
     static ref CURRENT_CONFIG: RwLock<Arc<Config>> = RwLock::new(Default::default());
 }
 
-fn read_config(path: String) -> Result<Config, Box<dyn Error>> {
-    let file = File::open(path);
-    match file {
-        Ok(file) => {
-            let reader = BufReader::new(file);
-            let config: Config = serde_json::from_reader(reader)?;
-            let config = read_env(config);
-            Ok(config)
-        }
-        Err(e) => Err(Box::new(e)),
+fn to_object(config: &Config) -> Map<String, Value> {
+    match serde_json::to_value(config) {
+        Ok(Value::Object(object)) => object,
+        _ => Map::new(),
     }
 }
 
-fn read_env(mut config: Config) -> Config {
-    config.kusama_db_name = env::var("KUSAMA_DB_NAME").unwrap_or(config.kusama_db_name);
-    config.polkadot_db_name = env::var("POLKADOT_DB_NAME").unwrap_or(config.polkadot_db_name);
-    config.westend_db_name = env::var("WESTEND_DB_NAME").unwrap_or(config.westend_db_name);
-    config.users_db_name = env::var("USERS_DB_NAME").unwrap_or(config.users_db_name);
-    config.port =
-        str::parse::<u16>(&env::var("PORT").unwrap_or_else(|_| config.port.to_string())).unwrap();
-    config.redis = env::var("REDIS").unwrap_or(config.redis);
-    let redis_port = str::parse::<u16>(
-        &env::var("REDIS_PORT").unwrap_or_else(|_| config.redis_port.to_string()),
-    );
-    config.redis_port = redis_port.unwrap();
-    let serve_www = str::parse::<bool>(
-        &env::var("SERVE_WWW").unwrap_or_else(|_| config.serve_www.unwrap_or(false).to_string()),
-    );
-    config.serve_www = Some(serve_www.unwrap());
-    config.staking_rewards_collector_dir =
-        env::var("STAKING_REWARDS_COLLECTOR_DIR").unwrap_or(config.staking_rewards_collector_dir);
-    let cors_url = serde_json::from_str(
-        &env::var("CORS_URL").unwrap_or_else(|_| serde_json::to_string(&config.cors_url).unwrap()),
-    );
-    config.cors_url = cors_url.unwrap();
-    config.db_address = env::var("DB_ADDRESS").unwrap_or(config.db_address);
-    let db_has_credential = str::parse::<bool>(
-        &env::var("DB_HAS_CREDENTIAL").unwrap_or_else(|_| config.db_has_credential.to_string()),
-    );
-    config.db_has_credential = db_has_credential.unwrap();
-    config.db_password =
-        Some(env::var("DB_PASSWORD").unwrap_or_else(|_| config.db_password.clone().unwrap()));
-    config.db_port =
-        str::parse::<u16>(&env::var("DB_PORT").unwrap_or_else(|_| config.db_port.to_string()))
-            .unwrap();
-    config.db_username =
-        Some(env::var("DB_USERNAME").unwrap_or_else(|_| config.db_username.clone().unwrap()));
-    let db_has_tls = str::parse::<bool>(
-        &env::var("DB_HAS_TLS").unwrap_or_else(|_| config.db_has_tls.to_string()),
-    );
-    config.db_has_tls = db_has_tls.unwrap();
-    let db_cert_key_file = Some(
-        env::var("DB_CERT_KEY_FILE").unwrap_or_else(|_| config.db_cert_key_file.clone().unwrap()),
-    );
-    if db_cert_key_file.is_some() && db_cert_key_file.unwrap().is_empty() {
-        config.db_cert_key_file = None;
+// whether `value` is valid for `key`, checked against the defaults to ignore the other keys
+fn fits(defaults: &Map<String, Value>, key: &str, value: &Value) -> Result<(), String> {
+    let mut probe = defaults.clone();
+    if value.is_null() {
+        probe.remove(key);
     } else {
-        config.db_cert_key_file = Some(
-            env::var("DB_CERT_KEY_FILE").unwrap_or_else(|_| config.db_cert_key_file.clone().unwrap()),
-        );
+        probe.insert(key.to_string(), value.clone());
     }
-    config.db_ca_file =
-        Some(env::var("DB_CA_FILE").unwrap_or_else(|_| config.db_ca_file.clone().unwrap()));
-    let support_westend = str::parse::<bool>(
-        &env::var("SUPPORT_WESTEND").unwrap_or_else(|_| config.support_westend.to_string()));
-    config.support_westend = support_westend.unwrap();
-    config.signature_verifier = env::var("SIGNATURE_VERIFIER").unwrap_or(config.signature_verifier);
-    config
+    serde_json::from_value::<Config>(Value::Object(probe)).map(|_| ()).map_err(|e| e.to_string())
+}
+
+// sets `key`, "email.smtp_password" for a key of a section, to the JSON of `raw` when it fits the key and to the
+// string otherwise; `create` adds a missing section
+fn set_key(object: &mut Map<String, Value>, key: &str, raw: &str, create: bool) {
+    let (top, field) = match key.split_once('.') {
+        Some((section, field)) => (section, Some(field)),
+        None => (key, None),
+    };
+    let candidates = serde_json::from_str::<Value>(raw).into_iter().chain([Value::String(raw.to_string())]);
+    let defaults = to_object(&Config::default());
+    let mut chosen = None;
+    for candidate in candidates {
+        let value = match field {
+            None => candidate,
+            Some(field) => {
+                let mut section = match object.get(top) {
+                    Some(Value::Object(section)) => section.clone(),
+                    Some(_) | None if !create => return,
+                    _ => Map::new(),
+                };
+                section.insert(field.to_string(), candidate);
+                Value::Object(section)
+            },
+        };
+        let fits = fits(&defaults, top, &value).is_ok();
+        chosen = Some(value);
+        if fits {
+            break;
+        }
+    }
+    if let Some(value) = chosen {
+        object.insert(top.to_string(), value);
+    }
+}
+
+#[cfg(test)]
+fn env(variables: &[(&str, &str)]) -> Vec<(String, String)> {
+    variables.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect()
+}
+
+#[test]
+fn test_parse() {
+    let toml = r#"
+        db_address = "127.0.0.1"
+        db_port = 27017
+        users_db_name = "users"
+        port = 3030
+        cors_url = ["http://localhost:3030"]
+        db_has_credential = false
+        db_has_tls = false
+        db_ca_file = ""
+        cache_backend = "memory"
+
+        [[chains]]
+        ticker = "KSM"
+        ss58_prefix = 2
+        decimals = 12
+        db_name = "kusama"
+        src_network = "Kusama"
+        era_length_hours = 6
+
+        [email]
+        transport = "log"
+        from = "CryptoLab <newsletter@cryptolab.network>"
+        base_url = "http://localhost:3030"
+        token_secret = "secret"
+
+        [rate_limit.collector]
+        burst = 1
+        per_minute = 1
+    "#;
+    let config = Config::parse(toml, Format::Toml, &env(&[
+        ("PORT", "8080"),
+        ("CRYPTOLAB_PORT", "9090"),
+        ("CRYPTOLAB_DB_PASSWORD", "1234"),
+        ("CRYPTOLAB_CORS_URL", r#"["https://cryptolab.network"]"#),
+        ("CRYPTOLAB_EMAIL__TOKEN_SECRET", "from env"),
+        ("SMTP_PASSWORD", "smtp"),
+        ("KSM_DB_NAME", "kusama2"),
+        ("CRYPTOLAB_RATE_LIMIT__TRUSTED_PROXIES", r#"["10.0.0.1"]"#),
+    ])).unwrap();
+    // the prefixed variables win over the legacy ones
+    assert_eq!(9090, config.port);
+    assert_eq!(Some("1234".to_string()), config.db_password);
+    assert_eq!(vec!["https://cryptolab.network".to_string()], config.cors_url);
+    assert_eq!(None, config.db_ca_file);
+    assert_eq!("kusama2", config.chains[0].db_name);
+    let email = config.email.unwrap();
+    assert_eq!("from env", email.token_secret);
+    assert_eq!(Some("smtp".to_string()), email.smtp_password);
+    let rate_limit = config.rate_limit.unwrap();
+    assert_eq!(Some(RateLimit { burst: 1, per_minute: 1 }), rate_limit.collector);
+    assert_eq!(vec!["10.0.0.1".parse::<IpAddr>().unwrap()], rate_limit.trusted_proxies);
+
+    // the legacy variables without the optional keys do not panic
+    let json = include_str!("../config/sample.json");
+    let config = Config::parse(json, Format::Json, &env(&[("DB_USERNAME", "cryptolab")])).unwrap();
+    assert_eq!(Some("cryptolab".to_string()), config.db_username);
+    assert_eq!(3, config.chains().len());
+}
+
+#[test]
+fn test_sample_chains() {
+    let config = Config::parse(include_str!("../config/sample.json"), Format::Json, &[]).unwrap();
+    let caps: Vec<(&str, u32)> = config.chains.iter().map(|chain| (chain.ticker.as_str(), chain.max_nominations)).collect();
+    assert_eq!(vec![("KSM", 24), ("DOT", 16), ("WND", 16)], caps);
+}
+
+#[test]
+fn test_parse_baseline_sample() {
+    // the sample of the first deployments, before the chains and the in-process rewards and signatures
+    let json = r#"{
+        "db_address": "127.0.0.1",
+        "db_port": 27017,
+        "kusama_db_name": "kusama",
+        "polkadot_db_name": "polkadot",
+        "westend_db_name": "westend",
+        "users_db_name": "users",
+        "db_has_credential": false,
+        "db_username": "",
+        "db_password": "",
+        "db_has_tls": false,
+        "db_ca_file": "",
+        "db_cert_key_file": "",
+        "port": 3030,
+        "cors_url": ["http://127.0.0.1:3030", "http://localhost:3030", "http://127.0.0.1:8080"],
+        "new_cache_folder": "../chain-data-collector/cache/kusama",
+        "new_cache_folder_polkadot": "../chain-data-collector/cache/polkadot",
+        "staking_rewards_collector_dir": "../staking-rewards-collector",
+        "serve_www": true,
+        "redis": "192.168.2.116",
+        "redis_port": 6379,
+        "support_westend": true,
+        "signature_verifier": "E:\\git\\polkadot-signature-verifier"
+    }"#;
+    let config = Config::parse(json, Format::Json, &[]).unwrap();
+    assert_eq!(vec![
+        "staking_rewards_collector_dir: ignored, the rewards reports are computed in-process".to_string(),
+        "signature_verifier: ignored, the signatures are verified in-process".to_string(),
+    ], config.warnings);
+    assert_eq!(3, config.chains().len());
+}
+
+#[test]
+fn test_parse_problems() {
+    let json = r#"{
+        "db_address": "127.0.0.1",
+        "db_port": "27017",
+        "kusama_db_name": "kusama",
+        "polkadot_db_name": "polkadot",
+        "users_db_name": "users",
+        "cors_url": ["localhost:3030"],
+        "db_has_credential": true,
+        "db_has_tls": false,
+        "cache_backend": "disk",
+        "log_format": "xml",
+        "mongo": true,
+        "email": {"transport": "smtp", "from": "nobody", "base_url": "http://localhost", "token_secret": "s"}
+    }"#;
+    let problems = Config::parse(json, Format::Json, &env(&[("CRYPTOLAB_STREAM_POLL_SECS", "soon")])).unwrap_err();
+    assert_eq!(vec![
+        "mongo: unknown key",
+        "db_port: invalid type: string \"27017\", expected u16",
+        "port: missing",
+        "stream_poll_secs: invalid type: string \"soon\", expected u64",
+        "db_username: missing, db_has_credential is set",
+        "db_password: missing, db_has_credential is set",
+        "cache_backend: unknown backend disk, expected redis or memory",
+        "cors_url: localhost:3030 is not an origin, e.g. https://cryptolab.network",
+        "log_format: unknown format xml, expected json or text",
+        "email.smtp_host: missing, the transport is smtp",
+    ], problems.iter().map(|p| p.as_str()).take(10).collect::<Vec<_>>());
+    // the invalid `from` is reported after them
+    assert!(problems[10].starts_with("email.from: nobody is not an address"));
+    assert_eq!(11, problems.len());
+
+    let json = include_str!("../config/sample.json").replacen('{', r#"{"rate_limit": {"write": {"burst": 0, "per_minute": 10}},"#, 1);
+    let problems = Config::parse(&json, Format::Json, &[]).unwrap_err();
+    assert_eq!(vec!["rate_limit.write: burst and per_minute must not be 0".to_string()], problems);
+}
+
+#[test]
+fn test_merge_hot_keys() {
+    let current = Config { port: 3030, cors_url: vec!["http://localhost:3030".to_string()], ..Default::default() };
+    let new = Config {
+        port: 8080,
+        cors_url: vec!["https://cryptolab.network".to_string()],
+        log_level: Some("debug".to_string()),
+        ..Default::default()
+    };
+    let (merged, reloaded) = current.merge_hot_keys(new);
+    assert_eq!(3030, merged.port);
+    assert_eq!(vec!["https://cryptolab.network".to_string()], merged.cors_url);
+    assert_eq!(Some("debug".to_string()), merged.log_level);
+    assert_eq!(Reloaded {
+        applied: vec!["cors_url".to_string(), "log_level".to_string()],
+        ignored: vec!["port".to_string()],
+    }, reloaded);
 }
@@ -1,24 +1,15 @@
-
-use mongodb::bson::{self, Bson};
+use mongodb::bson::{self, Bson, Document};
 
 use crate::types;
 
-use super::{Database, DatabaseError};
+use super::{Database, DatabaseError, InCollection};
 
 impl Database {
   pub async fn get_chain_info(&self) -> Result<types::ChainInfo, DatabaseError> {
-    let client = self.client.as_ref().ok_or(DatabaseError::Mongo);
-    if let Ok(client) = client {
-        let db = client.database(&self.db_name);
-        match db.collection("chainInfo").find_one(None, None).await {
-            Ok(cursor) => Ok(bson::from_bson(Bson::Document(cursor.unwrap())).unwrap()),
-            Err(e) => {
-                println!("{:?}", e);
-                Err(DatabaseError::GetFailed)
-            },
-        }
-    } else {
-        Err(DatabaseError::Disconnected)
-    }
+    let client = self.client.as_ref().ok_or(DatabaseError::Disconnected)?;
+    let db = client.database(&self.db_name);
+    let info = db.collection::<Document>("chainInfo").find_one(None, None).await.in_collection("chainInfo")?
+        .ok_or_else(|| DatabaseError::not_found("chainInfo", "chain info"))?;
+    bson::from_bson(Bson::Document(info)).in_collection("chainInfo")
   }
 }
\ No newline at end of file
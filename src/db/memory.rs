@@ -0,0 +1,474 @@
+use std::{cmp::Ordering, collections::HashMap, fs, io, sync::RwLock};
+
+use async_trait::async_trait;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::chain::Chain;
+use crate::referer;
+use crate::risk::ValidatorRisk;
+use crate::types::{CBStashEraReward, ChainInfo, ChillEvent, KickEvent, NewsletterStatus, NewsletterSubscriber,
+  NewsletterSubscriberOptions, NominationOptions, NominationResultOptions, NominatorNomination, OverSubscribeEvent,
+  OverSubscribeEventOutput, StakingEvents, StashEraReward, StashRewards, UserEventMappingOptions, ValidatorCommission,
+  ValidatorNominationInfo, ValidatorNominationTrend, ValidatorSlash, ValidatorStalePayoutEvent};
+use crate::webhooks::WebhookSubscription;
+
+use super::DatabaseError;
+use super::params::{AllValidatorOptions, DbRefKeyOptions, ValidatorSort};
+use super::repo::{EventRepo, NominatorRepo, UserActionRepo, ValidatorRepo, WebhookRepo};
+use super::validator::percentile_rank;
+
+// the fields of the users collections the repository filters on
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct NominationRecord {
+  stash: String,
+  tag: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RefKeyRecord {
+  stash: String,
+  ref_key: String,
+}
+
+#[derive(Deserialize)]
+struct InactiveEvent {
+  address: String,
+  era: u32,
+}
+
+// Documents in the shape the queries return them, keyed like the collections they come from.
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase", default)]
+struct Fixtures {
+  chain_info: Option<ChainInfo>,
+  // one entry per validator and era
+  validators: Vec<ValidatorNominationInfo>,
+  unclaimed_eras: HashMap<String, Vec<i32>>,
+  nominators: Vec<NominatorNomination>,
+  payouts: Vec<CBStashEraReward>,
+  // prices keyed by the start of the day in seconds
+  prices: HashMap<i64, f64>,
+  commissions: Vec<ValidatorCommission>,
+  slashes: Vec<ValidatorSlash>,
+  inactive: Vec<InactiveEvent>,
+  stale_payouts: Vec<ValidatorStalePayoutEvent>,
+  kicks: Vec<KickEvent>,
+  chills: Vec<ChillEvent>,
+  over_subscribes: Vec<OverSubscribeEvent>,
+  nomination_records: Vec<NominationRecord>,
+  ref_keys: Vec<RefKeyRecord>,
+  newsletter: Vec<NewsletterSubscriber>,
+  risks: Vec<ValidatorRisk>,
+  webhooks: Vec<WebhookSubscription>,
+}
+
+// Repository over JSON fixtures, so the API can be tested without MongoDB.
+pub struct MemoryRepo {
+  data: RwLock<Fixtures>,
+}
+
+impl MemoryRepo {
+  pub fn from_json(json: &str) -> serde_json::Result<Self> {
+    Ok(MemoryRepo {
+      data: RwLock::new(serde_json::from_str(json)?),
+    })
+  }
+
+  pub fn load(path: &str) -> io::Result<Self> {
+    MemoryRepo::from_json(&fs::read_to_string(path)?).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+  }
+
+  // the chain and users collections of tests/fixtures/kusama.json
+  pub fn kusama() -> Self {
+    MemoryRepo::load(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/kusama.json")).unwrap()
+  }
+}
+
+// the chain of `MemoryRepo::kusama`, as the legacy configuration defines it
+pub fn kusama_chain() -> Chain {
+  crate::chain::from_legacy_config(&crate::config::Config::default()).remove(0)
+}
+
+fn in_range(era: u32, from: u32, to: u32) -> bool {
+  era >= from && era <= to
+}
+
+// the same keys the Mongo pipeline sorts by
+fn sort_key(v: &ValidatorNominationInfo, sort: Option<ValidatorSort>) -> Value {
+  match sort {
+    None => json!(v.id),
+    Some(ValidatorSort::Apy) => json!(v.info.apy as f64),
+    Some(ValidatorSort::Commission) => json!(v.info.commission as f64),
+    Some(ValidatorSort::NominatorCount) => json!(v.info.nominator_count),
+    Some(ValidatorSort::Total) => json!(format!("{:032x}", v.info.total)),
+    Some(ValidatorSort::SelfStake) => json!(format!("{:032x}", v.info.self_stake.unwrap_or(0))),
+  }
+}
+
+fn average_era_points(v: &ValidatorNominationInfo) -> f64 {
+  match &v.staker_points {
+    Some(points) if !points.is_empty() => points.iter().map(|p| p.points as f64).sum::<f64>() / points.len() as f64,
+    _ => 0.0,
+  }
+}
+
+fn compare_keys(a: &Value, b: &Value) -> Ordering {
+  match (a.as_f64(), b.as_f64()) {
+    (Some(a), Some(b)) => a.partial_cmp(&b).unwrap_or(Ordering::Equal),
+    _ => a.as_str().cmp(&b.as_str()),
+  }
+}
+
+fn or_empty<T>(wanted: bool, events: Vec<T>) -> Vec<T> {
+  if wanted { events } else { vec![] }
+}
+
+#[async_trait]
+impl ValidatorRepo for MemoryRepo {
+  async fn get_chain_info(&self) -> Result<ChainInfo, DatabaseError> {
+    self.data.read().unwrap().chain_info.clone().ok_or_else(|| DatabaseError::not_found("chainInfo", "chain info"))
+  }
+
+  async fn get_validator(&self, stash: String) -> Result<ValidatorNominationTrend, DatabaseError> {
+    let eras = self.data.read().unwrap().validators.iter().filter(|v| v.id == stash).cloned().collect();
+    ValidatorNominationTrend::from_eras(eras).ok_or_else(|| DatabaseError::not_found("validator", format!("stash {}", stash)))
+  }
+
+  async fn get_validator_unclaimed_eras(&self, stash: String) -> Result<Vec<i32>, DatabaseError> {
+    Ok(self.data.read().unwrap().unclaimed_eras.get(&stash).cloned().unwrap_or_default())
+  }
+
+  async fn get_validator_slashes(&self, stash: String) -> Result<Vec<ValidatorSlash>, DatabaseError> {
+    Ok(self.data.read().unwrap().slashes.iter().filter(|s| s.address == stash).cloned().collect())
+  }
+
+  async fn get_all_validator_info_of_era(&self, era: u32, options: AllValidatorOptions)
+    -> Result<Vec<ValidatorNominationInfo>, DatabaseError> {
+    let mut validators: Vec<ValidatorNominationInfo> = self.validators_of_era(era, &options).into_iter()
+      .map(|mut v| {
+        v.sort_key = Some(sort_key(&v, options.sort));
+        v
+      })
+      .filter(|v| match &options.after {
+        Some(cursor) => {
+          let key = v.sort_key.as_ref().unwrap();
+          let ordering = compare_keys(key, &cursor.key).then_with(|| v.id.cmp(&cursor.id));
+          if options.descending { ordering == Ordering::Less } else { ordering == Ordering::Greater }
+        },
+        None => true,
+      })
+      .collect();
+    validators.sort_by(|a, b| {
+      let ordering = compare_keys(a.sort_key.as_ref().unwrap(), b.sort_key.as_ref().unwrap()).then_with(|| a.id.cmp(&b.id));
+      if options.descending { ordering.reverse() } else { ordering }
+    });
+    let skip = if options.after.is_some() { 0 } else { options.skip() };
+    Ok(self.with_risks(validators.into_iter().skip(skip as usize).take(options.size as usize).collect()))
+  }
+
+  async fn count_validators_of_era(&self, era: u32, options: &AllValidatorOptions) -> Result<u64, DatabaseError> {
+    Ok(self.validators_of_era(era, options).len() as u64)
+  }
+
+  async fn get_validator_info(&self, stashes: &[String], era: &u32) -> Result<Vec<ValidatorNominationInfo>, DatabaseError> {
+    let validators = self.data.read().unwrap().validators.iter()
+      .filter(|v| v.info.era == *era && stashes.contains(&v.id)).cloned().collect();
+    Ok(self.with_risks(validators))
+  }
+
+  async fn get_validator_risk(&self, stash: &str) -> Result<ValidatorRisk, DatabaseError> {
+    self.data.read().unwrap().risks.iter().find(|r| r.stash == stash).cloned()
+      .ok_or_else(|| DatabaseError::not_found("validatorRisk", format!("stash {}", stash)))
+  }
+}
+
+#[async_trait]
+impl NominatorRepo for MemoryRepo {
+  async fn get_nominator_info(&self, stash: &str) -> Result<NominatorNomination, DatabaseError> {
+    let mut nominator = self.data.read().unwrap().nominators.iter().find(|n| n.account_id == stash).cloned()
+      .ok_or_else(|| DatabaseError::not_found("nominator", format!("stash {}", stash)))?;
+    nominator.rewards = Some(self.stash_reward(stash));
+    Ok(nominator)
+  }
+}
+
+impl MemoryRepo {
+  fn with_risks(&self, mut validators: Vec<ValidatorNominationInfo>) -> Vec<ValidatorNominationInfo> {
+    let data = self.data.read().unwrap();
+    for v in validators.iter_mut() {
+      v.risk = data.risks.iter().find(|r| r.stash == v.id).cloned();
+    }
+    validators
+  }
+
+  fn validators_of_era(&self, era: u32, options: &AllValidatorOptions) -> Vec<ValidatorNominationInfo> {
+    let data = self.data.read().unwrap();
+    let of_era: Vec<&ValidatorNominationInfo> = data.validators.iter().filter(|v| v.info.era == era).collect();
+    let threshold = options.era_points_percentile
+      .and_then(|p| percentile_rank(of_era.iter().map(|v| average_era_points(v)).collect(), p));
+    let within = |amount: u128, min: Option<u128>, max: Option<u128>| {
+      min.is_none_or(|min| amount >= min) && max.is_none_or(|max| amount <= max)
+    };
+    of_era.into_iter()
+      .filter(|v| v.info.apy >= options.apy_min && v.info.apy <= options.apy_max)
+      .filter(|v| v.info.commission >= options.commission_min * 100.0 && v.info.commission <= options.commission_max * 100.0)
+      .filter(|v| !options.has_verified_identity
+        || v.identity.as_ref().and_then(|i| i.is_verified).unwrap_or(false))
+      .filter(|v| options.stashes.as_ref().is_none_or(|stashes| stashes.contains(&v.id)))
+      .filter(|v| within(v.info.total, options.total_min, options.total_max))
+      .filter(|v| within(v.info.self_stake.unwrap_or(0), options.self_stake_min, options.self_stake_max))
+      .filter(|v| options.nominator_limit.is_none_or(|limit| v.info.nominator_count < limit))
+      .filter(|v| !options.has_parent_identity || v.identity.as_ref()
+        .is_some_and(|i| [&i.parent, &i.sub].iter().any(|name| name.as_ref().is_some_and(|name| !name.is_empty()))))
+      .filter(|v| !options.exclude_blocked || !v.block_nomination)
+      .filter(|v| threshold.is_none_or(|t| average_era_points(v) >= t))
+      .filter(|v| options.slash_free_eras.is_none_or(|eras| !data.slashes.iter()
+        .any(|s| s.address == v.id && s.era as i64 > era as i64 - eras as i64 && s.era <= era)))
+      .cloned()
+      .collect()
+  }
+
+  fn stash_reward(&self, stash: &str) -> StashRewards {
+    let data = self.data.read().unwrap();
+    let era_rewards: Vec<StashEraReward> = data.payouts.iter().filter(|p| p.address == stash).map(|p| {
+      let day = p.timestamp / 1000 / 86400 * 86400;
+      let price = data.prices.get(&day).cloned().unwrap_or(0.0);
+      StashEraReward {
+        era: p.era,
+        amount: p.amount,
+        timestamp: p.timestamp,
+        price: Some(price),
+        total: Some(price * p.amount),
+      }
+    }).collect();
+    StashRewards {
+      stash: stash.to_string(),
+      total_in_fiat: era_rewards.iter().map(|r| r.total.unwrap_or(0.0)).sum(),
+      era_rewards,
+      annualized_return: None,
+    }
+  }
+}
+
+#[async_trait]
+impl EventRepo for MemoryRepo {
+  // there is no userEventMapping collection here, the events of the stash and its targets are picked directly
+  async fn get_user_events_by_mapping(&self, options: UserEventMappingOptions) -> Result<StakingEvents, DatabaseError> {
+    let (from, to, stash) = (options.from_era, options.to_era, options.stash.clone());
+    let wanted = |event_type: u32| options.event_types.contains(&event_type);
+    let targets = self.data.read().unwrap().nominators.iter()
+      .find(|n| n.account_id == stash).map(|n| n.targets.clone()).unwrap_or_default();
+    let over_subscribes = self.get_oversubscribe_events(&stash, &from, &to).await?.into_iter().map(|e| OverSubscribeEventOutput {
+      nominator: stash.clone(),
+      amount: e.nominators.iter().find(|n| n.who == stash).map(|n| n.value.clone()).unwrap_or_default(),
+      address: e.address,
+      era: e.era,
+    }).collect();
+    Ok(StakingEvents {
+      payouts: or_empty(wanted(0), self.get_nominated_validators_payout_events(stash.clone(), from, to).await?),
+      commissions: or_empty(wanted(1), self.get_is_commission_changed(&targets, from, to).await?),
+      kicks: or_empty(wanted(2), self.get_kick_events(&stash, &from, &to).await?),
+      chills: or_empty(wanted(3), self.get_chill_events(&targets, &from, &to).await?),
+      inactive: or_empty(wanted(4), self.get_all_validators_inactive(&stash, from, to).await?),
+      stale_payouts: or_empty(wanted(5), self.get_nominated_validators_stale_payout_events(&targets, from, to).await?),
+      over_subscribes: or_empty(wanted(6), over_subscribes),
+      slashes: vec![],
+    })
+  }
+
+  async fn get_is_commission_changed(&self, validators: &[String], from: u32, to: u32)
+    -> Result<Vec<ValidatorCommission>, DatabaseError> {
+    Ok(self.data.read().unwrap().commissions.iter()
+      .filter(|e| validators.contains(&e.address) && in_range(e.era, from, to)).cloned().collect())
+  }
+
+  async fn get_multiple_validators_slashes(&self, validators: &[String], from: u32, to: u32)
+    -> Result<Vec<ValidatorSlash>, DatabaseError> {
+    Ok(self.data.read().unwrap().slashes.iter()
+      .filter(|e| validators.contains(&e.address) && in_range(e.era, from, to)).cloned().collect())
+  }
+
+  async fn get_all_validators_inactive(&self, stash: &str, from: u32, to: u32) -> Result<Vec<u32>, DatabaseError> {
+    Ok(self.data.read().unwrap().inactive.iter()
+      .filter(|e| e.address == stash && in_range(e.era, from, to)).map(|e| e.era).collect())
+  }
+
+  async fn get_nominated_validators_stale_payout_events(&self, validators: &[String], from: u32, to: u32)
+    -> Result<Vec<ValidatorStalePayoutEvent>, DatabaseError> {
+    Ok(self.data.read().unwrap().stale_payouts.iter()
+      .filter(|e| validators.contains(&e.address) && in_range(e.era, from, to)).cloned().collect())
+  }
+
+  async fn get_nominated_validators_payout_events(&self, stash: String, from: u32, to: u32)
+    -> Result<Vec<CBStashEraReward>, DatabaseError> {
+    Ok(self.data.read().unwrap().payouts.iter()
+      .filter(|e| e.address == stash && e.era >= from as i32 && e.era <= to as i32).cloned().collect())
+  }
+
+  async fn get_kick_events(&self, nominator: &str, from: &u32, to: &u32) -> Result<Vec<KickEvent>, DatabaseError> {
+    Ok(self.data.read().unwrap().kicks.iter()
+      .filter(|e| e.nominator == nominator && in_range(e.era, *from, *to)).cloned().collect())
+  }
+
+  async fn get_chill_events(&self, validators: &[String], from: &u32, to: &u32) -> Result<Vec<ChillEvent>, DatabaseError> {
+    Ok(self.data.read().unwrap().chills.iter()
+      .filter(|e| validators.contains(&e.address) && in_range(e.era, *from, *to)).cloned().collect())
+  }
+
+  async fn get_oversubscribe_events(&self, nominator: &str, from: &u32, to: &u32)
+    -> Result<Vec<OverSubscribeEvent>, DatabaseError> {
+    Ok(self.data.read().unwrap().over_subscribes.iter()
+      .filter(|e| e.nominators.iter().any(|n| n.who == nominator) && in_range(e.era, *from, *to)).cloned().collect())
+  }
+}
+
+#[async_trait]
+impl UserActionRepo for MemoryRepo {
+  async fn insert_nomination_action(&self, _chain: String, options: NominationOptions) -> Result<String, DatabaseError> {
+    let mut data = self.data.write().unwrap();
+    let tag = format!("tag{}", data.nomination_records.len());
+    data.nomination_records.push(NominationRecord {
+      stash: options.stash,
+      tag: tag.clone(),
+    });
+    Ok(tag)
+  }
+
+  async fn insert_nomination_result(&self, options: NominationResultOptions) -> Result<(), DatabaseError> {
+    let data = self.data.read().unwrap();
+    // the extrinsic hash and the ref key are not read back
+    data.nomination_records.iter().find(|r| r.tag == options.tag)
+      .ok_or_else(|| DatabaseError::not_found("nominationRecords", format!("tag {}", options.tag)))?;
+    Ok(())
+  }
+
+  async fn has_nomination_records(&self, stash: &str) -> Result<bool, DatabaseError> {
+    Ok(self.data.read().unwrap().nomination_records.iter().any(|r| r.stash == stash))
+  }
+
+  async fn get_validator_ref_key(&self, stash: &str) -> Result<String, DatabaseError> {
+    self.data.read().unwrap().ref_keys.iter().find(|r| r.stash == stash).map(|r| r.ref_key.clone())
+      .ok_or_else(|| DatabaseError::not_found("refKeyRecords", format!("stash {}", stash)))
+  }
+
+  async fn decode_validator_ref_key(&self, ref_key: &str) -> Result<DbRefKeyOptions, DatabaseError> {
+    let data = self.data.read().unwrap();
+    let record = data.ref_keys.iter().find(|r| r.ref_key == ref_key)
+      .ok_or_else(|| DatabaseError::not_found("refKeyRecords", format!("ref key {}", ref_key)))?;
+    referer::decrypt_ref_key(&record.ref_key).map_err(|e| DatabaseError::decode("refKeyRecords", e))
+  }
+
+  async fn insert_validator_ref_key(&self, options: DbRefKeyOptions) -> Result<(), DatabaseError> {
+    let mut data = self.data.write().unwrap();
+    data.ref_keys.retain(|r| r.stash != options.stash);
+    data.ref_keys.push(RefKeyRecord {
+      stash: options.stash,
+      ref_key: options.ref_key,
+    });
+    Ok(())
+  }
+
+  async fn insert_newsletter_subsriber(&self, options: NewsletterSubscriberOptions)
+    -> Result<Option<NewsletterStatus>, DatabaseError> {
+    let mut data = self.data.write().unwrap();
+    let previous = data.newsletter.iter().find(|s| s.email == options.email).map(|s| s.status);
+    if previous == Some(NewsletterStatus::Confirmed) {
+      return Err(DatabaseError::Duplicated { collection: "newsletter".to_string(), key: options.email });
+    }
+    data.newsletter.retain(|s| s.email != options.email);
+    data.newsletter.push(NewsletterSubscriber {
+      email: options.email,
+      status: NewsletterStatus::Pending,
+      stashes: options.stashes,
+      timestamp: chrono::Utc::now().timestamp(),
+      confirmed_at: None,
+      digest_eras: HashMap::new(),
+    });
+    Ok(previous)
+  }
+
+  async fn get_newsletter_subscriber(&self, email: &str) -> Result<NewsletterSubscriber, DatabaseError> {
+    self.data.read().unwrap().newsletter.iter().find(|s| s.email == email).cloned()
+      .ok_or_else(|| DatabaseError::not_found("newsletter", format!("email {}", email)))
+  }
+
+  async fn get_newsletter_subscribers(&self, status: NewsletterStatus) -> Result<Vec<NewsletterSubscriber>, DatabaseError> {
+    Ok(self.data.read().unwrap().newsletter.iter().filter(|s| s.status == status).cloned().collect())
+  }
+
+  async fn count_newsletter_subscribers(&self, status: NewsletterStatus) -> Result<u64, DatabaseError> {
+    Ok(self.data.read().unwrap().newsletter.iter().filter(|s| s.status == status).count() as u64)
+  }
+
+  async fn set_newsletter_status(&self, email: &str, status: NewsletterStatus) -> Result<(), DatabaseError> {
+    let mut data = self.data.write().unwrap();
+    let subscriber = data.newsletter.iter_mut().find(|s| s.email == email)
+      .ok_or_else(|| DatabaseError::not_found("newsletter", format!("email {}", email)))?;
+    subscriber.status = status;
+    if status == NewsletterStatus::Confirmed {
+      subscriber.confirmed_at = Some(chrono::Utc::now().timestamp());
+    }
+    Ok(())
+  }
+
+  async fn swap_newsletter_digest_era(&self, email: &str, chain: &str, from: Option<u32>, to: Option<u32>)
+    -> Result<bool, DatabaseError> {
+    let mut data = self.data.write().unwrap();
+    let subscriber = match data.newsletter.iter_mut().find(|s| s.email == email) {
+      Some(subscriber) if subscriber.digest_eras.get(chain).copied() == from => subscriber,
+      _ => return Ok(false),
+    };
+    match to {
+      Some(era) => subscriber.digest_eras.insert(chain.to_string(), era),
+      None => subscriber.digest_eras.remove(chain),
+    };
+    Ok(true)
+  }
+
+  async fn delete_newsletter_subscriber(&self, email: &str) -> Result<(), DatabaseError> {
+    let mut data = self.data.write().unwrap();
+    let count = data.newsletter.len();
+    data.newsletter.retain(|s| s.email != email);
+    if data.newsletter.len() == count {
+      return Err(DatabaseError::not_found("newsletter", format!("email {}", email)));
+    }
+    Ok(())
+  }
+
+}
+
+#[async_trait]
+impl WebhookRepo for MemoryRepo {
+  async fn insert_webhook_subscription(&self, subscription: &WebhookSubscription) -> Result<(), DatabaseError> {
+    self.data.write().unwrap().webhooks.push(subscription.clone());
+    Ok(())
+  }
+
+  async fn get_webhook_subscriptions(&self, chain: &str) -> Result<Vec<WebhookSubscription>, DatabaseError> {
+    Ok(self.data.read().unwrap().webhooks.iter().filter(|w| w.chain == chain).cloned().collect())
+  }
+
+  async fn swap_webhook_last_era(&self, id: &str, from: u32, to: u32) -> Result<bool, DatabaseError> {
+    let mut data = self.data.write().unwrap();
+    match data.webhooks.iter_mut().find(|w| w.id == id && w.last_era == from) {
+      Some(subscription) => {
+        subscription.last_era = to;
+        Ok(true)
+      },
+      None => Ok(false),
+    }
+  }
+
+  async fn delete_webhook_subscription(&self, id: &str, secret: &str) -> Result<(), DatabaseError> {
+    let mut data = self.data.write().unwrap();
+    let count = data.webhooks.len();
+    data.webhooks.retain(|w| w.id != id || w.secret != secret);
+    if data.webhooks.len() == count {
+      return Err(DatabaseError::not_found("webhookSubscriptions", format!("id {}", id)));
+    }
+    Ok(())
+  }
+}
@@ -0,0 +1,363 @@
+use chrono::Utc;
+use futures::StreamExt;
+use mongodb::bson::{Bson, Document, doc};
+use mongodb::error::ErrorKind;
+use mongodb::options::UpdateOptions;
+use tracing::{error, info, warn};
+
+use super::{Database, DatabaseError, InCollection, is_duplicate_key};
+
+// A migration changes a database once: the versions applied to a database are recorded in its `migrations`
+// collection. Migrations only add indexes or fill missing fields, so older servers keep working on a migrated
+// database, and do not depend on each other: one failing does not stop the next ones. New migrations are appended
+// with the next version.
+
+const MIGRATIONS: &str = "migrations";
+// error code of the server for a collection which does not exist
+const NAMESPACE_NOT_FOUND: i32 = 26;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Target {
+  // the database of each chain, written by chain-data-collector
+  Chain,
+  // the users database
+  Users,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Index {
+  pub collection: &'static str,
+  pub name: &'static str,
+  pub keys: &'static [(&'static str, i32)],
+  pub unique: bool,
+  // documents without the keys are not indexed
+  pub sparse: bool,
+  // documents are removed this many seconds after the date of the first key
+  pub expire_after_secs: Option<i64>,
+}
+
+pub enum Step {
+  CreateIndexes(&'static [Index]),
+  // see `Database::backfill_newsletter_status`
+  BackfillNewsletterStatus,
+}
+
+pub struct Migration {
+  pub version: u32,
+  pub description: &'static str,
+  pub target: Target,
+  pub step: Step,
+}
+
+const fn index(collection: &'static str, name: &'static str, keys: &'static [(&'static str, i32)]) -> Index {
+  Index { collection, name, keys, unique: false, sparse: false, expire_after_secs: None }
+}
+
+const fn unique_index(collection: &'static str, name: &'static str, keys: &'static [(&'static str, i32)]) -> Index {
+  Index { collection, name, keys, unique: true, sparse: false, expire_after_secs: None }
+}
+
+// the filters of db/validator.rs, db/nominator.rs, db/staking_rewards.rs and db/user_actions.rs
+const CHAIN_INDEXES: &[Index] = &[
+  index("nomination", "era_validator", &[("era", 1), ("validator", 1)]),
+  index("nomination", "validator_era", &[("validator", 1), ("era", 1)]),
+  index("validator", "id", &[("id", 1)]),
+  index("stashInfo", "stash_era", &[("stash", 1), ("era", 1)]),
+  index("stashInfo", "stash_timestamp", &[("stash", 1), ("timestamp", 1)]),
+  index("userEventMapping", "address_era_type", &[("address", 1), ("era", 1), ("type", 1)]),
+];
+
+// version 2 used to create the indexes of all the users collections, one duplicate email blocked the others; it was
+// split by collection so a failure only delays the indexes of its collection
+const REF_KEY_RECORDS_INDEXES: &[Index] = &[
+  unique_index("refKeyRecords", "stash", &[("stash", 1)]),
+  index("refKeyRecords", "refKey", &[("refKey", 1)]),
+];
+
+const NEWSLETTER_INDEXES: &[Index] = &[
+  unique_index("newsletter", "email", &[("email", 1)]),
+];
+
+const NOMINATION_RECORDS_INDEXES: &[Index] = &[
+  index("nominationRecords", "stash", &[("stash", 1)]),
+  index("nominationRecords", "tag", &[("tag", 1)]),
+];
+
+const WEBHOOK_SUBSCRIPTIONS_INDEXES: &[Index] = &[
+  index("webhookSubscriptions", "chain", &[("chain", 1)]),
+];
+
+const REWARDS_JOBS_INDEXES: &[Index] = &[
+  Index { collection: "rewardsJobs", name: "expireAt_ttl", keys: &[("expireAt", 1)], unique: false, sparse: false,
+    expire_after_secs: Some(0) },
+  unique_index("rewardsJobs", "jobId", &[("jobId", 1)]),
+  index("rewardsJobs", "key", &[("key", 1)]),
+];
+
+// one live job by request, see `Database::insert_rewards_job`
+const REWARDS_JOBS_LIVE_KEY: &[Index] = &[
+  Index { collection: "rewardsJobs", name: "liveKey", keys: &[("liveKey", 1)], unique: true, sparse: true,
+    expire_after_secs: None },
+];
+
+pub const ALL: &[Migration] = &[
+  Migration {
+    version: 1,
+    description: "indexes of the chain collections",
+    target: Target::Chain,
+    step: Step::CreateIndexes(CHAIN_INDEXES),
+  },
+  Migration {
+    version: 2,
+    description: "indexes of refKeyRecords, unique stash",
+    target: Target::Users,
+    step: Step::CreateIndexes(REF_KEY_RECORDS_INDEXES),
+  },
+  Migration {
+    version: 3,
+    description: "pending status of the newsletter subscribers stored before double opt-in",
+    target: Target::Users,
+    step: Step::BackfillNewsletterStatus,
+  },
+  Migration {
+    version: 4,
+    description: "unique sparse liveKey of rewardsJobs, concurrent requests share one job",
+    target: Target::Users,
+    step: Step::CreateIndexes(REWARDS_JOBS_LIVE_KEY),
+  },
+  Migration {
+    version: 5,
+    description: "unique email of newsletter",
+    target: Target::Users,
+    step: Step::CreateIndexes(NEWSLETTER_INDEXES),
+  },
+  Migration {
+    version: 6,
+    description: "indexes of nominationRecords",
+    target: Target::Users,
+    step: Step::CreateIndexes(NOMINATION_RECORDS_INDEXES),
+  },
+  Migration {
+    version: 7,
+    description: "indexes of webhookSubscriptions",
+    target: Target::Users,
+    step: Step::CreateIndexes(WEBHOOK_SUBSCRIPTIONS_INDEXES),
+  },
+  Migration {
+    version: 8,
+    description: "indexes of rewardsJobs, expiry of the finished jobs",
+    target: Target::Users,
+    step: Step::CreateIndexes(REWARDS_JOBS_INDEXES),
+  },
+];
+
+impl Index {
+  fn spec(&self) -> Document {
+    let mut keys = Document::new();
+    for (field, order) in self.keys {
+      keys.insert(*field, *order);
+    }
+    let mut spec = doc! {"key": keys, "name": self.name};
+    if self.unique {
+      spec.insert("unique", true);
+    }
+    if self.sparse {
+      spec.insert("sparse", true);
+    }
+    if let Some(secs) = self.expire_after_secs {
+      spec.insert("expireAfterSeconds", secs);
+    }
+    spec
+  }
+
+  // whether an index listed by `listIndexes` is this one, whatever its name
+  fn matches(&self, existing: &Document) -> bool {
+    let keys: Vec<(String, i64)> = existing.get_document("key")
+      .map(|keys| keys.iter().map(|(field, order)| (field.clone(), number(order))).collect())
+      .unwrap_or_default();
+    let expected: Vec<(String, i64)> = self.keys.iter().map(|(field, order)| (field.to_string(), *order as i64)).collect();
+    keys == expected
+      && existing.get_bool("unique").unwrap_or(false) == self.unique
+      && existing.get_bool("sparse").unwrap_or(false) == self.sparse
+      && existing.get("expireAfterSeconds").map(number) == self.expire_after_secs
+  }
+}
+
+fn number(value: &Bson) -> i64 {
+  match value {
+    Bson::Int32(n) => *n as i64,
+    Bson::Int64(n) => *n,
+    Bson::Double(n) => *n as i64,
+    _ => 0,
+  }
+}
+
+fn pending_of(applied: &[u32], target: Target) -> Vec<&'static Migration> {
+  ALL.iter().filter(|m| m.target == target && !applied.contains(&m.version)).collect()
+}
+
+impl Database {
+  async fn applied_migrations(&self) -> Result<Vec<u32>, DatabaseError> {
+    let client = self.client.as_ref().ok_or(DatabaseError::Disconnected)?;
+    let mut cursor = client.database(&self.db_name).collection::<Document>(MIGRATIONS)
+      .find(None, None).await.in_collection(MIGRATIONS)?;
+    let mut versions = vec![];
+    while let Some(result) = cursor.next().await {
+      if let Some(version) = result.in_collection(MIGRATIONS)?.get("version") {
+        versions.push(number(version) as u32);
+      }
+    }
+    Ok(versions)
+  }
+
+  async fn record_migration(&self, migration: &Migration) -> Result<(), DatabaseError> {
+    let client = self.client.as_ref().ok_or(DatabaseError::Disconnected)?;
+    client.database(&self.db_name).collection::<Document>(MIGRATIONS).update_one(
+      doc! {"version": migration.version},
+      doc! {"$set": {"description": migration.description, "appliedAt": Utc::now().timestamp()}},
+      UpdateOptions::builder().upsert(true).build(),
+    ).await.in_collection(MIGRATIONS)?;
+    Ok(())
+  }
+
+  // an index which exists with the same name and keys is left as is
+  async fn create_indexes(&self, indexes: &[Index]) -> Result<(), DatabaseError> {
+    let client = self.client.as_ref().ok_or(DatabaseError::Disconnected)?;
+    let db = client.database(&self.db_name);
+    let mut collections: Vec<&str> = vec![];
+    for index in indexes {
+      if !collections.contains(&index.collection) {
+        collections.push(index.collection);
+      }
+    }
+    for collection in collections {
+      let specs: Vec<Document> = indexes.iter().filter(|index| index.collection == collection).map(Index::spec).collect();
+      let created = db.run_command(doc! {"createIndexes": collection, "indexes": specs}, None).await.in_collection(collection);
+      match created {
+        Err(e) if is_duplicate_key(&e) => return Err(DatabaseError::WriteFailed {
+          collection: collection.to_string(),
+          message: format!("documents share the value of a unique index, remove the duplicates and migrate again: {}", e),
+        }),
+        created => created?,
+      };
+    }
+    Ok(())
+  }
+
+  async fn list_indexes(&self, collection: &str) -> Result<Vec<Document>, DatabaseError> {
+    let client = self.client.as_ref().ok_or(DatabaseError::Disconnected)?;
+    let reply = client.database(&self.db_name).run_command(doc! {"listIndexes": collection}, None).await;
+    let reply = match reply {
+      Err(e) if matches!(e.kind.as_ref(), ErrorKind::Command(e) if e.code == NAMESPACE_NOT_FOUND) => return Ok(vec![]),
+      reply => reply.in_collection(collection)?,
+    };
+    let batch = reply.get_document("cursor").and_then(|cursor| cursor.get_array("firstBatch")).in_collection(collection)?;
+    Ok(batch.iter().filter_map(|index| index.as_document().cloned()).collect())
+  }
+}
+
+impl Migration {
+  async fn apply(&self, db: &Database) -> Result<(), DatabaseError> {
+    match self.step {
+      Step::CreateIndexes(indexes) => db.create_indexes(indexes).await,
+      Step::BackfillNewsletterStatus => {
+        let updated = db.backfill_newsletter_status().await?;
+        info!("{} newsletter subscribers without a status are pending", updated);
+        Ok(())
+      },
+    }
+  }
+}
+
+// the migrations of `target` not applied to `db` yet, in order
+pub async fn pending(db: &Database, target: Target) -> Result<Vec<&'static Migration>, DatabaseError> {
+  Ok(pending_of(&db.applied_migrations().await?, target))
+}
+
+// applies the pending migrations in order and records those which succeeded, a failed one is tried again next time
+pub async fn migrate(db: &Database, target: Target)
+  -> Result<Vec<(&'static Migration, Result<(), DatabaseError>)>, DatabaseError> {
+  let mut results = vec![];
+  for migration in pending(db, target).await? {
+    let result = match migration.apply(db).await {
+      Ok(()) => db.record_migration(migration).await,
+      Err(e) => Err(e),
+    };
+    results.push((migration, result));
+  }
+  Ok(results)
+}
+
+// the indexes of the migrations of `target` which are not in `db`, e.g. after one was dropped by hand
+pub async fn missing_indexes(db: &Database, target: Target) -> Result<Vec<&'static Index>, DatabaseError> {
+  let mut missing = vec![];
+  for migration in ALL.iter().filter(|m| m.target == target) {
+    if let Step::CreateIndexes(indexes) = migration.step {
+      for index in indexes {
+        let existing = db.list_indexes(index.collection).await?;
+        if !existing.iter().any(|existing| index.matches(existing)) {
+          missing.push(index);
+        }
+      }
+    }
+  }
+  Ok(missing)
+}
+
+// At startup: applies the pending migrations when `apply` is set, then checks the indexes. Failures are logged, the
+// server runs without the indexes.
+pub async fn migrate_on_start(name: String, db: Database, target: Target, apply: bool) {
+  if apply {
+    match migrate(&db, target).await {
+      Ok(results) => {
+        for (migration, result) in results {
+          match result {
+            Ok(()) => info!("migration {} applied to the {} database: {}", migration.version, name, migration.description),
+            Err(e) => error!("failed to apply migration {} to the {} database: {}", migration.version, name, e),
+          }
+        }
+      },
+      Err(e) => error!("failed to migrate the {} database: {}", name, e),
+    }
+  }
+  match missing_indexes(&db, target).await {
+    Ok(missing) => {
+      for index in missing {
+        warn!("index {} of {} is missing in the {} database, run migrate", index.name, index.collection, name);
+      }
+    },
+    Err(e) => error!("failed to check the indexes of the {} database: {}", name, e),
+  }
+}
+
+#[test]
+fn test_migrations() {
+  let versions: Vec<u32> = ALL.iter().map(|m| m.version).collect();
+  assert_eq!((1..=ALL.len() as u32).collect::<Vec<_>>(), versions);
+  let pending: Vec<u32> = pending_of(&[2], Target::Users).iter().map(|m| m.version).collect();
+  assert_eq!(vec![3, 4, 5, 6, 7, 8], pending);
+  // each migration creates the indexes of one collection
+  for migration in ALL.iter().filter(|m| m.target == Target::Users) {
+    if let Step::CreateIndexes(indexes) = migration.step {
+      assert!(indexes.iter().all(|index| index.collection == indexes[0].collection), "migration {}", migration.version);
+    }
+  }
+  assert!(REF_KEY_RECORDS_INDEXES.iter().any(|index| index.keys == [("stash", 1)] && index.unique));
+  assert!(NEWSLETTER_INDEXES.iter().any(|index| index.keys == [("email", 1)] && index.unique));
+}
+
+#[test]
+fn test_index_matches() {
+  let ttl = &REWARDS_JOBS_INDEXES[0];
+  assert!(ttl.matches(&doc! {"v": 2, "key": {"expireAt": 1}, "name": "expireAt_1", "expireAfterSeconds": 0_i32}));
+  assert!(!ttl.matches(&doc! {"v": 2, "key": {"expireAt": 1}, "name": "expireAt_ttl"}));
+  let stash = &REF_KEY_RECORDS_INDEXES[0];
+  assert!(stash.matches(&doc! {"key": {"stash": 1.0}, "name": "stash", "unique": true}));
+  assert!(!stash.matches(&doc! {"key": {"stash": 1}, "name": "stash"}));
+  let era_validator = &CHAIN_INDEXES[0];
+  assert!(!era_validator.matches(&doc! {"key": {"validator": 1, "era": 1}, "name": "era_validator"}));
+  assert_eq!(doc! {"key": {"expireAt": 1}, "name": "expireAt_ttl", "expireAfterSeconds": 0_i64}, ttl.spec());
+  let live_key = &REWARDS_JOBS_LIVE_KEY[0];
+  assert_eq!(doc! {"key": {"liveKey": 1}, "name": "liveKey", "unique": true, "sparse": true}, live_key.spec());
+  assert!(!live_key.matches(&doc! {"key": {"liveKey": 1}, "name": "liveKey", "unique": true}));
+}
@@ -1,39 +1,121 @@
 use super::config::Config;
+use mongodb::error::{ErrorKind, WriteFailure};
 use mongodb::options::{Tls, TlsOptions};
-use mongodb::{options::ClientOptions, Client};
+use mongodb::{bson, options::ClientOptions, Client};
 use std::fmt;
 use std::path::PathBuf;
+use std::sync::Arc;
 use std::{collections::HashMap};
+use monitor::CommandMonitor;
 pub(crate) mod params;
 mod nominator;
 mod validator;
 mod chain_info;
 mod staking_rewards;
 mod user_actions;
+mod risk;
+mod webhooks;
+mod monitor;
+pub mod migrations;
+pub mod rewards_jobs;
+pub mod repo;
+#[cfg(test)]
+pub mod memory;
 
 #[derive(Debug)]
 pub enum DatabaseError {
-    Mongo(mongodb::error::Error),
-    GetFailed,
+    // the client could not be created from the configured address
+    Connection(mongodb::error::Error),
+    // no client, `connect` was not called or failed
     Disconnected,
+    // the command sent to `collection` failed
+    Mongo { collection: String, source: mongodb::error::Error },
+    // a document of `collection` does not have the expected shape
+    Decode { collection: String, message: String },
+    // nothing in `collection` matches `key`, e.g. `stash 1ZGz...` or `era 2500`
+    NotFound { collection: String, key: String },
+    WriteFailed { collection: String, message: String },
+    Duplicated { collection: String, key: String },
     CacheMissed,
-    WriteFailed,
-    Duplicated
+}
+
+impl DatabaseError {
+    pub fn not_found(collection: &str, key: impl fmt::Display) -> Self {
+        DatabaseError::NotFound { collection: collection.to_string(), key: key.to_string() }
+    }
+
+    pub fn decode(collection: &str, message: impl fmt::Display) -> Self {
+        DatabaseError::Decode { collection: collection.to_string(), message: message.to_string() }
+    }
+
+    pub fn missing_field(collection: &str, field: &str) -> Self {
+        DatabaseError::decode(collection, format!("field {} is missing", field))
+    }
 }
 
 impl fmt::Display for DatabaseError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Database error: {:?}", self)
+        match self {
+            DatabaseError::Connection(e) => write!(f, "Database error: cannot connect: {}", e),
+            DatabaseError::Disconnected => write!(f, "Database error: not connected"),
+            DatabaseError::Mongo { collection, source } => write!(f, "Database error: {}: {}", collection, source),
+            DatabaseError::Decode { collection, message } => write!(f, "Database error: invalid document in {}: {}", collection, message),
+            DatabaseError::NotFound { collection, key } => write!(f, "Database error: {} is not found in {}", key, collection),
+            DatabaseError::WriteFailed { collection, message } => write!(f, "Database error: cannot write to {}: {}", collection, message),
+            DatabaseError::Duplicated { collection, key } => write!(f, "Database error: {} already exists in {}", key, collection),
+            DatabaseError::CacheMissed => write!(f, "Database error: price cache missed"),
+        }
     }
 }
 
 impl std::error::Error for DatabaseError {
-    
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            DatabaseError::Connection(e) | DatabaseError::Mongo { source: e, .. } => Some(e),
+            _ => None,
+        }
+    }
+}
+
+// E11000, a unique index rejected the write, see `migrations`
+pub(crate) fn is_duplicate_key(error: &DatabaseError) -> bool {
+    const DUPLICATE_KEY: i32 = 11000;
+    match error {
+        DatabaseError::Mongo { source, .. } => match source.kind.as_ref() {
+            ErrorKind::Command(e) => e.code == DUPLICATE_KEY,
+            ErrorKind::Write(WriteFailure::WriteError(e)) => e.code == DUPLICATE_KEY,
+            _ => false,
+        },
+        _ => false,
+    }
 }
 
-impl From<mongodb::error::Error> for DatabaseError {
-    fn from(err: mongodb::error::Error) -> DatabaseError {
-        DatabaseError::Mongo(err)
+// Attaches the collection to errors of the driver and of BSON (de)serialization.
+pub(crate) trait InCollection<T> {
+    fn in_collection(self, collection: &str) -> Result<T, DatabaseError>;
+}
+
+impl<T> InCollection<T> for Result<T, mongodb::error::Error> {
+    fn in_collection(self, collection: &str) -> Result<T, DatabaseError> {
+        self.map_err(|source| DatabaseError::Mongo { collection: collection.to_string(), source })
+    }
+}
+
+impl<T> InCollection<T> for Result<T, bson::de::Error> {
+    fn in_collection(self, collection: &str) -> Result<T, DatabaseError> {
+        self.map_err(|e| DatabaseError::decode(collection, e))
+    }
+}
+
+impl<T> InCollection<T> for Result<T, bson::document::ValueAccessError> {
+    fn in_collection(self, collection: &str) -> Result<T, DatabaseError> {
+        self.map_err(|e| DatabaseError::decode(collection, format!("{:?}", e)))
+    }
+}
+
+impl<T> InCollection<T> for Result<T, bson::ser::Error> {
+    fn in_collection(self, collection: &str) -> Result<T, DatabaseError> {
+        self.map_err(|e| DatabaseError::WriteFailed { collection: collection.to_string(), message: e.to_string() })
     }
 }
 
@@ -57,6 +139,13 @@ impl Database {
         }
     }
 
+    // a round trip to the server, the client only connects when it sends a command
+    pub async fn ping(&self) -> Result<(), DatabaseError> {
+        let client = self.client.as_ref().ok_or(DatabaseError::Disconnected)?;
+        client.database(&self.db_name).run_command(bson::doc! {"ping": 1}, None).await.in_collection("ping")?;
+        Ok(())
+    }
+
     pub async fn connect(&mut self) -> Result<(), DatabaseError> {
         let need_credential = Config::current().db_has_credential;
         let mut url = "mongodb://".to_string();
@@ -70,12 +159,13 @@ impl Database {
         } else {
             url += format!("{}:{}/{}", self.ip, self.port, self.db_name).as_str();
         }
-        let client_options = ClientOptions::parse(url.as_str()).await.map_err(DatabaseError::Mongo);
+        let client_options = ClientOptions::parse(url.as_str()).await.map_err(DatabaseError::Connection);
         match client_options {
             Ok(mut client_options) => {
                 // Manually set an option.
                 client_options.app_name = Some("cryptolab".to_string());
                 client_options.retry_writes = Some(false);
+                client_options.command_event_handler = Some(Arc::new(CommandMonitor::default()));
                 let mut ca_file_path: Option<PathBuf> = None;
                 if Config::current().db_ca_file.clone().is_some() {
                     ca_file_path = Some(PathBuf::from(Config::current().db_ca_file.clone().unwrap()));
@@ -0,0 +1,74 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use mongodb::bson::Document;
+use mongodb::event::command::{CommandEventHandler, CommandFailedEvent, CommandStartedEvent, CommandSucceededEvent};
+use tracing::Span;
+
+use crate::metrics;
+
+struct Started {
+  collection: String,
+  command: String,
+  span: Span,
+}
+
+// Times the commands of a client for the metrics, and opens a span for each of them in the span of the caller, e.g.
+// the request which runs an aggregation. Only the collection and the shape of a command are logged, not its documents.
+#[derive(Default)]
+pub struct CommandMonitor {
+  started: Mutex<HashMap<i32, Started>>,
+}
+
+// the collection is the value of the command name, e.g. `{"aggregate": "validator"}`
+fn collection(command_name: &str, command: &Document) -> String {
+  command.get_str(command_name)
+    .or_else(|_| command.get_str("collection"))
+    .unwrap_or("none")
+    .to_string()
+}
+
+impl CommandMonitor {
+  fn finish(&self, request_id: i32, duration: Duration, error: Option<String>) {
+    if let Some(started) = self.started.lock().unwrap().remove(&request_id) {
+      metrics::record_mongo_command(&started.collection, &started.command, duration);
+      started.span.record("duration_ms", duration.as_millis() as u64);
+      if let Some(error) = error {
+        started.span.record("error", error.as_str());
+      }
+    }
+  }
+}
+
+impl CommandEventHandler for CommandMonitor {
+  fn handle_command_started_event(&self, event: CommandStartedEvent) {
+    let collection = collection(&event.command_name, &event.command);
+    let span = tracing::debug_span!("mongo", collection = collection.as_str(), command = event.command_name.as_str(),
+      stages = tracing::field::Empty, duration_ms = tracing::field::Empty, error = tracing::field::Empty);
+    if let Ok(pipeline) = event.command.get_array("pipeline") {
+      span.record("stages", pipeline.len() as u64);
+    }
+    self.started.lock().unwrap().insert(event.request_id, Started {
+      collection,
+      command: event.command_name,
+      span,
+    });
+  }
+
+  fn handle_command_succeeded_event(&self, event: CommandSucceededEvent) {
+    self.finish(event.request_id, event.duration, None);
+  }
+
+  fn handle_command_failed_event(&self, event: CommandFailedEvent) {
+    self.finish(event.request_id, event.duration, Some(event.failure.to_string()));
+  }
+}
+
+#[test]
+fn test_collection() {
+  use mongodb::bson::doc;
+  assert_eq!("validator", collection("aggregate", &doc! {"aggregate": "validator", "pipeline": []}));
+  assert_eq!("nomination", collection("getMore", &doc! {"getMore": 42_i64, "collection": "nomination"}));
+  assert_eq!("none", collection("ping", &doc! {"ping": 1}));
+}
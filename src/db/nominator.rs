@@ -3,11 +3,11 @@ use mongodb::bson::{self, Document, doc};
 
 use crate::{db::params::Inactive, types::NominatorNomination};
 
-use super::{Database, DatabaseError};
+use super::{Database, DatabaseError, InCollection};
 
 impl Database {
     pub async fn get_all_validators_inactive(
-    &mut self,
+    &self,
     stash: &str,
     from: u32,
     to: u32,
@@ -28,17 +28,17 @@ impl Database {
             
         };
         let mut eras = Vec::<u32>::new();
-        let client = self.client.as_ref().ok_or(DatabaseError::Mongo);
+        let client = self.client.as_ref().ok_or(DatabaseError::Disconnected);
         if let Ok(client) = client {
             let db = client.database(&self.db_name);
                 let mut cursor = db
                     .collection::<Document>("inactiveEvents")
                     .aggregate(vec![match_command], None)
                     .await
-                    .unwrap();
+                    .in_collection("inactiveEvents")?;
                 while let Some(result) = cursor.next().await {
-                    let doc = result.unwrap();
-                    let n: Inactive = bson::from_document(doc).unwrap();
+                    let doc = result.in_collection("inactiveEvents")?;
+                    let n: Inactive = bson::from_document(doc).in_collection("inactiveEvents")?;
                     eras.push(n.era);
                 }
                 Ok(eras)
@@ -74,25 +74,25 @@ impl Database {
                 "address": stash
             },
         };
-        let client = self.client.as_ref().ok_or(DatabaseError::Mongo);
+        let client = self.client.as_ref().ok_or(DatabaseError::Disconnected);
         if let Ok(client) = client {
             let db = client.database(&self.db_name);
             let mut cursor = db
                 .collection::<Document>("nominator")
                 .aggregate(vec![match_command], None)
                 .await
-                .unwrap();
+                .in_collection("nominator")?;
             if let Some(result) = cursor.next().await {
-                let doc = result.unwrap();
+                let doc = result.in_collection("nominator")?;
                 let n = doc! {
-                    "accountId": doc.get("address").unwrap().as_str().unwrap().to_string(),
-                    "balance": doc.get("balance").unwrap(),
-                    "targets": doc.get_array("targets").unwrap()
+                    "accountId": doc.get_str("address").in_collection("nominator")?,
+                    "balance": doc.get("balance").ok_or_else(|| DatabaseError::missing_field("nominator", "balance"))?,
+                    "targets": doc.get_array("targets").in_collection("nominator")?
                 };
-                let n = bson::from_document(n).unwrap();
+                let n = bson::from_document(n).in_collection("nominator")?;
                 Ok(n)
             } else {
-                Err(DatabaseError::GetFailed)
+                Err(DatabaseError::not_found("nominator", format!("stash {}", stash)))
             }
         } else {
             Err(DatabaseError::Disconnected)
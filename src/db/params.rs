@@ -1,9 +1,5 @@
-use serde::Deserialize;
-
-#[derive(Deserialize)]
-pub struct ValidDetailOptions {
-    pub option: String,
-}
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
 
 #[derive(Deserialize)]
 pub struct AllValidatorOptions {
@@ -14,12 +10,80 @@ pub struct AllValidatorOptions {
     pub commission_min: f32,
     pub commission_max: f32,
     pub has_verified_identity: bool,
+    // amounts in the smallest unit of the chain
+    pub total_min: Option<u128>,
+    pub total_max: Option<u128>,
+    pub self_stake_min: Option<u128>,
+    pub self_stake_max: Option<u128>,
+    // only validators with fewer nominators than this
+    pub nominator_limit: Option<u32>,
+    // only validators without slashes in this many eras up to the queried one
+    pub slash_free_eras: Option<u32>,
+    pub exclude_blocked: bool,
+    pub has_parent_identity: bool,
+    // only validators whose average era points reach this percentile of the era, between 0 and 1
+    pub era_points_percentile: Option<f32>,
+    // only these stashes
+    pub stashes: Option<Vec<String>>,
+    // validators are ordered by the stash when there is no sort key
+    pub sort: Option<ValidatorSort>,
+    pub descending: bool,
+    // continues after the last validator of the previous page instead of skipping `page` pages
+    pub after: Option<ValidatorCursor>,
 }
 
-#[derive(Deserialize)]
-pub struct EventFilterOptions {
-    pub from_era: u32,
-    pub to_era: u32,
+impl AllValidatorOptions {
+    // every validator of the era, as the legacy routes list them
+    pub fn new() -> Self {
+        AllValidatorOptions {
+            size: 5000,
+            page: 0,
+            apy_min: 0.0,
+            apy_max: 1.0,
+            commission_min: 0.0,
+            commission_max: 1.0,
+            has_verified_identity: false,
+            total_min: None,
+            total_max: None,
+            self_stake_min: None,
+            self_stake_max: None,
+            nominator_limit: None,
+            slash_free_eras: None,
+            exclude_blocked: false,
+            has_parent_identity: false,
+            era_points_percentile: None,
+            stashes: None,
+            sort: None,
+            descending: false,
+            after: None,
+        }
+    }
+
+    // validators before the page; the API rejects the pages where it overflows
+    pub fn skip(&self) -> u32 {
+        self.page.saturating_mul(self.size)
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum ValidatorSort {
+    Apy,
+    Commission,
+    Total,
+    NominatorCount,
+    SelfStake,
+}
+
+// Position of the last validator of a page. `key` is the sort key of that validator as the query computes it,
+// amounts are compared as 32 digit hex strings.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ValidatorCursor {
+    pub era: u32,
+    pub sort: Option<ValidatorSort>,
+    pub descending: bool,
+    pub key: serde_json::Value,
+    pub id: String,
 }
 
 
@@ -28,11 +92,9 @@ pub struct DbRefKeyOptions {
     pub stash: String,
     pub ref_key: String,
     pub timestamp: u32,
-    pub rand: String,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone)]
 pub struct Inactive {
-    pub address: String,
     pub era: u32,
 }
\ No newline at end of file
@@ -0,0 +1,323 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+
+use crate::risk::ValidatorRisk;
+use crate::types::{CBStashEraReward, ChainInfo, ChillEvent, KickEvent, NewsletterStatus, NewsletterSubscriber,
+  NewsletterSubscriberOptions, NominationOptions, NominationResultOptions, NominatorNomination, OverSubscribeEvent,
+  OverSubscribeEventOutput, StakingEvents, UserEventMappingOptions, ValidatorCommission, ValidatorNominationInfo,
+  ValidatorNominationTrend, ValidatorSlash, ValidatorStalePayoutEvent};
+use crate::webhooks::WebhookSubscription;
+
+use super::params::{AllValidatorOptions, DbRefKeyOptions};
+use super::{Database, DatabaseError};
+
+// The queries behind the API, split by the collections they read. `Database` implements them
+// against MongoDB, `memory::MemoryRepo` against JSON fixtures.
+#[async_trait]
+pub trait ValidatorRepo: Send + Sync {
+  async fn get_chain_info(&self) -> Result<ChainInfo, DatabaseError>;
+
+  async fn get_validator(&self, stash: String) -> Result<ValidatorNominationTrend, DatabaseError>;
+
+  async fn get_validator_unclaimed_eras(&self, stash: String) -> Result<Vec<i32>, DatabaseError>;
+
+  async fn get_validator_slashes(&self, stash: String) -> Result<Vec<ValidatorSlash>, DatabaseError>;
+
+  async fn get_all_validator_info_of_era(&self, era: u32, options: AllValidatorOptions)
+    -> Result<Vec<ValidatorNominationInfo>, DatabaseError>;
+
+  async fn count_validators_of_era(&self, era: u32, options: &AllValidatorOptions) -> Result<u64, DatabaseError>;
+
+  async fn get_validator_info(&self, stashes: &[String], era: &u32) -> Result<Vec<ValidatorNominationInfo>, DatabaseError>;
+
+  async fn get_validator_risk(&self, stash: &str) -> Result<ValidatorRisk, DatabaseError>;
+}
+
+#[async_trait]
+pub trait NominatorRepo: Send + Sync {
+  async fn get_nominator_info(&self, stash: &str) -> Result<NominatorNomination, DatabaseError>;
+}
+
+#[async_trait]
+pub trait EventRepo: Send + Sync {
+  async fn get_user_events_by_mapping(&self, options: UserEventMappingOptions) -> Result<StakingEvents, DatabaseError>;
+
+  async fn get_is_commission_changed(&self, validators: &[String], from: u32, to: u32)
+    -> Result<Vec<ValidatorCommission>, DatabaseError>;
+
+  async fn get_multiple_validators_slashes(&self, validators: &[String], from: u32, to: u32)
+    -> Result<Vec<ValidatorSlash>, DatabaseError>;
+
+  async fn get_all_validators_inactive(&self, stash: &str, from: u32, to: u32) -> Result<Vec<u32>, DatabaseError>;
+
+  async fn get_nominated_validators_stale_payout_events(&self, validators: &[String], from: u32, to: u32)
+    -> Result<Vec<ValidatorStalePayoutEvent>, DatabaseError>;
+
+  async fn get_nominated_validators_payout_events(&self, stash: String, from: u32, to: u32)
+    -> Result<Vec<CBStashEraReward>, DatabaseError>;
+
+  async fn get_kick_events(&self, nominator: &str, from: &u32, to: &u32) -> Result<Vec<KickEvent>, DatabaseError>;
+
+  async fn get_chill_events(&self, validators: &[String], from: &u32, to: &u32) -> Result<Vec<ChillEvent>, DatabaseError>;
+
+  async fn get_oversubscribe_events(&self, nominator: &str, from: &u32, to: &u32)
+    -> Result<Vec<OverSubscribeEvent>, DatabaseError>;
+}
+
+#[async_trait]
+pub trait UserActionRepo: Send + Sync {
+  async fn insert_nomination_action(&self, chain: String, options: NominationOptions) -> Result<String, DatabaseError>;
+
+  async fn insert_nomination_result(&self, options: NominationResultOptions) -> Result<(), DatabaseError>;
+
+  async fn has_nomination_records(&self, stash: &str) -> Result<bool, DatabaseError>;
+
+  async fn get_validator_ref_key(&self, stash: &str) -> Result<String, DatabaseError>;
+
+  async fn decode_validator_ref_key(&self, ref_key: &str) -> Result<DbRefKeyOptions, DatabaseError>;
+
+  async fn insert_validator_ref_key(&self, options: DbRefKeyOptions) -> Result<(), DatabaseError>;
+
+  // returns the status the address had before, None when it is new
+  async fn insert_newsletter_subsriber(&self, options: NewsletterSubscriberOptions)
+    -> Result<Option<NewsletterStatus>, DatabaseError>;
+
+  async fn get_newsletter_subscriber(&self, email: &str) -> Result<NewsletterSubscriber, DatabaseError>;
+
+  async fn get_newsletter_subscribers(&self, status: NewsletterStatus) -> Result<Vec<NewsletterSubscriber>, DatabaseError>;
+
+  async fn count_newsletter_subscribers(&self, status: NewsletterStatus) -> Result<u64, DatabaseError>;
+
+  async fn set_newsletter_status(&self, email: &str, status: NewsletterStatus) -> Result<(), DatabaseError>;
+
+  // moves the last digest era of `chain` from `from` to `to`, None being no era; false when the era was not `from`,
+  // e.g. because another instance moved it first
+  async fn swap_newsletter_digest_era(&self, email: &str, chain: &str, from: Option<u32>, to: Option<u32>)
+    -> Result<bool, DatabaseError>;
+
+  async fn delete_newsletter_subscriber(&self, email: &str) -> Result<(), DatabaseError>;
+}
+
+#[async_trait]
+pub trait WebhookRepo: Send + Sync {
+  async fn insert_webhook_subscription(&self, subscription: &WebhookSubscription) -> Result<(), DatabaseError>;
+
+  async fn get_webhook_subscriptions(&self, chain: &str) -> Result<Vec<WebhookSubscription>, DatabaseError>;
+
+  // moves the last era of the subscription from `from` to `to`; false when it was not `from`, e.g. because another
+  // instance moved it first
+  async fn swap_webhook_last_era(&self, id: &str, from: u32, to: u32) -> Result<bool, DatabaseError>;
+
+  // only the holder of the secret of the subscription can delete it
+  async fn delete_webhook_subscription(&self, id: &str, secret: &str) -> Result<(), DatabaseError>;
+}
+
+pub trait Repository: ValidatorRepo + NominatorRepo + EventRepo + UserActionRepo + WebhookRepo {}
+
+impl<T: ValidatorRepo + NominatorRepo + EventRepo + UserActionRepo + WebhookRepo> Repository for T {}
+
+pub type Repo = Arc<dyn Repository>;
+
+#[async_trait]
+impl ValidatorRepo for Database {
+  async fn get_chain_info(&self) -> Result<ChainInfo, DatabaseError> {
+    Database::get_chain_info(self).await
+  }
+
+  async fn get_validator(&self, stash: String) -> Result<ValidatorNominationTrend, DatabaseError> {
+    Database::get_validator(self, stash).await
+  }
+
+  async fn get_validator_unclaimed_eras(&self, stash: String) -> Result<Vec<i32>, DatabaseError> {
+    Database::get_validator_unclaimed_eras(self, stash).await
+  }
+
+  async fn get_validator_slashes(&self, stash: String) -> Result<Vec<ValidatorSlash>, DatabaseError> {
+    Database::get_validator_slashes(self, stash).await
+  }
+
+  async fn get_all_validator_info_of_era(&self, era: u32, options: AllValidatorOptions)
+    -> Result<Vec<ValidatorNominationInfo>, DatabaseError> {
+    Database::get_all_validator_info_of_era(self, era, options).await
+  }
+
+  async fn count_validators_of_era(&self, era: u32, options: &AllValidatorOptions) -> Result<u64, DatabaseError> {
+    Database::count_validators_of_era(self, era, options).await
+  }
+
+  async fn get_validator_info(&self, stashes: &[String], era: &u32) -> Result<Vec<ValidatorNominationInfo>, DatabaseError> {
+    Database::get_validator_info(self, stashes, era).await
+  }
+
+  async fn get_validator_risk(&self, stash: &str) -> Result<ValidatorRisk, DatabaseError> {
+    Database::get_validator_risk(self, stash).await
+  }
+}
+
+// the price cache of `Database` is per clone, so each call works on its own copy
+#[async_trait]
+impl NominatorRepo for Database {
+  async fn get_nominator_info(&self, stash: &str) -> Result<NominatorNomination, DatabaseError> {
+    Database::get_nominator_info(&mut self.clone(), stash).await
+  }
+}
+
+#[async_trait]
+impl EventRepo for Database {
+  async fn get_user_events_by_mapping(&self, options: UserEventMappingOptions) -> Result<StakingEvents, DatabaseError> {
+    Database::get_user_events_by_mapping(self, options).await
+  }
+
+  async fn get_is_commission_changed(&self, validators: &[String], from: u32, to: u32)
+    -> Result<Vec<ValidatorCommission>, DatabaseError> {
+    Database::get_is_commission_changed(self, validators, from, to).await
+  }
+
+  async fn get_multiple_validators_slashes(&self, validators: &[String], from: u32, to: u32)
+    -> Result<Vec<ValidatorSlash>, DatabaseError> {
+    Database::get_multiple_validators_slashes(self, validators, from, to).await
+  }
+
+  async fn get_all_validators_inactive(&self, stash: &str, from: u32, to: u32) -> Result<Vec<u32>, DatabaseError> {
+    Database::get_all_validators_inactive(self, stash, from, to).await
+  }
+
+  async fn get_nominated_validators_stale_payout_events(&self, validators: &[String], from: u32, to: u32)
+    -> Result<Vec<ValidatorStalePayoutEvent>, DatabaseError> {
+    Database::get_nominated_validators_stale_payout_events(self, validators, from, to).await
+  }
+
+  async fn get_nominated_validators_payout_events(&self, stash: String, from: u32, to: u32)
+    -> Result<Vec<CBStashEraReward>, DatabaseError> {
+    Database::get_nominated_validators_payout_events(self, stash, from, to).await
+  }
+
+  async fn get_kick_events(&self, nominator: &str, from: &u32, to: &u32) -> Result<Vec<KickEvent>, DatabaseError> {
+    Database::get_kick_events(self, nominator, from, to).await
+  }
+
+  async fn get_chill_events(&self, validators: &[String], from: &u32, to: &u32) -> Result<Vec<ChillEvent>, DatabaseError> {
+    Database::get_chill_events(self, validators, from, to).await
+  }
+
+  async fn get_oversubscribe_events(&self, nominator: &str, from: &u32, to: &u32)
+    -> Result<Vec<OverSubscribeEvent>, DatabaseError> {
+    Database::get_oversubscribe_events(self, nominator, from, to).await
+  }
+}
+
+#[async_trait]
+impl UserActionRepo for Database {
+  async fn insert_nomination_action(&self, chain: String, options: NominationOptions) -> Result<String, DatabaseError> {
+    Database::insert_nomination_action(self, chain, options).await
+  }
+
+  async fn insert_nomination_result(&self, options: NominationResultOptions) -> Result<(), DatabaseError> {
+    Database::insert_nomination_result(self, options).await
+  }
+
+  async fn has_nomination_records(&self, stash: &str) -> Result<bool, DatabaseError> {
+    Database::has_nomination_records(self, stash).await
+  }
+
+  async fn get_validator_ref_key(&self, stash: &str) -> Result<String, DatabaseError> {
+    Database::get_validator_ref_key(self, stash).await
+  }
+
+  async fn decode_validator_ref_key(&self, ref_key: &str) -> Result<DbRefKeyOptions, DatabaseError> {
+    Database::decode_validator_ref_key(self, ref_key).await
+  }
+
+  async fn insert_validator_ref_key(&self, options: DbRefKeyOptions) -> Result<(), DatabaseError> {
+    Database::insert_validator_ref_key(self, options).await
+  }
+
+  async fn insert_newsletter_subsriber(&self, options: NewsletterSubscriberOptions)
+    -> Result<Option<NewsletterStatus>, DatabaseError> {
+    Database::insert_newsletter_subsriber(self, options).await
+  }
+
+  async fn get_newsletter_subscriber(&self, email: &str) -> Result<NewsletterSubscriber, DatabaseError> {
+    Database::get_newsletter_subscriber(self, email).await
+  }
+
+  async fn get_newsletter_subscribers(&self, status: NewsletterStatus) -> Result<Vec<NewsletterSubscriber>, DatabaseError> {
+    Database::get_newsletter_subscribers(self, status).await
+  }
+
+  async fn count_newsletter_subscribers(&self, status: NewsletterStatus) -> Result<u64, DatabaseError> {
+    Database::count_newsletter_subscribers(self, status).await
+  }
+
+  async fn set_newsletter_status(&self, email: &str, status: NewsletterStatus) -> Result<(), DatabaseError> {
+    Database::set_newsletter_status(self, email, status).await
+  }
+
+  async fn swap_newsletter_digest_era(&self, email: &str, chain: &str, from: Option<u32>, to: Option<u32>)
+    -> Result<bool, DatabaseError> {
+    Database::swap_newsletter_digest_era(self, email, chain, from, to).await
+  }
+
+  async fn delete_newsletter_subscriber(&self, email: &str) -> Result<(), DatabaseError> {
+    Database::delete_newsletter_subscriber(self, email).await
+  }
+}
+
+#[async_trait]
+impl WebhookRepo for Database {
+  async fn insert_webhook_subscription(&self, subscription: &WebhookSubscription) -> Result<(), DatabaseError> {
+    Database::insert_webhook_subscription(self, subscription).await
+  }
+
+  async fn get_webhook_subscriptions(&self, chain: &str) -> Result<Vec<WebhookSubscription>, DatabaseError> {
+    Database::get_webhook_subscriptions(self, chain).await
+  }
+
+  async fn swap_webhook_last_era(&self, id: &str, from: u32, to: u32) -> Result<bool, DatabaseError> {
+    Database::swap_webhook_last_era(self, id, from, to).await
+  }
+
+  async fn delete_webhook_subscription(&self, id: &str, secret: &str) -> Result<(), DatabaseError> {
+    Database::delete_webhook_subscription(self, id, secret).await
+  }
+}
+
+// The events of a nominator between two eras, from the event mapping when the stash nominated through the app and
+// from the event collections of its targets otherwise.
+pub async fn get_nominator_events(db: &dyn Repository, user_db: &dyn Repository, stash: &str, from_era: u32, to_era: u32)
+  -> Result<StakingEvents, DatabaseError> {
+  let nominator = db.get_nominator_info(stash).await?;
+  if let Ok(true) = user_db.has_nomination_records(stash).await {
+    return db.get_user_events_by_mapping(UserEventMappingOptions {
+      stash: stash.to_string(),
+      from_era,
+      to_era,
+      event_types: vec![0, 1, 2, 3, 4, 5, 6],
+    }).await;
+  }
+  let commission = db.get_is_commission_changed(&nominator.targets, from_era, to_era).await;
+  let slash = db.get_multiple_validators_slashes(&nominator.targets, from_era, to_era).await;
+  let inactive = db.get_all_validators_inactive(stash, from_era, to_era).await;
+  let stale_payouts = db.get_nominated_validators_stale_payout_events(&nominator.targets, from_era, to_era).await;
+  let payouts = db.get_nominated_validators_payout_events(nominator.account_id, from_era, to_era).await;
+  let kicks = db.get_kick_events(stash, &from_era, &to_era).await;
+  let chills = db.get_chill_events(&nominator.targets, &from_era, &to_era).await;
+  let over_subscribes = db.get_oversubscribe_events(stash, &from_era, &to_era).await.unwrap_or_default()
+    .into_iter().map(|e| OverSubscribeEventOutput {
+      era: e.era,
+      nominator: stash.to_string(),
+      amount: e.nominators.iter().find(|n| n.who == stash).map(|n| n.value.clone()).unwrap_or_default(),
+      address: e.address,
+    }).collect();
+  Ok(StakingEvents {
+    commissions: commission.unwrap_or_default(),
+    slashes: slash.unwrap_or_default(),
+    inactive: inactive.unwrap_or_default(),
+    stale_payouts: stale_payouts.unwrap_or_default(),
+    payouts: payouts.unwrap_or_default(),
+    kicks: kicks.unwrap_or_default(),
+    chills: chills.unwrap_or_default(),
+    over_subscribes,
+  })
+}
@@ -0,0 +1,213 @@
+use chrono::{Duration, Utc};
+use futures::StreamExt;
+use mongodb::bson::{self, Bson, Document, doc};
+use mongodb::options::{FindOneAndUpdateOptions, FindOneOptions, FindOptions, ReturnDocument};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::staking_rewards_collector::StakingRewardsCollector;
+use crate::types::StashRewards;
+
+use super::{Database, DatabaseError, InCollection, is_duplicate_key};
+
+const REWARDS_JOBS: &str = "rewardsJobs";
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum RewardsJobStatus {
+    Pending,
+    Running,
+    Done,
+    Failed,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RewardsJobError {
+    pub code: i32,
+    pub message: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RewardsJob {
+    pub job_id: String,
+    pub stash: String,
+    pub status: RewardsJobStatus,
+    pub progress: i32,
+    pub request: StakingRewardsCollector,
+    pub result: Option<StashRewards>,
+    pub error: Option<RewardsJobError>,
+    pub created_at: i64,
+    pub updated_at: i64,
+}
+
+// identical (stash, start, end, currency, start balance, price data) requests share one job
+pub fn rewards_job_key(request: &StakingRewardsCollector) -> String {
+    let address = &request.addresses[0];
+    format!("{}:{}:{}:{}:{}:{}", address.address, request.start, request.end, request.currency, address.start_balance,
+        request.price_data)
+}
+
+fn expire_at(ttl_hours: u32) -> bson::DateTime {
+    bson::DateTime::from_millis((Utc::now() + Duration::hours(ttl_hours as i64)).timestamp_millis())
+}
+
+impl Database {
+  // Returns the live job of the same request if there is one, otherwise inserts a pending job. A live job holds its
+  // key in `liveKey`, which has a unique index, so concurrent requests cannot both insert one; the key is released
+  // when the job fails or expires.
+  pub async fn insert_rewards_job(&self, job_id: &str, request: &StakingRewardsCollector, ttl_hours: u32)
+    -> Result<(RewardsJob, bool), DatabaseError> {
+    let client = self.client.as_ref().ok_or(DatabaseError::Disconnected)?;
+    let db = client.database(&self.db_name);
+    let collection = db.collection::<Document>(REWARDS_JOBS);
+    let key = rewards_job_key(request);
+    let now = Utc::now().timestamp();
+    let job = RewardsJob {
+        job_id: job_id.to_string(),
+        stash: request.addresses[0].address.clone(),
+        status: RewardsJobStatus::Pending,
+        progress: 0,
+        request: request.clone(),
+        result: None,
+        error: None,
+        created_at: now,
+        updated_at: now,
+    };
+    let mut document = bson::to_document(&job).in_collection(REWARDS_JOBS)?;
+    document.insert("key", &key);
+    document.insert("expireAt", expire_at(ttl_hours));
+    let options = FindOneAndUpdateOptions::builder().upsert(true).return_document(ReturnDocument::After).build();
+    let mut result = Ok(None);
+    // a concurrent insert of the same key fails one of the upserts, which then finds the other job
+    for _ in 0..2 {
+        // the TTL monitor removes the expired jobs about once a minute
+        collection.update_one(
+            doc! {"liveKey": &key, "expireAt": {"$lte": bson::DateTime::now()}},
+            doc! {"$unset": {"liveKey": ""}},
+            None,
+        ).await.in_collection(REWARDS_JOBS)?;
+        // an inserted job gets `liveKey` from the filter
+        result = collection.find_one_and_update(
+            doc! {"liveKey": &key},
+            doc! {"$setOnInsert": &document},
+            options.clone(),
+        ).await.in_collection(REWARDS_JOBS);
+        match &result {
+            Err(e) if is_duplicate_key(e) => continue,
+            _ => break,
+        }
+    }
+    let live = result?.ok_or_else(|| DatabaseError::not_found(REWARDS_JOBS, format!("job of {}", key)))?;
+    let live: RewardsJob = bson::from_document(live).in_collection(REWARDS_JOBS)?;
+    let inserted = live.job_id == job_id;
+    Ok((live, inserted))
+  }
+
+  pub async fn get_rewards_job(&self, job_id: &str) -> Result<RewardsJob, DatabaseError> {
+    let client = self.client.as_ref().ok_or(DatabaseError::Disconnected)?;
+    let db = client.database(&self.db_name);
+    db.collection::<RewardsJob>(REWARDS_JOBS)
+        .find_one(doc! {"jobId": job_id}, None).await.in_collection(REWARDS_JOBS)?
+        .ok_or_else(|| DatabaseError::not_found(REWARDS_JOBS, format!("job {}", job_id)))
+  }
+
+  pub async fn get_latest_finished_rewards_job(&self, stash: &str) -> Result<RewardsJob, DatabaseError> {
+    let client = self.client.as_ref().ok_or(DatabaseError::Disconnected)?;
+    let db = client.database(&self.db_name);
+    let options = FindOneOptions::builder().sort(doc! {"updatedAt": -1}).build();
+    db.collection::<RewardsJob>(REWARDS_JOBS)
+        .find_one(doc! {"stash": stash, "status": "done"}, options).await.in_collection(REWARDS_JOBS)?
+        .ok_or_else(|| DatabaseError::not_found(REWARDS_JOBS, format!("report of stash {}", stash)))
+  }
+
+  // jobs which were queued or running when the server stopped
+  pub async fn get_unfinished_rewards_jobs(&self) -> Result<Vec<String>, DatabaseError> {
+    let client = self.client.as_ref().ok_or(DatabaseError::Disconnected)?;
+    let db = client.database(&self.db_name);
+    let options = FindOptions::builder().sort(doc! {"createdAt": 1}).build();
+    let mut cursor = db.collection::<RewardsJob>(REWARDS_JOBS)
+        .find(doc! {"status": {"$in": ["pending", "running"]}}, options).await.in_collection(REWARDS_JOBS)?;
+    let mut job_ids = vec![];
+    while let Some(job) = cursor.next().await {
+        job_ids.push(job.in_collection(REWARDS_JOBS)?.job_id);
+    }
+    Ok(job_ids)
+  }
+
+  pub async fn update_rewards_job(
+    &self,
+    job_id: &str,
+    status: RewardsJobStatus,
+    progress: i32,
+    result: Option<&StashRewards>,
+    error: Option<RewardsJobError>,
+    ttl_hours: u32,
+  ) -> Result<(), DatabaseError> {
+    let client = self.client.as_ref().ok_or(DatabaseError::Disconnected)?;
+    let db = client.database(&self.db_name);
+    let to_bson = |v: Result<Bson, bson::ser::Error>| v.in_collection(REWARDS_JOBS);
+    let failed = status == RewardsJobStatus::Failed;
+    let mut update = doc! {
+        "$set": {
+            "status": to_bson(bson::to_bson(&status))?,
+            "progress": progress,
+            "result": to_bson(bson::to_bson(&result))?,
+            "error": to_bson(bson::to_bson(&error))?,
+            "updatedAt": Utc::now().timestamp(),
+            "expireAt": expire_at(ttl_hours),
+        }
+    };
+    // the next request of a failed job runs again
+    if failed {
+        update.insert("$unset", doc! {"liveKey": ""});
+    }
+    db.collection::<Document>(REWARDS_JOBS).update_one(doc! {"jobId": job_id}, update, None)
+        .await.in_collection(REWARDS_JOBS)?;
+    Ok(())
+  }
+}
+
+#[test]
+fn test_rewards_job_bson_round_trip() {
+    use crate::staking_rewards_collector::StakingRewardsAddress;
+    use crate::types::StashEraReward;
+    let request = StakingRewardsCollector::new("2021-06-01".to_string(), "2021-06-28".to_string(), "USD".to_string(),
+      true, vec![StakingRewardsAddress::new("".to_string(),
+      "15Uv8ppUZVb8dM2uDf8rLnNPo4QdK9mHrJSUn6fqAhAtDZKu".to_string(), 0.0, "Polkadot".to_string())]).unwrap();
+    assert_eq!("15Uv8ppUZVb8dM2uDf8rLnNPo4QdK9mHrJSUn6fqAhAtDZKu:2021-06-01:2021-06-28:USD:0:true",
+      rewards_job_key(&request));
+    // the start balance and the price data change the report
+    let mut other = request.clone();
+    other.addresses[0].start_balance = 10.5;
+    other.price_data = false;
+    assert_eq!("15Uv8ppUZVb8dM2uDf8rLnNPo4QdK9mHrJSUn6fqAhAtDZKu:2021-06-01:2021-06-28:USD:10.5:false",
+      rewards_job_key(&other));
+    let job = RewardsJob {
+        job_id: "job".to_string(),
+        stash: request.addresses[0].address.clone(),
+        status: RewardsJobStatus::Done,
+        progress: 100,
+        request,
+        result: Some(StashRewards {
+            stash: "15Uv8ppUZVb8dM2uDf8rLnNPo4QdK9mHrJSUn6fqAhAtDZKu".to_string(),
+            era_rewards: vec![StashEraReward {
+                era: 422,
+                amount: 2.0,
+                timestamp: 1622592000000,
+                price: Some(20.0),
+                total: Some(40.0),
+            }],
+            total_in_fiat: 40.0,
+            annualized_return: None,
+        }),
+        error: None,
+        created_at: 0,
+        updated_at: 0,
+    };
+    let document = bson::to_document(&job).unwrap();
+    assert_eq!("done", document.get_str("status").unwrap());
+    let job: RewardsJob = bson::from_document(document).unwrap();
+    assert_eq!(1622592000000, job.result.unwrap().era_rewards[0].timestamp);
+}
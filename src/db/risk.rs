@@ -0,0 +1,170 @@
+use std::collections::HashMap;
+
+use futures::StreamExt;
+use mongodb::bson::{self, Bson, Document, doc};
+
+use crate::risk::{self, RiskEvents, ValidatorRisk};
+use super::{Database, DatabaseError, InCollection};
+
+const VALIDATOR_RISK: &str = "validatorRisk";
+// scores written by one command, far below the limits of the size and statements of a command
+const WRITE_BATCH: usize = 1000;
+
+fn risk_document(risk: &ValidatorRisk) -> Document {
+  let c = &risk.components;
+  let e = &risk.events;
+  doc! {
+    "validator": &risk.stash,
+    "era": risk.era,
+    "score": risk.score,
+    "components": {
+      "slashes": c.slashes,
+      "commissionIncreases": c.commission_increases,
+      "kicks": c.kicks,
+      "chills": c.chills,
+      "inactivity": c.inactivity,
+      "stalePayouts": c.stale_payouts,
+      "unclaimedEras": c.unclaimed_eras,
+      "oversubscription": c.oversubscription,
+    },
+    "events": {
+      "slashes": e.slashes,
+      "commissionIncreases": e.commission_increases,
+      "kicks": e.kicks,
+      "chills": e.chills,
+      "inactiveEras": e.inactive_eras,
+      "stalePayouts": e.stale_payouts,
+      "unclaimedEras": e.unclaimed_eras,
+      "oversubscribedEras": e.oversubscribed_eras,
+    },
+  }
+}
+
+impl Database {
+  // scores the validators elected in the era from the events of the `window_eras` eras up to it, the era included,
+  // see `risk::window_eras`
+  pub async fn compute_validator_risks(&self, era: u32, window_eras: u32) -> Result<Vec<ValidatorRisk>, DatabaseError> {
+    let client = self.client.as_ref().ok_or(DatabaseError::Disconnected)?;
+    let db = client.database(&self.db_name);
+    let validators: Vec<String> = db.collection::<Document>("nomination").distinct("validator", doc! {"era": era}, None)
+      .await.in_collection("nomination")?
+      .into_iter().filter_map(|v| v.as_str().map(|s| s.to_string())).collect();
+    let from = era.saturating_sub(window_eras.max(1) - 1);
+    let in_window = |field: &str| doc! {
+      field: {"$in": &validators},
+      "era": {"$gte": from, "$lte": era},
+    };
+    let slashes = self.count_by_validator("validatorSlash", "address", in_window("address")).await?;
+    let mut increases = in_window("address");
+    increases.insert("$expr", doc! {"$gt": ["$commissionTo", "$commissionFrom"]});
+    let commission_increases = self.count_by_validator("commission", "address", increases).await?;
+    let kicks = self.count_by_validator("kickEvents", "address", in_window("address")).await?;
+    let chills = self.count_by_validator("chillEvents", "address", in_window("address")).await?;
+    let stale_payouts = self.count_by_validator("stalePayouts", "address", in_window("address")).await?;
+    let oversubscribed = self.count_by_validator("overSubscribeEvents", "address", in_window("address")).await?;
+    let unclaimed = self.aggregate_by_validator("unclaimedEraInfo", vec![
+      doc! {"$match": {"validator": {"$in": &validators}}},
+      doc! {"$project": {"_id": "$validator", "count": {"$size": {"$ifNull": ["$eras", []]}}}},
+    ]).await?;
+    // eras in the window since the validator was first elected in it
+    let inactive = self.aggregate_by_validator("nomination", vec![
+      doc! {"$match": in_window("validator")},
+      doc! {"$group": {"_id": "$validator", "elected": {"$sum": 1}, "first": {"$min": "$era"}}},
+      doc! {"$project": {"count": {"$max": [0, {"$subtract": [{"$subtract": [era as i64 + 1, "$first"]}, "$elected"]}]}}},
+    ]).await?;
+    let count = |counts: &HashMap<String, u32>, stash: &str| counts.get(stash).cloned().unwrap_or(0);
+    Ok(validators.iter().map(|stash| risk::score(stash, era, RiskEvents {
+      slashes: count(&slashes, stash),
+      commission_increases: count(&commission_increases, stash),
+      kicks: count(&kicks, stash),
+      chills: count(&chills, stash),
+      inactive_eras: count(&inactive, stash),
+      stale_payouts: count(&stale_payouts, stash),
+      unclaimed_eras: count(&unclaimed, stash),
+      oversubscribed_eras: count(&oversubscribed, stash),
+    })).collect())
+  }
+
+  // keeps the latest score of each validator, replacing the scores with one update command by batch
+  pub async fn save_validator_risks(&self, risks: &[ValidatorRisk]) -> Result<(), DatabaseError> {
+    let client = self.client.as_ref().ok_or(DatabaseError::Disconnected)?;
+    let db = client.database(&self.db_name);
+    for batch in risks.chunks(WRITE_BATCH) {
+      let updates: Vec<Document> = batch.iter()
+        .map(|risk| doc! {"q": {"validator": &risk.stash}, "u": risk_document(risk), "upsert": true})
+        .collect();
+      let reply = db.run_command(doc! {"update": VALIDATOR_RISK, "updates": updates, "ordered": false}, None)
+        .await.in_collection(VALIDATOR_RISK)?;
+      match reply.get_array("writeErrors") {
+        Ok(errors) if !errors.is_empty() => return Err(DatabaseError::WriteFailed {
+          collection: VALIDATOR_RISK.to_string(),
+          message: format!("{} of {} scores not saved, first error: {}", errors.len(), batch.len(), errors[0]),
+        }),
+        _ => {},
+      }
+    }
+    Ok(())
+  }
+
+  pub async fn get_validator_risk(&self, stash: &str) -> Result<ValidatorRisk, DatabaseError> {
+    let client = self.client.as_ref().ok_or(DatabaseError::Disconnected)?;
+    let doc = client.database(&self.db_name).collection::<Document>(VALIDATOR_RISK)
+      .find_one(doc! {"validator": stash}, None).await.in_collection(VALIDATOR_RISK)?
+      .ok_or_else(|| DatabaseError::not_found(VALIDATOR_RISK, format!("stash {}", stash)))?;
+    bson::from_bson(Bson::Document(doc)).in_collection(VALIDATOR_RISK)
+  }
+
+  // era of the stored scores, 0 when there are none
+  pub async fn get_validator_risk_era(&self) -> Result<u32, DatabaseError> {
+    let client = self.client.as_ref().ok_or(DatabaseError::Disconnected)?;
+    let mut cursor = client.database(&self.db_name).collection::<Document>(VALIDATOR_RISK)
+      .aggregate(vec![doc! {"$group": {"_id": Bson::Null, "era": {"$max": "$era"}}}], None)
+      .await.in_collection(VALIDATOR_RISK)?;
+    match cursor.next().await {
+      Some(result) => Ok(result.in_collection(VALIDATOR_RISK)?.get_i64("era").unwrap_or(0) as u32),
+      None => Ok(0),
+    }
+  }
+
+  async fn count_by_validator(&self, collection: &str, field: &str, filter: Document)
+    -> Result<HashMap<String, u32>, DatabaseError> {
+    self.aggregate_by_validator(collection, vec![
+      doc! {"$match": filter},
+      doc! {"$group": {"_id": format!("${}", field), "count": {"$sum": 1}}},
+    ]).await
+  }
+
+  // runs a pipeline whose documents have the stash as `_id` and a number as `count`
+  async fn aggregate_by_validator(&self, collection: &str, pipeline: Vec<Document>)
+    -> Result<HashMap<String, u32>, DatabaseError> {
+    let client = self.client.as_ref().ok_or(DatabaseError::Disconnected)?;
+    let mut cursor = client.database(&self.db_name).collection::<Document>(collection)
+      .aggregate(pipeline, None).await.in_collection(collection)?;
+    let mut counts = HashMap::new();
+    while let Some(result) = cursor.next().await {
+      let doc = result.in_collection(collection)?;
+      let count = match doc.get("count") {
+        Some(Bson::Int32(n)) => *n as u32,
+        Some(Bson::Int64(n)) => *n as u32,
+        Some(Bson::Double(n)) => *n as u32,
+        _ => 0,
+      };
+      if let Ok(stash) = doc.get_str("_id") {
+        counts.insert(stash.to_string(), count);
+      }
+    }
+    Ok(counts)
+  }
+}
+
+#[test]
+fn test_risk_document_round_trip() {
+  let risk = risk::score("FcjmeNzPk3vgdENm1rHeiMCxFK96beUoi2kb59FmCoZtkGF", 3001, RiskEvents {
+    slashes: 1,
+    commission_increases: 2,
+    inactive_eras: 3,
+    ..Default::default()
+  });
+  let stored: ValidatorRisk = bson::from_bson(Bson::Document(risk_document(&risk))).unwrap();
+  assert_eq!(risk, stored);
+}
@@ -1,8 +1,10 @@
+use std::collections::HashMap;
+
 use chrono::{DateTime, NaiveDateTime, Utc};
 use futures::StreamExt;
 use mongodb::bson::{Document, doc};
 use crate::types;
-use super::{Database, DatabaseError};
+use super::{Database, DatabaseError, InCollection};
 
 impl Database {
   
@@ -14,22 +16,22 @@ impl Database {
 }
 
 async fn get_price_of_day(&self, timestamp: i64) -> Result<types::CoinPrice, DatabaseError> {
-    let client = self.client.as_ref().ok_or(DatabaseError::Mongo);
+    let client = self.client.as_ref().ok_or(DatabaseError::Disconnected);
     if let Ok(client) = client {
         let db = client.database(&self.db_name);
         let mut cursor = db
             .collection::<Document>("price")
             .find(doc! {"timestamp": timestamp}, None)
             .await
-            .unwrap();
+            .in_collection("price")?;
         if let Some(coin_price) = cursor.next().await {
-            let doc = coin_price.unwrap();
-            let price = doc.get("price").unwrap().as_f64().unwrap_or(0.0);
-            let timestamp = doc.get("timestamp").unwrap().as_i32().unwrap_or(0);
+            let doc = coin_price.in_collection("price")?;
+            let price = doc.get("price").and_then(|p| p.as_f64()).unwrap_or(0.0);
+            let timestamp = doc.get("timestamp").and_then(|t| t.as_i32()).unwrap_or(0);
             if timestamp == 0 {
-                let timestamp = doc.get("timestamp").unwrap().as_i64().unwrap_or(0);
+                let timestamp = doc.get("timestamp").and_then(|t| t.as_i64()).unwrap_or(0);
                 let price = types::CoinPrice {
-                    timestamp: timestamp as i64,
+                    timestamp,
                     price,
                 };
                 return Ok(price);
@@ -41,7 +43,7 @@ async fn get_price_of_day(&self, timestamp: i64) -> Result<types::CoinPrice, Dat
                 return Ok(price);
             }
         }
-        Err(DatabaseError::GetFailed)
+        Err(DatabaseError::not_found("price", format!("timestamp {}", timestamp)))
     } else {
         Err(DatabaseError::Disconnected)
     }
@@ -51,40 +53,27 @@ pub async fn get_stash_reward(
     &mut self,
     stash: &str,
 ) -> Result<types::StashRewards, DatabaseError> {
-    let client = self.client.as_ref().ok_or(DatabaseError::Mongo);
+    let client = self.client.as_ref().ok_or(DatabaseError::Disconnected);
     if let Ok(client) = client {
         let db = client.database(&self.db_name);
         let mut cursor = db
             .collection::<Document>("stashInfo")
             .find(doc! {"stash": stash}, None)
             .await
-            .unwrap();
+            .in_collection("stashInfo")?;
         let mut total_in_fiat = 0.0;
         let mut era_rewards: Vec<types::StashEraReward> = vec![];
         while let Some(stash_reward) = cursor.next().await {
-            let doc = stash_reward.unwrap();
-            let era;
-            match doc.get("era").unwrap().as_i32() {
-                Some(_era) => era = _era,
+            let doc = stash_reward.in_collection("stashInfo")?;
+            let era = match doc.get("era").and_then(|e| e.as_i32()) {
+                Some(_era) => _era,
                 None => continue,
-            }
-            let amount = doc.get("amount").unwrap().as_f64().unwrap_or(0.0);
-            let _timestamp = doc.get("timestamp").unwrap().as_i64();
-            let _timestamp = _timestamp.map_or_else(|| {
-                let timestamp_f64 = doc.get("timestamp").unwrap().as_f64().unwrap();
-                let naive =
-                NaiveDateTime::from_timestamp((timestamp_f64.round() / 1000.0) as i64, 0);
-                let timestamp = timestamp_f64.round() as i64;
-                (timestamp, naive)
-                },move |t| {
-                let naive =
-                NaiveDateTime::from_timestamp((t / 1000) as i64, 0);
-                let timestamp = t;
-                (timestamp, naive)
-                }
-            );
+            };
+            let amount = doc.get("amount").and_then(|a| a.as_f64()).unwrap_or(0.0);
+            let timestamp = Database::read_timestamp(&doc);
+            let naive = NaiveDateTime::from_timestamp(timestamp / 1000, 0);
             // Create a normal DateTime from the NaiveDateTime
-            let datetime: DateTime<Utc> = DateTime::from_utc(_timestamp.1, Utc);
+            let datetime: DateTime<Utc> = DateTime::from_utc(naive, Utc);
             let t = datetime.date().and_hms(0, 0, 0).timestamp();
             let result = self.get_price_from_cache(t);
             let mut price = 0.0;
@@ -104,7 +93,7 @@ pub async fn get_stash_reward(
             era_rewards.push(types::StashEraReward {
                 era,
                 amount,
-                timestamp: (_timestamp.0) as i64,
+                timestamp,
                 price: Some(price),
                 total: Some(price * amount),
             })
@@ -112,10 +101,70 @@ pub async fn get_stash_reward(
         Ok(types::StashRewards {
             stash: stash.to_string(),
             era_rewards,
-            total_in_fiat
+            total_in_fiat,
+            annualized_return: None,
         })
     } else {
         Err(DatabaseError::Disconnected)
     }
   }
-}
\ No newline at end of file
+
+  // timestamps of stashInfo are in milliseconds and may be stored as either int64 or double
+  fn read_timestamp(doc: &Document) -> i64 {
+    match doc.get("timestamp") {
+      Some(t) => t.as_i64().unwrap_or_else(|| t.as_f64().unwrap_or(0.0).round() as i64),
+      None => 0,
+    }
+  }
+
+  pub async fn get_stash_payouts(
+    &self,
+    stash: &str,
+    from: i64,
+    to: i64,
+  ) -> Result<Vec<types::CBStashEraReward>, DatabaseError> {
+    let client = self.client.as_ref().ok_or(DatabaseError::Disconnected)?;
+    let db = client.database(&self.db_name);
+    let mut cursor = db
+        .collection::<Document>("stashInfo")
+        .find(doc! {"stash": stash, "timestamp": {"$gte": from, "$lte": to}}, None)
+        .await
+        .in_collection("stashInfo")?;
+    let mut payouts = vec![];
+    while let Some(doc) = cursor.next().await {
+        let doc = doc.in_collection("stashInfo")?;
+        let era = match doc.get("era").and_then(|e| e.as_i32()) {
+            Some(era) => era,
+            None => continue,
+        };
+        payouts.push(types::CBStashEraReward {
+            era,
+            amount: doc.get("amount").and_then(|a| a.as_f64()).unwrap_or(0.0),
+            timestamp: Database::read_timestamp(&doc),
+            address: stash.to_string(),
+        });
+    }
+    Ok(payouts)
+  }
+
+  // returns prices keyed by the start of the day in seconds
+  pub async fn get_prices_of_days(&self, from: i64, to: i64) -> Result<HashMap<i64, f64>, DatabaseError> {
+    let client = self.client.as_ref().ok_or(DatabaseError::Disconnected)?;
+    let db = client.database(&self.db_name);
+    let mut cursor = db
+        .collection::<Document>("price")
+        .find(doc! {"timestamp": {"$gte": from, "$lte": to}}, None)
+        .await
+        .in_collection("price")?;
+    let mut prices = HashMap::new();
+    while let Some(doc) = cursor.next().await {
+        let doc = doc.in_collection("price")?;
+        let timestamp = match doc.get("timestamp") {
+            Some(t) => t.as_i64().unwrap_or_else(|| t.as_i32().unwrap_or(0) as i64),
+            None => continue,
+        };
+        prices.insert(timestamp, doc.get("price").and_then(|p| p.as_f64()).unwrap_or(0.0));
+    }
+    Ok(prices)
+  }
+}
@@ -1,31 +1,17 @@
 use futures::StreamExt;
-use log::info;
 use serde::{Deserialize};
 use mongodb::bson::{self, Bson, Document, doc};
+use mongodb::options::UpdateOptions;
 use rand::{Rng, thread_rng};
 
-use crate::{db::params::Inactive, referer, types::{CBStashEraReward,ValidatorStalePayoutEvent, ChillEvent, KickEvent, NewsletterSubscriberOptions, NominationOptions, NominationResultOptions, OverSubscribeEventOutput, StakingEvents, UserEventMapping, UserEventMappingOptions, ValidatorCommission, ValidatorSlash}};
+use crate::{db::params::Inactive, referer, types::{CBStashEraReward,ValidatorStalePayoutEvent, ChillEvent, KickEvent, NewsletterStatus, NewsletterSubscriber, NewsletterSubscriberOptions, NominationOptions, NominationResultOptions, OverSubscribeEventOutput, StakingEvents, UserEventMapping, UserEventMappingOptions, ValidatorCommission, ValidatorSlash}};
 
-use super::{Database, DatabaseError, params::DbRefKeyOptions};
+use super::{Database, DatabaseError, InCollection, is_duplicate_key, params::DbRefKeyOptions};
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
-pub struct NominationRecords {
-    pub stash: String,
-    pub validators: Vec<String>,
-    pub amount: String,
-    pub strategy: u32,
-    pub tag: String,
-    pub extrinsic_hash: Option<String>,
-    pub ref_key: Option<String>,
-}
-
-#[derive(Deserialize, Debug)]
-#[serde(rename_all = "camelCase")]
-pub struct RefKeyRecords {
-    pub stash: String,
-    pub ref_key: String,
-    pub timestamp: u32,
+struct RefKeyRecords {
+    ref_key: String,
 }
 
 impl Database {
@@ -44,7 +30,7 @@ impl Database {
     let mut chills: Vec<ChillEvent> = Vec::new();
     let mut over_subscribes: Vec<OverSubscribeEventOutput> = Vec::new();
     let mut commissions: Vec<ValidatorCommission> = Vec::new();
-    let mut slashes: Vec<ValidatorSlash> = Vec::new();
+    let slashes: Vec<ValidatorSlash> = Vec::new();
     let match_command = doc! {
       "$match":{
         "$and": [
@@ -63,7 +49,7 @@ impl Database {
         ]
       }
     };
-    let client = self.client.as_ref().ok_or(DatabaseError::Mongo);
+    let client = self.client.as_ref().ok_or(DatabaseError::Disconnected);
     if let Ok(client) = client {
       let db = client.database(&self.db_name);
       let mut cursor = db
@@ -75,10 +61,10 @@ impl Database {
               None,
           )
           .await
-          .unwrap();
+          .in_collection("userEventMapping")?;
       while let Some(result) = cursor.next().await {
-          let doc = result.unwrap();
-          let em: UserEventMapping = bson::from_bson(Bson::Document(doc)).unwrap();
+          let doc = result.in_collection("userEventMapping")?;
+          let em: UserEventMapping = bson::from_bson(Bson::Document(doc)).in_collection("userEventMapping")?;
           if em.event_type == 0 {
             array0.push(em);
           } else if em.event_type == 1 {
@@ -129,10 +115,10 @@ impl Database {
         .collection::<Document>("stashInfo")
         .find(doc! {"_id": {"$in": &array_payouts}}, None)
         .await
-        .unwrap();
+        .in_collection("stashInfo")?;
       while let Some(result) = cursor.next().await {
-        let doc = result.unwrap();
-        let ev = bson::from_bson(Bson::Document(doc)).unwrap();
+        let doc = result.in_collection("stashInfo")?;
+        let ev = bson::from_bson(Bson::Document(doc)).in_collection("stashInfo")?;
         payouts.push(ev);
       }
       // commissions
@@ -140,10 +126,10 @@ impl Database {
         .collection::<Document>("commission")
         .find(doc! {"_id": {"$in": &array_commission_changes}}, None)
         .await
-        .unwrap();
+        .in_collection("commission")?;
       while let Some(result) = cursor.next().await {
-        let doc = result.unwrap();
-        let ev = bson::from_bson(Bson::Document(doc)).unwrap();
+        let doc = result.in_collection("commission")?;
+        let ev = bson::from_bson(Bson::Document(doc)).in_collection("commission")?;
         commissions.push(ev);
       }
       // kicks
@@ -151,10 +137,10 @@ impl Database {
         .collection::<Document>("kickEvents")
         .find(doc! {"_id": {"$in": &array_kicks}}, None)
         .await
-        .unwrap();
+        .in_collection("kickEvents")?;
       while let Some(result) = cursor.next().await {
-        let doc = result.unwrap();
-        let ev = bson::from_bson(Bson::Document(doc)).unwrap();
+        let doc = result.in_collection("kickEvents")?;
+        let ev = bson::from_bson(Bson::Document(doc)).in_collection("kickEvents")?;
         kicks.push(ev);
       }
       // chills
@@ -162,10 +148,10 @@ impl Database {
         .collection::<Document>("chillEvents")
         .find(doc! {"_id": {"$in": &array_chills}}, None)
         .await
-        .unwrap();
+        .in_collection("chillEvents")?;
       while let Some(result) = cursor.next().await {
-        let doc = result.unwrap();
-        let ev = bson::from_bson(Bson::Document(doc)).unwrap();
+        let doc = result.in_collection("chillEvents")?;
+        let ev = bson::from_bson(Bson::Document(doc)).in_collection("chillEvents")?;
         chills.push(ev);
       }
       // inactives
@@ -173,10 +159,10 @@ impl Database {
         .collection::<Document>("inactiveEvents")
         .find(doc! {"_id": {"$in": &array_inactives}}, None)
         .await
-        .unwrap();
+        .in_collection("inactiveEvents")?;
       while let Some(result) = cursor.next().await {
-        let doc = result.unwrap();
-        let ev: Inactive = bson::from_bson(Bson::Document(doc)).unwrap();
+        let doc = result.in_collection("inactiveEvents")?;
+        let ev: Inactive = bson::from_bson(Bson::Document(doc)).in_collection("inactiveEvents")?;
         inactive.push(ev.era);
       }
       // stale_payouts
@@ -184,10 +170,10 @@ impl Database {
         .collection::<Document>("stalePayouts")
         .find(doc! {"_id": {"$in": &array_stale_payouts}}, None)
         .await
-        .unwrap();
+        .in_collection("stalePayouts")?;
       while let Some(result) = cursor.next().await {
-        let doc = result.unwrap();
-        let ev = bson::from_bson(Bson::Document(doc)).unwrap();
+        let doc = result.in_collection("stalePayouts")?;
+        let ev = bson::from_bson(Bson::Document(doc)).in_collection("stalePayouts")?;
         stale_payouts.push(ev);
       }
       // over_subsribes
@@ -195,10 +181,10 @@ impl Database {
         .collection::<Document>("overSubscribeEvents")
         .find(doc! {"_id": {"$in": &array_over_subsribes}}, None)
         .await
-        .unwrap();
+        .in_collection("overSubscribeEvents")?;
       while let Some(result) = cursor.next().await {
-        let doc = result.unwrap();
-        let ev = bson::from_bson(Bson::Document(doc)).unwrap();
+        let doc = result.in_collection("overSubscribeEvents")?;
+        let ev = bson::from_bson(Bson::Document(doc)).in_collection("overSubscribeEvents")?;
         over_subscribes.push(ev);
       }
       Ok(StakingEvents {
@@ -217,224 +203,209 @@ impl Database {
   }
 
   pub async fn insert_nomination_action(&self, chain: String, options: NominationOptions) -> Result<String, DatabaseError> {
-    let client = self.client.as_ref().ok_or(DatabaseError::Mongo);
-    if let Ok(client) = client {
-      let rand_string: String = thread_rng()
+    let client = self.client.as_ref().ok_or(DatabaseError::Disconnected)?;
+    let rand_string: String = thread_rng()
       .sample_iter(&rand::distributions::Alphanumeric)
       .take(16)
       .map(char::from)
       .collect();
-        let db = client.database(&self.db_name);
-        match db.collection("nominationRecords").insert_one(doc! {
-          "stash": options.stash,
-          "validators": options.validators,
-          "amount": options.amount.to_string(),
-          "strategy": options.strategy as u32,
-          "tag": &rand_string,
-          "chain": chain,
-        }, None).await {
-            Ok(_) => Ok(rand_string),
-            Err(e) => {
-                println!("{:?}", e);
-                Err(DatabaseError::WriteFailed)
-            },
-        }
-    } else {
-        Err(DatabaseError::Disconnected)
-    }
+    let db = client.database(&self.db_name);
+    db.collection("nominationRecords").insert_one(doc! {
+      "stash": options.stash,
+      "validators": options.validators,
+      "amount": options.amount.to_string(),
+      "strategy": options.strategy,
+      "tag": &rand_string,
+      "chain": chain,
+    }, None).await.in_collection("nominationRecords")?;
+    Ok(rand_string)
   }
 
   pub async fn insert_nomination_result(&self, options: NominationResultOptions) -> Result<(), DatabaseError> {
-    let client = self.client.as_ref().ok_or(DatabaseError::Mongo);
-    if let Ok(client) = client {
-      let db = client.database(&self.db_name);
-      match db.collection::<NominationRecords>("nominationRecords").find_one_and_update(doc! {
-        "tag": &options.tag,
-      }, doc! {
-        "$set": {
-          "extrinsicHash": options.extrinsic_hash,
-          "refKey": options.ref_key,
-        }
-      }, None).await {
-          Ok(m) => {
-            if m.is_none() {
-              return Err(DatabaseError::GetFailed);
-            }
-            Ok(())
-          },
-          Err(e) => {
-              println!("{:?}", e);
-              Err(DatabaseError::WriteFailed)
-          },
+    let client = self.client.as_ref().ok_or(DatabaseError::Disconnected)?;
+    let db = client.database(&self.db_name);
+    let tag = options.tag;
+    db.collection::<Document>("nominationRecords").find_one_and_update(doc! {
+      "tag": &tag,
+    }, doc! {
+      "$set": {
+        "extrinsicHash": options.extrinsic_hash,
+        "refKey": options.ref_key,
       }
-    } else {
-        Err(DatabaseError::Disconnected)
-    }
+    }, None).await.in_collection("nominationRecords")?
+      .ok_or_else(|| DatabaseError::not_found("nominationRecords", format!("tag {}", tag)))?;
+    Ok(())
   }
 
-  pub async fn get_nomination_records(&self, stash: &str) -> Result<NominationRecords, DatabaseError> {
-    let client = self.client.as_ref().ok_or(DatabaseError::Mongo);
-    if let Ok(client) = client {
-      let db = client.database(&self.db_name);
-      match db.collection::<NominationRecords>("nominationRecords").find_one(doc! {"stash": stash}, None).await {
-        Ok(c) => {
-          match c {
-            Some(c) => {
-              Ok(c)
-            },
-            None => {
-              Err(DatabaseError::GetFailed)
-            }
-          }
-        },
-        Err(_) => {
-          Err(DatabaseError::GetFailed)
-        }
-      }
-    } else {
-        Err(DatabaseError::Disconnected)
-    }
+  // whether the stash nominated through the app
+  pub async fn has_nomination_records(&self, stash: &str) -> Result<bool, DatabaseError> {
+    let client = self.client.as_ref().ok_or(DatabaseError::Disconnected)?;
+    let db = client.database(&self.db_name);
+    let record = db.collection::<Document>("nominationRecords").find_one(doc! {"stash": stash}, None)
+      .await.in_collection("nominationRecords")?;
+    Ok(record.is_some())
   }
 
   pub async fn get_validator_ref_key(&self, stash: &str) -> Result<String, DatabaseError> {
-    let client = self.client.as_ref().ok_or(DatabaseError::Mongo);
-    if let Ok(client) = client {
-      let db = client.database(&self.db_name);
-      match db.collection::<RefKeyRecords>("refKeyRecords").find_one(doc! {"stash": stash}, None).await {
-        Ok(c) => {
-          info!("{:?}", c);
-          match c {
-            Some(c) => {
-              Ok(c.ref_key)
-            },
-            None => {
-              Err(DatabaseError::GetFailed)
-            }
-          }
-        },
-        Err(_) => {
-          Err(DatabaseError::GetFailed)
-        }
-      }
-    } else {
-        Err(DatabaseError::Disconnected)
-    }
+    let client = self.client.as_ref().ok_or(DatabaseError::Disconnected)?;
+    let db = client.database(&self.db_name);
+    let record = db.collection::<RefKeyRecords>("refKeyRecords").find_one(doc! {"stash": stash}, None)
+      .await.in_collection("refKeyRecords")?;
+    record.map(|r| r.ref_key)
+      .ok_or_else(|| DatabaseError::not_found("refKeyRecords", format!("stash {}", stash)))
   }
 
   pub async fn decode_validator_ref_key(&self, ref_key: &str) -> Result<DbRefKeyOptions, DatabaseError> {
-    let client = self.client.as_ref().ok_or(DatabaseError::Mongo);
-    if let Ok(client) = client {
-      let db = client.database(&self.db_name);
-      match db.collection::<RefKeyRecords>("refKeyRecords").find_one(doc! {"refKey": ref_key}, None).await {
-        Ok(c) => {
-          info!("{:?}", c);
-          match c {
-            Some(c) => {
-              let decoded = referer::decrypt_ref_key(&c.ref_key).unwrap();
-              Ok(decoded)
-            },
-            None => {
-              Err(DatabaseError::GetFailed)
-            }
-          }
-        },
-        Err(_) => {
-          Err(DatabaseError::GetFailed)
-        }
-      }
-    } else {
-        Err(DatabaseError::Disconnected)
-    }
+    let client = self.client.as_ref().ok_or(DatabaseError::Disconnected)?;
+    let db = client.database(&self.db_name);
+    let record = db.collection::<RefKeyRecords>("refKeyRecords").find_one(doc! {"refKey": ref_key}, None)
+      .await.in_collection("refKeyRecords")?;
+    let record = record.ok_or_else(|| DatabaseError::not_found("refKeyRecords", format!("ref key {}", ref_key)))?;
+    referer::decrypt_ref_key(&record.ref_key).map_err(|e| DatabaseError::decode("refKeyRecords", e))
   }
 
   pub async fn insert_validator_ref_key(&self, options: DbRefKeyOptions) -> Result<(), DatabaseError> {
-    let client = self.client.as_ref().ok_or(DatabaseError::Mongo);
-    if let Ok(client) = client {
-      let db = client.database(&self.db_name);
-      match db.collection::<RefKeyRecords>("refKeyRecords").find_one(doc! {"stash": &options.stash}, None).await {
-        Ok(c) => {
-          match c {
-            Some(_) => {
-              let result = db.collection::<RefKeyRecords>("refKeyRecords").update_one(doc! {
-                "stash": options.stash,
-              }, doc! {
-                "$set": {
-                  "refKey": options.ref_key,
-                  "timestamp": options.timestamp,
-                }
-              }, None).await;
-              match result {
-                  Ok(_) => {Ok(())},
-                  Err(_) => {
-                    Err(DatabaseError::WriteFailed)
-                  },
-              }
-            },
-            None => {
-              match db.collection("refKeyRecords").insert_one(doc! {
-                "stash": options.stash,
-                "timestamp": options.timestamp,
-                "refKey": options.ref_key,
-              }, None).await {
-                  Ok(_) => Ok(()),
-                  Err(e) => {
-                    println!("{:?}", e);
-                    Err(DatabaseError::WriteFailed)
-                  },
-              }
-            }
-          }
-          
-        },
-        Err(_) => {
-          Err(DatabaseError::WriteFailed)
-        }
+    let client = self.client.as_ref().ok_or(DatabaseError::Disconnected)?;
+    let db = client.database(&self.db_name);
+    // one record per stash, the stash index is unique
+    db.collection::<Document>("refKeyRecords").update_one(doc! {
+      "stash": options.stash,
+    }, doc! {
+      "$set": {
+        "refKey": options.ref_key,
+        "timestamp": options.timestamp,
       }
-    } else {
-        Err(DatabaseError::Disconnected)
+    }, UpdateOptions::builder().upsert(true).build()).await.in_collection("refKeyRecords")?;
+    Ok(())
+  }
+
+  // An address which unsubscribed can subscribe again, and has to confirm it again; a pending one gets a new
+  // confirmation. Returns the status the address had before, None when it is new.
+  pub async fn insert_newsletter_subsriber(&self, options: NewsletterSubscriberOptions)
+    -> Result<Option<NewsletterStatus>, DatabaseError> {
+    let client = self.client.as_ref().ok_or(DatabaseError::Disconnected)?;
+    let db = client.database(&self.db_name);
+    let existing = db.collection::<NewsletterSubscriber>("newsletter").find_one(doc! {"email": &options.email}, None)
+      .await.in_collection("newsletter")?;
+    let pending = bson::to_bson(&NewsletterStatus::Pending).in_collection("newsletter")?;
+    let timestamp = chrono::Utc::now().naive_utc().timestamp();
+    match existing {
+      Some(subscriber) if subscriber.status == NewsletterStatus::Confirmed => {
+        Err(DatabaseError::Duplicated { collection: "newsletter".to_string(), key: options.email })
+      },
+      Some(subscriber) => {
+        // the digests start over from the confirmation, not from when the address left
+        db.collection::<Document>("newsletter").update_one(doc! {"email": &options.email}, doc! {
+          "$set": {
+            "status": pending,
+            "stashes": options.stashes,
+            "timestamp": timestamp,
+          },
+          "$unset": {"confirmedAt": "", "digestEras": ""},
+        }, None).await.in_collection("newsletter")?;
+        Ok(Some(subscriber.status))
+      },
+      None => {
+        let inserted = db.collection("newsletter").insert_one(doc! {
+          "email": &options.email,
+          "status": pending,
+          "stashes": options.stashes,
+          "timestamp": timestamp,
+        }, None).await.in_collection("newsletter");
+        // the same address subscribed meanwhile, the email index is unique
+        match inserted {
+          Err(e) if is_duplicate_key(&e) => {
+            return Err(DatabaseError::Duplicated { collection: "newsletter".to_string(), key: options.email });
+          },
+          inserted => inserted?,
+        };
+        Ok(None)
+      },
     }
   }
 
-  pub async fn insert_newsletter_subsriber(&self, options: NewsletterSubscriberOptions) -> Result<(), DatabaseError> {
-    let client = self.client.as_ref().ok_or(DatabaseError::Mongo);
-    if let Ok(client) = client {
-      let db = client.database(&self.db_name);
-      match db.collection::<NewsletterSubscriberOptions>("newsletter").find_one(doc! {"email": &options.email}, None).await {
-        Ok(c) => {
-          match c {
-            Some(_) => {
-              Err(DatabaseError::Duplicated)
-            },
-            None => {
-              match db.collection("newsletter").insert_one(doc! {
-                "email": options.email,
-                "timestamp": chrono::Utc::now().naive_utc().timestamp(),
-              }, None).await {
-                  Ok(_) => Ok(()),
-                  Err(e) => {
-                    println!("{:?}", e);
-                    Err(DatabaseError::WriteFailed)
-                  },
-              }
-            }
-          }
-          
-        },
-        Err(_) => {
-          match db.collection("newsletter").insert_one(doc! {
-            "email": options.email,
-            "timestamp": chrono::Utc::now().naive_utc().timestamp(),
-          }, None).await {
-              Ok(_) => Ok(()),
-              Err(e) => {
-                  println!("{:?}", e);
-                  Err(DatabaseError::WriteFailed)
-              },
-          }
-        }
-      }
-    } else {
-        Err(DatabaseError::Disconnected)
+  pub async fn get_newsletter_subscriber(&self, email: &str) -> Result<NewsletterSubscriber, DatabaseError> {
+    let client = self.client.as_ref().ok_or(DatabaseError::Disconnected)?;
+    client.database(&self.db_name).collection::<NewsletterSubscriber>("newsletter").find_one(doc! {"email": email}, None)
+      .await.in_collection("newsletter")?
+      .ok_or_else(|| DatabaseError::not_found("newsletter", format!("email {}", email)))
+  }
+
+  pub async fn get_newsletter_subscribers(&self, status: NewsletterStatus) -> Result<Vec<NewsletterSubscriber>, DatabaseError> {
+    let client = self.client.as_ref().ok_or(DatabaseError::Disconnected)?;
+    let mut cursor = client.database(&self.db_name).collection::<NewsletterSubscriber>("newsletter")
+      .find(newsletter_status_filter(status)?, None).await.in_collection("newsletter")?;
+    let mut subscribers = Vec::new();
+    while let Some(result) = cursor.next().await {
+      subscribers.push(result.in_collection("newsletter")?);
     }
+    Ok(subscribers)
   }
-}
\ No newline at end of file
+
+  pub async fn count_newsletter_subscribers(&self, status: NewsletterStatus) -> Result<u64, DatabaseError> {
+    let client = self.client.as_ref().ok_or(DatabaseError::Disconnected)?;
+    client.database(&self.db_name).collection::<Document>("newsletter")
+      .count_documents(newsletter_status_filter(status)?, None).await.in_collection("newsletter")
+  }
+
+  pub async fn set_newsletter_status(&self, email: &str, status: NewsletterStatus) -> Result<(), DatabaseError> {
+    let client = self.client.as_ref().ok_or(DatabaseError::Disconnected)?;
+    let mut update = doc! {"status": bson::to_bson(&status).in_collection("newsletter")?};
+    if status == NewsletterStatus::Confirmed {
+      update.insert("confirmedAt", chrono::Utc::now().naive_utc().timestamp());
+    }
+    let result = client.database(&self.db_name).collection::<Document>("newsletter")
+      .update_one(doc! {"email": email}, doc! {"$set": update}, None).await.in_collection("newsletter")?;
+    if result.matched_count == 0 {
+      return Err(DatabaseError::not_found("newsletter", format!("email {}", email)));
+    }
+    Ok(())
+  }
+
+  pub async fn swap_newsletter_digest_era(&self, email: &str, chain: &str, from: Option<u32>, to: Option<u32>)
+    -> Result<bool, DatabaseError> {
+    let client = self.client.as_ref().ok_or(DatabaseError::Disconnected)?;
+    let key = format!("digestEras.{}", chain);
+    let mut filter = doc! {"email": email};
+    match from {
+      Some(era) => filter.insert(key.clone(), era),
+      None => filter.insert(key.clone(), doc! {"$exists": false}),
+    };
+    let update = match to {
+      Some(era) => doc! {"$set": {key: era}},
+      None => doc! {"$unset": {key: ""}},
+    };
+    let result = client.database(&self.db_name).collection::<Document>("newsletter")
+      .update_one(filter, update, None).await.in_collection("newsletter")?;
+    Ok(result.matched_count == 1)
+  }
+
+  // gives the subscribers stored before double opt-in their pending status, returns how many were updated
+  pub async fn backfill_newsletter_status(&self) -> Result<u64, DatabaseError> {
+    let client = self.client.as_ref().ok_or(DatabaseError::Disconnected)?;
+    let pending = bson::to_bson(&NewsletterStatus::Pending).in_collection("newsletter")?;
+    let result = client.database(&self.db_name).collection::<Document>("newsletter")
+      .update_many(doc! {"status": {"$exists": false}}, doc! {"$set": {"status": pending}}, None)
+      .await.in_collection("newsletter")?;
+    Ok(result.modified_count)
+  }
+
+  pub async fn delete_newsletter_subscriber(&self, email: &str) -> Result<(), DatabaseError> {
+    let client = self.client.as_ref().ok_or(DatabaseError::Disconnected)?;
+    let result = client.database(&self.db_name).collection::<Document>("newsletter")
+      .delete_one(doc! {"email": email}, None).await.in_collection("newsletter")?;
+    if result.deleted_count == 0 {
+      return Err(DatabaseError::not_found("newsletter", format!("email {}", email)));
+    }
+    Ok(())
+  }
+}
+
+// subscribers stored before double opt-in have no status, they are pending
+fn newsletter_status_filter(status: NewsletterStatus) -> Result<Document, DatabaseError> {
+  let filter = doc! {"status": bson::to_bson(&status).in_collection("newsletter")?};
+  if status == NewsletterStatus::Pending {
+    return Ok(doc! {"$or": [filter, {"status": {"$exists": false}}]});
+  }
+  Ok(filter)
+}
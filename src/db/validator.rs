@@ -2,7 +2,7 @@ use futures::StreamExt;
 use mongodb::bson::{self, Bson, Document, doc, bson};
 
 use crate::types::{self, CBStashEraReward, ChillEvent, KickEvent, OverSubscribeEvent, ValidatorCommission, ValidatorNominationInfo, ValidatorSlash, ValidatorStalePayoutEvent};
-use super::{Database, DatabaseError, params::AllValidatorOptions};
+use super::{Database, DatabaseError, InCollection, params::{AllValidatorOptions, ValidatorSort}};
 
 impl Database {
   
@@ -27,17 +27,17 @@ impl Database {
             ]
             }
         };
-        let client = self.client.as_ref().ok_or(DatabaseError::Mongo);
+        let client = self.client.as_ref().ok_or(DatabaseError::Disconnected);
         if let Ok(client) = client {
             let db = client.database(&self.db_name);
             let mut cursor = db
                 .collection::<Document>("validatorSlash")
                 .aggregate(vec![match_command], None)
                 .await
-                .unwrap();
+                .in_collection("validatorSlash")?;
             while let Some(result) = cursor.next().await {
-                let doc = result.unwrap();
-                let slash: ValidatorSlash = bson::from_bson(Bson::Document(doc)).unwrap();
+                let doc = result.in_collection("validatorSlash")?;
+                let slash: ValidatorSlash = bson::from_bson(Bson::Document(doc)).in_collection("validatorSlash")?;
                 array.push(slash);
             }
             Ok(array)
@@ -67,7 +67,7 @@ impl Database {
             ]
         },
     };
-    let client = self.client.as_ref().ok_or(DatabaseError::Mongo);
+    let client = self.client.as_ref().ok_or(DatabaseError::Disconnected);
     if let Ok(client) = client {
         let db = client.database(&self.db_name);
         let mut cursor = db
@@ -79,11 +79,11 @@ impl Database {
                 None,
             )
             .await
-            .unwrap();
+            .in_collection("commission")?;
         while let Some(result) = cursor.next().await {
-            let doc = result.unwrap();
+            let doc = result.in_collection("commission")?;
             let vc: ValidatorCommission =
-                    bson::from_bson(Bson::Document(doc)).unwrap();
+                    bson::from_bson(Bson::Document(doc)).in_collection("commission")?;
                     array.push(vc);
         }
         Ok(array)
@@ -152,7 +152,7 @@ impl Database {
               "info": {"$push": "$info"}
           }
       };
-      let client = self.client.as_ref().ok_or(DatabaseError::Mongo);
+      let client = self.client.as_ref().ok_or(DatabaseError::Disconnected);
         if let Ok(client) = client {
             let db = client.database(&self.db_name);
             let mut cursor = db
@@ -168,11 +168,11 @@ impl Database {
                 None,
             )
             .await
-            .unwrap();
+            .in_collection("validator")?;
             if let Some(result) = cursor.next().await {
-            let unwrapped = result.unwrap();
+            let unwrapped = result.in_collection("validator")?;
             let mut info: types::ValidatorNominationTrend =
-                bson::from_bson(Bson::Document(unwrapped)).unwrap();
+                bson::from_bson(Bson::Document(unwrapped)).in_collection("validator")?;
             let mut cursor2 = db
                 .collection::<Document>("nomination")
                 .aggregate(
@@ -215,10 +215,10 @@ impl Database {
                     None,
                 )
                 .await
-                .unwrap();
+                .in_collection("nomination")?;
             if let Some(result2) = cursor2.next().await {
                 let info2: types::NominationInfo =
-                    bson::from_bson(Bson::Document(result2.unwrap())).unwrap();
+                    bson::from_bson(Bson::Document(result2.in_collection("nomination")?)).in_collection("nomination")?;
                 let mut index: i32 = -1;
                 for (i, era_info) in info.info.iter().enumerate() {
                     if era_info.era == info2.era {
@@ -227,12 +227,12 @@ impl Database {
                 }
                 if index >= 0 {
                     info.info[index as usize]
-                        .set_nominators(info2.nominators.unwrap_or_else(std::vec::Vec::new));
+                        .set_nominators(info2.nominators.unwrap_or_default());
                 }
             }
             return Ok(info);
             }
-            Err(DatabaseError::GetFailed)
+            Err(DatabaseError::not_found("validator", format!("stash {}", stash)))
         } else {
             Err(DatabaseError::Disconnected)
         }
@@ -248,19 +248,19 @@ impl Database {
             "validator": stash
         },
     };
-    let client = self.client.as_ref().ok_or(DatabaseError::Mongo);
+    let client = self.client.as_ref().ok_or(DatabaseError::Disconnected);
     if let Ok(client) = client {
         let db = client.database(&self.db_name);
         let mut cursor = db
             .collection::<Document>("unclaimedEraInfo")
             .aggregate(vec![match_command], None)
             .await
-            .unwrap();
+            .in_collection("unclaimedEraInfo")?;
         while let Some(result) = cursor.next().await {
-            let doc = result.unwrap();
-            let eras = doc.get_array("eras").unwrap();
+            let doc = result.in_collection("unclaimedEraInfo")?;
+            let eras = doc.get_array("eras").in_collection("unclaimedEraInfo")?;
             for era in eras {
-                array.push(era.as_i32().unwrap());
+                array.push(era.as_i32().ok_or_else(|| DatabaseError::decode("unclaimedEraInfo", "era is not an int32"))?);
             }
         }
         Ok(array)
@@ -279,17 +279,17 @@ impl Database {
                 "address": stash
             },
         };
-        let client = self.client.as_ref().ok_or(DatabaseError::Mongo);
+        let client = self.client.as_ref().ok_or(DatabaseError::Disconnected);
         if let Ok(client) = client {
             let db = client.database(&self.db_name);
             let mut cursor = db
                 .collection::<Document>("validatorSlash")
                 .aggregate(vec![match_command], None)
                 .await
-                .unwrap();
+                .in_collection("validatorSlash")?;
             while let Some(result) = cursor.next().await {
-                let doc = result.unwrap();
-                let slash: ValidatorSlash = bson::from_bson(Bson::Document(doc)).unwrap();
+                let doc = result.in_collection("validatorSlash")?;
+                let slash: ValidatorSlash = bson::from_bson(Bson::Document(doc)).in_collection("validatorSlash")?;
                 array.push(slash);
             }
             Ok(array)
@@ -319,17 +319,17 @@ impl Database {
                 ]
             },
         };
-        let client = self.client.as_ref().ok_or(DatabaseError::Mongo);
+        let client = self.client.as_ref().ok_or(DatabaseError::Disconnected);
         if let Ok(client) = client {
             let db = client.database(&self.db_name);
             let mut cursor = db
                 .collection::<Document>("stalePayouts")
                 .aggregate(vec![match_command], None)
                 .await
-                .unwrap();
+                .in_collection("stalePayouts")?;
             while let Some(result) = cursor.next().await {
-                let doc = result.unwrap();
-                let events: ValidatorStalePayoutEvent = bson::from_bson(Bson::Document(doc)).unwrap();
+                let doc = result.in_collection("stalePayouts")?;
+                let events: ValidatorStalePayoutEvent = bson::from_bson(Bson::Document(doc)).in_collection("stalePayouts")?;
                 array.push(events);
             }
             Ok(array)
@@ -359,17 +359,17 @@ impl Database {
                 ]
             },
         };
-        let client = self.client.as_ref().ok_or(DatabaseError::Mongo);
+        let client = self.client.as_ref().ok_or(DatabaseError::Disconnected);
         if let Ok(client) = client {
             let db = client.database(&self.db_name);
             let mut cursor = db
                 .collection::<Document>("stashInfo")
                 .aggregate(vec![match_command], None)
                 .await
-                .unwrap();
+                .in_collection("stashInfo")?;
             while let Some(result) = cursor.next().await {
-                let doc = result.unwrap();
-                let events: CBStashEraReward = bson::from_bson(Bson::Document(doc)).unwrap();
+                let doc = result.in_collection("stashInfo")?;
+                let events: CBStashEraReward = bson::from_bson(Bson::Document(doc)).in_collection("stashInfo")?;
                 array.push(events);
             }
             Ok(array)
@@ -384,76 +384,97 @@ impl Database {
       options: AllValidatorOptions,
   ) -> Result<Vec<types::ValidatorNominationInfo>, DatabaseError> {
       let array = Vec::new();
-      let match_command = doc! {
-          "$match":{
-              "era": era,
-              "apy": {"$lte": options.apy_max, "$gte": options.apy_min},
-              "commission": {"$lte": options.commission_max * 100.0, "$gte": options.commission_min * 100.0},
-          },
-      };
-      let lookup_command = doc! {
-          "$lookup": {
-              "from": "validator",
-              "localField": "validator",
-              "foreignField": "id",
-              "as": "data"
-          },
-      };
-      let match_command2 = doc! {
-          "$match": {
-              "data.identity.isVerified": true
-          },
-      };
-      let lookup_command2 = doc! {
+      let direction = if options.descending { -1 } else { 1 };
+      let mut pipeline = self.filter_validators_of_era(era, &options).await?;
+      pipeline.push(doc! {
+          "$addFields": {"sortKey": sort_key(options.sort)},
+      });
+      if let Some(cursor) = &options.after {
+          let key = cursor_key(&cursor.key);
+          let op = if options.descending { "$lt" } else { "$gt" };
+          pipeline.push(doc! {
+              "$match": {
+                  "$or": [
+                      {"sortKey": {op: &key}},
+                      {"sortKey": &key, "validator": {op: &cursor.id}},
+                  ]
+              },
+          });
+      }
+      pipeline.push(doc! {
+          "$sort": {"sortKey": direction, "validator": direction},
+      });
+      if options.after.is_none() {
+          pipeline.push(doc! {
+              "$skip": options.skip(),
+          });
+      }
+      pipeline.push(doc! {
+          "$limit": options.size,
+      });
+      pipeline.push(doc! {
           "$lookup": {
               "from": "unclaimedEraInfo",
               "localField": "validator",
               "foreignField": "validator",
               "as": "unclaimedEraInfo"
           },
-      };
-      let lookup_command3 = doc! {
+      });
+      pipeline.push(doc! {
           "$lookup": {
               "from": "validatorSlash",
               "localField": "validator",
               "foreignField": "address",
               "as": "slashes"
           },
+      });
+      self.do_get_validator_info(array, pipeline).await
+  }
+
+  pub async fn count_validators_of_era(&self, era: u32, options: &AllValidatorOptions) -> Result<u64, DatabaseError> {
+      let client = self.client.as_ref().ok_or(DatabaseError::Disconnected)?;
+      let mut pipeline = self.filter_validators_of_era(era, options).await?;
+      pipeline.push(doc! {"$count": "count"});
+      let mut cursor = client.database(&self.db_name).collection::<Document>("nomination")
+          .aggregate(pipeline, None).await.in_collection("nomination")?;
+      match cursor.next().await {
+          Some(result) => Ok(result.in_collection("nomination")?.get_i32("count").in_collection("nomination")? as u64),
+          None => Ok(0),
+      }
+  }
+
+  // the validators of an era which pass the filters of the options, with their `validator` document as `data`
+  async fn filter_validators_of_era(&self, era: u32, options: &AllValidatorOptions) -> Result<Vec<Document>, DatabaseError> {
+      let threshold = match options.era_points_percentile {
+          Some(percentile) => self.era_points_threshold(era, percentile).await?,
+          None => None,
       };
-      let skip_command = doc! {
-          "$skip": options.page * options.size,
-      };
-      let limit_command = doc! {
-          "$limit": options.size,
-      };
-      if options.has_verified_identity {
-          self.do_get_validator_info(
-              array,
-              vec![
-                  match_command,
-                  lookup_command,
-                  match_command2,
-                  lookup_command2,
-                  lookup_command3,
-                  skip_command,
-                  limit_command,
-              ],
-          )
-          .await
-      } else {
-          self.do_get_validator_info(
-              array,
-              vec![
-                  match_command,
-                  lookup_command,
-                  lookup_command2,
-                  lookup_command3,
-                  skip_command,
-                  limit_command,
-              ],
-          )
-          .await
+      Ok(filter_validators_of_era(era, options, threshold))
+  }
+
+  // the average era points a validator of the era needs to reach the percentile, by nearest rank
+  async fn era_points_threshold(&self, era: u32, percentile: f32) -> Result<Option<f64>, DatabaseError> {
+      let client = self.client.as_ref().ok_or(DatabaseError::Disconnected)?;
+      let pipeline = vec![
+          doc! {"$match": {"era": era}},
+          doc! {
+              "$lookup": {
+                  "from": "validator",
+                  "localField": "validator",
+                  "foreignField": "id",
+                  "as": "data"
+              },
+          },
+          doc! {"$project": {"eraPoints": average_era_points()}},
+      ];
+      let mut cursor = client.database(&self.db_name).collection::<Document>("nomination")
+          .aggregate(pipeline, None).await.in_collection("nomination")?;
+      let mut points = vec![];
+      while let Some(result) = cursor.next().await {
+          let doc = result.in_collection("nomination")?;
+          points.push(doc.get_f64("eraPoints").unwrap_or(0.0));
       }
+      Ok(percentile_rank(points, percentile))
   }
 
   pub async fn get_kick_events(
@@ -477,7 +498,7 @@ impl Database {
             ]
         },
     };
-    let client = self.client.as_ref().ok_or(DatabaseError::Mongo);
+    let client = self.client.as_ref().ok_or(DatabaseError::Disconnected);
     if let Ok(client) = client {
         let db = client.database(&self.db_name);
         let mut cursor = db
@@ -489,11 +510,11 @@ impl Database {
                 None,
             )
             .await
-            .unwrap();
+            .in_collection("kickEvents")?;
         while let Some(result) = cursor.next().await {
-            let doc = result.unwrap();
+            let doc = result.in_collection("kickEvents")?;
             let vc: KickEvent =
-                    bson::from_bson(Bson::Document(doc)).unwrap();
+                    bson::from_bson(Bson::Document(doc)).in_collection("kickEvents")?;
                     array.push(vc);
         }
         Ok(array)
@@ -525,7 +546,7 @@ impl Database {
             ]
         },
     };
-    let client = self.client.as_ref().ok_or(DatabaseError::Mongo);
+    let client = self.client.as_ref().ok_or(DatabaseError::Disconnected);
     if let Ok(client) = client {
         let db = client.database(&self.db_name);
         let mut cursor = db
@@ -537,11 +558,11 @@ impl Database {
                 None,
             )
             .await
-            .unwrap();
+            .in_collection("kickEvents")?;
         while let Some(result) = cursor.next().await {
-            let doc = result.unwrap();
+            let doc = result.in_collection("kickEvents")?;
             let vc: ChillEvent =
-                    bson::from_bson(Bson::Document(doc)).unwrap();
+                    bson::from_bson(Bson::Document(doc)).in_collection("kickEvents")?;
                     array.push(vc);
         }
         Ok(array)
@@ -571,7 +592,7 @@ impl Database {
             ]
         },
     };
-    let client = self.client.as_ref().ok_or(DatabaseError::Mongo);
+    let client = self.client.as_ref().ok_or(DatabaseError::Disconnected);
     if let Ok(client) = client {
         let db = client.database(&self.db_name);
         let mut cursor = db
@@ -583,11 +604,11 @@ impl Database {
                 None,
             )
             .await
-            .unwrap();
+            .in_collection("overSubscribeEvents")?;
         while let Some(result) = cursor.next().await {
-            let doc = result.unwrap();
+            let doc = result.in_collection("overSubscribeEvents")?;
             let vc: OverSubscribeEvent =
-                    bson::from_bson(Bson::Document(doc)).unwrap();
+                    bson::from_bson(Bson::Document(doc)).in_collection("overSubscribeEvents")?;
                     array.push(vc);
         }
         Ok(array)
@@ -688,31 +709,41 @@ impl Database {
   async fn do_get_validator_info(
       &self,
       mut array: Vec<ValidatorNominationInfo>,
-      pipeline: Vec<Document>,
+      mut pipeline: Vec<Document>,
   ) -> Result<Vec<ValidatorNominationInfo>, DatabaseError> {
-    let client = self.client.as_ref().ok_or(DatabaseError::Mongo);
+    pipeline.push(doc! {
+        "$lookup": {
+            "from": "validatorRisk",
+            "localField": "validator",
+            "foreignField": "validator",
+            "as": "risk"
+        },
+    });
+    let client = self.client.as_ref().ok_or(DatabaseError::Disconnected);
     if let Ok(client) = client {
         let db = client.database(&self.db_name);
               let mut cursor = db
                   .collection::<Document>("nomination")
                   .aggregate(pipeline, None)
                   .await
-                  .unwrap();
+                  .in_collection("nomination")?;
               while let Some(result) = cursor.next().await {
-                  let doc = result.unwrap();
-                  let _data = &doc.get_array("data").unwrap()[0];
-                  let id = _data.as_document().unwrap().get("id");
-                  let default_unclaimed_eras = vec![bson! ({
-                      "eras": [],
-                      "validator": id.unwrap(),
-                  })];
-                  let unclaimed_era_infos = doc
-                      .get_array("unclaimedEraInfo")
-                      .unwrap_or(&default_unclaimed_eras);
-                  let status_change = _data.as_document().unwrap().get("statusChange");
-                  let identity = _data.as_document().unwrap().get("identity");
-                  let staker_points = _data.as_document().unwrap().get("stakerPoints");
-                  let average_apy = _data.as_document().unwrap().get("averageApy");
+                  let doc = result.in_collection("nomination")?;
+                  let field = |d: &'_ Document, key: &str| d.get(key).cloned()
+                      .ok_or_else(|| DatabaseError::missing_field("nomination", key));
+                  let data = doc.get_array("data").in_collection("nomination")?
+                      .first()
+                      .and_then(|d| d.as_document())
+                      .ok_or_else(|| DatabaseError::missing_field("nomination", "data"))?;
+                  let id = field(data, "id")?;
+                  let unclaimed_eras = match doc.get_array("unclaimedEraInfo").ok().and_then(|infos| infos.first()) {
+                      Some(info) => Bson::Array(info.as_document()
+                          .ok_or_else(|| DatabaseError::decode("unclaimedEraInfo", "not a document"))?
+                          .get_array("eras").in_collection("unclaimedEraInfo")?.clone()),
+                      None => bson!([]),
+                  };
+                  let identity = data.get("identity");
+                  let average_apy = data.get("averageApy");
                   let slashes = doc.get("slashes");
                   let default_identity = bson!({
                       "display": "",
@@ -720,8 +751,8 @@ impl Database {
                       "sub": "",
                       "is_verified": false,
                   });
-                  let blocked = _data.as_document().unwrap().get("blocked");
-                  let nominators = doc.get_array("nominators").unwrap();
+                  let blocked = data.get("blocked");
+                  let nominators = doc.get_array("nominators").in_collection("nomination")?;
                   let mut _nominators = vec![];
 
                   for n in nominators {
@@ -733,53 +764,30 @@ impl Database {
                           _nominators.push(n.clone());
                       }
                   }
-                  let mut output: Document;
-                  if !unclaimed_era_infos.is_empty() {
-                    output = doc! {
-                        "id": id.unwrap(),
-                        "statusChange": status_change.unwrap(),
-                        "identity": identity.unwrap_or(&default_identity),
-                        "info": {
-                            "nominators": &_nominators,
-                            "nominatorCount": doc.get_array("nominators").unwrap().len() as u32,
-                            "era": doc.get("era").unwrap(),
-                            "commission": doc.get("commission").unwrap(),
-                            "apy": doc.get("apy").unwrap(),
-                            "exposure": doc.get("exposure").unwrap(),
-                            "unclaimedEras": unclaimed_era_infos[0].as_document().unwrap().get_array("eras").unwrap(),
-                            "total": doc.get("total").unwrap_or(&Bson::String("0x00".to_string())),
-                            "selfStake": doc.get("selfStake").unwrap_or(&Bson::String("0x00".to_string())),
-                        },
-                        "stakerPoints": staker_points.unwrap(),
-                        "averageApy": average_apy.unwrap_or(&Bson::Int32(0)),
-                        "blocked": blocked.unwrap_or(&Bson::Boolean(false)),
-                    };
-                  } else {
-                    output = doc! {
-                        "id": id.unwrap(),
-                        "statusChange": status_change.unwrap(),
-                        "identity": identity.unwrap_or(&default_identity),
-                        "info": {
-                            "nominators": &_nominators,
-                            "nominatorCount": doc.get_array("nominators").unwrap().len() as u32,
-                            "era": doc.get("era").unwrap(),
-                            "commission": doc.get("commission").unwrap(),
-                            "apy": doc.get("apy").unwrap(),
-                            "exposure": doc.get("exposure").unwrap(),
-                            "unclaimedEras": [],
-                            "total": doc.get("total").unwrap_or(&Bson::String("0x00".to_string())),
-                            "selfStake": doc.get("selfStake").unwrap_or(&Bson::String("0x00".to_string())),
-                        },
-                        "stakerPoints": staker_points.unwrap(),
-                        "averageApy": average_apy.unwrap_or(&Bson::Int32(0)),
-                        "blocked": blocked.unwrap_or(&Bson::Boolean(false)),
-                    };
+                  let mut output = doc! {
+                      "id": id,
+                      "statusChange": field(data, "statusChange")?,
+                      "identity": identity.unwrap_or(&default_identity),
+                      "info": {
+                          "nominators": &_nominators,
+                          "nominatorCount": nominators.len() as u32,
+                          "era": field(&doc, "era")?,
+                          "commission": field(&doc, "commission")?,
+                          "apy": field(&doc, "apy")?,
+                          "exposure": field(&doc, "exposure")?,
+                          "unclaimedEras": unclaimed_eras,
+                          "total": doc.get("total").unwrap_or(&Bson::String("0x00".to_string())),
+                          "selfStake": doc.get("selfStake").unwrap_or(&Bson::String("0x00".to_string())),
+                      },
+                      "stakerPoints": field(data, "stakerPoints")?,
+                      "averageApy": average_apy.unwrap_or(&Bson::Int32(0)),
+                      "blocked": blocked.unwrap_or(&Bson::Boolean(false)),
+                  };
+                  if let Some(risk) = doc.get_array("risk").ok().and_then(|r| r.first()) {
+                      output.insert("risk", risk);
                   }
-                  let info = output.get_document_mut("info").unwrap();
-                  if unclaimed_era_infos.is_empty() {
-                      info.insert("unclaimedEras", bson! ([]));
-                  } else {
-                      info.insert("unclaimedEras", unclaimed_era_infos[0].as_document().unwrap().get_array("eras").unwrap());
+                  if let Some(key) = doc.get("sortKey") {
+                      output.insert("sortKey", key);
                   }
                   match slashes {
                       Some(slashes) => {
@@ -791,7 +799,7 @@ impl Database {
                       }
                   }
                   let info: ValidatorNominationInfo =
-                      bson::from_bson(Bson::Document(output)).unwrap();
+                      bson::from_bson(Bson::Document(output)).in_collection("nomination")?;
                   array.push(info);
               }
               Ok(array)
@@ -800,4 +808,143 @@ impl Database {
     }
   }
 
-}
\ No newline at end of file
+}
+
+fn filter_validators_of_era(era: u32, options: &AllValidatorOptions, points_threshold: Option<f64>) -> Vec<Document> {
+    let mut filters = vec![
+        doc! {"era": era},
+        doc! {"apy": {"$lte": options.apy_max, "$gte": options.apy_min}},
+        doc! {"commission": {"$lte": options.commission_max * 100.0, "$gte": options.commission_min * 100.0}},
+    ];
+    if let Some(stashes) = &options.stashes {
+        filters.push(doc! {"validator": {"$in": stashes}});
+    }
+    if let Some(min) = options.total_min {
+        filters.push(doc! {"$expr": {"$gte": [padded_amount("total"), format!("{:032x}", min)]}});
+    }
+    if let Some(max) = options.total_max {
+        filters.push(doc! {"$expr": {"$lte": [padded_amount("total"), format!("{:032x}", max)]}});
+    }
+    if let Some(min) = options.self_stake_min {
+        filters.push(doc! {"$expr": {"$gte": [padded_amount("selfStake"), format!("{:032x}", min)]}});
+    }
+    if let Some(max) = options.self_stake_max {
+        filters.push(doc! {"$expr": {"$lte": [padded_amount("selfStake"), format!("{:032x}", max)]}});
+    }
+    if let Some(limit) = options.nominator_limit {
+        filters.push(doc! {"$expr": {"$lt": [{"$size": {"$ifNull": ["$nominators", []]}}, limit]}});
+    }
+    let mut pipeline = vec![
+        doc! {
+            "$match": {"$and": filters},
+        },
+        doc! {
+            "$lookup": {
+                "from": "validator",
+                "localField": "validator",
+                "foreignField": "id",
+                "as": "data"
+            },
+        },
+    ];
+    let mut data_filters = vec![];
+    if options.has_verified_identity {
+        data_filters.push(doc! {"data.identity.isVerified": true});
+    }
+    if options.has_parent_identity {
+        // chain-data-collector sets the parent, the sub name or both for a sub-identity
+        data_filters.push(doc! {"$or": [
+            {"data.identity.parent": {"$nin": [Bson::Null, ""]}},
+            {"data.identity.sub": {"$nin": [Bson::Null, ""]}},
+        ]});
+    }
+    if options.exclude_blocked {
+        data_filters.push(doc! {"data.blocked": {"$ne": true}});
+    }
+    if let Some(threshold) = points_threshold {
+        pipeline.push(doc! {
+            "$addFields": {"eraPoints": average_era_points()},
+        });
+        data_filters.push(doc! {"eraPoints": {"$gte": threshold}});
+    }
+    if !data_filters.is_empty() {
+        pipeline.push(doc! {
+            "$match": {"$and": data_filters},
+        });
+    }
+    if let Some(eras) = options.slash_free_eras {
+        // slashes of the last `eras` eras, the current one included
+        pipeline.push(doc! {
+            "$lookup": {
+                "from": "validatorSlash",
+                "let": {"validator": "$validator"},
+                "pipeline": [
+                    {"$match": {"$expr": {"$and": [
+                        {"$eq": ["$address", "$$validator"]},
+                        {"$gt": ["$era", era as i64 - eras as i64]},
+                        {"$lte": ["$era", era as i64]},
+                    ]}}},
+                    {"$limit": 1},
+                ],
+                "as": "recentSlashes"
+            },
+        });
+        pipeline.push(doc! {
+            "$match": {"recentSlashes": {"$size": 0}},
+        });
+    }
+    pipeline
+}
+
+fn average_era_points() -> Bson {
+    // validators without points count as 0
+    bson!({"$ifNull": [{"$avg": {"$ifNull": [{"$arrayElemAt": ["$data.stakerPoints.points", 0]}, []]}}, 0.0]})
+}
+
+// nearest rank percentile, percentile being between 0 and 1
+pub(super) fn percentile_rank(mut points: Vec<f64>, percentile: f32) -> Option<f64> {
+    if points.is_empty() {
+        return None;
+    }
+    points.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+    let rank = (percentile as f64 * points.len() as f64).ceil() as usize;
+    Some(points[rank.saturating_sub(1).min(points.len() - 1)])
+}
+
+// amounts are stored as hex strings, so they are left padded to 32 digits to be compared as strings
+fn padded_amount(field: &str) -> Bson {
+    bson!({
+        "$let": {
+            "vars": {
+                "hex": {"$toLower": {"$substrCP": [{"$ifNull": [format!("${}", field), "0x0"]}, 2, 32]}},
+            },
+            "in": {"$concat": [
+                {"$substrCP": ["0".repeat(32), 0, {"$subtract": [32, {"$strLenCP": "$$hex"}]}]},
+                "$$hex",
+            ]},
+        }
+    })
+}
+
+fn sort_key(sort: Option<ValidatorSort>) -> Bson {
+    match sort {
+        None => bson!("$validator"),
+        Some(ValidatorSort::Apy) => bson!("$apy"),
+        Some(ValidatorSort::Commission) => bson!("$commission"),
+        Some(ValidatorSort::NominatorCount) => bson!({"$size": {"$ifNull": ["$nominators", []]}}),
+        Some(ValidatorSort::Total) => padded_amount("total"),
+        Some(ValidatorSort::SelfStake) => padded_amount("selfStake"),
+    }
+}
+
+// serde_json keeps non negative integers unsigned, which bson cannot serialize
+fn cursor_key(key: &serde_json::Value) -> Bson {
+    match key {
+        serde_json::Value::Number(n) => match n.as_i64() {
+            Some(i) => Bson::Int64(i),
+            None => Bson::Double(n.as_f64().unwrap_or_default()),
+        },
+        serde_json::Value::String(s) => Bson::String(s.clone()),
+        _ => Bson::Null,
+    }
+}
@@ -0,0 +1,56 @@
+use futures::StreamExt;
+use mongodb::bson::{self, Bson, Document, doc};
+
+use crate::webhooks::WebhookSubscription;
+use super::{Database, DatabaseError, InCollection};
+
+const WEBHOOK_SUBSCRIPTIONS: &str = "webhookSubscriptions";
+
+impl Database {
+  pub async fn insert_webhook_subscription(&self, subscription: &WebhookSubscription) -> Result<(), DatabaseError> {
+    let client = self.client.as_ref().ok_or(DatabaseError::Disconnected)?;
+    let event_types = bson::to_bson(&subscription.event_types).in_collection(WEBHOOK_SUBSCRIPTIONS)?;
+    client.database(&self.db_name).collection::<Document>(WEBHOOK_SUBSCRIPTIONS).insert_one(doc! {
+      "id": &subscription.id,
+      "chain": &subscription.chain,
+      "stash": &subscription.stash,
+      "url": &subscription.url,
+      "eventTypes": event_types,
+      "secret": &subscription.secret,
+      "lastEra": subscription.last_era,
+      "createdAt": subscription.created_at,
+    }, None).await.in_collection(WEBHOOK_SUBSCRIPTIONS)?;
+    Ok(())
+  }
+
+  pub async fn get_webhook_subscriptions(&self, chain: &str) -> Result<Vec<WebhookSubscription>, DatabaseError> {
+    let client = self.client.as_ref().ok_or(DatabaseError::Disconnected)?;
+    let mut cursor = client.database(&self.db_name).collection::<Document>(WEBHOOK_SUBSCRIPTIONS)
+      .find(doc! {"chain": chain}, None).await.in_collection(WEBHOOK_SUBSCRIPTIONS)?;
+    let mut subscriptions = Vec::new();
+    while let Some(result) = cursor.next().await {
+      let doc = result.in_collection(WEBHOOK_SUBSCRIPTIONS)?;
+      subscriptions.push(bson::from_bson(Bson::Document(doc)).in_collection(WEBHOOK_SUBSCRIPTIONS)?);
+    }
+    Ok(subscriptions)
+  }
+
+  pub async fn swap_webhook_last_era(&self, id: &str, from: u32, to: u32) -> Result<bool, DatabaseError> {
+    let client = self.client.as_ref().ok_or(DatabaseError::Disconnected)?;
+    let result = client.database(&self.db_name).collection::<Document>(WEBHOOK_SUBSCRIPTIONS)
+      .update_one(doc! {"id": id, "lastEra": from}, doc! {"$set": {"lastEra": to}}, None)
+      .await.in_collection(WEBHOOK_SUBSCRIPTIONS)?;
+    Ok(result.matched_count == 1)
+  }
+
+  pub async fn delete_webhook_subscription(&self, id: &str, secret: &str) -> Result<(), DatabaseError> {
+    let client = self.client.as_ref().ok_or(DatabaseError::Disconnected)?;
+    let result = client.database(&self.db_name).collection::<Document>(WEBHOOK_SUBSCRIPTIONS)
+      .delete_one(doc! {"id": id, "secret": secret}, None)
+      .await.in_collection(WEBHOOK_SUBSCRIPTIONS)?;
+    if result.deleted_count == 0 {
+      return Err(DatabaseError::not_found(WEBHOOK_SUBSCRIPTIONS, format!("id {}", id)));
+    }
+    Ok(())
+  }
+}
@@ -0,0 +1,201 @@
+use std::fmt;
+use std::sync::Arc;
+
+use lettre::message::header::{ContentType, HeaderName, HeaderValue};
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncFileTransport, AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use tracing::info;
+
+use crate::config::EmailConfig;
+
+#[derive(Debug)]
+pub enum EmailError {
+  // the configuration, an address or a message is invalid
+  Invalid(String),
+  // the transport could not send the message
+  Transport(String),
+}
+
+impl fmt::Display for EmailError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      EmailError::Invalid(message) => write!(f, "Email error: {}", message),
+      EmailError::Transport(message) => write!(f, "Email error: cannot send: {}", message),
+    }
+  }
+}
+
+impl std::error::Error for EmailError {}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Email {
+  pub to: String,
+  pub subject: String,
+  // plain text
+  pub body: String,
+  // sent as List-Unsubscribe, with List-Unsubscribe-Post so that mail clients can unsubscribe in one click
+  pub unsubscribe_url: Option<String>,
+}
+
+enum Transport {
+  Smtp(AsyncSmtpTransport<Tokio1Executor>),
+  File(AsyncFileTransport<Tokio1Executor>),
+  // only logs the recipient and the subject
+  Log,
+  #[cfg(test)]
+  Memory(std::sync::Mutex<Vec<Email>>),
+}
+
+#[derive(Clone)]
+pub struct Mailer {
+  transport: Arc<Transport>,
+  from: Mailbox,
+}
+
+impl Mailer {
+  pub fn new(config: &EmailConfig) -> Result<Self, EmailError> {
+    let transport = match config.transport.as_str() {
+      "smtp" => {
+        let host = config.smtp_host.as_deref().ok_or_else(|| EmailError::Invalid("smtp_host is missing".to_string()))?;
+        let invalid = |e: lettre::transport::smtp::Error| EmailError::Invalid(e.to_string());
+        let mut builder = match config.smtp_tls.as_deref().unwrap_or("starttls") {
+          "starttls" => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host).map_err(invalid)?,
+          "tls" => AsyncSmtpTransport::<Tokio1Executor>::relay(host).map_err(invalid)?,
+          "none" => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(host),
+          tls => return Err(EmailError::Invalid(format!("unknown smtp_tls {}", tls))),
+        };
+        if let Some(port) = config.smtp_port {
+          builder = builder.port(port);
+        }
+        if let Some(username) = &config.smtp_username {
+          builder = builder.credentials(Credentials::new(username.clone(), config.smtp_password.clone().unwrap_or_default()));
+        }
+        Transport::Smtp(builder.build())
+      },
+      "file" => Transport::File(AsyncFileTransport::new(config.file_folder.as_deref().unwrap_or("./mail"))),
+      "log" => Transport::Log,
+      transport => return Err(EmailError::Invalid(format!("unknown transport {}", transport))),
+    };
+    Ok(Mailer {
+      transport: Arc::new(transport),
+      from: parse_mailbox(&config.from)?,
+    })
+  }
+
+  // for a server without an email configuration
+  pub fn log() -> Self {
+    Mailer {
+      transport: Arc::new(Transport::Log),
+      from: parse_mailbox("CryptoLab <newsletter@cryptolab.network>").unwrap(),
+    }
+  }
+
+  // keeps the emails, see `sent`
+  #[cfg(test)]
+  pub fn memory() -> Self {
+    Mailer {
+      transport: Arc::new(Transport::Memory(std::sync::Mutex::new(vec![]))),
+      from: parse_mailbox("CryptoLab <newsletter@cryptolab.network>").unwrap(),
+    }
+  }
+
+  #[cfg(test)]
+  pub fn sent(&self) -> Vec<Email> {
+    match &*self.transport {
+      Transport::Memory(sent) => sent.lock().unwrap().clone(),
+      _ => vec![],
+    }
+  }
+
+  fn message(&self, email: &Email) -> Result<Message, EmailError> {
+    let mut message = Message::builder()
+      .from(self.from.clone())
+      .to(parse_mailbox(&email.to)?)
+      .subject(email.subject.clone())
+      .header(ContentType::TEXT_PLAIN)
+      .body(email.body.clone())
+      .map_err(|e| EmailError::Invalid(e.to_string()))?;
+    if let Some(url) = &email.unsubscribe_url {
+      let headers = message.headers_mut();
+      headers.insert_raw(HeaderValue::new(HeaderName::new_from_ascii_str("List-Unsubscribe"), format!("<{}>", url)));
+      headers.insert_raw(HeaderValue::new(HeaderName::new_from_ascii_str("List-Unsubscribe-Post"),
+        "List-Unsubscribe=One-Click".to_string()));
+    }
+    Ok(message)
+  }
+
+  pub async fn send(&self, email: Email) -> Result<(), EmailError> {
+    let message = self.message(&email)?;
+    match &*self.transport {
+      Transport::Smtp(transport) => transport.send(message).await.map(|_| ()).map_err(|e| EmailError::Transport(e.to_string())),
+      Transport::File(transport) => transport.send(message).await.map(|_| ()).map_err(|e| EmailError::Transport(e.to_string())),
+      Transport::Log => {
+        info!("email \"{}\" to {} is not sent, no transport is configured", email.subject, email.to);
+        Ok(())
+      },
+      #[cfg(test)]
+      Transport::Memory(sent) => {
+        sent.lock().unwrap().push(email);
+        Ok(())
+      },
+    }
+  }
+}
+
+fn parse_mailbox(address: &str) -> Result<Mailbox, EmailError> {
+  address.parse().map_err(|e| EmailError::Invalid(format!("{} is not a valid address: {}", address, e)))
+}
+
+// Fills the `{{name}}` placeholders of a template. The first line of a template is `Subject: ...` and the body
+// starts after the blank line following it.
+pub fn render(template: &str, values: &[(&str, String)]) -> (String, String) {
+  let mut text = template.to_string();
+  for (name, value) in values {
+    text = text.replace(&format!("{{{{{}}}}}", name), value);
+  }
+  let (subject, body) = text.split_once('\n').unwrap_or((&text, ""));
+  (subject.trim_start_matches("Subject:").trim().to_string(), body.trim_start_matches('\n').to_string())
+}
+
+#[test]
+fn test_render() {
+  let (subject, body) = render("Subject: Era {{era}}\n\nHello {{name}}, era {{era}}.\n",
+    &[("era", "3001".to_string()), ("name", "Alice".to_string())]);
+  assert_eq!("Era 3001", subject);
+  assert_eq!("Hello Alice, era 3001.\n", body);
+}
+
+#[tokio::test]
+async fn test_file_transport() {
+  let folder = std::env::temp_dir().join(format!("cryptolab-mail-{}", std::process::id()));
+  std::fs::create_dir_all(&folder).unwrap();
+  let mailer = Mailer::new(&EmailConfig {
+    transport: "file".to_string(),
+    file_folder: Some(folder.to_string_lossy().to_string()),
+    from: "CryptoLab <newsletter@cryptolab.network>".to_string(),
+    ..Default::default()
+  }).unwrap();
+  mailer.send(Email {
+    to: "subscriber@cryptolab.network".to_string(),
+    subject: "Digest".to_string(),
+    body: "Nothing happened.".to_string(),
+    unsubscribe_url: Some("https://cryptolab.network/unsubscribe".to_string()),
+  }).await.unwrap();
+  let files: Vec<_> = std::fs::read_dir(&folder).unwrap().map(|f| f.unwrap().path()).collect();
+  assert_eq!(1, files.len());
+  let message = std::fs::read_to_string(&files[0]).unwrap();
+  std::fs::remove_dir_all(&folder).unwrap();
+  assert!(message.contains("To: subscriber@cryptolab.network"));
+  assert!(message.contains("List-Unsubscribe: <https://cryptolab.network/unsubscribe>"));
+  assert!(message.contains("List-Unsubscribe-Post: List-Unsubscribe=One-Click"));
+  assert!(message.contains("Nothing happened."));
+
+  let invalid = mailer.send(Email {
+    to: "not an address".to_string(),
+    subject: "Digest".to_string(),
+    body: "".to_string(),
+    unsubscribe_url: None,
+  }).await;
+  assert!(matches!(invalid, Err(EmailError::Invalid(_))));
+}
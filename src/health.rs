@@ -0,0 +1,161 @@
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use tracing::error;
+
+use crate::cache::Cache;
+use crate::db::Database;
+use crate::metrics;
+use crate::rewards_jobs::RewardsJobQueue;
+use crate::signature;
+
+// a dependency that does not answer in time is down, MongoDB would otherwise wait for its server selection timeout
+const CHECK_TIMEOUT: Duration = Duration::from_secs(3);
+// the era job runs every 10 minutes, it is stale once it missed two runs
+const ERA_JOB_MAX_AGE_SECS: i64 = 1800;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+  Ok,
+  Fail,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Check {
+  pub status: Status,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub latency_ms: Option<u128>,
+  // the error of a failed check, or what a passed one found
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub detail: Option<String>,
+}
+
+impl Check {
+  fn from_result(result: Result<(), String>) -> Self {
+    match result {
+      Ok(()) => Check { status: Status::Ok, latency_ms: None, detail: None },
+      Err(e) => Check { status: Status::Fail, latency_ms: None, detail: Some(e) },
+    }
+  }
+}
+
+#[derive(Serialize, Debug)]
+pub struct HealthReport {
+  pub status: Status,
+  pub checks: BTreeMap<String, Check>,
+}
+
+impl HealthReport {
+  fn new(checks: BTreeMap<String, Check>) -> Self {
+    let status = if checks.values().all(|check| check.status == Status::Ok) { Status::Ok } else { Status::Fail };
+    HealthReport { status, checks }
+  }
+
+  pub fn is_ok(&self) -> bool {
+    self.status == Status::Ok
+  }
+}
+
+async fn timed<F: Future<Output = Result<(), String>>>(check: F) -> Check {
+  let started = Instant::now();
+  let result = match tokio::time::timeout(CHECK_TIMEOUT, check).await {
+    Ok(result) => result,
+    Err(_) => Err(format!("no answer within {} seconds", CHECK_TIMEOUT.as_secs())),
+  };
+  Check { latency_ms: Some(started.elapsed().as_millis()), ..Check::from_result(result) }
+}
+
+fn scheduler_check(chain: &str, now: i64) -> Check {
+  match metrics::scheduler_last_success("era", chain) {
+    Some(timestamp) => Check {
+      status: if now - timestamp <= ERA_JOB_MAX_AGE_SECS { Status::Ok } else { Status::Fail },
+      latency_ms: None,
+      detail: Some(format!("the era job last succeeded {} seconds ago", now - timestamp)),
+    },
+    None => Check::from_result(Err("the era job has not succeeded yet".to_string())),
+  }
+}
+
+// the endpoints are public and driver errors may contain hosts and credentials, the full error is only logged
+fn unreachable<E: std::fmt::Display>(name: &str, err: E) -> String {
+  error!("health check of {} failed: {}", name, err);
+  "unreachable".to_string()
+}
+
+// What the health endpoints check: the databases, the cache, the schedulers of the chains and the in-process tools.
+#[derive(Clone)]
+pub struct Dependencies {
+  pub chain_dbs: HashMap<&'static str, Database>,
+  pub users_db: Database,
+  pub cache: Cache,
+  pub rewards_jobs: RewardsJobQueue,
+}
+
+impl Dependencies {
+  // the in-process parts, a failure is only fixed by a restart
+  fn liveness_checks(&self) -> BTreeMap<String, Check> {
+    let mut checks = BTreeMap::new();
+    checks.insert("rewardsJobs".to_string(), Check::from_result(if self.rewards_jobs.is_running() {
+      Ok(())
+    } else {
+      Err("the rewards job worker stopped".to_string())
+    }));
+    checks.insert("signatureVerifier".to_string(),
+      Check::from_result(signature::self_check().map_err(|e| e.to_string())));
+    checks
+  }
+
+  pub fn liveness(&self) -> HealthReport {
+    HealthReport::new(self.liveness_checks())
+  }
+
+  // the liveness checks, the databases, the cache and the freshness of the era job of each chain
+  pub async fn readiness(&self) -> HealthReport {
+    let mut checks = self.liveness_checks();
+    let mut databases: Vec<(String, &Database)> = self.chain_dbs.iter()
+      .map(|(ticker, db)| (format!("mongo:{}", ticker), db)).collect();
+    databases.push(("mongo:users".to_string(), &self.users_db));
+    let results = futures::future::join_all(databases.iter()
+      .map(|(name, db)| timed(async move { db.ping().await.map_err(|e| unreachable(name, e)) }))).await;
+    for ((name, _), check) in databases.into_iter().zip(results) {
+      checks.insert(name, check);
+    }
+    checks.insert("cache".to_string(), timed(async { self.cache.ping().await.map_err(|e| unreachable("cache", e)) }).await);
+    let now = chrono::Utc::now().timestamp();
+    for ticker in self.chain_dbs.keys() {
+      checks.insert(format!("scheduler:{}", ticker), scheduler_check(ticker, now));
+    }
+    HealthReport::new(checks)
+  }
+}
+
+#[tokio::test]
+async fn test_readiness() {
+  let db = Database::new("127.0.0.1".to_string(), 27017, "health");
+  let dependencies = Dependencies {
+    chain_dbs: vec![("HLT", db.clone())].into_iter().collect(),
+    users_db: db.clone(),
+    cache: std::sync::Arc::new(crate::cache_memory::MemoryCache::new()),
+    rewards_jobs: RewardsJobQueue::start(db, HashMap::new()),
+  };
+  assert!(dependencies.liveness().is_ok());
+
+  // the databases are not connected and the era job never ran
+  let report = dependencies.readiness().await;
+  assert_eq!(Status::Fail, report.status);
+  assert_eq!(Status::Ok, report.checks["cache"].status);
+  assert_eq!(Status::Ok, report.checks["rewardsJobs"].status);
+  assert_eq!(Status::Fail, report.checks["mongo:HLT"].status);
+  assert_eq!(Status::Fail, report.checks["mongo:users"].status);
+  assert_eq!(Some("unreachable"), report.checks["mongo:users"].detail.as_deref());
+  assert_eq!(Some("the era job has not succeeded yet"), report.checks["scheduler:HLT"].detail.as_deref());
+
+  metrics::record_scheduler_success("era", "HLT");
+  let report = dependencies.readiness().await;
+  assert_eq!(Status::Ok, report.checks["scheduler:HLT"].status);
+  assert_eq!(Status::Fail, scheduler_check("HLT", chrono::Utc::now().timestamp() + 3600).status);
+}
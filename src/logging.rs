@@ -0,0 +1,203 @@
+use std::fmt;
+use std::sync::Mutex;
+
+use rand::{Rng, distributions::Alphanumeric, thread_rng};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id};
+use tracing::{Span, Subscriber};
+use tracing_subscriber::filter::{EnvFilter, filter_fn};
+use tracing_subscriber::fmt::format::FmtSpan;
+use tracing_subscriber::layer::{Context, SubscriberExt};
+use tracing_subscriber::registry::{LookupSpan, Registry};
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{Layer, reload};
+
+use crate::chain;
+
+// The server logs with `tracing`. Each API request runs in a `request` span which carries its request id, chain and
+// stash; MongoDB commands, Redis calls and rewards jobs open child spans at debug level. Spans are logged when they
+// close, with their duration.
+
+const DEFAULT_LEVEL: &str = "info";
+const REQUEST_SPAN: &str = "request";
+
+type Reload = Box<dyn Fn(EnvFilter) -> Result<(), String> + Send + Sync>;
+
+struct Level {
+  directives: String,
+  reload: Option<Reload>,
+}
+
+static LEVEL: Mutex<Level> = Mutex::new(Level {
+  directives: String::new(),
+  reload: None,
+});
+
+#[derive(Debug)]
+pub struct InvalidLevel(String);
+
+impl fmt::Display for InvalidLevel {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "invalid log level: {}", self.0)
+  }
+}
+
+// Installs the subscriber. `level` takes `RUST_LOG` directives, e.g. `info,cryptolab_backend=debug`; `RUST_LOG`
+// itself wins over it. `format` is "json" (default) or "text".
+pub fn init(level: Option<&str>, format: Option<&str>) {
+  let directives = std::env::var("RUST_LOG").ok().or_else(|| level.map(String::from))
+    .unwrap_or_else(|| DEFAULT_LEVEL.to_string());
+  let filter = EnvFilter::try_new(&directives).unwrap_or_else(|e| {
+    eprintln!("invalid log level {}, {} is used: {}", directives, DEFAULT_LEVEL, e);
+    EnvFilter::new(DEFAULT_LEVEL)
+  });
+  let (filter, handle) = reload::Layer::new(filter);
+  let output = match format.unwrap_or("json") {
+    "text" => tracing_subscriber::fmt::layer().with_span_events(FmtSpan::CLOSE).boxed(),
+    _ => tracing_subscriber::fmt::layer().json().with_span_events(FmtSpan::CLOSE).boxed(),
+  };
+  // the request ids are kept whatever the level, the error envelopes need them
+  let request_ids = RequestIdLayer.with_filter(filter_fn(|metadata| metadata.name() == REQUEST_SPAN));
+  tracing_subscriber::registry()
+    .with(request_ids)
+    .with(output.with_filter(filter))
+    .init();
+  let mut current = LEVEL.lock().unwrap();
+  current.directives = directives;
+  current.reload = Some(Box::new(move |filter| handle.reload(filter).map_err(|e| e.to_string())));
+}
+
+// Logs of the support commands, on stderr to keep their output apart. `RUST_LOG` sets the level, "warn" by default.
+pub fn init_cli() {
+  let filter = std::env::var("RUST_LOG").ok().and_then(|directives| EnvFilter::try_new(directives).ok())
+    .unwrap_or_else(|| EnvFilter::new("warn"));
+  tracing_subscriber::fmt().with_writer(std::io::stderr).with_env_filter(filter).init();
+}
+
+// the directives of the running filter
+pub fn level() -> String {
+  LEVEL.lock().unwrap().directives.clone()
+}
+
+// replaces the filter of the running subscriber
+pub fn set_level(directives: &str) -> Result<(), InvalidLevel> {
+  let filter = EnvFilter::try_new(directives).map_err(|e| InvalidLevel(e.to_string()))?;
+  let mut current = LEVEL.lock().unwrap();
+  if let Some(reload) = current.reload.as_ref() {
+    reload(filter).map_err(InvalidLevel)?;
+  }
+  current.directives = directives.to_string();
+  Ok(())
+}
+
+pub fn new_request_id() -> String {
+  thread_rng()
+    .sample_iter(&Alphanumeric)
+    .take(16)
+    .map(char::from)
+    .collect()
+}
+
+// an X-Request-Id set by a proxy is kept if it looks like one
+fn is_valid_request_id(id: &str) -> bool {
+  (1..=64).contains(&id.len()) && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+// the chain and the stash of an API path, e.g. `/api/v1/validator/<stash>/risk/KSM`
+fn path_params(path: &str) -> (Option<&str>, Option<&str>) {
+  let segments = || path.split('/').filter(|s| !s.is_empty());
+  let chain = segments().find(|s| chain::all().iter().any(|c| c.ticker == *s));
+  let stash = segments().find(|s| s.len() > 40 && chain::ss58_decode(s).is_some());
+  (chain, stash)
+}
+
+// the span of an API request, see `warp::trace`
+pub fn request_span(info: warp::trace::Info) -> Span {
+  let request_id = info.request_headers().get("x-request-id")
+    .and_then(|id| id.to_str().ok())
+    .filter(|id| is_valid_request_id(id))
+    .map(String::from)
+    .unwrap_or_else(new_request_id);
+  let (chain, stash) = path_params(info.path());
+  tracing::info_span!(REQUEST_SPAN, request_id = %request_id, method = %info.method(), path = info.path(),
+    chain = chain, stash = stash)
+}
+
+// logs the outcome of an API request, see `warp::log::custom`
+pub fn log_request(info: warp::log::Info) {
+  tracing::info!(status = info.status().as_u16(), elapsed_ms = info.elapsed().as_millis() as u64, "{} {}",
+    info.method(), info.path());
+}
+
+// the id of the request being handled, None outside of a request or without the subscriber of `init`
+pub fn request_id() -> Option<String> {
+  let id = Span::current().id()?;
+  tracing::dispatcher::get_default(|dispatch| {
+    let registry = dispatch.downcast_ref::<Registry>()?;
+    let span = registry.span(&id)?;
+    let request_id = span.scope().find_map(|span| span.extensions().get::<RequestId>().map(|id| id.0.clone()));
+    request_id
+  })
+}
+
+struct RequestId(String);
+
+// keeps the request id of the request spans in their extensions
+struct RequestIdLayer;
+
+struct RequestIdVisitor(Option<String>);
+
+impl Visit for RequestIdVisitor {
+  fn record_str(&mut self, field: &Field, value: &str) {
+    if field.name() == "request_id" {
+      self.0 = Some(value.to_string());
+    }
+  }
+
+  fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+    if field.name() == "request_id" {
+      self.0 = Some(format!("{:?}", value));
+    }
+  }
+}
+
+impl<S: Subscriber + for<'a> LookupSpan<'a>> Layer<S> for RequestIdLayer {
+  fn on_new_span(&self, attributes: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+    let mut visitor = RequestIdVisitor(None);
+    attributes.record(&mut visitor);
+    if let (Some(request_id), Some(span)) = (visitor.0, ctx.span(id)) {
+      span.extensions_mut().insert(RequestId(request_id));
+    }
+  }
+}
+
+// keeps the request ids but logs nothing
+#[cfg(test)]
+pub fn test_subscriber() -> impl Subscriber {
+  tracing_subscriber::registry()
+    .with(RequestIdLayer.with_filter(filter_fn(|metadata| metadata.name() == REQUEST_SPAN)))
+    .with(tracing_subscriber::fmt::layer().with_test_writer().with_filter(EnvFilter::new("off")))
+}
+
+#[test]
+fn test_request_id() {
+  tracing::subscriber::with_default(test_subscriber(), || {
+    assert_eq!(None, request_id());
+    let span = tracing::info_span!(REQUEST_SPAN, request_id = %"abc123", chain = tracing::field::Empty);
+    let _request = span.enter();
+    let _child = tracing::debug_span!("mongo", collection = "validator").entered();
+    assert_eq!(Some("abc123".to_string()), request_id());
+  });
+  assert!(is_valid_request_id("4f1c-a9_B"));
+  assert!(!is_valid_request_id("a b"));
+  assert!(!is_valid_request_id(""));
+}
+
+#[test]
+fn test_set_level() {
+  assert!(set_level("info,cryptolab_backend=debug").is_ok());
+  assert_eq!("info,cryptolab_backend=debug", level());
+  assert!(set_level("info,=[").is_err());
+  assert_eq!("info,cryptolab_backend=debug", level());
+}
+
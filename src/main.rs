@@ -2,86 +2,181 @@
 #[macro_use]
 extern crate lazy_static;
 mod cache;
+mod chain;
+mod cli;
 mod config;
 mod db;
+mod email;
+mod health;
+mod logging;
+mod metrics;
 mod types;
 mod cache_redis;
+mod cache_memory;
+mod rate_limit;
 // mod polkadot_cache;
 mod web;
 mod staking_rewards_collector;
 mod scheduler;
 mod referer;
+mod risk;
+mod newsletter;
+mod rewards_jobs;
+mod signature;
+mod simulation;
+mod strategy;
+mod stream;
+mod webhooks;
 
+use clap::Parser;
+use cli::{Cli, Command};
 use config::Config;
 use db::Database;
-use log::debug;
+use db::migrations::{self, Target};
+use tracing::{debug, error, info, warn};
+use newsletter::Newsletter;
+use stream::{Broadcaster, DEFAULT_MAX_STREAMS};
+use rewards_jobs::RewardsJobQueue;
 use web::{WebServer, WebServerOptions};
-use std::{env};
+use std::{collections::HashMap, env, sync::Arc};
+use tokio::signal::unix::{signal, SignalKind};
 
-use crate::{cache_redis::Cache, scheduler::cache_era_info};
+use crate::{cache::Cache, cache_memory::MemoryCache, cache_redis::RedisCache, scheduler::{cache_era_info, notify_webhooks, refresh_validator_risks, send_newsletter_digests, stream_updates}};
+
+async fn create_cache() -> Cache {
+    let config = Config::current();
+    match config.cache_backend.as_deref().unwrap_or("redis") {
+        "memory" => {
+            let cache = MemoryCache::new();
+            for chain in chain::all() {
+                if let Some(folder) = &chain.cache_folder {
+                    if let Err(e) = cache.load_folder(&chain.ticker, folder) {
+                        error!("failed to load the cache files of {}: {}", chain.ticker, e);
+                    }
+                }
+            }
+            Arc::new(cache)
+        },
+        "redis" => Arc::new(RedisCache::new(&config.redis, config.redis_port).await),
+        backend => panic!("Unknown cache backend {}", backend),
+    }
+}
+
+// reloads the configuration on SIGHUP, only the hot keys change, see `Config::reload`
+fn reload_config_on_hangup(path: String) {
+    tokio::spawn(async move {
+        let mut hangups = match signal(SignalKind::hangup()) {
+            Ok(hangups) => hangups,
+            Err(e) => {
+                error!("failed to listen to SIGHUP, the configuration will not be reloaded: {}", e);
+                return;
+            }
+        };
+        while hangups.recv().await.is_some() {
+            let reloaded = match Config::reload(&path) {
+                Ok(reloaded) => reloaded,
+                Err(e) => {
+                    error!("{}, the current configuration is kept", e);
+                    continue;
+                }
+            };
+            if reloaded.applied.iter().any(|key| key == "log_level") && env::var("RUST_LOG").is_err() {
+                let level = Config::current().log_level.clone().unwrap_or_else(|| "info".to_string());
+                if let Err(e) = logging::set_level(&level) {
+                    error!("{}", e);
+                }
+            }
+            if !reloaded.ignored.is_empty() {
+                warn!("{} changed, they are applied on restart", reloaded.ignored.join(", "));
+            }
+            info!("configuration {} reloaded, changed: {}", path, reloaded.applied.join(", "));
+        }
+    });
+}
 
 #[tokio::main]
 async fn main() {
-    // env::set_var("RUST_LOG", "warp");
-    env_logger::builder().filter_module("hyper", log::LevelFilter::Info).filter_level(log::LevelFilter::Debug).init();
-    Config::init();
+    let cli = Cli::parse();
+    let config_path = config::path(cli.config.as_deref());
+    let command = cli.command.unwrap_or(Command::Serve);
+    if command.needs_config() {
+        if let Err(e) = Config::init(&config_path) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        chain::init(Config::current().chains());
+    }
+    match command {
+        Command::Serve => serve(config_path).await,
+        command => {
+            logging::init_cli();
+            if let Err(e) = cli::run(command, &config_path).await {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        },
+    }
+}
+
+async fn serve(config_path: String) {
+    logging::init(Config::current().log_level.as_deref(), Config::current().log_format.as_deref());
+    for warning in &Config::current().warnings {
+        warn!("configuration {}: {}", config_path, warning);
+    }
+    reload_config_on_hangup(config_path);
     let mongo_ip = env::var("MONGO_IP_ADDR");
-    debug!("{:?}", mongo_ip);
     let mongo_ip = mongo_ip.unwrap_or_else(|_| Config::current().db_address.parse().unwrap());
-    debug!("{}", mongo_ip);
-    let mut kusama_db = Database::new(
+    debug!("MongoDB address {}", mongo_ip);
+    let mut chain_dbs = HashMap::new();
+    for chain in chain::all() {
+        let mut db = Database::new(
+            mongo_ip.clone(),
+            Config::current().db_port,
+            chain.db_name.as_str(),
+        );
+        // connect only parses the options, the driver reconnects to the servers by itself; a failure is a configuration
+        // error that retrying does not fix, the server still starts and /readyz reports the database until it is fixed
+        if let Err(e) = db.connect().await {
+            error!("failed to connect to the {} database: {}", chain.ticker, e);
+        }
+        chain_dbs.insert(chain.ticker.as_str(), db);
+    }
+    let mut users_db = Database::new(
         mongo_ip.clone(),
         Config::current().db_port,
-        Config::current().kusama_db_name.as_str(),
+        Config::current().users_db_name.as_str(),
     );
-    let result = kusama_db.connect().await;
-    match result {
-        Ok(_) => {
-            let mut polkadot_db = Database::new(
-                mongo_ip.clone(),
-                Config::current().db_port,
-                Config::current().polkadot_db_name.as_str(),
-            );
-            let _ = polkadot_db.connect().await;
-            let mut users_db = Database::new(
-                mongo_ip.clone(),
-                Config::current().db_port,
-                Config::current().users_db_name.as_str(),
-            );
-            let _ = users_db.connect().await;
-            if Config::current().support_westend {
-                let mut westend_db = Database::new(
-                    mongo_ip.clone(),
-                    Config::current().db_port,
-                    Config::current().westend_db_name.as_str(),
-                );
-                let _ = westend_db.connect().await;
-                let options = WebServerOptions {
-                    kusama_db,
-                    polkadot_db,
-                    users_db,
-                    westend_db: Some(westend_db),
-                    cache: Cache{},
-                };
-                cache_era_info("KSM");
-                cache_era_info("DOT");
-                cache_era_info("WND");
-                let server = WebServer::new(Config::current().port, options);
-                server.start().await;
-            } else {
-                let options = WebServerOptions {
-                    kusama_db,
-                    polkadot_db,
-                    users_db,
-                    westend_db: None,
-                    cache: Cache{},
-                };
-                cache_era_info("KSM");
-                cache_era_info("DOT");
-                let server = WebServer::new(Config::current().port, options);
-                server.start().await;
-            }
-        }
-        Err(_) => panic!("Failed to connect to the Kusama Database"),
+    if let Err(e) = users_db.connect().await {
+        error!("failed to connect to the users database: {}", e);
+    }
+    // the server does not wait for the migrations, they can take a while on large collections
+    let apply_migrations = Config::current().migrate_on_start.unwrap_or(true);
+    for (ticker, db) in &chain_dbs {
+        tokio::spawn(migrations::migrate_on_start(ticker.to_string(), db.clone(), Target::Chain, apply_migrations));
+    }
+    tokio::spawn(migrations::migrate_on_start("users".to_string(), users_db.clone(), Target::Users, apply_migrations));
+    let rewards_jobs = RewardsJobQueue::start(users_db.clone(), chain_dbs.clone());
+    let cache = create_cache().await;
+    let newsletter = match Newsletter::from_config(Config::current().email.as_ref()) {
+        Ok(newsletter) => newsletter,
+        Err(e) => panic!("Invalid email configuration: {}", e),
+    };
+    let broadcaster = Broadcaster::new(Config::current().max_streams.map_or(DEFAULT_MAX_STREAMS, |max| max as usize));
+    for chain in chain::all() {
+        cache_era_info(chain, cache.clone());
+        stream_updates(chain, cache.clone(), Arc::new(users_db.clone()), broadcaster.clone());
+        refresh_validator_risks(chain);
+        notify_webhooks(chain, Arc::new(users_db.clone()));
+        send_newsletter_digests(chain, Arc::new(users_db.clone()), newsletter.clone());
     }
+    let options = WebServerOptions {
+        chain_dbs,
+        users_db,
+        cache,
+        rewards_jobs,
+        newsletter,
+        broadcaster,
+    };
+    let server = WebServer::new(Config::current().port, options);
+    server.start().await;
 }
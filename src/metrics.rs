@@ -0,0 +1,299 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+// Metrics in the Prometheus text format, served at /metrics. Series are kept in a process-wide registry; the label
+// values are bounded (routes are the templates of the served routes, collections and key families are fixed) so they
+// cannot grow unbounded.
+
+const REQUESTS: &str = "cryptolab_http_requests_total";
+const REQUEST_DURATION: &str = "cryptolab_http_request_duration_seconds";
+const MONGO_DURATION: &str = "cryptolab_mongo_command_duration_seconds";
+const CACHE_REQUESTS: &str = "cryptolab_redis_requests_total";
+const REWARDS_JOB_DURATION: &str = "cryptolab_rewards_job_duration_seconds";
+const REWARDS_JOB_FAILURES: &str = "cryptolab_rewards_job_failures_total";
+const SCHEDULER_LAST_SUCCESS: &str = "cryptolab_scheduler_last_success_timestamp_seconds";
+const RATE_LIMITED: &str = "cryptolab_rate_limited_requests_total";
+
+// name, type and help of each family, in the order they are rendered
+const FAMILIES: &[(&str, &str, &str)] = &[
+  (REQUESTS, "counter", "HTTP requests by route, chain, method and status"),
+  (REQUEST_DURATION, "histogram", "HTTP request latency by route and chain"),
+  (MONGO_DURATION, "histogram", "MongoDB command duration by collection and command"),
+  (CACHE_REQUESTS, "counter", "Redis reads by key family and result (hit, miss or error)"),
+  (REWARDS_JOB_DURATION, "histogram", "Staking rewards job duration by status"),
+  (REWARDS_JOB_FAILURES, "counter", "Failed staking rewards jobs"),
+  (SCHEDULER_LAST_SUCCESS, "gauge", "Unix time of the last successful run of a scheduler job by chain"),
+  (RATE_LIMITED, "counter", "Requests answered with 429 by route class"),
+];
+
+const LATENCY_BUCKETS: &[f64] = &[0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+const JOB_BUCKETS: &[f64] = &[1.0, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0, 600.0];
+
+type Series = (&'static str, Vec<(&'static str, String)>);
+
+struct Histogram {
+  buckets: &'static [f64],
+  // observations up to each bucket, not cumulative
+  counts: Vec<u64>,
+  sum: f64,
+  count: u64,
+}
+
+struct Registry {
+  counters: BTreeMap<Series, f64>,
+  gauges: BTreeMap<Series, f64>,
+  histograms: BTreeMap<Series, Histogram>,
+}
+
+static REGISTRY: Mutex<Registry> = Mutex::new(Registry {
+  counters: BTreeMap::new(),
+  gauges: BTreeMap::new(),
+  histograms: BTreeMap::new(),
+});
+
+fn series(name: &'static str, labels: &[(&'static str, &str)]) -> Series {
+  (name, labels.iter().map(|(k, v)| (*k, v.to_string())).collect())
+}
+
+fn increment(name: &'static str, labels: &[(&'static str, &str)]) {
+  *REGISTRY.lock().unwrap().counters.entry(series(name, labels)).or_insert(0.0) += 1.0;
+}
+
+fn set(name: &'static str, labels: &[(&'static str, &str)], value: f64) {
+  REGISTRY.lock().unwrap().gauges.insert(series(name, labels), value);
+}
+
+fn observe(name: &'static str, labels: &[(&'static str, &str)], buckets: &'static [f64], value: f64) {
+  let mut registry = REGISTRY.lock().unwrap();
+  let histogram = registry.histograms.entry(series(name, labels)).or_insert_with(|| Histogram {
+    buckets,
+    counts: vec![0; buckets.len()],
+    sum: 0.0,
+    count: 0,
+  });
+  if let Some(i) = histogram.buckets.iter().position(|bound| value <= *bound) {
+    histogram.counts[i] += 1;
+  }
+  histogram.sum += value;
+  histogram.count += 1;
+}
+
+fn templates() -> &'static [String] {
+  static TEMPLATES: OnceLock<Vec<String>> = OnceLock::new();
+  TEMPLATES.get_or_init(crate::web::route_templates)
+}
+
+// the number of literal segments of `template` when it matches `segments`, a {chain} only matches one of `tickers`
+fn match_template(template: &str, segments: &[&str], tickers: &[&str]) -> Option<usize> {
+  let parts: Vec<&str> = template.split('/').filter(|s| !s.is_empty()).collect();
+  if parts.len() != segments.len() {
+    return None;
+  }
+  let mut literals = 0;
+  for (part, segment) in parts.iter().zip(segments) {
+    if *part == "{chain}" {
+      if !tickers.contains(segment) {
+        return None;
+      }
+    } else if part.starts_with('{') {
+      // a parameter matches any segment
+    } else if part == segment {
+      literals += 1;
+    } else {
+      return None;
+    }
+  }
+  Some(literals)
+}
+
+// Returns the template of the route serving `path`, e.g. /api/v1/validators/{chain}, and the chain. Paths which no
+// route serves are `unmatched` whatever their status, e.g. the 429 of the rate limiter which runs before the routes.
+pub fn route_label(path: &str, tickers: &[&str]) -> (String, String) {
+  if path == "/metrics" || path == "/healthz" || path == "/readyz" {
+    return (path.to_string(), String::new());
+  }
+  if !path.starts_with("/api/") {
+    return ("static".to_string(), String::new());
+  }
+  let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+  let best = templates().iter()
+    .filter_map(|template| match_template(template, &segments, tickers).map(|literals| (literals, template)))
+    .max_by_key(|(literals, _)| *literals);
+  match best {
+    Some((_, template)) => {
+      let chain = segments.iter().find(|segment| tickers.contains(segment)).map(|chain| chain.to_string());
+      (template.clone(), chain.unwrap_or_default())
+    },
+    None => ("unmatched".to_string(), String::new()),
+  }
+}
+
+// the methods counted by name, clients choose the method and any other one would add a series
+const METHODS: &[&str] = &["GET", "HEAD", "POST", "PUT", "DELETE", "OPTIONS", "PATCH"];
+
+fn method_label(method: &str) -> &str {
+  if METHODS.contains(&method) { method } else { "other" }
+}
+
+pub fn record_request(path: &str, method: &str, status: u16, elapsed: Duration) {
+  let tickers: Vec<&str> = crate::chain::all().iter().map(|chain| chain.ticker.as_str()).collect();
+  let (route, chain) = route_label(path, &tickers);
+  increment(REQUESTS, &[("route", &route), ("chain", &chain), ("method", method_label(method)),
+    ("status", &status.to_string())]);
+  observe(REQUEST_DURATION, &[("route", &route), ("chain", &chain)], LATENCY_BUCKETS, elapsed.as_secs_f64());
+}
+
+// the key without its chain prefix, e.g. `KSMvalidDetailAll` is `validDetailAll`
+pub fn key_family(key: &str) -> &'static str {
+  if key.ends_with("validDetailAll") {
+    "validDetailAll"
+  } else if key.contains("onekv") {
+    "onekv"
+  } else if key.ends_with("nominators") {
+    "nominators"
+  } else if key.ends_with("Era") {
+    "Era"
+  } else {
+    "other"
+  }
+}
+
+pub fn record_cache_read<T, E>(key: &str, result: &Result<Option<T>, E>) {
+  let result = match result {
+    Ok(Some(_)) => "hit",
+    Ok(None) => "miss",
+    Err(_) => "error",
+  };
+  increment(CACHE_REQUESTS, &[("family", key_family(key)), ("result", result)]);
+}
+
+pub fn record_rewards_job(elapsed: Duration, failed: bool) {
+  let status = if failed { "failed" } else { "done" };
+  observe(REWARDS_JOB_DURATION, &[("status", status)], JOB_BUCKETS, elapsed.as_secs_f64());
+  if failed {
+    increment(REWARDS_JOB_FAILURES, &[]);
+  }
+}
+
+pub fn record_scheduler_success(job: &str, chain: &str) {
+  set(SCHEDULER_LAST_SUCCESS, &[("job", job), ("chain", chain)], chrono::Utc::now().timestamp() as f64);
+}
+
+// Unix time of the last successful run of a scheduler job, None before the first one
+pub fn scheduler_last_success(job: &str, chain: &str) -> Option<i64> {
+  REGISTRY.lock().unwrap().gauges.get(&series(SCHEDULER_LAST_SUCCESS, &[("job", job), ("chain", chain)]))
+    .map(|timestamp| *timestamp as i64)
+}
+
+pub fn record_rate_limited(class: &str) {
+  increment(RATE_LIMITED, &[("class", class)]);
+}
+
+pub fn record_mongo_command(collection: &str, command: &str, elapsed: Duration) {
+  observe(MONGO_DURATION, &[("collection", collection), ("command", command)], LATENCY_BUCKETS, elapsed.as_secs_f64());
+}
+
+fn write_labels(out: &mut String, labels: &[(&'static str, String)], extra: Option<(&str, String)>) {
+  let mut pairs: Vec<String> = labels.iter()
+    .map(|(k, v)| format!("{}=\"{}\"", k, v.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")))
+    .collect();
+  if let Some((k, v)) = extra {
+    pairs.push(format!("{}=\"{}\"", k, v));
+  }
+  if !pairs.is_empty() {
+    let _ = write!(out, "{{{}}}", pairs.join(","));
+  }
+}
+
+pub fn render() -> String {
+  let registry = REGISTRY.lock().unwrap();
+  let mut out = String::new();
+  for (name, kind, help) in FAMILIES {
+    let _ = writeln!(out, "# HELP {} {}\n# TYPE {} {}", name, help, name, kind);
+    let values = match *kind {
+      "counter" => Some(&registry.counters),
+      "gauge" => Some(&registry.gauges),
+      _ => None,
+    };
+    if let Some(values) = values {
+      for ((_, labels), value) in values.iter().filter(|((n, _), _)| n == name) {
+        out.push_str(name);
+        write_labels(&mut out, labels, None);
+        let _ = writeln!(out, " {}", value);
+      }
+      continue;
+    }
+    for ((_, labels), histogram) in registry.histograms.iter().filter(|((n, _), _)| n == name) {
+      let mut cumulative = 0;
+      for (bound, count) in histogram.buckets.iter().zip(histogram.counts.iter()) {
+        cumulative += count;
+        let _ = write!(out, "{}_bucket", name);
+        write_labels(&mut out, labels, Some(("le", bound.to_string())));
+        let _ = writeln!(out, " {}", cumulative);
+      }
+      let _ = write!(out, "{}_bucket", name);
+      write_labels(&mut out, labels, Some(("le", "+Inf".to_string())));
+      let _ = writeln!(out, " {}", histogram.count);
+      let _ = write!(out, "{}_sum", name);
+      write_labels(&mut out, labels, None);
+      let _ = writeln!(out, " {}", histogram.sum);
+      let _ = write!(out, "{}_count", name);
+      write_labels(&mut out, labels, None);
+      let _ = writeln!(out, " {}", histogram.count);
+    }
+  }
+  out
+}
+
+#[test]
+fn test_route_label() {
+  let stash = "HNZata7iMYWmk5RvZRTiAsSDhV8366zq2YGb3tLH5Upf74F";
+  let tickers = ["KSM", "DOT"];
+  assert_eq!(("/api/v1/validator/{stash}/risk/{chain}".to_string(), "KSM".to_string()),
+    route_label(&format!("/api/v1/validator/{}/risk/KSM", stash), &tickers));
+  assert_eq!(("/api/v1/validator/{stash}/{chain}".to_string(), "KSM".to_string()),
+    route_label(&format!("/api/v1/validator/{}/KSM", stash), &tickers));
+  assert_eq!(("/api/v1/rewards/jobs/{jobId}".to_string(), String::new()),
+    route_label("/api/v1/rewards/jobs/Xk2a9fLw0pQz7RtYb3nVc8sDe1uGh4Ji", &tickers));
+  assert_eq!(("/api/v1/strategy/{chain}/{strategy}".to_string(), "DOT".to_string()),
+    route_label("/api/v1/strategy/DOT/default", &tickers));
+  assert_eq!(("/api/v1/newsletter/data".to_string(), String::new()), route_label("/api/v1/newsletter/data", &tickers));
+  assert_eq!(("/api/dot/stash/{stash}/rewards/collector".to_string(), String::new()),
+    route_label(&format!("/api/dot/stash/{}/rewards/collector", stash), &tickers));
+  // whatever the status, e.g. a 429 answered before the routes
+  assert_eq!(("unmatched".to_string(), String::new()), route_label("/api/v1/whatever/you/like", &tickers));
+  assert_eq!(("unmatched".to_string(), String::new()), route_label("/api/x7", &tickers));
+  assert_eq!(("unmatched".to_string(), String::new()), route_label("/api/v1/validators/WND", &tickers));
+  assert_eq!(("static".to_string(), String::new()), route_label("/tools/ksmVN", &tickers));
+}
+
+#[test]
+fn test_key_family() {
+  assert_eq!("validDetailAll", key_family("KSMvalidDetailAll"));
+  assert_eq!("onekv", key_family("DOTonekv_timestamp"));
+  assert_eq!("onekv", key_family("KSMonekvNominators"));
+  assert_eq!("nominators", key_family("KSMnominators"));
+  assert_eq!("Era", key_family("WNDEra"));
+}
+
+#[test]
+fn test_render() {
+  record_request("/api/v1/rewards/jobs/render", "GET", 200, Duration::from_millis(20));
+  record_request("/api/v1/rewards/jobs/render", "GET", 200, Duration::from_millis(200));
+  record_request("/api/v1/rewards/jobs/render", "BREW", 404, Duration::from_millis(1));
+  record_cache_read::<String, ()>("WNDnominators", &Ok(None));
+  record_scheduler_success("test", "WND");
+  let text = render();
+  assert!(text.contains("# TYPE cryptolab_http_requests_total counter\n"));
+  assert!(text.contains("cryptolab_http_requests_total{route=\"/api/v1/rewards/jobs/{jobId}\",chain=\"\",method=\"GET\",status=\"200\"} 2\n"));
+  assert!(text.contains("cryptolab_http_requests_total{route=\"/api/v1/rewards/jobs/{jobId}\",chain=\"\",method=\"other\",status=\"404\"} 1\n"));
+  let labels = "route=\"/api/v1/rewards/jobs/{jobId}\",chain=\"\"";
+  assert!(text.contains(&format!("cryptolab_http_request_duration_seconds_bucket{{{},le=\"0.01\"}} 1\n", labels)));
+  assert!(text.contains(&format!("cryptolab_http_request_duration_seconds_bucket{{{},le=\"0.025\"}} 2\n", labels)));
+  assert!(text.contains(&format!("cryptolab_http_request_duration_seconds_bucket{{{},le=\"+Inf\"}} 3\n", labels)));
+  assert!(text.contains(&format!("cryptolab_http_request_duration_seconds_count{{{}}} 3\n", labels)));
+  assert!(text.contains("cryptolab_redis_requests_total{family=\"nominators\",result=\"miss\"}"));
+  assert!(text.contains("cryptolab_scheduler_last_success_timestamp_seconds{job=\"test\",chain=\"WND\"}"));
+}
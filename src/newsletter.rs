@@ -0,0 +1,372 @@
+use std::fmt;
+
+use hmac::{Hmac, Mac};
+use rand::{Rng, distributions::Alphanumeric, thread_rng};
+use sha2::Sha256;
+use tracing::error;
+
+use crate::chain::{self, Chain};
+use crate::config::EmailConfig;
+use crate::db::DatabaseError;
+use crate::db::repo::{self, Repository};
+use crate::email::{self, Email, EmailError, Mailer};
+use crate::types::{NewsletterSubscriber, StakingEvents};
+
+const CONFIRM_TEMPLATE: &str = include_str!("../templates/email/confirm.txt");
+const DIGEST_TEMPLATE: &str = include_str!("../templates/email/digest.txt");
+const DATA_TEMPLATE: &str = include_str!("../templates/email/data.txt");
+
+// a confirmation link is valid for 7 days, a data link for a day and an unsubscribe link forever
+pub const CONFIRM_TOKEN_TTL_SECS: i64 = 7 * 24 * 3600;
+pub const DATA_TOKEN_TTL_SECS: i64 = 24 * 3600;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TokenPurpose {
+  Confirm,
+  Unsubscribe,
+  // export or deletion of the data of the address
+  Data,
+}
+
+impl TokenPurpose {
+  fn as_str(self) -> &'static str {
+    match self {
+      TokenPurpose::Confirm => "confirm",
+      TokenPurpose::Unsubscribe => "unsubscribe",
+      TokenPurpose::Data => "data",
+    }
+  }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum TokenError {
+  Malformed,
+  BadSignature,
+  // a confirmation token used to unsubscribe, or the other way around
+  WrongPurpose,
+  Expired,
+}
+
+impl fmt::Display for TokenError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      TokenError::Malformed => write!(f, "Token error: malformed token"),
+      TokenError::BadSignature => write!(f, "Token error: invalid signature"),
+      TokenError::WrongPurpose => write!(f, "Token error: the token is meant for another link"),
+      TokenError::Expired => write!(f, "Token error: the token expired"),
+    }
+  }
+}
+
+#[derive(Debug)]
+pub enum NewsletterError {
+  Database(DatabaseError),
+  Email(EmailError),
+}
+
+impl fmt::Display for NewsletterError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      NewsletterError::Database(e) => write!(f, "{}", e),
+      NewsletterError::Email(e) => write!(f, "{}", e),
+    }
+  }
+}
+
+impl From<DatabaseError> for NewsletterError {
+  fn from(err: DatabaseError) -> Self {
+    NewsletterError::Database(err)
+  }
+}
+
+impl From<EmailError> for NewsletterError {
+  fn from(err: EmailError) -> Self {
+    NewsletterError::Email(err)
+  }
+}
+
+#[derive(Clone)]
+pub struct Newsletter {
+  mailer: Mailer,
+  // public address of the server, without a trailing slash
+  base_url: String,
+  secret: String,
+}
+
+impl Newsletter {
+  pub fn new(mailer: Mailer, base_url: &str, secret: &str) -> Self {
+    Newsletter {
+      mailer,
+      base_url: base_url.trim_end_matches('/').to_string(),
+      secret: secret.to_string(),
+    }
+  }
+
+  // Without an email configuration the emails are only logged, and the tokens are signed with a key which does not
+  // survive a restart.
+  pub fn from_config(config: Option<&EmailConfig>) -> Result<Self, EmailError> {
+    match config {
+      Some(config) => {
+        if config.token_secret.is_empty() {
+          return Err(EmailError::Invalid("token_secret is missing".to_string()));
+        }
+        Ok(Newsletter::new(Mailer::new(config)?, &config.base_url, &config.token_secret))
+      },
+      None => {
+        let secret: String = thread_rng().sample_iter(&Alphanumeric).take(32).map(char::from).collect();
+        Ok(Newsletter::new(Mailer::log(), "http://localhost", &secret))
+      },
+    }
+  }
+
+  #[cfg(test)]
+  pub fn mailer(&self) -> &Mailer {
+    &self.mailer
+  }
+
+  fn signature(&self, payload: &[u8]) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(self.secret.as_bytes()).expect("HMAC takes keys of any size");
+    mac.update(payload);
+    mac
+  }
+
+  // `purpose:expires:email` and its HMAC-SHA256, both base58 encoded and joined by a dot
+  pub fn token(&self, purpose: TokenPurpose, email: &str) -> String {
+    let expires = match purpose {
+      TokenPurpose::Confirm => chrono::Utc::now().timestamp() + CONFIRM_TOKEN_TTL_SECS,
+      TokenPurpose::Unsubscribe => 0,
+      TokenPurpose::Data => chrono::Utc::now().timestamp() + DATA_TOKEN_TTL_SECS,
+    };
+    self.token_expiring(purpose, email, expires)
+  }
+
+  fn token_expiring(&self, purpose: TokenPurpose, email: &str, expires: i64) -> String {
+    let payload = format!("{}:{}:{}", purpose.as_str(), expires, email);
+    let signature = self.signature(payload.as_bytes()).finalize().into_bytes();
+    format!("{}.{}", bs58::encode(payload).into_string(), bs58::encode(signature).into_string())
+  }
+
+  // returns the email address the token was made for
+  pub fn verify(&self, purpose: TokenPurpose, token: &str) -> Result<String, TokenError> {
+    let (payload, signature) = token.split_once('.').ok_or(TokenError::Malformed)?;
+    let payload = bs58::decode(payload).into_vec().map_err(|_| TokenError::Malformed)?;
+    let signature = bs58::decode(signature).into_vec().map_err(|_| TokenError::Malformed)?;
+    self.signature(&payload).verify_slice(&signature).map_err(|_| TokenError::BadSignature)?;
+    let payload = String::from_utf8(payload).map_err(|_| TokenError::Malformed)?;
+    let mut parts = payload.splitn(3, ':');
+    let (kind, expires, email) = match (parts.next(), parts.next(), parts.next()) {
+      (Some(kind), Some(expires), Some(email)) => (kind, expires, email),
+      _ => return Err(TokenError::Malformed),
+    };
+    if kind != purpose.as_str() {
+      return Err(TokenError::WrongPurpose);
+    }
+    let expires: i64 = expires.parse().map_err(|_| TokenError::Malformed)?;
+    if expires > 0 && expires < chrono::Utc::now().timestamp() {
+      return Err(TokenError::Expired);
+    }
+    Ok(email.to_string())
+  }
+
+  fn link(&self, purpose: TokenPurpose, email: &str) -> String {
+    format!("{}/api/v1/newsletter/{}?token={}", self.base_url, purpose.as_str(), self.token(purpose, email))
+  }
+
+  pub async fn send_confirmation(&self, email: &str) -> Result<(), EmailError> {
+    let (subject, body) = email::render(CONFIRM_TEMPLATE, &[
+      ("email", email.to_string()),
+      ("confirm_url", self.link(TokenPurpose::Confirm, email)),
+    ]);
+    self.mailer.send(Email {
+      to: email.to_string(),
+      subject,
+      body,
+      unsubscribe_url: None,
+    }).await
+  }
+
+  // the link to the data held about the address, which only its owner receives
+  pub async fn send_data_link(&self, email: &str) -> Result<(), EmailError> {
+    let (subject, body) = email::render(DATA_TEMPLATE, &[
+      ("email", email.to_string()),
+      ("export_url", self.link(TokenPurpose::Data, email)),
+    ]);
+    self.mailer.send(Email {
+      to: email.to_string(),
+      subject,
+      body,
+      unsubscribe_url: None,
+    }).await
+  }
+
+  // Sends the events of the subscriber's stashes on `chain` since the last digest, up to `era`. The first digest of a
+  // chain only covers `era`. The era is claimed before the email is sent, so that one instance of the server sends
+  // it, and given back when the email cannot be sent. Returns whether an email was sent.
+  pub async fn send_digest(&self, db: &dyn Repository, user_db: &dyn Repository, chain: &Chain,
+    subscriber: &NewsletterSubscriber, era: u32) -> Result<bool, NewsletterError> {
+    let last_era = subscriber.digest_eras.get(&chain.ticker).copied();
+    if last_era.is_some_and(|last_era| era <= last_era) {
+      return Ok(false);
+    }
+    if !user_db.swap_newsletter_digest_era(&subscriber.email, &chain.ticker, last_era, Some(era)).await? {
+      return Ok(false);
+    }
+    let from_era = last_era.map(|last_era| last_era + 1).unwrap_or(era);
+    let sent = self.send_events(db, user_db, chain, subscriber, from_era, era).await;
+    if sent.is_err() {
+      if let Err(e) = user_db.swap_newsletter_digest_era(&subscriber.email, &chain.ticker, Some(era), last_era).await {
+        error!("failed to give back the {} digest era of {}: {}", chain.ticker, subscriber.email, e);
+      }
+    }
+    sent
+  }
+
+  async fn send_events(&self, db: &dyn Repository, user_db: &dyn Repository, chain: &Chain,
+    subscriber: &NewsletterSubscriber, from_era: u32, era: u32) -> Result<bool, NewsletterError> {
+    let mut sections = vec![];
+    for stash in subscriber.stashes.iter().filter(|s| chain::ss58_prefix(s) == Some(chain.ss58_prefix)) {
+      let events = match repo::get_nominator_events(db, user_db, stash, from_era, era).await {
+        Ok(events) => events,
+        // the stash no longer nominates
+        Err(DatabaseError::NotFound { .. }) => continue,
+        Err(e) => return Err(e.into()),
+      };
+      let lines = describe(&events);
+      if !lines.is_empty() {
+        sections.push(format!("{}\n{}", stash, lines.join("\n")));
+      }
+    }
+    if sections.is_empty() {
+      return Ok(false);
+    }
+    let unsubscribe_url = self.link(TokenPurpose::Unsubscribe, &subscriber.email);
+    let (subject, body) = email::render(DIGEST_TEMPLATE, &[
+      ("chain", chain.ticker.clone()),
+      ("from_era", from_era.to_string()),
+      ("to_era", era.to_string()),
+      ("events", sections.join("\n\n")),
+      ("base_url", self.base_url.clone()),
+      ("unsubscribe_url", unsubscribe_url.clone()),
+    ]);
+    self.mailer.send(Email {
+      to: subscriber.email.clone(),
+      subject,
+      body,
+      unsubscribe_url: Some(unsubscribe_url),
+    }).await?;
+    Ok(true)
+  }
+}
+
+// one line per event, ordered by era
+fn describe(events: &StakingEvents) -> Vec<String> {
+  let mut lines: Vec<(u32, String)> = vec![];
+  for e in events.commissions.iter() {
+    lines.push((e.era, format!("validator {} changed its commission from {}% to {}%", e.address, e.commission_from,
+      e.commission_to)));
+  }
+  for e in events.slashes.iter() {
+    lines.push((e.era, format!("validator {} was slashed", e.address)));
+  }
+  for era in events.inactive.iter() {
+    lines.push((*era, "none of the nominated validators was active".to_string()));
+  }
+  for e in events.stale_payouts.iter() {
+    lines.push((e.era, format!("validator {} has unclaimed payouts", e.address)));
+  }
+  for e in events.payouts.iter() {
+    lines.push((e.era.max(0) as u32, format!("received a payout of {}", e.amount)));
+  }
+  for e in events.kicks.iter() {
+    lines.push((e.era, format!("validator {} removed the nomination", e.address)));
+  }
+  for e in events.chills.iter() {
+    lines.push((e.era, format!("validator {} was chilled", e.address)));
+  }
+  for e in events.over_subscribes.iter() {
+    lines.push((e.era, format!("validator {} was oversubscribed, {} was not rewarded", e.address, e.amount)));
+  }
+  lines.sort_by_key(|(era, _)| *era);
+  lines.into_iter().map(|(era, line)| format!("  era {}: {}", era, line)).collect()
+}
+
+#[cfg(test)]
+fn test_newsletter() -> Newsletter {
+  Newsletter::new(Mailer::memory(), "https://cryptolab.network/", "secret")
+}
+
+#[test]
+fn test_tokens() {
+  let newsletter = test_newsletter();
+  let token = newsletter.token(TokenPurpose::Confirm, "a:b@cryptolab.network");
+  assert_eq!(Ok("a:b@cryptolab.network".to_string()), newsletter.verify(TokenPurpose::Confirm, &token));
+  assert_eq!(Err(TokenError::WrongPurpose), newsletter.verify(TokenPurpose::Unsubscribe, &token));
+  let other = Newsletter::new(Mailer::memory(), "https://cryptolab.network", "other secret");
+  assert_eq!(Err(TokenError::BadSignature), other.verify(TokenPurpose::Confirm, &token));
+  let (payload, signature) = token.split_once('.').unwrap();
+  let tampered = format!("{}.{}", bs58::encode("confirm:0:c@cryptolab.network").into_string(), signature);
+  assert_eq!(Err(TokenError::BadSignature), newsletter.verify(TokenPurpose::Confirm, &tampered));
+  assert_eq!(Err(TokenError::Malformed), newsletter.verify(TokenPurpose::Confirm, payload));
+  assert_eq!(Err(TokenError::Malformed), newsletter.verify(TokenPurpose::Confirm, "0OIl.0OIl"));
+
+  let expired = newsletter.token_expiring(TokenPurpose::Confirm, "a@cryptolab.network", chrono::Utc::now().timestamp() - 1);
+  assert_eq!(Err(TokenError::Expired), newsletter.verify(TokenPurpose::Confirm, &expired));
+  let unsubscribe = newsletter.token(TokenPurpose::Unsubscribe, "a@cryptolab.network");
+  assert_eq!(Ok("a@cryptolab.network".to_string()), newsletter.verify(TokenPurpose::Unsubscribe, &unsubscribe));
+}
+
+#[tokio::test]
+async fn test_send_confirmation() {
+  let newsletter = test_newsletter();
+  newsletter.send_confirmation("a@cryptolab.network").await.unwrap();
+  let sent = newsletter.mailer().sent();
+  assert_eq!(1, sent.len());
+  assert_eq!("a@cryptolab.network", sent[0].to);
+  let link = sent[0].body.lines().find(|l| l.starts_with("https://cryptolab.network/api/v1/newsletter/confirm?token="))
+    .unwrap();
+  let token = link.split_once("token=").unwrap().1;
+  assert_eq!(Ok("a@cryptolab.network".to_string()), newsletter.verify(TokenPurpose::Confirm, token));
+}
+
+#[tokio::test]
+async fn test_send_digest() {
+  use crate::db::repo::UserActionRepo;
+  let repo = crate::db::memory::MemoryRepo::kusama();
+  let newsletter = test_newsletter();
+  let kusama = crate::db::memory::kusama_chain();
+  let subscriber = repo.get_newsletter_subscriber("subscriber@cryptolab.network").await.unwrap();
+
+  assert!(newsletter.send_digest(&repo, &repo, &kusama, &subscriber, 3001).await.unwrap());
+  let sent = newsletter.mailer().sent();
+  assert_eq!(1, sent.len());
+  assert_eq!("KSM staking events up to era 3001", sent[0].subject);
+  assert!(sent[0].body.contains("HNZata7iMYWmk5RvZRTiAsSDhV8366zq2YGb3tLH5Upf74F\n  era 3001: validator"));
+  let unsubscribe_url = sent[0].unsubscribe_url.clone().unwrap();
+  assert!(sent[0].body.contains(&unsubscribe_url));
+  let token = unsubscribe_url.split_once("token=").unwrap().1;
+  assert_eq!(Ok(subscriber.email.clone()), newsletter.verify(TokenPurpose::Unsubscribe, token));
+
+  // the era is recorded, a second run has nothing to send
+  let subscriber = repo.get_newsletter_subscriber("subscriber@cryptolab.network").await.unwrap();
+  assert_eq!(Some(&3001), subscriber.digest_eras.get("KSM"));
+  assert!(!newsletter.send_digest(&repo, &repo, &kusama, &subscriber, 3001).await.unwrap());
+  assert_eq!(1, newsletter.mailer().sent().len());
+
+  // another instance which read the subscriber before the era was claimed does not send it again
+  let mut stale = subscriber.clone();
+  stale.digest_eras.clear();
+  assert!(!newsletter.send_digest(&repo, &repo, &kusama, &stale, 3001).await.unwrap());
+  assert_eq!(1, newsletter.mailer().sent().len());
+
+  // an email which cannot be sent gives the era back, for the next run
+  assert!(repo.swap_newsletter_digest_era(&subscriber.email, "KSM", Some(3001), None).await.unwrap());
+  let failing = Newsletter::new(Mailer::new(&EmailConfig {
+    transport: "file".to_string(),
+    file_folder: Some("/nonexistent/cryptolab-mail".to_string()),
+    from: "CryptoLab <newsletter@cryptolab.network>".to_string(),
+    ..Default::default()
+  }).unwrap(), "https://cryptolab.network", "secret");
+  assert!(failing.send_digest(&repo, &repo, &kusama, &stale, 3001).await.is_err());
+  let subscriber = repo.get_newsletter_subscriber("subscriber@cryptolab.network").await.unwrap();
+  assert!(subscriber.digest_eras.is_empty());
+}
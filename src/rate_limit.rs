@@ -0,0 +1,182 @@
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+
+use crate::config::{RateLimit, RateLimitConfig};
+
+// Requests are limited by client address and route class with token buckets: a bucket holds up to `burst` tokens, a
+// request takes one and `per_minute` tokens are added back each minute. The buckets are kept in the cache backend so
+// that the instances behind a load balancer share them, see `CacheBackend::take_token`.
+
+const DEFAULT_COLLECTOR: RateLimit = RateLimit { burst: 5, per_minute: 2 };
+const DEFAULT_WRITE: RateLimit = RateLimit { burst: 20, per_minute: 10 };
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RouteClass {
+  Collector,
+  Write,
+  Read,
+}
+
+impl RouteClass {
+  pub fn name(self) -> &'static str {
+    match self {
+      RouteClass::Collector => "collector",
+      RouteClass::Write => "write",
+      RouteClass::Read => "read",
+    }
+  }
+}
+
+// the class of an API request, None for the other paths (static files, health checks and metrics)
+pub fn classify(method: &str, path: &str) -> Option<RouteClass> {
+  if !path.starts_with("/api/") {
+    None
+  } else if path.trim_end_matches('/').ends_with("/rewards/collector") {
+    Some(RouteClass::Collector)
+  } else if ["GET", "HEAD", "OPTIONS"].contains(&method) {
+    Some(RouteClass::Read)
+  } else {
+    Some(RouteClass::Write)
+  }
+}
+
+// The address of the client: the peer, or the last address of X-Forwarded-For which is not a trusted proxy when the
+// peer is one. The addresses before it could be set by the client.
+pub fn client_ip(remote: Option<IpAddr>, forwarded_for: Option<&str>, trusted_proxies: &[IpAddr]) -> Option<IpAddr> {
+  let mut client = remote?;
+  if !trusted_proxies.contains(&client) {
+    return Some(client);
+  }
+  for hop in forwarded_for.unwrap_or_default().rsplit(',').map(str::trim) {
+    match hop.parse::<IpAddr>().or_else(|_| hop.parse::<SocketAddr>().map(|addr| addr.ip())) {
+      Ok(ip) => client = ip,
+      Err(_) => break,
+    }
+    if !trusted_proxies.contains(&client) {
+      break;
+    }
+  }
+  Some(client)
+}
+
+impl RateLimitConfig {
+  pub fn is_enabled(&self) -> bool {
+    self.enabled.unwrap_or(true)
+  }
+
+  // the limit of a class, None when it is not limited
+  pub fn limit(&self, class: RouteClass) -> Option<RateLimit> {
+    match class {
+      RouteClass::Collector => Some(self.collector.unwrap_or(DEFAULT_COLLECTOR)),
+      RouteClass::Write => Some(self.write.unwrap_or(DEFAULT_WRITE)),
+      RouteClass::Read => self.read,
+    }
+  }
+}
+
+// The cache key of the bucket of a client for a class. An IPv6 client usually holds a whole /64, which is limited as
+// one client; IPv4 addresses mapped to IPv6 are limited as the IPv4 address.
+pub fn bucket_key(class: RouteClass, ip: IpAddr) -> String {
+  let client = match ip {
+    IpAddr::V6(v6) => match v6.to_ipv4_mapped() {
+      Some(v4) => v4.to_string(),
+      None => {
+        let s = v6.segments();
+        format!("{}/64", Ipv6Addr::new(s[0], s[1], s[2], s[3], 0, 0, 0, 0))
+      },
+    },
+    IpAddr::V4(v4) => v4.to_string(),
+  };
+  format!("rateLimit:{}:{}", class.name(), client)
+}
+
+// The tokens of a bucket when it was last counted. `cache_redis::TAKE_TOKEN` is the same computation in Lua, both
+// have to be changed together.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bucket {
+  pub tokens: f64,
+  pub updated_ms: u64,
+}
+
+impl Bucket {
+  // takes a token at `now_ms` from `bucket`, a full one when None; returns the bucket afterwards and, when it was
+  // empty, the wait in milliseconds before the next token
+  pub fn take(bucket: Option<Bucket>, limit: &RateLimit, now_ms: u64) -> (Bucket, Option<u64>) {
+    let per_ms = limit.per_minute as f64 / 60_000.0;
+    let burst = limit.burst as f64;
+    let tokens = match bucket {
+      Some(bucket) => burst.min(bucket.tokens + now_ms.saturating_sub(bucket.updated_ms) as f64 * per_ms),
+      None => burst,
+    };
+    if tokens >= 1.0 {
+      (Bucket { tokens: tokens - 1.0, updated_ms: now_ms }, None)
+    } else {
+      (Bucket { tokens, updated_ms: now_ms }, Some(((1.0 - tokens) / per_ms).ceil() as u64))
+    }
+  }
+}
+
+// after this many milliseconds without requests a bucket is full again and can be dropped
+pub fn refill_ms(limit: &RateLimit) -> u64 {
+  (limit.burst as u64 * 60_000).div_ceil(limit.per_minute.max(1) as u64)
+}
+
+#[test]
+fn test_classify() {
+  assert_eq!(Some(RouteClass::Collector), classify("GET", "/api/v1/stash/1abc/rewards/collector"));
+  assert_eq!(Some(RouteClass::Collector), classify("POST", "/api/v1/stash/1abc/rewards/collector"));
+  assert_eq!(Some(RouteClass::Collector), classify("GET", "/api/stash/1abc/rewards/collector/"));
+  // the stored reports do not run the collector
+  assert_eq!(Some(RouteClass::Read), classify("GET", "/api/v1/stash/1abc/rewards/collector/csv"));
+  assert_eq!(Some(RouteClass::Write), classify("POST", "/api/v1/nominate"));
+  assert_eq!(Some(RouteClass::Write), classify("DELETE", "/api/v1/newsletter/a@b.c"));
+  assert_eq!(Some(RouteClass::Read), classify("GET", "/api/v1/validators/KSM"));
+  assert_eq!(None, classify("GET", "/healthz"));
+  assert_eq!(None, classify("GET", "/tools/ksmVN"));
+}
+
+#[test]
+fn test_client_ip() {
+  let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+  let proxies = [ip("10.0.0.1"), ip("10.0.0.2")];
+  assert_eq!(Some(ip("1.2.3.4")), client_ip(Some(ip("1.2.3.4")), Some("5.6.7.8"), &proxies));
+  assert_eq!(Some(ip("5.6.7.8")), client_ip(Some(ip("10.0.0.1")), Some("9.9.9.9, 5.6.7.8, 10.0.0.2"), &proxies));
+  assert_eq!(Some(ip("5.6.7.8")), client_ip(Some(ip("10.0.0.1")), Some("5.6.7.8:4000"), &proxies));
+  assert_eq!(Some(ip("10.0.0.1")), client_ip(Some(ip("10.0.0.1")), None, &proxies));
+  // a forged entry ends the chain
+  assert_eq!(Some(ip("10.0.0.2")), client_ip(Some(ip("10.0.0.1")), Some("5.6.7.8, unknown, 10.0.0.2"), &proxies));
+  assert_eq!(None, client_ip(None, Some("5.6.7.8"), &proxies));
+}
+
+#[test]
+fn test_bucket_key() {
+  let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+  assert_eq!("rateLimit:read:1.2.3.4", bucket_key(RouteClass::Read, ip("1.2.3.4")));
+  assert_eq!("rateLimit:read:1.2.3.4", bucket_key(RouteClass::Read, ip("::ffff:1.2.3.4")));
+  assert_eq!("rateLimit:write:2001:db8:1:2::/64", bucket_key(RouteClass::Write, ip("2001:db8:1:2:aaaa::1")));
+  assert_eq!(bucket_key(RouteClass::Write, ip("2001:db8:1:2::1")), bucket_key(RouteClass::Write, ip("2001:db8:1:2:ffff::2")));
+  assert_ne!(bucket_key(RouteClass::Write, ip("2001:db8:1:2::1")), bucket_key(RouteClass::Write, ip("2001:db8:1:3::1")));
+}
+
+#[test]
+fn test_bucket() {
+  let limit = RateLimit { burst: 2, per_minute: 6 };
+  let (bucket, wait) = Bucket::take(None, &limit, 1_000);
+  assert_eq!((Bucket { tokens: 1.0, updated_ms: 1_000 }, None), (bucket, wait));
+  let (bucket, wait) = Bucket::take(Some(bucket), &limit, 1_000);
+  assert_eq!(None, wait);
+  let (bucket, wait) = Bucket::take(Some(bucket), &limit, 6_000);
+  // half a token after 5 seconds, the other half in 5 more
+  assert_eq!(Some(5_000), wait);
+  assert_eq!(0.5, bucket.tokens);
+  let (_, wait) = Bucket::take(Some(bucket), &limit, 11_000);
+  assert_eq!(None, wait);
+  // a bucket does not fill over its burst
+  let (bucket, _) = Bucket::take(Some(Bucket { tokens: 0.0, updated_ms: 0 }), &limit, 3_600_000);
+  assert_eq!(1.0, bucket.tokens);
+  assert_eq!(20_000, refill_ms(&limit));
+
+  let config = RateLimitConfig { read: Some(limit), ..Default::default() };
+  assert_eq!(Some(DEFAULT_COLLECTOR), config.limit(RouteClass::Collector));
+  assert_eq!(Some(limit), config.limit(RouteClass::Read));
+  assert_eq!(None, RateLimitConfig::default().limit(RouteClass::Read));
+}
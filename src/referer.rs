@@ -1,5 +1,5 @@
 use chrono::Utc;
-use log::{error, info};
+use tracing::error;
 use rand::{Rng, distributions::Alphanumeric, thread_rng};
 
 use crate::{db::params::DbRefKeyOptions};
@@ -21,29 +21,43 @@ pub fn gen_ref_key(stash: &str) -> String {
     .map(char::from)
     .collect();
   let ref_key = format!("{}|{}|{}", stash, timestamp, rand_string);
-  let encoded = bs58::encode(ref_key).into_string();
-  encoded
+  bs58::encode(ref_key).into_string()
 }
 
 pub fn decrypt_ref_key(ref_key: &str) -> Result<DbRefKeyOptions, RefKeyError> {
-  let vec = bs58::decode(ref_key).into_vec();
-  match vec {
-      Ok(vec) => {
-        let raw = std::str::from_utf8(&vec).unwrap();
-        let tokens: Vec<&str> = raw.split('|').collect();
-        let timestamp = str::parse::<u32>(tokens[1]);
-        Ok(DbRefKeyOptions {
-          stash: tokens[0].to_string(),
-          ref_key: ref_key.to_string(),
-          timestamp: timestamp.unwrap(),
-          rand: tokens[2].to_string(),
-        })
-      },
-      Err(err) => {
-        error!("{}", err);
-        Err(RefKeyError {
-          repr: ErrorRepr::IncorrectRefKey(err.to_string())
-        })
-      },
+  let incorrect = |message: String| {
+    error!("{}", message);
+    RefKeyError {
+      repr: ErrorRepr::IncorrectRefKey(message)
+    }
+  };
+  let vec = bs58::decode(ref_key).into_vec().map_err(|e| incorrect(e.to_string()))?;
+  let raw = std::str::from_utf8(&vec).map_err(|e| incorrect(e.to_string()))?;
+  let tokens: Vec<&str> = raw.split('|').collect();
+  if tokens.len() != 3 {
+    return Err(incorrect(format!("ref key has {} fields", tokens.len())));
   }
-}
\ No newline at end of file
+  let timestamp = str::parse::<u32>(tokens[1]).map_err(|e| incorrect(e.to_string()))?;
+  Ok(DbRefKeyOptions {
+    stash: tokens[0].to_string(),
+    ref_key: ref_key.to_string(),
+    timestamp,
+  })
+}
+
+impl std::fmt::Display for RefKeyError {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    match &self.repr {
+      ErrorRepr::IncorrectRefKey(message) => write!(f, "incorrect ref key: {}", message),
+    }
+  }
+}
+
+#[test]
+fn test_decrypt_ref_key() {
+  let ref_key = gen_ref_key("1ZGzHnrAgt3eKQvtZXgDPE9aduT4UWJH4fUL9HHdfWk6nx3");
+  assert_eq!("1ZGzHnrAgt3eKQvtZXgDPE9aduT4UWJH4fUL9HHdfWk6nx3", decrypt_ref_key(&ref_key).unwrap().stash);
+  assert!(decrypt_ref_key("0OIl").is_err());
+  assert!(decrypt_ref_key(&bs58::encode("stash|notatimestamp|rand").into_string()).is_err());
+  assert!(decrypt_ref_key(&bs58::encode("stash").into_string()).is_err());
+}
@@ -0,0 +1,185 @@
+use std::{collections::HashMap, sync::Arc, time::Instant};
+
+use tracing::{Instrument, error, info};
+use rand::{Rng, distributions::Alphanumeric, thread_rng};
+use tokio::sync::{Semaphore, mpsc};
+
+use crate::chain;
+use crate::config::Config;
+use crate::metrics;
+use crate::db::{Database, DatabaseError};
+use crate::db::rewards_jobs::{RewardsJob, RewardsJobError, RewardsJobStatus};
+use crate::staking_rewards_collector::{SRCError, StakingRewardsCollector};
+use crate::types::StashRewards;
+
+const DEFAULT_JOB_TTL_HOURS: u32 = 24;
+const DEFAULT_JOB_WORKERS: u32 = 4;
+
+fn job_ttl_hours() -> u32 {
+  Config::current().rewards_job_ttl_hours.unwrap_or(DEFAULT_JOB_TTL_HOURS)
+}
+
+fn to_src_error(e: DatabaseError) -> SRCError {
+  SRCError {
+    message: e.to_string(),
+    err_code: -10,
+  }
+}
+
+// What a rewards report request gets: the report once its job is done, the job until then
+pub enum RewardsReport {
+  Done(StashRewards),
+  Pending(Box<RewardsJob>),
+}
+
+// Rewards reports are computed in the background, `rewards_job_workers` at a time. Jobs are kept in the users database,
+// so finished reports can be downloaded later and unfinished jobs are resumed after a restart.
+#[derive(Clone)]
+pub struct RewardsJobQueue {
+  users_db: Database,
+  sender: mpsc::UnboundedSender<String>,
+}
+
+impl RewardsJobQueue {
+  pub fn start(users_db: Database, chain_dbs: HashMap<&'static str, Database>) -> Self {
+    let (sender, receiver) = mpsc::unbounded_channel();
+    let queue = RewardsJobQueue {
+      users_db,
+      sender,
+    };
+    let workers = Config::current().rewards_job_workers.unwrap_or(DEFAULT_JOB_WORKERS).max(1) as usize;
+    tokio::spawn(queue.clone().work(receiver, Arc::new(chain_dbs), workers));
+    queue
+  }
+
+  // false once the dispatcher stopped
+  pub fn is_running(&self) -> bool {
+    !self.sender.is_closed()
+  }
+
+  // Each job runs in its own task, so that a panic fails that job only
+  async fn work(self, mut receiver: mpsc::UnboundedReceiver<String>, chain_dbs: Arc<HashMap<&'static str, Database>>,
+    workers: usize) {
+    match self.users_db.get_unfinished_rewards_jobs().await {
+      Ok(job_ids) => {
+        info!("resuming {} rewards jobs", job_ids.len());
+        for job_id in job_ids {
+          let _ = self.sender.send(job_id);
+        }
+      },
+      Err(e) => error!("failed to resume rewards jobs: {}", e),
+    }
+    let workers = Arc::new(Semaphore::new(workers));
+    while let Some(job_id) = receiver.recv().await {
+      let permit = match workers.clone().acquire_owned().await {
+        Ok(permit) => permit,
+        Err(_) => break,
+      };
+      let queue = self.clone();
+      let chain_dbs = chain_dbs.clone();
+      tokio::spawn(async move {
+        let started = Instant::now();
+        let execution = tokio::spawn({
+          let queue = queue.clone();
+          let job_id = job_id.clone();
+          async move { queue.execute(&job_id, &chain_dbs).await }
+        });
+        if let Err(e) = execution.await {
+          error!("rewards job {} panicked: {}", job_id, e);
+          metrics::record_rewards_job(started.elapsed(), true);
+          let error = RewardsJobError {
+            code: -10,
+            message: "Rewards job failed".to_string(),
+          };
+          let failed = queue.users_db
+            .update_rewards_job(&job_id, RewardsJobStatus::Failed, 100, None, Some(error), job_ttl_hours()).await;
+          if let Err(e) = failed {
+            error!("failed to update rewards job {}: {}", job_id, e);
+          }
+        }
+        drop(permit);
+      });
+    }
+  }
+
+  async fn execute(&self, job_id: &str, chain_dbs: &HashMap<&'static str, Database>) {
+    let job = match self.users_db.get_rewards_job(job_id).await {
+      Ok(job) => job,
+      Err(e) => {
+        error!("failed to get rewards job {}: {}", job_id, e);
+        return;
+      }
+    };
+    if job.status == RewardsJobStatus::Done || job.status == RewardsJobStatus::Failed {
+      return;
+    }
+    let ttl_hours = job_ttl_hours();
+    let started = Instant::now();
+    let _ = self.users_db.update_rewards_job(job_id, RewardsJobStatus::Running, 50, None, None, ttl_hours).await;
+    let result = match chain::find_by_address(&job.stash).and_then(|chain| chain_dbs.get(chain.ticker.as_str())) {
+      Some(db) => job.request.collect(db)
+        .instrument(tracing::info_span!("rewards_job", job_id, stash = job.stash.as_str())).await,
+      None => Err(SRCError {
+        message: "Network is not supported".to_string(),
+        err_code: -8,
+      }),
+    };
+    metrics::record_rewards_job(started.elapsed(), result.is_err());
+    let updated = match result {
+      Ok(rewards) => {
+        self.users_db.update_rewards_job(job_id, RewardsJobStatus::Done, 100, Some(&rewards), None, ttl_hours).await
+      },
+      Err(e) => {
+        error!("rewards job {} failed: {}", job_id, e);
+        let error = RewardsJobError {
+          code: e.err_code,
+          message: e.message,
+        };
+        self.users_db.update_rewards_job(job_id, RewardsJobStatus::Failed, 100, None, Some(error), ttl_hours).await
+      },
+    };
+    if let Err(e) = updated {
+      error!("failed to update rewards job {}: {}", job_id, e);
+    }
+  }
+
+  // returns the existing job if the same report is already queued or done
+  pub async fn submit(&self, request: &StakingRewardsCollector) -> Result<RewardsJob, DatabaseError> {
+    let job_id: String = thread_rng()
+      .sample_iter(&Alphanumeric)
+      .take(32)
+      .map(char::from)
+      .collect();
+    let (job, created) = self.users_db.insert_rewards_job(&job_id, request, job_ttl_hours()).await?;
+    if created {
+      let _ = self.sender.send(job.job_id.clone());
+    }
+    Ok(job)
+  }
+
+  pub async fn get(&self, job_id: &str) -> Result<RewardsJob, DatabaseError> {
+    self.users_db.get_rewards_job(job_id).await
+  }
+
+  // the report of the request if its job is done, otherwise its job, which is queued if it was not yet
+  pub async fn report(&self, request: &StakingRewardsCollector) -> Result<RewardsReport, SRCError> {
+    let job = self.submit(request).await.map_err(to_src_error)?;
+    match job.status {
+      RewardsJobStatus::Done => job.result.map(RewardsReport::Done).ok_or(SRCError {
+        message: "Reward report is not found".to_string(),
+        err_code: -20,
+      }),
+      RewardsJobStatus::Failed => {
+        let error = job.error.unwrap_or(RewardsJobError {
+          code: -10,
+          message: "Rewards job failed".to_string(),
+        });
+        Err(SRCError {
+          message: error.message,
+          err_code: error.code,
+        })
+      },
+      _ => Ok(RewardsReport::Pending(Box::new(job))),
+    }
+  }
+}
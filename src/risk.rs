@@ -0,0 +1,123 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::chain::Chain;
+
+// days of events a score looks back on, the same on every chain so that the scores compare
+pub const RISK_WINDOW_DAYS: u32 = 21;
+
+// eras of `RISK_WINDOW_DAYS` on the chain, the era of the score included, e.g. 84 on Kusama and 21 on Polkadot
+pub fn window_eras(chain: &Chain) -> u32 {
+  (RISK_WINDOW_DAYS * 24 / chain.era_length_hours.max(1)).max(1)
+}
+
+// What a validator did in the window, as counted from the event collections.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Default, PartialEq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct RiskEvents {
+  pub slashes: u32,
+  // commission changes to a higher commission
+  pub commission_increases: u32,
+  // nominators the validator kicked
+  pub kicks: u32,
+  pub chills: u32,
+  // eras since the first era of the validator in the window where it was not elected
+  pub inactive_eras: u32,
+  pub stale_payouts: u32,
+  // eras whose rewards are still unclaimed, the window does not apply
+  pub unclaimed_eras: u32,
+  pub oversubscribed_eras: u32,
+}
+
+// Points of each component, capped at the weight of the component. The caps add up to 100.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Default, PartialEq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct RiskComponents {
+  // 15 per slash, up to 30
+  pub slashes: u32,
+  // 5 per increase, up to 15
+  pub commission_increases: u32,
+  // 2 per kick, up to 10
+  pub kicks: u32,
+  // 5 per chill, up to 10
+  pub chills: u32,
+  // 1 per inactive era, up to 10
+  pub inactivity: u32,
+  // 2 per stale payout, up to 10
+  pub stale_payouts: u32,
+  // 1 per unclaimed era, up to 10
+  pub unclaimed_eras: u32,
+  // 1 per oversubscribed era, up to 5
+  pub oversubscription: u32,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ValidatorRisk {
+  #[serde(alias = "validator")]
+  pub stash: String,
+  // era the score was computed at
+  pub era: u32,
+  // 0 for a validator without any event, 100 at most
+  pub score: u32,
+  pub components: RiskComponents,
+  pub events: RiskEvents,
+}
+
+pub fn score(stash: &str, era: u32, events: RiskEvents) -> ValidatorRisk {
+  let points = |count: u32, per_event: u32, cap: u32| count.saturating_mul(per_event).min(cap);
+  let components = RiskComponents {
+    slashes: points(events.slashes, 15, 30),
+    commission_increases: points(events.commission_increases, 5, 15),
+    kicks: points(events.kicks, 2, 10),
+    chills: points(events.chills, 5, 10),
+    inactivity: points(events.inactive_eras, 1, 10),
+    stale_payouts: points(events.stale_payouts, 2, 10),
+    unclaimed_eras: points(events.unclaimed_eras, 1, 10),
+    oversubscription: points(events.oversubscribed_eras, 1, 5),
+  };
+  ValidatorRisk {
+    stash: stash.to_string(),
+    era,
+    score: components.slashes + components.commission_increases + components.kicks + components.chills
+      + components.inactivity + components.stale_payouts + components.unclaimed_eras + components.oversubscription,
+    components,
+    events,
+  }
+}
+
+#[test]
+fn test_window_eras() {
+  let chains = crate::chain::from_legacy_config(&crate::config::Config::default());
+  // 21 days of 6 hour eras on Kusama, of 24 hour eras on Polkadot
+  assert_eq!(84, window_eras(&chains[0]));
+  assert_eq!(21, window_eras(&chains[1]));
+}
+
+#[test]
+fn test_score() {
+  let risk = score("stash", 3001, RiskEvents::default());
+  assert_eq!(0, risk.score);
+
+  let risk = score("stash", 3001, RiskEvents {
+    slashes: 1,
+    commission_increases: 1,
+    kicks: 2,
+    unclaimed_eras: 3,
+    ..Default::default()
+  });
+  assert_eq!(15, risk.components.slashes);
+  assert_eq!(15 + 5 + 4 + 3, risk.score);
+
+  let risk = score("stash", 3001, RiskEvents {
+    slashes: 5,
+    commission_increases: 5,
+    kicks: 50,
+    chills: 5,
+    inactive_eras: 84,
+    stale_payouts: 10,
+    unclaimed_eras: 84,
+    oversubscribed_eras: 84,
+  });
+  assert_eq!(100, risk.score);
+}
@@ -1,46 +1,160 @@
 use std::{env, time::Duration};
 
-use crate::{cache_redis::Cache, config::Config, db::Database};
+use futures::StreamExt;
+use tracing::{error, info};
 
+use crate::{cache::Cache, chain::Chain, config::Config, db::{Database, repo::Repo}};
+use crate::metrics;
+use crate::newsletter::Newsletter;
+use crate::risk;
+use crate::stream::{self, Broadcaster, StreamState};
+use crate::types::NewsletterStatus;
+use crate::webhooks::{self, WebhookSender};
 
-pub fn cache_era_info(chain: &'static str) {
+// webhooks notified at the same time
+const WEBHOOK_DELIVERIES: usize = 16;
+// wait before connecting to a database again, doubled after each failure up to the maximum
+const CONNECT_RETRY: Duration = Duration::from_secs(5);
+const MAX_CONNECT_RETRY: Duration = Duration::from_secs(300);
+
+// the database of the chain, the jobs wait for it until it can be connected to
+async fn connect_chain_db(chain: &Chain) -> Database {
+  let address = env::var("MONGO_IP_ADDR").unwrap_or_else(|_| Config::current().db_address.clone());
+  let mut db = Database::new(address, Config::current().db_port, chain.db_name.as_str());
+  let mut wait = CONNECT_RETRY;
+  loop {
+    match db.connect().await {
+      Ok(()) => return db,
+      Err(e) => error!("failed to connect to the database of {}, retrying in {}s: {}", chain.ticker, wait.as_secs(), e),
+    }
+    tokio::time::sleep(wait).await;
+    wait = (wait * 2).min(MAX_CONNECT_RETRY);
+  }
+}
+
+pub fn cache_era_info(chain: &'static Chain, cache: Cache) {
+  tokio::spawn(async move {
+    let db = connect_chain_db(chain).await;
+    loop {
+      // put data from db to cache
+      match db.get_chain_info().await {
+        Ok(chain_info) => match cache.cache_current_era(&chain.ticker, chain_info.active_era).await {
+          Ok(()) => metrics::record_scheduler_success("era", &chain.ticker),
+          Err(e) => error!("failed to cache the era of {}: {}", chain.ticker, e),
+        },
+        Err(e) => error!("failed to get the era of {}: {}", chain.ticker, e),
+      }
+      // sleep for 10 minutes
+      tokio::time::sleep(Duration::from_secs(600)).await;
+    }
+  });
+}
+
+// scores the validators once per era, the scores of an era are computed when the scheduler first sees it
+pub fn refresh_validator_risks(chain: &'static Chain) {
+  tokio::spawn(async move {
+    let db = connect_chain_db(chain).await;
+    let mut scored_era = db.get_validator_risk_era().await.unwrap_or(0);
+    loop {
+      match db.get_chain_info().await {
+        Ok(chain_info) if chain_info.active_era > scored_era => {
+          let era = chain_info.active_era;
+          let result = match db.compute_validator_risks(era, risk::window_eras(chain)).await {
+            Ok(risks) => db.save_validator_risks(&risks).await.map(|_| risks.len()),
+            Err(e) => Err(e),
+          };
+          match result {
+            Ok(count) => {
+              info!("scored the risk of {} validators of {} at era {}", count, chain.ticker, era);
+              scored_era = era;
+              metrics::record_scheduler_success("risks", &chain.ticker);
+            },
+            Err(e) => error!("failed to score the validators of {} at era {}: {}", chain.ticker, era, e),
+          }
+        },
+        Ok(_) => metrics::record_scheduler_success("risks", &chain.ticker),
+        Err(e) => error!("failed to get the era of {}: {}", chain.ticker, e),
+      }
+      // sleep for 10 minutes
+      tokio::time::sleep(Duration::from_secs(600)).await;
+    }
+  });
+}
+
+// delivers the events of the eras a subscription has not seen yet, failed deliveries are retried with the next run
+pub fn notify_webhooks(chain: &'static Chain, users_db: Repo) {
   tokio::spawn(async move {
-    let mongo_ip = env::var("MONGO_IP_ADDR");
-    let mongo_ip = mongo_ip.unwrap_or_else(|_| Config::current().db_address.parse().unwrap());
-    let mut db: Database;
-    if chain == "KSM" {
-      db = Database::new(
-        mongo_ip.clone(),
-        Config::current().db_port,
-        Config::current().kusama_db_name.as_str(),
-      );
-    } else if chain == "DOT" {
-      db = Database::new(
-        mongo_ip.clone(),
-        Config::current().db_port,
-        Config::current().polkadot_db_name.as_str(),
-      );
-    } else {
-      db = Database::new(
-        mongo_ip.clone(),
-        Config::current().db_port,
-        Config::current().westend_db_name.as_str(),
-      );
+    let db = connect_chain_db(chain).await;
+    let config = Config::current();
+    let sender = WebhookSender::new(
+      config.webhook_attempts.unwrap_or(5),
+      Duration::from_secs(config.webhook_backoff_secs.unwrap_or(30)),
+    );
+    loop {
+      match (db.get_chain_info().await, users_db.get_webhook_subscriptions(&chain.ticker).await) {
+        (Ok(chain_info), Ok(subscriptions)) => {
+          let era = chain_info.active_era;
+          let (db, users_db, sender) = (&db, &*users_db, &sender);
+          let results: Vec<_> = futures::stream::iter(subscriptions.into_iter().map(|subscription| async move {
+            let result = webhooks::notify(db, users_db, sender, &subscription, era).await;
+            (subscription, result)
+          })).buffer_unordered(WEBHOOK_DELIVERIES).collect().await;
+          for (subscription, result) in results {
+            match result {
+              Ok(true) => info!("delivered the events of {} up to era {} to webhook {}", subscription.stash, era, subscription.id),
+              Ok(false) => {},
+              Err(e) => error!("failed to notify webhook {} of {}: {}", subscription.id, chain.ticker, e),
+            }
+          }
+          metrics::record_scheduler_success("webhooks", &chain.ticker);
+        },
+        (Err(e), _) => error!("failed to get the era of {}: {}", chain.ticker, e),
+        (_, Err(e)) => error!("failed to get the webhook subscriptions of {}: {}", chain.ticker, e),
+      }
+      // sleep for 10 minutes
+      tokio::time::sleep(Duration::from_secs(600)).await;
     }
-    let result = db.connect().await;
-    if let Ok(()) = result {
-      // create a cache client
-      loop {
-        // put data from db to cache
-        let chain_info = db.get_chain_info().await;
-        if let Ok(chain_info) = chain_info {
+  });
+}
+
+// sends the confirmed subscribers a digest of their stashes' events once an era ends, unsent digests are retried with
+// the next run
+pub fn send_newsletter_digests(chain: &'static Chain, users_db: Repo, newsletter: Newsletter) {
+  tokio::spawn(async move {
+    let db = connect_chain_db(chain).await;
+    loop {
+      match (db.get_chain_info().await, users_db.get_newsletter_subscribers(NewsletterStatus::Confirmed).await) {
+        (Ok(chain_info), Ok(subscribers)) => {
           let era = chain_info.active_era;
-          let cache = Cache {};
-          cache.cache_current_era(chain, era);
-        }
-        // sleep for 10 minutes
-        tokio::time::sleep(Duration::from_secs(600)).await;
-      }   
+          for subscriber in subscribers.iter() {
+            match newsletter.send_digest(&db, &*users_db, chain, subscriber, era).await {
+              Ok(true) => info!("sent the {} digest of era {} to {}", chain.ticker, era, subscriber.email),
+              Ok(false) => {},
+              Err(e) => error!("failed to send the {} digest to {}: {}", chain.ticker, subscriber.email, e),
+            }
+          }
+          metrics::record_scheduler_success("newsletter", &chain.ticker);
+        },
+        (Err(e), _) => error!("failed to get the era of {}: {}", chain.ticker, e),
+        (_, Err(e)) => error!("failed to get the newsletter subscribers: {}", e),
+      }
+      // sleep for 10 minutes
+      tokio::time::sleep(Duration::from_secs(600)).await;
+    }
+  });
+}
+
+// publishes era transitions, 1KV refreshes and the events of the watched stashes to the open streams
+pub fn stream_updates(chain: &'static Chain, cache: Cache, users_db: Repo, broadcaster: Broadcaster) {
+  tokio::spawn(async move {
+    let db = connect_chain_db(chain).await;
+    let mut state = StreamState::default();
+    loop {
+      match stream::poll(&db, &*users_db, &cache, chain, &broadcaster, &mut state).await {
+        Ok(()) => metrics::record_scheduler_success("stream", &chain.ticker),
+        Err(e) => error!("failed to poll the stream updates of {}: {}", chain.ticker, e),
+      }
+      tokio::time::sleep(Duration::from_secs(Config::current().stream_poll_secs.unwrap_or(30))).await;
     }
   });
-}
\ No newline at end of file
+}
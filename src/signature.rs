@@ -0,0 +1,185 @@
+use blake2::{Blake2b, Digest, digest::consts::U32};
+use k256::ecdsa::{RecoveryId, Signature as EcdsaSignature, VerifyingKey};
+use std::fmt;
+
+use crate::chain;
+
+const SIGNING_CONTEXT: &[u8] = b"substrate";
+const BYTES_PREFIX: &[u8] = b"<Bytes>";
+const BYTES_POSTFIX: &[u8] = b"</Bytes>";
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum CryptoType {
+  Sr25519,
+  Ed25519,
+  Ecdsa,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Verified {
+  pub crypto_type: CryptoType,
+  // whether the signed message was wrapped in <Bytes>...</Bytes> by the signer
+  pub wrapped: bool,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum SignatureError {
+  MalformedSignature,
+  InvalidAddress,
+  // verifies with neither the message nor its <Bytes> wrapping, so the address did not sign it
+  BadSignature,
+}
+
+impl fmt::Display for SignatureError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      SignatureError::MalformedSignature => write!(f, "signature is malformed"),
+      SignatureError::InvalidAddress => write!(f, "address is invalid"),
+      SignatureError::BadSignature => write!(f, "signature was not made by the address for the message"),
+    }
+  }
+}
+
+fn blake2_256(data: &[u8]) -> [u8; 32] {
+  let mut hasher = Blake2b::<U32>::new();
+  hasher.update(data);
+  hasher.finalize().into()
+}
+
+// polkadot-js signs raw payloads as <Bytes>message</Bytes>, so both forms are accepted
+fn message_candidates(message: &[u8]) -> Vec<(Vec<u8>, bool)> {
+  if message.starts_with(BYTES_PREFIX) && message.ends_with(BYTES_POSTFIX) {
+    let unwrapped = message[BYTES_PREFIX.len()..message.len() - BYTES_POSTFIX.len()].to_vec();
+    vec![(message.to_vec(), true), (unwrapped, false)]
+  } else {
+    let wrapped = [BYTES_PREFIX, message, BYTES_POSTFIX].concat();
+    vec![(message.to_vec(), false), (wrapped, true)]
+  }
+}
+
+fn verify_sr25519(message: &[u8], signature: &[u8], public: &[u8; 32]) -> bool {
+  let public = match schnorrkel::PublicKey::from_bytes(public) {
+    Ok(public) => public,
+    Err(_) => return false,
+  };
+  match schnorrkel::Signature::from_bytes(signature) {
+    Ok(signature) => public.verify_simple(SIGNING_CONTEXT, message, &signature).is_ok(),
+    Err(_) => false,
+  }
+}
+
+fn verify_ed25519(message: &[u8], signature: &[u8], public: &[u8; 32]) -> bool {
+  let public = match ed25519_dalek::VerifyingKey::from_bytes(public) {
+    Ok(public) => public,
+    Err(_) => return false,
+  };
+  match ed25519_dalek::Signature::from_slice(signature) {
+    Ok(signature) => public.verify_strict(message, &signature).is_ok(),
+    Err(_) => false,
+  }
+}
+
+// ecdsa accounts are the blake2-256 hash of the compressed public key
+fn verify_ecdsa(message: &[u8], signature: &[u8], account_id: &[u8; 32]) -> bool {
+  let recovery_id = match signature[64] {
+    v @ 0..=3 => v,
+    v @ 27..=30 => v - 27,
+    _ => return false,
+  };
+  let (recovery_id, signature) = match (RecoveryId::from_byte(recovery_id), EcdsaSignature::from_slice(&signature[..64])) {
+    (Some(recovery_id), Ok(signature)) => (recovery_id, signature),
+    _ => return false,
+  };
+  match VerifyingKey::recover_from_prehash(&blake2_256(message), &signature, recovery_id) {
+    Ok(public) => blake2_256(public.to_encoded_point(true).as_bytes()) == *account_id,
+    Err(_) => false,
+  }
+}
+
+fn decode_hex(value: &str) -> Result<Vec<u8>, SignatureError> {
+  hex::decode(value.trim_start_matches("0x")).map_err(|_| SignatureError::MalformedSignature)
+}
+
+// Verifies a signature produced by polkadot-js (or a wallet built on it) for the given address.
+// The signature is hex encoded, either bare (64 bytes for sr25519/ed25519, 65 bytes for ecdsa)
+// or as a MultiSignature with a leading type byte.
+pub fn verify(message: &[u8], signature: &str, address: &str) -> Result<Verified, SignatureError> {
+  let signature = decode_hex(signature)?;
+  let (_, account_id) = chain::ss58_decode(address).ok_or(SignatureError::InvalidAddress)?;
+  let candidates: Vec<(CryptoType, &[u8])> = match signature.len() {
+    64 => vec![(CryptoType::Sr25519, &signature[..]), (CryptoType::Ed25519, &signature[..])],
+    65 => match signature[0] {
+      0 => vec![(CryptoType::Ed25519, &signature[1..]), (CryptoType::Ecdsa, &signature[..])],
+      1 => vec![(CryptoType::Sr25519, &signature[1..]), (CryptoType::Ecdsa, &signature[..])],
+      _ => vec![(CryptoType::Ecdsa, &signature[..])],
+    },
+    66 if signature[0] == 2 => vec![(CryptoType::Ecdsa, &signature[1..])],
+    _ => return Err(SignatureError::MalformedSignature),
+  };
+  for (message, wrapped) in message_candidates(message) {
+    for (crypto_type, signature) in &candidates {
+      let valid = match crypto_type {
+        CryptoType::Sr25519 => verify_sr25519(&message, signature, &account_id),
+        CryptoType::Ed25519 => verify_ed25519(&message, signature, &account_id),
+        CryptoType::Ecdsa => verify_ecdsa(&message, signature, &account_id),
+      };
+      if valid {
+        return Ok(Verified {
+          crypto_type: *crypto_type,
+          wrapped,
+        });
+      }
+    }
+  }
+  Err(SignatureError::BadSignature)
+}
+
+const SR25519_ADDRESS: &str = "1ZGzHnrAgt3eKQvtZXgDPE9aduT4UWJH4fUL9HHdfWk6nx3";
+// sr25519 signature of <Bytes>cryptolab ref key</Bytes>
+const SR25519_SIGNATURE: &str = "0x6ea99f4f9db19690fc3ef09ccf0db2ec5505d478af28fc4bda7e788a2abdfd162b0da69cbfbe2858a2eb4137b6479035127186224c37089ccc6b38ee95c0a880";
+
+// verifies a known signature, for the health checks
+pub fn self_check() -> Result<(), SignatureError> {
+  verify(b"cryptolab ref key", SR25519_SIGNATURE, SR25519_ADDRESS).map(|_| ())
+}
+
+#[test]
+fn test_verify_sr25519_wrapped() {
+  assert_eq!(Ok(Verified { crypto_type: CryptoType::Sr25519, wrapped: true }),
+    verify(b"cryptolab ref key", SR25519_SIGNATURE, SR25519_ADDRESS));
+  assert_eq!(Ok(Verified { crypto_type: CryptoType::Sr25519, wrapped: true }),
+    verify(b"<Bytes>cryptolab ref key</Bytes>", SR25519_SIGNATURE, SR25519_ADDRESS));
+  // as a MultiSignature
+  let multi = format!("0x01{}", &SR25519_SIGNATURE[2..]);
+  assert_eq!(Ok(Verified { crypto_type: CryptoType::Sr25519, wrapped: true }),
+    verify(b"cryptolab ref key", &multi, SR25519_ADDRESS));
+}
+
+#[test]
+fn test_verify_ed25519_raw() {
+  // ed25519 signature of the bare message
+  let signature = "0xba43e66f2b5c712e90a4893f4b083318386616353c44cf16d1b985a3c9c56fed7449b48609f0ed0a48088bbab5c7f0686f627a5c0cdb7ebf33152da6b708b10e";
+  assert_eq!(Ok(Verified { crypto_type: CryptoType::Ed25519, wrapped: false }),
+    verify(b"cryptolab ref key", signature, "FVkmMjzeczW62D7WV8H7jeFCLD7WemJJ29dJs3RgZx9A1vb"));
+}
+
+#[test]
+fn test_verify_ecdsa() {
+  let signature = "0x0d2792e15074c85447f552809cab45e5515bae57a7afa2a1730acbacb6eebcea6e935284907caeaa88f0428ebe498567948ee4e566ca93ef8a44eaddf7e3d8ec01";
+  assert_eq!(Ok(Verified { crypto_type: CryptoType::Ecdsa, wrapped: true }),
+    verify(b"cryptolab ref key", signature, "5DzVsn1XYdhJv8Ts9N8teJjWUcqcfT6GAvUtxSrrsULaSMrF"));
+}
+
+#[test]
+fn test_verify_errors() {
+  assert_eq!(Err(SignatureError::BadSignature),
+    verify(b"another ref key", SR25519_SIGNATURE, SR25519_ADDRESS));
+  assert_eq!(Err(SignatureError::BadSignature),
+    verify(b"cryptolab ref key", SR25519_SIGNATURE, "5GrwvaEF5zXb26Fz9rcQpDWS57CtERHpNehXCPcNoHGKutQY"));
+  assert_eq!(Err(SignatureError::InvalidAddress),
+    verify(b"cryptolab ref key", SR25519_SIGNATURE, "5GrwvaEF5zXb26Fz9rcQpDWS57CtERHpNehXCPcNoHGKutQZ"));
+  assert_eq!(Err(SignatureError::MalformedSignature),
+    verify(b"cryptolab ref key", "0x1234", SR25519_ADDRESS));
+  assert_eq!(Err(SignatureError::MalformedSignature),
+    verify(b"cryptolab ref key", "not a signature", SR25519_ADDRESS));
+}
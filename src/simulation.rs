@@ -0,0 +1,102 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::chain::Chain;
+use crate::types::ValidatorNominationInfo;
+
+#[derive(Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SimulationOptions {
+  // in the smallest unit of the chain
+  pub amount: u128,
+  pub validators: Vec<String>,
+}
+
+#[derive(Serialize, JsonSchema, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SimulatedValidator {
+  pub stash: String,
+  // part of the amount backing the validator, the amount is split evenly between the known validators
+  pub stake: u128,
+  pub commission: f32,
+  pub apy: f32,
+  // apy of the stake after commission and after the stake joins the exposure
+  pub effective_apy: f64,
+  // in tokens
+  pub era_reward: f64,
+  // the stake would not be among the rewarded nominators of the validator
+  pub oversubscribed: bool,
+}
+
+#[derive(Serialize, JsonSchema, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SimulationResult {
+  // era of the exposures the simulation is based on
+  pub era: u32,
+  pub amount: u128,
+  // in tokens
+  pub era_reward: f64,
+  pub annual_reward: f64,
+  pub effective_apy: f64,
+  // true when the stake would not be rewarded by at least one validator
+  pub oversubscribed: bool,
+  pub validators: Vec<SimulatedValidator>,
+  // requested validators without exposure in the era, they get no stake
+  pub unknown: Vec<String>,
+}
+
+// The reward of a validator in an era is taken as fixed, apy * total stake / eras per year, so the stake joining
+// the exposure dilutes it. Nominators share what remains after commission in proportion to their stake.
+pub fn simulate(chain: &Chain, era: u32, options: &SimulationOptions, exposures: &[ValidatorNominationInfo]) -> SimulationResult {
+  let eras_per_year = 365.0 * 24.0 / chain.era_length_hours as f64;
+  let unit = 10f64.powi(chain.decimals as i32);
+  let known: Vec<&ValidatorNominationInfo> = options.validators.iter()
+    .filter_map(|stash| exposures.iter().find(|v| &v.id == stash))
+    .collect();
+  let unknown = options.validators.iter()
+    .filter(|stash| !known.iter().any(|v| &v.id == *stash))
+    .cloned()
+    .collect();
+  let stake = if known.is_empty() { 0 } else { options.amount / known.len() as u128 };
+  let validators: Vec<SimulatedValidator> = known.into_iter().map(|v| {
+    let total = v.info.exposure.total as f64;
+    let validator_reward = v.info.apy as f64 * total / eras_per_year;
+    let oversubscribed = is_oversubscribed(v, stake, chain.max_nominators_rewarded);
+    let era_reward = if oversubscribed || stake == 0 {
+      0.0
+    } else {
+      validator_reward * (1.0 - v.info.commission as f64 / 100.0) * stake as f64 / (total + stake as f64)
+    };
+    SimulatedValidator {
+      stash: v.id.clone(),
+      stake,
+      commission: v.info.commission,
+      apy: v.info.apy,
+      effective_apy: if stake > 0 { era_reward * eras_per_year / stake as f64 } else { 0.0 },
+      era_reward: era_reward / unit,
+      oversubscribed,
+    }
+  }).collect();
+  let era_reward: f64 = validators.iter().map(|v| v.era_reward).sum();
+  SimulationResult {
+    era,
+    amount: options.amount,
+    era_reward,
+    annual_reward: era_reward * eras_per_year,
+    effective_apy: if options.amount > 0 { era_reward * eras_per_year * unit / options.amount as f64 } else { 0.0 },
+    oversubscribed: validators.iter().any(|v| v.oversubscribed),
+    validators,
+    unknown,
+  }
+}
+
+// only the largest `max_nominators_rewarded` nominators of an exposure are rewarded
+fn is_oversubscribed(v: &ValidatorNominationInfo, stake: u128, max_nominators_rewarded: u32) -> bool {
+  let others = &v.info.exposure.others;
+  if others.len() < max_nominators_rewarded as usize {
+    return false;
+  }
+  let mut values: Vec<u128> = others.iter().map(|o| o.value).collect();
+  values.sort_unstable_by(|a, b| b.cmp(a));
+  (max_nominators_rewarded as usize).checked_sub(1).and_then(|i| values.get(i)).is_some_and(|smallest| stake <= *smallest)
+}
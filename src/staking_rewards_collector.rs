@@ -1,14 +1,13 @@
-use std::{fmt, fs::{self, File}, path::PathBuf, process::{Command, Output}, sync::{Arc, Mutex}};
+use std::{collections::{BTreeMap, HashMap}, fmt};
 
-use chrono::{DateTime, NaiveDateTime, Utc};
-use log::debug;
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+use schemars::JsonSchema;
 use serde::{Serialize, Deserialize};
 
-use crate::types::{StashEraReward, StashRewards};
+use crate::db::Database;
+use crate::types::{CBStashEraReward, StashEraReward, StashRewards};
 
-lazy_static! {
-  static ref MUTEX: Arc<std::sync::Mutex<i32>> = Arc::new(Mutex::new(0));
-}
+const SUPPORTED_CURRENCIES: [&str; 1] = ["USD"];
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct SRCError {
@@ -22,62 +21,17 @@ impl fmt::Display for SRCError {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct StakingRewardsCollector {
   pub start: String,
   pub end: String,
   pub currency: String,
-  pub price_data: String,
-  pub export_output: String,
+  pub price_data: bool,
   pub addresses: Vec<StakingRewardsAddress>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-#[serde(rename_all = "camelCase")]
-pub struct SRCResult {
-  address: String,
-	network: String,
-	currency: String,
-	start_balance: f64,
-	first_reward: String,
-	last_reward: String,
-	annualized_return: Option<f64>,
-	current_value_rewards_fiat: f64,
-	total_amount_human_readable: f64,
-	total_value_fiat: f64,
-  data: SRCRewardsData,
-}
-
-#[derive(Serialize, Deserialize, Debug)]
-#[serde(rename_all = "camelCase")]
-pub struct SRCRewardsData {
-  number_rewards_parsed: i32,
-  number_of_days: u32,
-  list: Vec<SRCDailyRewards>,
-}
-
-// "day": "01-01-2020",
-// 				"blockNumber": "",
-// 				"extrinsicHash": "",
-// 				"price": 0,
-// 				"volume": 0,
-// 				"amountPlanks": 0,
-// 				"numberPayouts": 0,
-// 				"amountHumanReadable": 0,
-// 				"valueFiat": 0
-
-#[derive(Serialize, Deserialize, Debug)]
-#[serde(rename_all = "camelCase")]
-pub struct SRCDailyRewards {
-  day: String,
-  price: f64,
-  volume: f64,
-  amount_human_readable: f64,
-  value_fiat: f64,
-}
-
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct StakingRewardsAddress {
   pub name: String,
@@ -89,27 +43,29 @@ pub struct StakingRewardsAddress {
 pub struct StakingRewardsReport {
   pub stash: String,
   pub format: String,
-
-  pub exe_dir: String,
 }
 
 impl StakingRewardsReport {
-  pub fn new(exe_dir: String, stash: String, format: String) -> Self {
-    StakingRewardsReport { stash, format, exe_dir }
+  pub fn new(stash: String, format: String) -> Self {
+    StakingRewardsReport { stash, format }
   }
 
-  pub fn get_report(&self) -> Result<String, SRCError> {
-    let mut path = PathBuf::new();
-    path.push(self.exe_dir.clone());
-    path.push(" ".to_string() + &self.stash + "." + &self.format);
-    let response_file = File::open(path.clone());
-    if response_file.is_ok() {
-      Ok(fs::read_to_string(path).unwrap())
-    } else {
-      Err(SRCError {
-        message: "Reward report is not found".to_string(),
-        err_code: -20,
-      })
+  // renders the latest finished rewards job of the stash
+  pub async fn get_report(&self, users_db: &Database) -> Result<String, SRCError> {
+    match users_db.get_latest_finished_rewards_job(&self.stash).await.ok().and_then(|job| job.result) {
+      Some(rewards) => {
+        if self.format == "csv" {
+          Ok(to_csv(&rewards))
+        } else {
+          Ok(serde_json::to_string(&rewards).unwrap_or_default())
+        }
+      },
+      None => {
+        Err(SRCError {
+          message: "Reward report is not found".to_string(),
+          err_code: -20,
+        })
+      }
     }
   }
 }
@@ -137,134 +93,114 @@ impl StakingRewardsCollector {
     price_data: bool,
     addresses: Vec<StakingRewardsAddress>) -> Result<Self, SRCError> {
     // validate inputs
-    if let Some(value) = validate_src_params(&start, &end) {
+    if let Some(value) = validate_src_params(&start, &end, &currency) {
             return value;
         }
     Ok(StakingRewardsCollector {
       start,
       end,
       currency,
-      price_data: price_data.to_string(),
+      price_data,
       addresses,
-      export_output: true.to_string(),
     })
   }
 
-  pub fn call_exe(&self, exe_dir: String) -> Result<StashRewards, SRCError> {
-    let mut mutex = MUTEX.lock().unwrap();
-    let input = exe_dir.clone() + "/config/userInput.json";
-    let file = &File::create(input);
-    if let Ok(file) = file {
-      let result = serde_json::to_writer_pretty(file, &self);
-      if let Ok(()) = result {
-        // call exe
-        let output  = self._call_exe(&exe_dir);
-        let result = std::str::from_utf8(&output.stdout);
-        debug!("{:?}", result);
-        // parse response
-        if let Ok(output) = result {
-          if output.contains("No rewards found to parse") ||
-            output.contains("Your requested time window lies before prices are available.") ||
-            output.is_empty() {
-            return Err(SRCError {
-              message: "No rewards are found".to_string(),
-              err_code: -2,
-            });
-          }
-          let path = exe_dir + "/ " + &self.addresses[0].address + ".json";
-          let response_file = File::open(path);
-          if let Ok(response_file) = response_file {
-            let response: Result<SRCResult, serde_json::Error> = serde_json::from_reader(&response_file);
-            if let Ok(mut r) = response {
-              r.data.list.reverse(); // make new data on top
-              *mutex += 1;
-              Ok(self.make_response(&r))
-            } else {
-              *mutex += 1;
-              Err(SRCError {
-                message: "failed to parse response to SRCResult".to_string(),
-                err_code: -9,
-              })
-            }
-          } else {
-            *mutex += 1;
-              Err(SRCError {
-                message: "failed to parse response to SRCResult".to_string(),
-                err_code: -9,
-              })
-          }
-        } else {
-          *mutex += 1;
-          Err(SRCError {
-            message: "staking rewards collector returned fail".to_string(),
-            err_code: -10,
-          })
-        }
-      } else {
-        *mutex += 1;
-        Err(SRCError {
-          message: "failed to write input json to file".to_string(),
-          err_code: -11,
-        })
-      }
-    } else {
-      *mutex += 1;
-      Err(SRCError {
-        message: "failed to create userInput.json".to_string(),
-        err_code: -12,
-      })
+  pub async fn collect(&self, db: &Database) -> Result<StashRewards, SRCError> {
+    let address = &self.addresses[0];
+    let (from, to) = self.time_range();
+    let payouts = db.get_stash_payouts(&address.address, from, to).await.map_err(|e| SRCError {
+      message: e.to_string(),
+      err_code: -10,
+    })?;
+    let mut prices = HashMap::new();
+    if self.price_data {
+      prices = db.get_prices_of_days(from / 1000, to / 1000).await.map_err(|e| SRCError {
+        message: e.to_string(),
+        err_code: -10,
+      })?;
     }
+    self.make_response(&payouts, &prices)
   }
 
-  fn _call_exe(&self, exe_dir: &str) -> Output {
-    let output = if cfg!(target_os = "windows") {
-      Command::new("cmd")
-      .current_dir(exe_dir)
-      .args(&["/C", "node src/index.js"])
-      .output()
-      .expect("failed to execute process")
-    } else {
-        Command::new("sh")
-        .current_dir(exe_dir)
-        .arg("-c")
-        .arg("node src/index.js")
-        .output()
-        .expect("failed to execute process")
-    };
-    output
+  // returns the inclusive [start, end] window in milliseconds
+  fn time_range(&self) -> (i64, i64) {
+    let start = NaiveDate::parse_from_str(&self.start, "%Y-%m-%d").unwrap().and_hms(0, 0, 0);
+    let end = NaiveDate::parse_from_str(&self.end, "%Y-%m-%d").unwrap().and_hms(23, 59, 59);
+    (start.timestamp_millis(), end.timestamp_millis() + 999)
   }
 
-  fn make_response(&self, src_result: &SRCResult) -> StashRewards {
+  fn make_response(&self, payouts: &[CBStashEraReward], prices: &HashMap<i64, f64>) -> Result<StashRewards, SRCError> {
+    let address = &self.addresses[0];
+    // era and amount of each day, keyed by the day's UTC midnight in seconds
+    let mut days: BTreeMap<i64, (i32, f64)> = BTreeMap::new();
+    for payout in payouts {
+      let day = NaiveDateTime::from_timestamp(payout.timestamp.div_euclid(1000), 0)
+        .date().and_hms(0, 0, 0).timestamp();
+      let entry = days.entry(day).or_insert((payout.era, 0.0));
+      entry.0 = entry.0.max(payout.era);
+      entry.1 += payout.amount;
+    }
+    if days.is_empty() {
+      return Err(SRCError {
+        message: "No rewards are found".to_string(),
+        err_code: -2,
+      });
+    }
     let mut era_rewards: Vec<StashEraReward> = vec![];
-    let first_date = NaiveDateTime::parse_from_str(&(src_result.first_reward.clone() + " 00:00:00"), "%d-%m-%Y %H:%M:%S").unwrap();
-    let last_date = NaiveDateTime::parse_from_str(&(src_result.last_reward.clone() + " 00:00:00"), "%d-%m-%Y %H:%M:%S").unwrap();
     let mut total_in_fiat = 0.0;
-    for daily_rewards in src_result.data.list.iter().clone() {
-      let date = chrono::NaiveDateTime::parse_from_str(&(daily_rewards.day.clone() + " 00:00:00"), "%d-%m-%Y %H:%M:%S").unwrap();
-      if date < first_date || date > last_date {
-        continue;
+    let mut total_amount = 0.0;
+    // make new data on top
+    for (day, (era, amount)) in days.iter().rev() {
+      let mut price = None;
+      let mut total = None;
+      // a day without a price has no value rather than a value of 0
+      if self.price_data {
+        price = prices.get(day).cloned();
+        total = price.map(|p| p * amount);
+        total_in_fiat += total.unwrap_or(0.0);
       }
-      let date_str = daily_rewards.day.clone() + " 00:00:00";
+      total_amount += amount;
       era_rewards.push(StashEraReward {
-        era: 0,
-        amount: daily_rewards.amount_human_readable,
-        timestamp: chrono::NaiveDateTime::parse_from_str(date_str.as_str(), "%d-%m-%Y %H:%M:%S")
-        .unwrap_or_else(|_| chrono::NaiveDateTime::from_timestamp(0, 0)).timestamp_millis(),
-        price: Some(daily_rewards.price),
-        total: Some(daily_rewards.value_fiat),
+        era: *era,
+        amount: *amount,
+        timestamp: day * 1000,
+        price,
+        total,
       });
-      total_in_fiat += daily_rewards.value_fiat;
     }
-
-    StashRewards {
-      stash: src_result.address.to_string(),
+    let first_day = *days.keys().next().unwrap();
+    let last_day = *days.keys().next_back().unwrap();
+    let number_of_days = ((last_day - first_day) / 86400 + 1) as f64;
+    let mut annualized_return = None;
+    if address.start_balance > 0.0 {
+      annualized_return = Some(total_amount / address.start_balance / number_of_days * 365.0);
+    }
+    Ok(StashRewards {
+      stash: address.address.to_string(),
       era_rewards,
       total_in_fiat,
-    }
+      annualized_return,
+    })
+  }
+}
+
+pub fn to_csv(rewards: &StashRewards) -> String {
+  let mut csv = "Day,Era,Amount,Price,Value\n".to_string();
+  for reward in &rewards.era_rewards {
+    let day = NaiveDateTime::from_timestamp(reward.timestamp / 1000, 0).format("%d-%m-%Y");
+    let or_empty = |value: Option<f64>| value.map(|value| value.to_string()).unwrap_or_default();
+    csv += &format!("{},{},{},{},{}\n", day, reward.era, reward.amount, or_empty(reward.price), or_empty(reward.total));
   }
+  csv
 }
 
-fn validate_src_params(start: &str, end: &str) -> Option<Result<StakingRewardsCollector, SRCError>> {
+fn validate_src_params(start: &str, end: &str, currency: &str) -> Option<Result<StakingRewardsCollector, SRCError>> {
+    if !SUPPORTED_CURRENCIES.contains(&currency) {
+      return Some(Err(SRCError{
+        err_code: -7,
+        message: "Currency is not supported".to_string(),
+      }));
+    }
     let start_time = NaiveDateTime::parse_from_str(&(start.to_string() + " 00:00:00"), "%Y-%m-%d %H:%M:%S");
     if start_time.is_err() {
       return Some(Err(SRCError{
@@ -314,62 +250,88 @@ fn validate_src_params(start: &str, end: &str) -> Option<Result<StakingRewardsCo
     None
 }
 
-#[test]
-fn test_call_exe_good() {
-  let src = StakingRewardsCollector::new("2020-01-01".to_string(), "2021-06-28".to_string(), "USD".to_string(), 
-true, vec![
+#[cfg(test)]
+fn polkadot_collector(start_balance: f64, price_data: bool) -> StakingRewardsCollector {
+  StakingRewardsCollector::new("2021-06-01".to_string(), "2021-06-28".to_string(), "USD".to_string(),
+   price_data, vec![
     StakingRewardsAddress {
       name: "".to_string(),
       address: "15Uv8ppUZVb8dM2uDf8rLnNPo4QdK9mHrJSUn6fqAhAtDZKu".to_string(),
-      start_balance: -0.1,
+      start_balance,
       network: "Polkadot".to_string(),
     }
-  ]);
-  crate::config::Config::init();
-  let result = src.unwrap().call_exe(crate::config::Config::current().staking_rewards_collector_dir.to_string());
-  assert!(result.is_ok());
+  ]).unwrap()
+}
+
+#[cfg(test)]
+fn payout(era: i32, amount: f64, timestamp: i64) -> CBStashEraReward {
+  CBStashEraReward {
+    era,
+    amount,
+    timestamp,
+    address: "15Uv8ppUZVb8dM2uDf8rLnNPo4QdK9mHrJSUn6fqAhAtDZKu".to_string(),
+  }
 }
 
 #[test]
-fn test_call_exe_incorrect_address() {
-  let src = StakingRewardsCollector::new("2020-01-01".to_string(), "2021-06-28".to_string(), "USD".to_string(),
-  true, vec![
-    StakingRewardsAddress {
-      name: "".to_string(),
-      address: "15Uv8ppUZVb8dMdK9mHrJSUn6fqAhAtDZKu".to_string(),
-      start_balance: 0.1,
-      network: "Polkadot".to_string(),
-    }
-  ]);
-  crate::config::Config::init();
-  let result = src.unwrap().call_exe(crate::config::Config::current().staking_rewards_collector_dir.to_string());
-  assert!(result.is_err());
-  let result = result.unwrap_err();
-  assert_eq!(SRCError {
-    message: "No rewards are found".to_string(),
-    err_code: -2,
-  }, result);
+fn test_make_response_good() {
+  let src = polkadot_collector(100.0, true);
+  // 2021-06-01 and 2021-06-02 (UTC), two payouts on the first day
+  let payouts = vec![
+    payout(420, 1.0, 1622520000000),
+    payout(421, 0.5, 1622530000000),
+    payout(422, 2.0, 1622606400000),
+  ];
+  let mut prices = HashMap::new();
+  prices.insert(1622505600, 10.0);
+  prices.insert(1622592000, 20.0);
+  let result = src.make_response(&payouts, &prices).unwrap();
+  assert_eq!(2, result.era_rewards.len());
+  // newest day first
+  assert_eq!(422, result.era_rewards[0].era);
+  assert_eq!(1622592000000, result.era_rewards[0].timestamp);
+  assert_eq!(Some(40.0), result.era_rewards[0].total);
+  assert_eq!(421, result.era_rewards[1].era);
+  assert_eq!(1.5, result.era_rewards[1].amount);
+  assert_eq!(Some(10.0), result.era_rewards[1].price);
+  assert_eq!(55.0, result.total_in_fiat);
+  assert_eq!(Some(3.5 / 100.0 / 2.0 * 365.0), result.annualized_return);
+  assert_eq!("Day,Era,Amount,Price,Value\n02-06-2021,422,2,20,40\n01-06-2021,421,1.5,10,15\n", to_csv(&result));
 }
 
 #[test]
-fn test_call_exe_no_rewards_found() {
-  let src = StakingRewardsCollector::new("2020-01-01".to_string(), "2020-01-02".to_string(), "USD".to_string(),
-   true, vec![
-    StakingRewardsAddress {
-      name: "".to_string(),
-      address: "15Uv8ppUZVb8dM2uDf8rLnNPo4QdK9mHrJSUn6fqAhAtDZKu".to_string(),
-      start_balance: 0.1,
-      network: "Polkadot".to_string(),
-    }
-  ]);
-  crate::config::Config::init();
-  let result = src.unwrap().call_exe(crate::config::Config::current().staking_rewards_collector_dir.to_string());
-  assert!(result.is_err());
-  let result = result.unwrap_err();
+fn test_make_response_missing_price() {
+  let src = polkadot_collector(100.0, true);
+  let payouts = vec![payout(420, 1.0, 1622520000000), payout(422, 2.0, 1622606400000)];
+  let mut prices = HashMap::new();
+  prices.insert(1622505600, 10.0);
+  let result = src.make_response(&payouts, &prices).unwrap();
+  assert_eq!(None, result.era_rewards[0].price);
+  assert_eq!(None, result.era_rewards[0].total);
+  assert_eq!(serde_json::Value::Null, serde_json::to_value(&result.era_rewards[0]).unwrap()["price"]);
+  assert_eq!(10.0, result.total_in_fiat);
+  assert_eq!("Day,Era,Amount,Price,Value\n02-06-2021,422,2,,\n01-06-2021,420,1,10,10\n", to_csv(&result));
+}
+
+#[test]
+fn test_make_response_without_price_data() {
+  let src = polkadot_collector(0.0, false);
+  let payouts = vec![payout(420, 1.0, 1622520000000)];
+  let result = src.make_response(&payouts, &HashMap::new()).unwrap();
+  assert_eq!(None, result.era_rewards[0].price);
+  assert_eq!(None, result.era_rewards[0].total);
+  assert_eq!(0.0, result.total_in_fiat);
+  assert_eq!(None, result.annualized_return);
+}
+
+#[test]
+fn test_make_response_no_rewards_found() {
+  let src = polkadot_collector(0.1, true);
+  let result = src.make_response(&[], &HashMap::new());
   assert_eq!(SRCError {
     message: "No rewards are found".to_string(),
     err_code: -2,
-  }, result);
+  }, result.unwrap_err());
 }
 
 #[test]
@@ -466,7 +428,10 @@ fn test_unsupported_currency() {
       network: "Polkadot".to_string(),
     }
   ]);
-  crate::config::Config::init();
-  let result = src.unwrap().call_exe(crate::config::Config::current().staking_rewards_collector_dir.to_string());
-  assert!(result.is_err());
+  assert_eq!(
+    SRCError{
+      err_code: -7,
+      message: "Currency is not supported".to_string(),
+    }, src.unwrap_err()
+  );
 }
@@ -0,0 +1,216 @@
+use std::collections::HashSet;
+
+use schemars::JsonSchema;
+use serde::Serialize;
+
+use crate::chain::Chain;
+use crate::types::{NominationStrategy, ValidatorNominationInfo};
+
+// unclaimed eras at which the unclaimed eras criterion reaches 0
+const UNCLAIMED_ERAS_LIMIT: usize = 10;
+
+// Each criterion scores a validator between 0 and 1, the score of a validator is the sum of its criteria
+// weighted by the strategy, so it is between 0 and 1 too.
+#[derive(Serialize, JsonSchema, Debug, Clone, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Criteria {
+  // apy relative to the highest apy of the era
+  pub apy: f64,
+  // 1 - commission
+  pub commission: f64,
+  // 1 without any slash on record, 0 otherwise
+  pub slashes: f64,
+  // 1 for a verified identity, 0.5 for an unverified one, 0 without identity
+  pub identity: f64,
+  // share of the rewarded nominator slots still free
+  pub oversubscription: f64,
+  // 1 without unclaimed eras, 0 from 10 unclaimed eras
+  pub unclaimed_eras: f64,
+  // 1 - total stake relative to the highest total stake of the era, a nomination earns more on smaller validators
+  pub stake_spread: f64,
+}
+
+impl Criteria {
+  fn weighted(&self, weights: &Criteria) -> f64 {
+    self.apy * weights.apy
+      + self.commission * weights.commission
+      + self.slashes * weights.slashes
+      + self.identity * weights.identity
+      + self.oversubscription * weights.oversubscription
+      + self.unclaimed_eras * weights.unclaimed_eras
+      + self.stake_spread * weights.stake_spread
+  }
+}
+
+#[derive(Serialize, JsonSchema, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct StrategyPick {
+  pub stash: String,
+  pub score: f64,
+  pub criteria: Criteria,
+  pub reasons: Vec<String>,
+}
+
+#[derive(Serialize, JsonSchema, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct StrategyResult {
+  pub era: u32,
+  pub strategy: NominationStrategy,
+  pub validators: Vec<StrategyPick>,
+}
+
+pub fn weights(strategy: NominationStrategy) -> Criteria {
+  match strategy {
+    NominationStrategy::Default => Criteria {
+      apy: 0.25,
+      commission: 0.15,
+      slashes: 0.15,
+      identity: 0.15,
+      oversubscription: 0.1,
+      unclaimed_eras: 0.1,
+      stake_spread: 0.1,
+    },
+    NominationStrategy::LowRisk => Criteria {
+      apy: 0.1,
+      commission: 0.15,
+      slashes: 0.25,
+      identity: 0.25,
+      oversubscription: 0.1,
+      unclaimed_eras: 0.1,
+      stake_spread: 0.05,
+    },
+    NominationStrategy::HighApy => Criteria {
+      apy: 0.5,
+      commission: 0.1,
+      slashes: 0.1,
+      identity: 0.05,
+      oversubscription: 0.15,
+      unclaimed_eras: 0.05,
+      stake_spread: 0.05,
+    },
+  }
+}
+
+// Picks up to `max_nominations` validators of the chain by descending score, ties broken by stash.
+// Validators blocking nominations, oversubscribed ones and, for the low risk strategy, slashed ones are left out,
+// and only the best validator of an identity (its parent identity for sub-identities) is picked.
+pub fn select(strategy: NominationStrategy, chain: &Chain, validators: &[ValidatorNominationInfo]) -> Vec<StrategyPick> {
+  let max_apy = validators.iter().map(|v| v.info.apy as f64).fold(0.0, f64::max);
+  let max_total = validators.iter().map(|v| v.info.total).max().unwrap_or(0);
+  let weights = weights(strategy);
+  let mut candidates: Vec<(&ValidatorNominationInfo, Criteria)> = validators.iter()
+    .filter(|v| !v.block_nomination)
+    .filter(|v| v.info.nominator_count < chain.max_nominators_rewarded)
+    .filter(|v| strategy != NominationStrategy::LowRisk || v.slashes.is_empty())
+    .map(|v| (v, criteria(v, chain, max_apy, max_total)))
+    .collect();
+  candidates.sort_by(|(a, ac), (b, bc)| {
+    bc.weighted(&weights).partial_cmp(&ac.weighted(&weights)).unwrap_or(std::cmp::Ordering::Equal)
+      .then_with(|| a.id.cmp(&b.id))
+  });
+  let mut operators = HashSet::new();
+  candidates.into_iter()
+    .filter(|(v, _)| match operator(v) {
+      Some(name) => operators.insert(name),
+      None => true,
+    })
+    .take(chain.max_nominations as usize)
+    .map(|(v, criteria)| StrategyPick {
+      stash: v.id.clone(),
+      score: criteria.weighted(&weights),
+      reasons: reasons(v, chain),
+      criteria,
+    })
+    .collect()
+}
+
+fn criteria(v: &ValidatorNominationInfo, chain: &Chain, max_apy: f64, max_total: u128) -> Criteria {
+  let unclaimed = v.info.unclaimed_eras.as_ref().map(|e| e.len()).unwrap_or(0);
+  Criteria {
+    apy: if max_apy > 0.0 { v.info.apy as f64 / max_apy } else { 0.0 },
+    commission: 1.0 - (v.info.commission as f64 / 100.0).clamp(0.0, 1.0),
+    slashes: if v.slashes.is_empty() { 1.0 } else { 0.0 },
+    identity: match v.identity.as_ref() {
+      Some(i) if i.is_verified.unwrap_or(false) => 1.0,
+      Some(i) if i.display.as_deref().is_some_and(|d| !d.is_empty()) => 0.5,
+      _ => 0.0,
+    },
+    oversubscription: 1.0 - v.info.nominator_count as f64 / chain.max_nominators_rewarded as f64,
+    unclaimed_eras: 1.0 - unclaimed.min(UNCLAIMED_ERAS_LIMIT) as f64 / UNCLAIMED_ERAS_LIMIT as f64,
+    stake_spread: if max_total > 0 { 1.0 - v.info.total as f64 / max_total as f64 } else { 0.0 },
+  }
+}
+
+fn operator(v: &ValidatorNominationInfo) -> Option<String> {
+  let identity = v.identity.as_ref()?;
+  identity.parent.as_ref().or(identity.display.as_ref()).filter(|name| !name.is_empty()).cloned()
+}
+
+fn reasons(v: &ValidatorNominationInfo, chain: &Chain) -> Vec<String> {
+  let unclaimed = v.info.unclaimed_eras.as_ref().map(|e| e.len()).unwrap_or(0);
+  let verified = v.identity.as_ref().and_then(|i| i.is_verified).unwrap_or(false);
+  vec![
+    format!("APY {:.2}%", v.info.apy * 100.0),
+    format!("commission {}%", v.info.commission),
+    if v.slashes.is_empty() { "never slashed".to_string() } else { format!("slashed {} times", v.slashes.len()) },
+    if verified { "verified identity".to_string() } else { "identity not verified".to_string() },
+    format!("{} of {} rewarded nominator slots taken", v.info.nominator_count, chain.max_nominators_rewarded),
+    format!("{} unclaimed eras", unclaimed),
+    format!("total stake {:.0} {}", v.info.total as f64 / 10f64.powi(chain.decimals as i32), chain.ticker),
+  ]
+}
+
+#[cfg(test)]
+fn test_validator(id: &str, apy: f32, commission: f32, verified: bool, nominators: u32) -> ValidatorNominationInfo {
+  serde_json::from_value(serde_json::json!({
+    "id": id,
+    "statusChange": {"commission": 0},
+    "identity": {"display": id, "parent": null, "sub": null, "isVerified": verified},
+    "info": {
+      "nominatorCount": nominators,
+      "era": 1,
+      "exposure": {"total": "0x0", "own": "0x0", "others": []},
+      "commission": commission,
+      "apy": apy,
+      "total": "0x038d7ea4c68000",
+      "selfStake": "0x038d7ea4c68000"
+    },
+    "slashes": [],
+    "blockNomination": false
+  })).unwrap()
+}
+
+#[cfg(test)]
+fn test_chain(max_nominations: u32) -> Chain {
+  Chain { max_nominations, ..crate::db::memory::kusama_chain() }
+}
+
+#[test]
+fn test_weights() {
+  for strategy in [NominationStrategy::Default, NominationStrategy::LowRisk, NominationStrategy::HighApy] {
+    let ones = Criteria {
+      apy: 1.0, commission: 1.0, slashes: 1.0, identity: 1.0, oversubscription: 1.0, unclaimed_eras: 1.0, stake_spread: 1.0,
+    };
+    assert!((ones.weighted(&weights(strategy)) - 1.0).abs() < 1e-9);
+  }
+}
+
+#[test]
+fn test_select() {
+  let validators = vec![
+    test_validator("b", 0.10, 1.0, true, 10),
+    test_validator("a", 0.10, 1.0, true, 10),
+    test_validator("high", 0.20, 10.0, false, 10),
+    test_validator("full", 0.30, 0.0, true, 256),
+  ];
+  // the oversubscribed validator is left out and ties are broken by stash
+  let picks = select(NominationStrategy::LowRisk, &test_chain(16), &validators);
+  let stashes: Vec<&str> = picks.iter().map(|p| p.stash.as_str()).collect();
+  assert_eq!(vec!["a", "b", "high"], stashes);
+  assert_eq!(7, picks[0].reasons.len());
+
+  let picks = select(NominationStrategy::HighApy, &test_chain(2), &validators);
+  let stashes: Vec<&str> = picks.iter().map(|p| p.stash.as_str()).collect();
+  assert_eq!(vec!["high", "a"], stashes);
+  assert!(picks[0].score > picks[1].score);
+}
@@ -0,0 +1,269 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use tracing::warn;
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+use crate::cache::Cache;
+use crate::chain::Chain;
+use crate::db::DatabaseError;
+use crate::db::repo::{self, Repository};
+use crate::types::StakingEvents;
+
+// events a slow stream can fall behind by before it misses some
+const CAPACITY: usize = 256;
+// open streams of all the chains, see `Config::max_streams`
+pub const DEFAULT_MAX_STREAMS: usize = 1000;
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum StreamEvent {
+  // the active era changed
+  #[serde(rename_all = "camelCase")]
+  Era { chain: String, era: u32 },
+  // chain-data-collector refreshed the 1KV snapshot
+  #[serde(rename_all = "camelCase")]
+  OneKv { chain: String, timestamp: String },
+  // staking events of a watched stash in the eras since the last transition
+  #[serde(rename_all = "camelCase")]
+  Events { chain: String, stash: String, from_era: u32, to_era: u32, events: StakingEvents },
+}
+
+impl StreamEvent {
+  // the SSE event name
+  pub fn name(&self) -> &'static str {
+    match self {
+      StreamEvent::Era { .. } => "era",
+      StreamEvent::OneKv { .. } => "onekv",
+      StreamEvent::Events { .. } => "events",
+    }
+  }
+
+  fn chain(&self) -> &str {
+    match self {
+      StreamEvent::Era { chain, .. } | StreamEvent::OneKv { chain, .. } | StreamEvent::Events { chain, .. } => chain,
+    }
+  }
+}
+
+#[derive(Default)]
+struct Streams {
+  // open streams of all the chains
+  open: usize,
+  // open streams by chain and stash
+  watched: HashMap<(String, String), usize>,
+}
+
+// Fans the scheduler's events out to the open streams. The stashes the streams watch are counted so that the
+// scheduler only collects the events of stashes somebody is listening to.
+#[derive(Clone)]
+pub struct Broadcaster {
+  sender: broadcast::Sender<Arc<StreamEvent>>,
+  streams: Arc<Mutex<Streams>>,
+  max_streams: usize,
+}
+
+impl Broadcaster {
+  pub fn new(max_streams: usize) -> Self {
+    let (sender, _) = broadcast::channel(CAPACITY);
+    Broadcaster {
+      sender,
+      streams: Arc::new(Mutex::new(Streams::default())),
+      max_streams,
+    }
+  }
+
+  pub fn publish(&self, event: StreamEvent) {
+    // there is no receiver when no stream is open
+    let _ = self.sender.send(Arc::new(event));
+  }
+
+  // None when `max_streams` streams are already open
+  pub fn subscribe(&self, chain: &str, stashes: Vec<String>) -> Option<Subscription> {
+    let mut streams = self.streams.lock().unwrap();
+    if streams.open >= self.max_streams {
+      return None;
+    }
+    streams.open += 1;
+    for stash in stashes.iter() {
+      *streams.watched.entry((chain.to_string(), stash.clone())).or_insert(0) += 1;
+    }
+    Some(Subscription {
+      receiver: self.sender.subscribe(),
+      chain: chain.to_string(),
+      stashes,
+      broadcaster: self.clone(),
+    })
+  }
+
+  // the stashes at least one open stream of the chain watches
+  pub fn watched(&self, chain: &str) -> Vec<String> {
+    let mut stashes: Vec<String> = self.streams.lock().unwrap().watched.keys()
+      .filter(|(c, _)| c == chain).map(|(_, stash)| stash.clone()).collect();
+    stashes.sort();
+    stashes
+  }
+}
+
+// the events of one stream, it stops watching its stashes when dropped
+pub struct Subscription {
+  receiver: broadcast::Receiver<Arc<StreamEvent>>,
+  chain: String,
+  stashes: Vec<String>,
+  broadcaster: Broadcaster,
+}
+
+impl Subscription {
+  // the next event of the chain, only the events of the watched stashes are kept. None once the broadcaster is gone.
+  pub async fn next(&mut self) -> Option<Arc<StreamEvent>> {
+    loop {
+      let event = match self.receiver.recv().await {
+        Ok(event) => event,
+        Err(broadcast::error::RecvError::Lagged(missed)) => {
+          warn!("a {} stream missed {} events", self.chain, missed);
+          continue;
+        },
+        Err(broadcast::error::RecvError::Closed) => return None,
+      };
+      let wanted = event.chain() == self.chain && match &*event {
+        StreamEvent::Events { stash, .. } => self.stashes.contains(stash),
+        _ => true,
+      };
+      if wanted {
+        return Some(event);
+      }
+    }
+  }
+}
+
+impl Drop for Subscription {
+  fn drop(&mut self) {
+    let mut streams = self.broadcaster.streams.lock().unwrap();
+    streams.open -= 1;
+    for stash in self.stashes.iter() {
+      let key = (self.chain.clone(), stash.clone());
+      if let Some(count) = streams.watched.get_mut(&key) {
+        *count -= 1;
+        if *count == 0 {
+          streams.watched.remove(&key);
+        }
+      }
+    }
+  }
+}
+
+// what the previous poll saw, nothing is published for what was there before the first one
+#[derive(Default)]
+pub struct StreamState {
+  era: Option<u32>,
+  onekv_timestamp: Option<String>,
+}
+
+// Publishes what changed since the previous poll: the era, the events of the watched stashes in the new eras and the
+// 1KV snapshot. The events of an era transition are all collected before any is published, a failed poll publishes
+// nothing and the next one retries the transition.
+pub async fn poll(db: &dyn Repository, user_db: &dyn Repository, cache: &Cache, chain: &Chain,
+  broadcaster: &Broadcaster, state: &mut StreamState) -> Result<(), DatabaseError> {
+  let era = db.get_chain_info().await?.active_era;
+  let mut events = vec![];
+  if let Some(last_era) = state.era.filter(|last_era| era > *last_era) {
+    events.push(StreamEvent::Era { chain: chain.ticker.clone(), era });
+    for stash in broadcaster.watched(&chain.ticker) {
+      match repo::get_nominator_events(db, user_db, &stash, last_era + 1, era).await {
+        Ok(stash_events) if !stash_events.is_empty() => events.push(StreamEvent::Events {
+          chain: chain.ticker.clone(),
+          stash,
+          from_era: last_era + 1,
+          to_era: era,
+          events: stash_events,
+        }),
+        Ok(_) => {},
+        // the stash no longer nominates
+        Err(DatabaseError::NotFound { .. }) => {},
+        Err(e) => return Err(e),
+      }
+    }
+  }
+  for event in events {
+    broadcaster.publish(event);
+  }
+  state.era = Some(era);
+  if chain.one_kv {
+    match cache.get(&format!("{}onekv_timestamp", chain.ticker)).await {
+      Ok(Some(timestamp)) => {
+        if state.onekv_timestamp.as_ref().is_some_and(|last| *last != timestamp) {
+          broadcaster.publish(StreamEvent::OneKv { chain: chain.ticker.clone(), timestamp: timestamp.clone() });
+        }
+        state.onekv_timestamp = Some(timestamp);
+      },
+      Ok(None) => {},
+      Err(e) => warn!("failed to get the 1KV timestamp of {}: {}", chain.ticker, e),
+    }
+  }
+  Ok(())
+}
+
+#[tokio::test]
+async fn test_subscription() {
+  let broadcaster = Broadcaster::new(2);
+  let mut ksm = broadcaster.subscribe("KSM", vec!["stash".to_string()]).unwrap();
+  let other = broadcaster.subscribe("KSM", vec!["stash".to_string(), "other".to_string()]).unwrap();
+  assert_eq!(vec!["other".to_string(), "stash".to_string()], broadcaster.watched("KSM"));
+  // at most 2 open streams
+  assert!(broadcaster.subscribe("DOT", vec![]).is_none());
+  drop(other);
+  assert_eq!(vec!["stash".to_string()], broadcaster.watched("KSM"));
+  assert!(broadcaster.subscribe("DOT", vec![]).is_some());
+
+  broadcaster.publish(StreamEvent::Era { chain: "DOT".to_string(), era: 7 });
+  broadcaster.publish(StreamEvent::Events {
+    chain: "KSM".to_string(), stash: "other".to_string(), from_era: 1, to_era: 2, events: StakingEvents::default(),
+  });
+  broadcaster.publish(StreamEvent::Era { chain: "KSM".to_string(), era: 3 });
+  let event = ksm.next().await.unwrap();
+  assert_eq!(serde_json::json!({"type": "era", "chain": "KSM", "era": 3}), serde_json::to_value(&*event).unwrap());
+  drop(ksm);
+  assert!(broadcaster.watched("KSM").is_empty());
+}
+
+#[tokio::test]
+async fn test_poll() {
+  let repo = crate::db::memory::MemoryRepo::kusama();
+  let cache: Cache = Arc::new(crate::cache_memory::MemoryCache::new());
+  let kusama = crate::db::memory::kusama_chain();
+  let broadcaster = Broadcaster::new(DEFAULT_MAX_STREAMS);
+  let stash = "HNZata7iMYWmk5RvZRTiAsSDhV8366zq2YGb3tLH5Upf74F";
+  let mut subscription = broadcaster.subscribe("KSM", vec![stash.to_string()]).unwrap();
+
+  // the first poll only records the era and the snapshot
+  cache.set("KSMonekv_timestamp", "1000".to_string()).await.unwrap();
+  let mut state = StreamState::default();
+  poll(&repo, &repo, &cache, &kusama, &broadcaster, &mut state).await.unwrap();
+  assert_eq!(Some(3001), state.era);
+
+  state.era = Some(3000);
+  cache.set("KSMonekv_timestamp", "2000".to_string()).await.unwrap();
+  poll(&repo, &repo, &cache, &kusama, &broadcaster, &mut state).await.unwrap();
+  assert_eq!("era", subscription.next().await.unwrap().name());
+  match &*subscription.next().await.unwrap() {
+    StreamEvent::Events { stash: s, from_era, to_era, events, .. } => {
+      assert_eq!(stash, s);
+      assert_eq!((3001, 3001), (*from_era, *to_era));
+      assert_eq!(1, events.commissions.len());
+    },
+    event => panic!("unexpected {:?}", event),
+  }
+  match &*subscription.next().await.unwrap() {
+    StreamEvent::OneKv { timestamp, .. } => assert_eq!("2000", timestamp),
+    event => panic!("unexpected {:?}", event),
+  }
+
+  // nothing changed
+  poll(&repo, &repo, &cache, &kusama, &broadcaster, &mut state).await.unwrap();
+  broadcaster.publish(StreamEvent::Era { chain: "KSM".to_string(), era: 0 });
+  match &*subscription.next().await.unwrap() {
+    StreamEvent::Era { era, .. } => assert_eq!(0, *era),
+    event => panic!("unexpected {:?}", event),
+  }
+}
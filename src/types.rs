@@ -1,23 +1,15 @@
 use serde::de;
+use schemars::JsonSchema;
 use serde::{Deserialize, Deserializer, Serialize};
 use serde_json::Value;
 use validator::Validate;
+use std::collections::HashMap;
 use std::str::FromStr;
+use tracing::warn;
 
-#[derive(Serialize, Deserialize, Debug)]
-#[serde(rename_all = "camelCase")]
-pub struct PolkadotApiValidators {
-    pub valid_detail_all: Option<ValidatorDetailAll>,
-    #[serde(rename = "validDetail")]
-    pub valid_detail_1kv: Option<ValidatorDetail1kv>,
-    #[serde(rename = "valid")]
-    pub valid: Option<Validator1kvSimple>,
-    pub nominators: Option<Vec<NominatorNomination>>,
-    #[serde(rename = "onekvNominators")]
-    pub one_kv_nominators: Option<OneKvNominators>,
-}
+use crate::risk::ValidatorRisk;
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct ValidatorDetail1kv {
     pub active_era: Option<u32>,
@@ -28,23 +20,13 @@ pub struct ValidatorDetail1kv {
     pub modified_time: Option<u64>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-#[serde(rename_all = "camelCase")]
-pub struct Validator1kvSimple {
-    pub active_era: Option<u32>,
-    pub validator_count: Option<u32>,
-    pub elected_count: Option<u32>,
-    pub election_rate: Option<f32>,
-    pub valid: Vec<ValidatorInfo1kvSimple>,
-}
-
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct ValidatorDetailAll {
     pub valid: Vec<ValidatorInfo>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct Validity {
     #[serde(rename="type")]
@@ -54,7 +36,7 @@ pub struct Validity {
     pub updated: u64,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct ValidatorInfo1kv {
     #[serde(default)]
@@ -74,19 +56,7 @@ pub struct ValidatorInfo1kv {
     pub validity: Vec<Validity>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-#[serde(rename_all = "camelCase")]
-pub struct ValidatorInfo1kvSimple {
-    #[serde(default)]
-    aggregate: Option<Aggregate>,
-    rank: u32,
-    inclusion: f32,
-    name: String,
-    stash: String,
-    elected: bool,
-}
-
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct StakingInfo {
     // account_id: String,
@@ -99,7 +69,7 @@ pub struct StakingInfo {
     // identity: Identity,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct Aggregate {
     total: f32,
@@ -140,29 +110,29 @@ pub struct ValidatorInfo {
     unclaimed_eras: Option<Vec<i32>>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 pub struct Exposure {
     #[serde(deserialize_with = "from_hex")]
-    total: u128,
+    pub total: u128,
     #[serde(deserialize_with = "from_hex")]
-    own: u128,
-    others: Vec<Others>,
+    pub own: u128,
+    pub others: Vec<Others>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 pub struct Others {
-    who: String,
+    pub who: String,
     #[serde(deserialize_with = "from_hex")]
-    value: u128,
+    pub value: u128,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 pub struct Nominator {
     pub address: String,
     pub balance: Option<Balance>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct NominatorNomination {
     #[serde(alias = "address", alias = "accountId")]
@@ -172,7 +142,7 @@ pub struct NominatorNomination {
     pub rewards: Option<StashRewards>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct Balance {
     #[serde(deserialize_with = "from_hex")]
@@ -181,7 +151,7 @@ pub struct Balance {
     pub(crate) free_balance: u128,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
 pub struct StakingLedger {
     #[serde(alias = "stash", alias = "stashId")]
     stash: String,
@@ -191,36 +161,54 @@ pub struct StakingLedger {
     active: u128,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
 pub struct ValidatorPrefs {
     commission: u64,
     blocked: bool,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct Identity {
-    display: Option<String>,
-    parent: Option<String>,
-    sub: Option<String>,
-    is_verified: Option<bool>,
+    pub display: Option<String>,
+    pub parent: Option<String>,
+    pub sub: Option<String>,
+    pub is_verified: Option<bool>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct ValidatorNominationInfo {
     pub id: String,
     status_change: StatusChange,
-    identity: Option<Identity>,
-    info: NominationInfoSimple,
-    staker_points: Option<Vec<StakerPoint>>,
+    pub identity: Option<Identity>,
+    pub info: NominationInfoSimple,
+    pub staker_points: Option<Vec<StakerPoint>>,
     average_apy: Option<f32>,
-    slashes: Vec<ValidatorSlash>,
+    pub slashes: Vec<ValidatorSlash>,
     #[serde(alias = "block_nomination", alias = "blocked")]
-    block_nomination: bool,
+    pub block_nomination: bool,
+    // latest risk score, absent until the scheduler scored the validator
+    #[serde(default)]
+    pub risk: Option<ValidatorRisk>,
+    // the value the validator was sorted by, only used to continue a paginated listing
+    #[serde(default, skip_serializing)]
+    #[schemars(skip)]
+    pub sort_key: Option<serde_json::Value>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Debug, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ValidatorList {
+    pub era: u32,
+    // number of validators of the era passing the filters, across all pages
+    pub total_count: u64,
+    // cursor of the next page, absent on the last page
+    pub next: Option<String>,
+    pub validators: Vec<ValidatorNominationInfo>,
+}
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct ValidatorNominationTrend {
     id: String,
@@ -231,31 +219,25 @@ pub struct ValidatorNominationTrend {
     pub info: Vec<NominationInfo>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct StakerPoint {
-    era: u32,
-    points: u32,
-}
-
-#[derive(Serialize, Deserialize, Debug, Clone)]
-#[serde(rename_all = "camelCase")]
-pub struct ValidatorTotalReward {
-    start: i32,
-    end: i32,
-    total: f32,
+    pub era: u32,
+    pub points: u32,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct StashRewards {
     #[serde(alias = "id")]
     pub stash: String,
     pub era_rewards: Vec<StashEraReward>,
     pub total_in_fiat: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub annualized_return: Option<f64>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct StashEraReward {
     pub era: i32,
@@ -266,7 +248,7 @@ pub struct StashEraReward {
     pub total: Option<f64>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct CBStashEraReward {
     pub era: i32,
@@ -284,12 +266,12 @@ pub struct CoinPrice {
     pub timestamp: i64,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 pub struct StatusChange {
     commission: u32,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct NominationInfo {
     pub nominators: Option<Vec<Nominator>>,
@@ -305,20 +287,20 @@ pub struct NominationInfo {
     self_stake: Option<u128>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct NominationInfoSimple {
     pub nominators: Option<Vec<Nominator>>,
     pub nominator_count: u32,
     pub era: u32,
-    exposure: Exposure,
-    commission: f32,
-    apy: f32,
-    unclaimed_eras: Option<Vec<i32>>,
+    pub exposure: Exposure,
+    pub commission: f32,
+    pub apy: f32,
+    pub unclaimed_eras: Option<Vec<i32>>,
     #[serde(default, deserialize_with = "from_hex")]
-    total: u128,
+    pub total: u128,
     #[serde(deserialize_with = "from_optional_hex")]
-    self_stake: Option<u128>,
+    pub self_stake: Option<u128>,
 }
 
 impl NominationInfo {
@@ -327,27 +309,61 @@ impl NominationInfo {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+impl From<NominationInfoSimple> for NominationInfo {
+    fn from(info: NominationInfoSimple) -> Self {
+        NominationInfo {
+            nominators: info.nominators,
+            nominator_count: info.nominator_count,
+            era: info.era,
+            exposure: info.exposure,
+            commission: info.commission,
+            apy: info.apy,
+            unclaimed_eras: info.unclaimed_eras,
+            total: info.total,
+            self_stake: info.self_stake,
+        }
+    }
+}
+
+// MongoDB builds the trend in the pipeline of `get_validator`, only the fixtures of the tests need it
+#[cfg(test)]
+impl ValidatorNominationTrend {
+    // the trend of a validator from its info of each era, sorted by era, the validator fields are taken from the latest
+    pub fn from_eras(mut eras: Vec<ValidatorNominationInfo>) -> Option<Self> {
+        eras.sort_by_key(|v| v.info.era);
+        let latest = eras.last()?.clone();
+        Some(ValidatorNominationTrend {
+            id: latest.id,
+            status_change: latest.status_change,
+            identity: latest.identity,
+            average_apy: latest.average_apy,
+            staker_points: latest.staker_points,
+            info: eras.into_iter().map(|v| v.info.into()).collect(),
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct ChainInfo {
     pub active_era: u32,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct OneKvNominators {
     pub active_era: u32,
     pub nominators: Vec<OneKvNominator>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct OneKvNominator {
     current: Vec<OneKvNominated>,
     last_nomination: String,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct OneKvNominated {
     stash: String,
@@ -357,25 +373,25 @@ pub struct OneKvNominated {
     elected: bool,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct ValidatorSlash {
-    address: String,
+    pub address: String,
     #[serde(deserialize_with = "from_hex")]
     total: u128,
     others: Vec<ValidatorSlashNominator>,
-    era: u32,
+    pub era: u32,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct ValidatorStalePayoutEvent {
-    address: String,
+    pub address: String,
     unclaimed_payout_eras: Vec<u32>,
-    era: u32,
+    pub era: u32,
 }
 
-#[derive(Serialize, Deserialize,  Clone, Debug)]
+#[derive(Serialize, Deserialize,  Clone, Debug, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct ValidatorSlashNominator {
     address: String,
@@ -387,10 +403,8 @@ pub struct ValidatorSlashNominator {
 #[derive(Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct UserEventMapping {
-  pub address: String,
   #[serde(rename="type")]
   pub event_type: u32,
-  pub era: u32,
   pub mapping: mongodb::bson::oid::ObjectId
 }
 
@@ -444,12 +458,12 @@ where
                 Ok(Some(u128::from_str(num.to_string().as_str()).map_err(de::Error::custom)?))
             }
             a => {
-                println!("{:?}", a);
+                warn!("unexpected value {:?}", a);
                 Err(de::Error::custom("wrong type"))
             },
         }
     } else {
-        println!("{:?}", v);
+        warn!("undecodable value {:?}", v);
         Ok(Some(0))
     }
     
@@ -480,25 +494,40 @@ where
                 Ok(i64::from_str(num.to_string().as_str()).map_err(de::Error::custom)?)
             }
             a => {
-                println!("{:?}", a);
+                warn!("unexpected value {:?}", a);
                 Err(de::Error::custom("wrong type"))
             },
         }
     } else {
-        println!("{:?}", v);
+        warn!("undecodable value {:?}", v);
         Ok(0)
     }
     
 }
 
-#[derive(Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
 pub enum NominationStrategy {
     Default = 0,
     LowRisk = 1,
     HighApy = 2,
 }
 
-#[derive(Deserialize)]
+impl FromStr for NominationStrategy {
+    type Err = String;
+
+    // the camelCase name or the number stored with nomination actions
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "default" | "0" => Ok(NominationStrategy::Default),
+            "lowRisk" | "1" => Ok(NominationStrategy::LowRisk),
+            "highApy" | "2" => Ok(NominationStrategy::HighApy),
+            _ => Err(format!("unknown strategy {}", s)),
+        }
+    }
+}
+
+#[derive(Deserialize, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct NominationOptions {
     pub stash: String,
@@ -516,7 +545,7 @@ pub struct UserEventMappingOptions {
     pub event_types: Vec<u32>,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct NominationResultOptions {
     pub tag: String,
@@ -524,26 +553,100 @@ pub struct NominationResultOptions {
     pub ref_key: Option<String>
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct NominationResultParams {
     pub ref_key: Option<String>
 } 
 
-#[derive(Deserialize, Validate, Debug)]
+#[derive(Deserialize, JsonSchema)]
+pub struct NewsletterTokenParams {
+    pub token: String,
+}
+
+#[derive(Deserialize, Validate, Debug, JsonSchema)]
+pub struct NewsletterDataRequest {
+    #[validate(email)]
+    pub email: String,
+}
+
+// `RUST_LOG` directives, e.g. "info,cryptolab_backend=debug"
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct LogLevel {
+    pub level: String,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct NewsletterAdminParams {
+    // every subscriber when absent
+    pub status: Option<NewsletterStatus>,
+}
+
+#[derive(Deserialize, Validate, Debug, JsonSchema)]
 pub struct NewsletterSubscriberOptions {
     #[validate(email)]
     pub email: String,
+    // stashes whose staking events are sent in a digest every era
+    #[serde(default)]
+    pub stashes: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum NewsletterStatus {
+    // waiting for the address to be confirmed, nothing is sent but the confirmation. Subscribers stored before
+    // double opt-in never confirmed their address, so they are pending as well.
+    #[default]
+    Pending,
+    Confirmed,
+    Unsubscribed,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct NewsletterSubscriber {
+    pub email: String,
+    #[serde(default)]
+    pub status: NewsletterStatus,
+    #[serde(default)]
+    pub stashes: Vec<String>,
+    // when the address subscribed, in seconds
+    #[serde(default)]
+    pub timestamp: i64,
+    #[serde(default)]
+    pub confirmed_at: Option<i64>,
+    // last era a digest was sent for, by ticker
+    #[serde(default)]
+    pub digest_eras: HashMap<String, u32>,
+}
+
+#[derive(Serialize, Debug, Default, PartialEq, JsonSchema)]
+pub struct NewsletterCounts {
+    pub pending: u64,
+    pub confirmed: u64,
+    pub unsubscribed: u64,
+}
+
+#[derive(Serialize, Debug, JsonSchema)]
+pub struct NewsletterSubscriberList {
+    pub counts: NewsletterCounts,
+    pub subscribers: Vec<NewsletterSubscriber>,
 }
 
-#[derive(Deserialize, Validate, Debug, Clone)]
+#[derive(Deserialize, Validate, Debug, Clone, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct RefKeyOptions {
     pub ref_key: String,
     pub encoded: Option<String>,
 }
 
-#[derive(Deserialize, Serialize, Debug)]
+#[derive(Serialize, Debug, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RefKey {
+    pub ref_key: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct ValidatorCommission {
     pub address: String,
@@ -553,7 +656,7 @@ pub struct ValidatorCommission {
 }
 
 
-#[derive(Deserialize, Serialize, Debug)]
+#[derive(Deserialize, Serialize, Debug, Default, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct StakingEvents {
     pub commissions: Vec<ValidatorCommission>,
@@ -566,7 +669,15 @@ pub struct StakingEvents {
     pub over_subscribes: Vec<OverSubscribeEventOutput>,
 }
 
-#[derive(Deserialize, Serialize, Debug)]
+impl StakingEvents {
+    pub fn is_empty(&self) -> bool {
+        self.commissions.is_empty() && self.slashes.is_empty() && self.inactive.is_empty()
+            && self.stale_payouts.is_empty() && self.payouts.is_empty() && self.kicks.is_empty()
+            && self.chills.is_empty() && self.over_subscribes.is_empty()
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct KickEvent {
     pub address: String,
@@ -575,7 +686,7 @@ pub struct KickEvent {
 }
 
 
-#[derive(Deserialize, Serialize, Debug)]
+#[derive(Deserialize, Serialize, Debug, Clone, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct ChillEvent {
     pub address: String,
@@ -583,7 +694,7 @@ pub struct ChillEvent {
 }
 
 
-#[derive(Deserialize, Serialize, Debug)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct OverSubscribeEvent {
     pub nominators: Vec<IndividualExposure>,
@@ -591,7 +702,7 @@ pub struct OverSubscribeEvent {
     pub era: u32,
 }
 
-#[derive(Deserialize, Serialize, Debug)]
+#[derive(Deserialize, Serialize, Debug, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct OverSubscribeEventOutput {
     pub nominator: String,
@@ -600,7 +711,7 @@ pub struct OverSubscribeEventOutput {
     pub era: u32,
 }
 
-#[derive(Deserialize, Serialize, Debug)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct IndividualExposure {
     pub who: String,
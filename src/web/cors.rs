@@ -0,0 +1,124 @@
+use std::convert::Infallible;
+
+use warp::filters::BoxedFilter;
+use warp::http::header::{self, HeaderValue};
+use warp::http::Method;
+use warp::hyper::StatusCode;
+use warp::reply::Response;
+use warp::{Filter, Rejection, Reply};
+
+use crate::config::Config;
+
+// Same policy as `warp::cors()`, but the origins are checked for each request so that a reloaded `cors_url` applies
+// without a restart.
+
+const ALLOWED_HEADERS: &[&str] = &[
+  "user-agent",
+  "sec-fetch-mode",
+  "referer",
+  "origin",
+  "access-control-request-method",
+  "access-control-request-headers",
+  "content-type",
+  "x-webhook-secret",
+  "authorization",
+];
+
+const ALLOWED_METHODS: &[Method] = &[Method::GET, Method::POST, Method::PUT, Method::DELETE, Method::OPTIONS];
+
+// whether `origin` is a `cors_url` of the current config
+pub fn is_config_origin(origin: &str) -> bool {
+  Config::current().cors_url.iter().any(|url| url == origin)
+}
+
+fn forbidden(reason: &str) -> Response {
+  warp::reply::with_status(format!("CORS request forbidden: {}", reason), StatusCode::FORBIDDEN).into_response()
+}
+
+fn allow_origin(mut response: Response, origin: &str) -> Response {
+  if let Ok(origin) = HeaderValue::from_str(origin) {
+    response.headers_mut().insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin);
+    response.headers_mut().append(header::VARY, HeaderValue::from_static("origin"));
+  }
+  response
+}
+
+fn preflight(allowed: bool, origin: String, method: String, headers: Option<String>) -> Response {
+  if !allowed {
+    return forbidden("origin not allowed");
+  }
+  if !ALLOWED_METHODS.iter().any(|allowed| allowed.as_str() == method) {
+    return forbidden("request-method not allowed");
+  }
+  let headers = headers.unwrap_or_default();
+  let mut requested = headers.split(',').map(|h| h.trim().to_ascii_lowercase()).filter(|h| !h.is_empty());
+  if requested.any(|h| !ALLOWED_HEADERS.contains(&h.as_str())) {
+    return forbidden("header not allowed");
+  }
+  let methods: Vec<&str> = ALLOWED_METHODS.iter().map(Method::as_str).collect();
+  let mut response = allow_origin(warp::reply().into_response(), &origin);
+  let headers = response.headers_mut();
+  headers.insert(header::ACCESS_CONTROL_ALLOW_METHODS, HeaderValue::from_str(&methods.join(", ")).unwrap());
+  headers.insert(header::ACCESS_CONTROL_ALLOW_HEADERS, HeaderValue::from_str(&ALLOWED_HEADERS.join(", ")).unwrap());
+  response
+}
+
+// answers the preflight requests and the requests of other origins, and adds the allowed origin to the replies of
+// `routes`; `is_allowed` checks an origin
+pub fn with_cors<F, R>(routes: F, is_allowed: fn(&str) -> bool) -> BoxedFilter<(Response,)>
+where
+  F: Filter<Extract = (R,), Error = Infallible> + Clone + Send + Sync + 'static,
+  R: Reply,
+{
+  let preflight = warp::options()
+    .and(warp::header::<String>("origin"))
+    .and(warp::header::<String>("access-control-request-method"))
+    .and(warp::header::optional::<String>("access-control-request-headers"))
+    .map(move |origin: String, method, headers| preflight(is_allowed(&origin), origin, method, headers));
+  let other_origin = warp::header::<String>("origin")
+    .and_then(move |origin: String| async move {
+      if is_allowed(&origin) {
+        Err(warp::reject())
+      } else {
+        Ok::<_, Rejection>(forbidden("origin not allowed"))
+      }
+    });
+  let request = warp::header::optional::<String>("origin")
+    .and(routes)
+    .map(|origin: Option<String>, reply: R| match origin {
+      Some(origin) => allow_origin(reply.into_response(), &origin),
+      None => reply.into_response(),
+    });
+  preflight.or(other_origin).unify().or(request).unify().boxed()
+}
+
+#[cfg(test)]
+static TEST_ORIGIN: std::sync::RwLock<&str> = std::sync::RwLock::new("https://cryptolab.network");
+
+#[tokio::test]
+async fn test_with_cors() {
+  let filter = with_cors(warp::any().map(|| "ok"), |origin| origin == *TEST_ORIGIN.read().unwrap());
+
+  let response = warp::test::request().header("origin", "https://cryptolab.network").reply(&filter).await;
+  assert_eq!(StatusCode::OK, response.status());
+  assert_eq!("https://cryptolab.network", response.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN]);
+  let response = warp::test::request().reply(&filter).await;
+  assert_eq!("ok", response.body());
+  assert!(response.headers().get(header::ACCESS_CONTROL_ALLOW_ORIGIN).is_none());
+  let response = warp::test::request().header("origin", "https://example.com").reply(&filter).await;
+  assert_eq!(StatusCode::FORBIDDEN, response.status());
+
+  let preflight = |origin: &'static str, headers: &'static str| warp::test::request()
+    .method("OPTIONS")
+    .header("origin", origin)
+    .header("access-control-request-method", "PUT")
+    .header("access-control-request-headers", headers);
+  let response = preflight("https://cryptolab.network", "Content-Type, Authorization").reply(&filter).await;
+  assert_eq!(StatusCode::OK, response.status());
+  assert!(response.headers()[header::ACCESS_CONTROL_ALLOW_METHODS].to_str().unwrap().contains("PUT"));
+  assert_eq!(StatusCode::FORBIDDEN, preflight("https://cryptolab.network", "X-Other").reply(&filter).await.status());
+
+  // a reloaded config applies to the next request
+  *TEST_ORIGIN.write().unwrap() = "https://example.com";
+  assert_eq!(StatusCode::OK, preflight("https://example.com", "").reply(&filter).await.status());
+}
@@ -1,28 +1,46 @@
-use crate::cache_redis::Cache;
-use crate::config::Config;
+use crate::cache::Cache;
+use crate::chain::{self, Chain};
 use crate::referer;
+use crate::signature::{self, SignatureError};
+use crate::rewards_jobs::{RewardsJobQueue, RewardsReport};
 use serde::Deserialize;
+use schemars::JsonSchema;
 use serde::de::DeserializeOwned;
-use serde_json::json;
 use validator::Validate;
 use crate::staking_rewards_collector::StakingRewardsCollector;
+use crate::simulation::{self, SimulationOptions};
+use crate::strategy::{self, StrategyResult};
+use crate::stream::{Broadcaster, StreamEvent};
 use crate::staking_rewards_collector::{StakingRewardsAddress, StakingRewardsReport};
-use crate::types::{NewsletterSubscriberOptions, NominationOptions, NominationResultOptions, NominationResultParams, OverSubscribeEventOutput, RefKeyOptions, StakingEvents, UserEventMappingOptions, ValidatorNominationInfo};
+use crate::logging;
+use crate::newsletter::{Newsletter, TokenError, TokenPurpose};
+use crate::config::Config;
+use crate::db::DatabaseError;
+use crate::types::{LogLevel, NewsletterAdminParams, NewsletterCounts, NewsletterDataRequest, NewsletterStatus,
+  NewsletterSubscriberList, NewsletterSubscriberOptions, NewsletterTokenParams, NominationOptions, NominationResultOptions, NominationStrategy, NominationResultParams, RefKey, RefKeyOptions, ValidatorList};
+use crate::webhooks::{self, WebhookOptions, WebhookSubscription};
 use crate::web::Invalid;
 
 // use super::super::cache;
 use super::super::db::Database;
-use super::params::{ErrorCode, EventFilterOptions, OperationFailed};
-use super::params::{AllValidatorOptions, InvalidParam};
-use std::path::Path;
-use std::process::Command;
-use std::{convert::Infallible};
-use log::{debug, error, info};
+use super::super::db::repo::{Repo, get_nominator_events};
+use super::error::{ApiError, cache_rejection, db_rejection, src_rejection};
+use super::params::{ErrorCode, EventFilterOptions, StreamOptions};
+use super::params::{AllValidatorOptions, InvalidParam, MAX_PAGE_SIZE, encode_cursor};
+use super::super::db::params::{AllValidatorOptions as DbAllValidatorOptions, ValidatorCursor};
+use std::{convert::Infallible, sync::Arc};
+use futures::{stream, StreamExt};
+use tracing::{debug, error, info, warn};
 use warp::http::StatusCode;
 use warp::{Filter, Rejection, Reply};
+use subtle::ConstantTimeEq;
 
-#[derive(Deserialize)]
-struct StakingRewardsOptions {
+// stashes a newsletter subscriber or a stream can watch
+const MAX_NEWSLETTER_STASHES: usize = 16;
+const MAX_STREAM_STASHES: usize = 16;
+
+#[derive(Deserialize, JsonSchema)]
+pub(super) struct StakingRewardsOptions {
   pub start: Option<String>,
   pub end: Option<String>,
   pub currency: Option<String>,
@@ -48,6 +66,26 @@ fn validate_get_all_validators() -> impl Filter<Extract = (AllValidatorOptions,)
       return Err(warp::reject::custom(InvalidParam::new("commission_max must be between 0 ~ 1.",
       ErrorCode::InvalidCommission)));
     }
+    if !(1..=MAX_PAGE_SIZE).contains(&params.size()) {
+      return Err(warp::reject::custom(InvalidParam::new(&format!("size must be between 1 ~ {}.", MAX_PAGE_SIZE),
+      ErrorCode::InvalidRequest)));
+    }
+    if params.skip().is_none() {
+      return Err(warp::reject::custom(InvalidParam::new("page is too large for the size.",
+      ErrorCode::InvalidRequest)));
+    }
+    if !(0.0..=1.0).contains(&params.era_points_percentile().unwrap_or(0.0)) {
+      return Err(warp::reject::custom(InvalidParam::new("era_points_percentile must be between 0 ~ 1.",
+      ErrorCode::InvalidRequest)));
+    }
+    for (name, min, max) in [("total", params.total_min(), params.total_max()),
+      ("self_stake", params.self_stake_min(), params.self_stake_max())] {
+      if min.unwrap_or(0.0) < 0.0 || max.unwrap_or(0.0) < 0.0 || min.unwrap_or(0.0) > max.unwrap_or(f64::MAX) {
+        return Err(warp::reject::custom(InvalidParam::new(&format!("{}_min and {}_max must be positive and ordered.", name, name),
+        ErrorCode::InvalidRequest)));
+      }
+    }
+    params.cursor().map_err(warp::reject::custom)?;
     Ok(params)
   })
 }
@@ -75,39 +113,97 @@ fn validate_ref_key_options() -> impl Filter<Extract = (RefKeyOptions,), Error =
 
 fn validate_newsletter_subscription() -> impl Filter<Extract = (NewsletterSubscriberOptions,), Error = Rejection> + Copy {
   warp::filters::body::json().and_then(|params: NewsletterSubscriberOptions| async move {
-    match params.validate() {
-      Ok(_) => {
-        Ok(params)
-      },
-      Err(e) => {
-        println!("{:?}", e);
-        Err(warp::reject::custom(InvalidParam::new("Must be a valid email address",
-      ErrorCode::InvalidEmailAddress)))
-      }
+    if let Err(e) = params.validate() {
+      debug!("invalid newsletter subscription: {}", e);
+      return Err(warp::reject::custom(InvalidParam::new("Must be a valid email address",
+      ErrorCode::InvalidEmailAddress)));
     }
+    if params.stashes.len() > MAX_NEWSLETTER_STASHES {
+      return Err(warp::reject::custom(InvalidParam::new(
+        &format!("at most {} stashes can be watched", MAX_NEWSLETTER_STASHES), ErrorCode::InvalidRequest)));
+    }
+    if let Some(stash) = params.stashes.iter().find(|s| chain::ss58_decode(s).is_none()) {
+      return Err(warp::reject::custom(InvalidParam::new(&format!("{} is not a valid address", stash),
+      ErrorCode::InvalidAddress)));
+    }
+    Ok(params)
   })
 }
 
-fn get_all_validators(chain: &'static str, db: Database, cache: Cache) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+fn validate_newsletter_data_request() -> impl Filter<Extract = (NewsletterDataRequest,), Error = Rejection> + Copy {
+  warp::filters::body::json().and_then(|params: NewsletterDataRequest| async move {
+    if params.validate().is_err() {
+      return Err(warp::reject::custom(InvalidParam::new("Must be a valid email address",
+      ErrorCode::InvalidEmailAddress)));
+    }
+    Ok(params)
+  })
+}
+
+fn token_rejection(err: TokenError) -> Rejection {
+  warp::reject::custom(InvalidParam::new(&err.to_string(), ErrorCode::InvalidToken))
+}
+
+fn get_all_validators(chain: &'static Chain, db: Repo, cache: Cache) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::path("api")
     .and(warp::path("v1"))
     .and(warp::path("validators"))
-    .and(warp::path(chain))
+    .and(warp::path(chain.ticker.as_str()))
     .and(warp::path::end())
-    .and(with_db(db))
+    .and(with_repo(db))
     .and(with_cache(cache))
     .and(validate_get_all_validators())
-    .and_then(move |db: Database, cache: Cache, p: AllValidatorOptions| async move {
-        let mut era = cache.get_current_era(chain);
-        if era == 0 {
-          era = db.get_chain_info().await.unwrap().active_era;
-        }
-        let validators = get_validator_data_from_db(db, cache, chain.to_string(), era, p).await;
-        validators
+    .and_then(move |db: Repo, cache: Cache, p: AllValidatorOptions| async move {
+        let era = current_era(&cache, &db, &chain.ticker).await?;
+        get_validator_data_from_db(db, cache, chain, era, p).await
     })
 }
 
-fn get_nominator_info(chain: &'static str, db: Database) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+// the era cached by the scheduler, or the active era in the chain database when the cache cannot answer
+async fn current_era(cache: &Cache, db: &Repo, chain: &str) -> Result<u32, Rejection> {
+  match cache.get_current_era(chain).await {
+    Ok(era) if era > 0 => Ok(era),
+    result => {
+      if let Err(e) = result {
+        warn!("{}", e);
+      }
+      match db.get_chain_info().await {
+        Ok(chain_info) => Ok(chain_info.active_era),
+        Err(e) => Err(warp::reject::custom(ApiError::unavailable(format!("current era of {} is unknown: {}", chain, e)))),
+      }
+    }
+  }
+}
+
+fn get_nomination_strategy(chain: &'static Chain, db: Repo, cache: Cache) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+  warp::path("api")
+  .and(warp::path("v1"))
+  .and(warp::path("strategy"))
+  .and(warp::path(chain.ticker.as_str()))
+  .and(warp::path::param())
+  .and(warp::path::end())
+  .and(with_repo(db))
+  .and(with_cache(cache))
+  .and_then(move |strategy: String, db: Repo, cache: Cache| async move {
+    let strategy: NominationStrategy = strategy.parse()
+      .map_err(|e: String| warp::reject::custom(InvalidParam::new(&e, ErrorCode::UnsupportedStrategy)))?;
+    let mut era = current_era(&cache, &db, &chain.ticker).await?;
+    let mut validators = db.get_all_validator_info_of_era(era, DbAllValidatorOptions::new()).await
+      .map_err(db_rejection)?;
+    if validators.is_empty() && era > 0 {
+      era -= 1;
+      validators = db.get_all_validator_info_of_era(era, DbAllValidatorOptions::new()).await
+        .map_err(db_rejection)?;
+    }
+    Ok::<_, Rejection>(warp::reply::json(&StrategyResult {
+      era,
+      strategy,
+      validators: strategy::select(strategy, chain, &validators),
+    }))
+  })
+}
+
+fn get_nominator_info(chain: &'static str, db: Repo) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
   warp::path("api")
   .and(warp::path("v1"))
   .and(warp::path("nominator"))
@@ -115,36 +211,34 @@ fn get_nominator_info(chain: &'static str, db: Database) -> impl Filter<Extract
   .and(warp::path::param())
   .and(warp::path(chain))
   .and(warp::path::end())
-  .and(with_db(db))
-  .and_then(|id: String, mut db: Database| async move {
-    let nominator = db.get_nominator_info(&id).await;
-    if let Ok(nominator) = nominator {
-      Ok(warp::reply::with_status(
+  .and(with_repo(db))
+  .and_then(|id: String, db: Repo| async move {
+    match db.get_nominator_info(&id).await {
+      Ok(nominator) => Ok(warp::reply::with_status(
         warp::reply::json(&nominator),
         StatusCode::OK,
-      ))
-    } else {
-      Err(warp::reject::not_found())
+      )),
+      Err(e) => Err(db_rejection(e)),
     }
   })
 }
 
 fn get_validator_history(
   chain: &'static str,
-  db: Database,
+  db: Repo,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
   warp::path("api")
   .and(warp::path("v1"))
   .and(warp::path("validator"))
-  .and(with_db(db))
+  .and(with_repo(db))
   .and(warp::path::param())
   .and(warp::path(chain))
   .and(warp::path::end())
-  .and_then(|db: Database, stash: String| async move {
+  .and_then(|db: Repo, stash: String| async move {
       let validator = db.get_validator(stash).await;
       match validator {
           Ok(v) => Ok(warp::reply::json(&[v])),
-          Err(_) => Err(warp::reject::not_found()),
+          Err(e) => Err(db_rejection(e)),
       }
   })
 }
@@ -158,7 +252,10 @@ fn get_all_nominators(
   .and(warp::path("nominators"))
   .and(warp::path(chain))
   .and(warp::path::end())
-  .map(move || warp::reply::json(&cache.get_nominators(chain)))
+  .and(with_cache(cache))
+  .and_then(move |cache: Cache| async move {
+    cache.get_nominators(chain).await.map(|v| warp::reply::json(&v)).map_err(cache_rejection)
+  })
 }
 
 fn get_1kv_validators(
@@ -172,7 +269,10 @@ fn get_1kv_validators(
     .and(warp::path("validators"))
     .and(warp::path(chain))
     .and(warp::path::end())
-    .map(move || warp::reply::json(&cache.get_1kv_info_detail(chain)))
+    .and(with_cache(cache))
+  .and_then(move |cache: Cache| async move {
+    cache.get_1kv_info_detail(chain).await.map(|v| warp::reply::json(&v)).map_err(cache_rejection)
+  })
 }
 
 fn get_1kv_nominators(
@@ -186,156 +286,180 @@ fn get_1kv_nominators(
   .and(warp::path("nominators"))
   .and(warp::path(chain))
   .and(warp::path::end())
-  .map(move || warp::reply::json(&cache.get_1kv_nominators(chain)))
+  .and(with_cache(cache))
+  .and_then(move |cache: Cache| async move {
+    cache.get_1kv_nominators(chain).await.map(|v| warp::reply::json(&v)).map_err(cache_rejection)
+  })
+}
+
+fn to_rewards_collector(stash: &str, p: StakingRewardsOptions) -> Result<StakingRewardsCollector, Rejection> {
+  let start = "2020-01-01".to_string();
+  let end = chrono::Utc::now().format("%Y-%m-%d").to_string();
+  let currency = "USD".to_string();
+  let network = match chain::find_by_address(stash) {
+    Some(chain) => chain.src_network.clone(),
+    None => {
+      return Err(warp::reject::custom(InvalidParam::new("stash does not belong to a supported network",
+      ErrorCode::UnsupportedNetwork)));
+    }
+  };
+  StakingRewardsCollector::new(p.start.unwrap_or(start), p.end.unwrap_or(end),
+  p.currency.unwrap_or(currency), p.price_data.unwrap_or(true),
+  vec![StakingRewardsAddress::new("".to_string(), stash.to_string(), p.start_balance.unwrap_or(0.0), network)])
+  .map_err(src_rejection)
 }
 
-fn get_stash_rewards_collector(src_path: String) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+// the report with 200 once it is computed, its job with 202 until then
+pub(super) fn rewards_report_reply(report: RewardsReport) -> warp::reply::WithStatus<warp::reply::Json> {
+  match report {
+    RewardsReport::Done(rewards) => warp::reply::with_status(warp::reply::json(&rewards), StatusCode::OK),
+    RewardsReport::Pending(job) => warp::reply::with_status(warp::reply::json(&job), StatusCode::ACCEPTED),
+  }
+}
+
+fn get_stash_rewards_collector(jobs: RewardsJobQueue) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
   warp::path("api")
   .and(warp::path("v1"))
   .and(warp::path("stash"))
       .and(warp::path::param())
-      .and(with_string(src_path))
+      .and(with_jobs(jobs))
       .and(warp::path("rewards"))
       .and(warp::path("collector"))
       .and(warp::path::end())
       .and(warp::query::<StakingRewardsOptions>())
-      .and_then(|stash: String, src_path: String, p: StakingRewardsOptions| async move {
-          let start = "2020-01-01".to_string();
-          let end = chrono::Utc::now().format("%Y-%m-%d").to_string();
-          let currency = "USD".to_string();
-          let mut network = "Kusama";
-          if stash.starts_with("1") {
-            network = "Polkadot";
-          }
-          let src = StakingRewardsCollector::new(p.start.unwrap_or(start), p.end.unwrap_or(end),
-          p.currency.unwrap_or(currency), p.price_data.unwrap_or(true),
-          vec![StakingRewardsAddress::new("".to_string(), stash.clone(), p.start_balance.unwrap_or(0.0), network.to_string())]);
-          match src {
-              Ok(src) => {
-                  let result = src.call_exe(src_path.to_string());
-                  match result {
-                      Ok(v) => {
-                          Ok(warp::reply::json(&v))
-                      },
-                      Err(e) => {
-                          error!("{}", e);
-                          if e.err_code == -2 {
-                              Err(warp::reject::not_found())
-                          } else {
-                              Err(warp::reject::custom(e))
-                          }
-                      },
-                  }
-              },
+      .and_then(|stash: String, jobs: RewardsJobQueue, p: StakingRewardsOptions| async move {
+          let src = to_rewards_collector(&stash, p)?;
+          match jobs.report(&src).await {
+              Ok(report) => Ok(rewards_report_reply(report)),
               Err(e) => {
-                  Err(warp::reject::custom(e))
+                  error!("{}", e);
+                  Err(src_rejection(e))
               },
           }
       })
 }
 
-fn get_stash_rewards_collector_csv(src_path: String) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+fn create_stash_rewards_job(jobs: RewardsJobQueue) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
   warp::path("api")
-      .and(warp::path("v1"))
-      .and(warp::path("stash"))
+  .and(warp::path("v1"))
+  .and(warp::path("stash"))
       .and(warp::path::param())
-      .and(with_string(src_path))
+      .and(with_jobs(jobs))
       .and(warp::path("rewards"))
       .and(warp::path("collector"))
-      .and(warp::path("csv"))
       .and(warp::path::end())
-      .and_then(|stash: String, src_path: String| async move{
-          // validate stash
-          if !stash.chars().all(char::is_alphanumeric) {
-              debug!("{}", stash);
-              Err(warp::reject::custom(Invalid))
-          } else {
-              // get file from src path
-              let srr = StakingRewardsReport::new(src_path, stash, "csv".to_string());
-              let file = srr.get_report();
-              match file {
-                  Ok(data) => {
-                      Ok(data)
-                  },
-                  Err(err) => {
-                      Err(warp::reject::custom(err))
-                  },
-              }
+      .and(warp::query::<StakingRewardsOptions>())
+      .and_then(|stash: String, jobs: RewardsJobQueue, p: StakingRewardsOptions| async move {
+          let src = to_rewards_collector(&stash, p)?;
+          match jobs.submit(&src).await {
+              Ok(job) => {
+                  Ok(warp::reply::with_status(warp::reply::json(&job), StatusCode::ACCEPTED))
+              },
+              Err(e) => Err(db_rejection(e)),
+          }
+      })
+}
+
+fn get_stash_rewards_job(jobs: RewardsJobQueue) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+  warp::path("api")
+  .and(warp::path("v1"))
+  .and(warp::path("rewards"))
+  .and(warp::path("jobs"))
+      .and(warp::path::param())
+      .and(warp::path::end())
+      .and(with_jobs(jobs))
+      .and_then(|job_id: String, jobs: RewardsJobQueue| async move {
+          match jobs.get(&job_id).await {
+              Ok(job) => Ok(warp::reply::json(&job)),
+              Err(e) => Err(db_rejection(e)),
           }
-          
       })
 }
 
-fn get_stash_rewards_collector_json(src_path: String) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+fn get_stash_rewards_collector_report(users_db: Database, format: &'static str) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
   warp::path("api")
   .and(warp::path("v1"))
   .and(warp::path("stash"))
       .and(warp::path::param())
-      .and(with_string(src_path))
+      .and(with_db(users_db))
       .and(warp::path("rewards"))
       .and(warp::path("collector"))
-      .and(warp::path("json"))
+      .and(warp::path(format))
       .and(warp::path::end())
-      .and_then(|stash: String, src_path: String| async move{
+      .and_then(move |stash: String, users_db: Database| async move {
           // validate stash
           if !stash.chars().all(char::is_alphanumeric) {
               debug!("{}", stash);
               Err(warp::reject::custom(Invalid))
           } else {
-              // get file from src path
-              let srr = StakingRewardsReport::new(src_path, stash, "json".to_string());
-              let file = srr.get_report();
-              match file {
+              let srr = StakingRewardsReport::new(stash, format.to_string());
+              match srr.get_report(&users_db).await {
                   Ok(data) => {
                       Ok(data)
                   },
                   Err(err) => {
-                      Err(warp::reject::custom(err))
+                      Err(src_rejection(err))
                   },
               }
           }
-          
       })
 }
 
 fn get_validator_unclaimed_eras(
   chain: &'static str,
-  db: Database,
+  db: Repo,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
   warp::path("api")
   .and(warp::path("v1"))
   .and(warp::path("validator"))
-  .and(with_db(db))
+  .and(with_repo(db))
   .and(warp::path::param())
   .and(warp::path("unclaimedEras"))
   .and(warp::path(chain))
   .and(warp::path::end())
-  .and_then(|db: Database, stash: String| async move {
+  .and_then(|db: Repo, stash: String| async move {
       let validator = db.get_validator_unclaimed_eras(stash).await;
       match validator {
           Ok(v) => Ok(warp::reply::json(&v)),
-          Err(_) => Err(warp::reject::not_found()),
+          Err(e) => Err(db_rejection(e)),
       }
   })
 }
 
+fn get_validator_risk(
+  chain: &'static str,
+  db: Repo,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+  warp::path("api")
+  .and(warp::path("v1"))
+  .and(warp::path("validator"))
+  .and(with_repo(db))
+  .and(warp::path::param())
+  .and(warp::path("risk"))
+  .and(warp::path(chain))
+  .and(warp::path::end())
+  .and_then(|db: Repo, stash: String| async move {
+      db.get_validator_risk(&stash).await.map(|v| warp::reply::json(&v)).map_err(db_rejection)
+  })
+}
+
 fn get_validator_slashes(
   chain: &'static str,
-  db: Database,
+  db: Repo,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
   warp::path("api")
   .and(warp::path("v1"))
   .and(warp::path("validator"))
-  .and(with_db(db))
+  .and(with_repo(db))
   .and(warp::path::param())
   .and(warp::path("slashes"))
   .and(warp::path(chain))
   .and(warp::path::end())
-  .and_then(|db: Database, stash: String| async move {
+  .and_then(|db: Repo, stash: String| async move {
       let validator = db.get_validator_slashes(stash).await;
       match validator {
           Ok(v) => Ok(warp::reply::json(&v)),
-          Err(_) => Err(warp::reject::not_found()),
+          Err(e) => Err(db_rejection(e)),
       }
   })
 }
@@ -346,115 +470,116 @@ fn with_db(
     warp::any().map(move || db.clone())
 }
 
+fn with_repo(
+  repo: Repo,
+) -> impl Filter<Extract = (Repo,), Error = std::convert::Infallible> + Clone {
+  warp::any().map(move || repo.clone())
+}
+
 fn with_cache(
   cache: Cache,
 ) -> impl Filter<Extract = (Cache,), Error = std::convert::Infallible> + Clone {
   warp::any().map(move || cache.clone())
 }
 
-fn with_string(
-  s: String
-) -> impl Filter<Extract = (String,), Error = std::convert::Infallible> + Clone {
-  warp::any().map(move || s.clone())
+fn with_jobs(
+  jobs: RewardsJobQueue,
+) -> impl Filter<Extract = (RewardsJobQueue,), Error = std::convert::Infallible> + Clone {
+  warp::any().map(move || jobs.clone())
 }
 
 async fn gen_ref_key(
-  db: Database,
+  db: Repo,
   stash: &str
 ) -> Result<warp::reply::Json, Infallible> {
-  match db.get_validator_ref_key(&stash).await {
-    Ok(ref_key) => {
-      Ok(warp::reply::json(&json!({
-        "refKey": ref_key
-      })))
-    },
-    Err(_) => {
-      let ref_key = referer::gen_ref_key(&stash);
-      Ok(warp::reply::json(&json!({
-        "refKey": ref_key
-      })))
-    },
-  }
+  let ref_key = match db.get_validator_ref_key(stash).await {
+    Ok(ref_key) => ref_key,
+    Err(_) => referer::gen_ref_key(stash),
+  };
+  Ok(warp::reply::json(&RefKey { ref_key }))
 }
 
 async fn get_validator_data_from_db(
-    db: Database,
+    db: Repo,
     cache: Cache,
-    chain: String,
+    chain: &Chain,
     era: u32,
     options: AllValidatorOptions,
-) -> Result<warp::reply::WithStatus<warp::reply::Json>, Infallible> {
-    let mut result = db.get_all_validator_info_of_era(era, options.to_db_all_validator_options()).await.unwrap();
-    if result.is_empty() {
-      result = db.get_all_validator_info_of_era(era - 1, options.to_db_all_validator_options()).await.unwrap();
+) -> Result<warp::reply::WithStatus<warp::reply::Json>, Rejection> {
+    if options.has_joined_1kv() && !chain.one_kv {
+      return Ok(warp::reply::with_status(
+        warp::reply::json(&ValidatorList { era, total_count: 0, next: None, validators: vec![] }),
+        StatusCode::OK,
+      ));
     }
+    let mut db_options = options.to_db_all_validator_options(chain);
     if options.has_joined_1kv() {
-      if chain == "WND" {
-        Ok(warp::reply::with_status(
-          warp::reply::json(&json!([])),
-          StatusCode::OK,
-        ))
-      } else {
-        let one_kv = cache.get_1kv_info_detail(&chain);
-        let mut one_kv_nodes: Vec<ValidatorNominationInfo> = [].to_vec();
-        for v in result {
-          let mut is1kv = false;
-          for o in &one_kv.valid {
-              if o.stash == v.id {
-                is1kv = true;
-                break;
-              }
-          }
-          if is1kv {
-            one_kv_nodes.push(v);
-          }
-        }
-        Ok(warp::reply::with_status(
-          warp::reply::json(&one_kv_nodes),
-          StatusCode::OK,
-        ))
-      }
-    } else {
-      Ok(warp::reply::with_status(
-          warp::reply::json(&result),
-          StatusCode::OK,
-      ))
+      let one_kv = cache.get_1kv_info_detail(&chain.ticker).await.map_err(cache_rejection)?;
+      db_options.stashes = Some(one_kv.valid.into_iter().map(|v| v.stash).collect());
     }
+    // a cursor keeps paging through the era it was issued for
+    let (era, total_count) = match &db_options.after {
+      Some(cursor) => (cursor.era, db.count_validators_of_era(cursor.era, &db_options).await.map_err(db_rejection)?),
+      None => {
+        let count = db.count_validators_of_era(era, &db_options).await.map_err(db_rejection)?;
+        if count == 0 && era > 0 {
+          (era - 1, db.count_validators_of_era(era - 1, &db_options).await.map_err(db_rejection)?)
+        } else {
+          (era, count)
+        }
+      },
+    };
+    let sort = db_options.sort;
+    let descending = db_options.descending;
+    let size = db_options.size as usize;
+    let result = db.get_all_validator_info_of_era(era, db_options).await
+      .map_err(db_rejection)?;
+    let next = match result.last() {
+      Some(last) if result.len() == size => last.sort_key.clone().map(|key| encode_cursor(&ValidatorCursor {
+        era,
+        sort,
+        descending,
+        key,
+        id: last.id.clone(),
+      })),
+      _ => None,
+    };
+    Ok(warp::reply::with_status(
+      warp::reply::json(&ValidatorList { era, total_count, next, validators: result }),
+      StatusCode::OK,
+    ))
 }
 
 fn get_nominated_validators(
   chain: &'static str,
-  db: Database,
+  db: Repo,
   cache: Cache,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
   warp::path("api")
   .and(warp::path("v1"))
   .and(warp::path("nominated"))
-  .and(with_db(db))
+  .and(with_repo(db))
   .and(with_cache(cache))
   .and(warp::path("stash"))
   .and(warp::path::param())
   .and(warp::path(chain))
   .and(warp::path::end())
-  .and_then(move |db: Database, cache: Cache, stash: String| async move {
-      let result = &cache.get_nominator(&chain, stash);
+  .and_then(move |db: Repo, cache: Cache, stash: String| async move {
+      let result = cache.get_nominator(chain, stash).await;
       match result {
           Ok(nominator) => {
-            let mut era = cache.get_current_era(chain);
-            if era == 0 {
-              era = db.get_chain_info().await.unwrap().active_era;
-            }
+            let era = current_era(&cache, &db, chain).await?;
             let result = db
               .get_validator_info(&nominator.targets, &era)
               .await;
               match result {
                   Ok(validators) => Ok(warp::reply::json(&validators)),
-                  Err(_) => Err(warp::reject::not_found()),
+                  Err(e) => Err(db_rejection(e)),
               }
           }
-          Err(_) => {
-              error!("{}", "failed to get nominated list from the cache");
-              Err(warp::reject::not_found())
+          Err(e) => {
+              error!("failed to get nominated list from the cache: {}", e);
+              Err(cache_rejection(e))
           }
       }
   })
@@ -462,95 +587,36 @@ fn get_nominated_validators(
 
 fn get_events(
   chain: &'static str,
-  db: Database,
-  user_db: Database,
+  db: Repo,
+  user_db: Repo,
   cache: Cache,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
   warp::path("api")
   .and(warp::path("v1"))
   .and(warp::path("events"))
-  .and(with_db(db))
-  .and(with_db(user_db))
+  .and(with_repo(db))
+  .and(with_repo(user_db))
   .and(with_cache(cache))
   .and(warp::path("stash"))
   .and(warp::path::param())
   .and(warp::path(chain))
   .and(warp::path::end())
   .and(validate_event_filters())
-  .and_then(move |mut db: Database, mut user_db: Database, cache: Cache, stash: String, filters: EventFilterOptions| async move {
-      let result = db.get_nominator_info(&stash).await;
-      match result {
-          Ok(nominator) => {
-              let mut era = cache.get_current_era(chain);
-              if era == 0 {
-                era = db.get_chain_info().await.unwrap().active_era;
-              }
-              let mut to_era = era;
-              if filters.to_era() > 0 {
-                to_era = filters.to_era();
-              }
-              let mut from_era = era - 84;
-              if filters.from_era() > 0 {
-                from_era = filters.from_era();
-              }
-              match user_db.get_nomination_records(&stash).await {
-                  Ok(c) => {
-                    let options = UserEventMappingOptions {
-                      stash,
-                      from_era,
-                      to_era,
-                      event_types: vec![0, 1, 2, 3, 4, 5, 6],
-                    };
-                    let events = db.get_user_events_by_mapping(options).await;
-                    match events {
-                        Ok(events) => {
-                          Ok(warp::reply::json(&events))
-                        },
-                        Err(_) => {
-                          Err(warp::reject::not_found())
-                        },
-                    }
-                  },
-                  Err(_) => {
-                    let commission = db
-                    .get_is_commission_changed(&nominator.targets, from_era, to_era)
-                    .await;
-                    let slash = db
-                    .get_multiple_validators_slashes(&nominator.targets, from_era, to_era)
-                    .await;
-                    let inactive = db.get_all_validators_inactive(&stash, from_era, to_era).await;
-                    let stale_payouts =
-                      db.get_nominated_validators_stale_payout_events(&nominator.targets, from_era, to_era).await;
-                    let payouts = db.get_nominated_validators_payout_events(nominator.account_id, from_era, to_era).await;
-                    let kicks = db.get_kick_events(&stash, &from_era, &to_era).await;
-                    let chills = db.get_chill_events(&nominator.targets, &from_era, &to_era).await;
-                    let oversubscribes = db.get_oversubscribe_events(&stash, &from_era, &to_era).await.unwrap_or_default();
-                    let mut o = Vec::<OverSubscribeEventOutput>::new();
-                    for ele in oversubscribes {
-                        o.push(OverSubscribeEventOutput {
-                          era: ele.era,
-                          address: ele.address,
-                          nominator: stash.clone(),
-                          amount: ele.nominators.iter().find(|&x| x.who == stash.clone()).unwrap().value.clone()
-                        });
-                    }
-                    let events = StakingEvents {
-                      commissions: commission.unwrap_or_default(),
-                      slashes: slash.unwrap_or_default(),
-                      inactive: inactive.unwrap_or_default(),
-                      stale_payouts: stale_payouts.unwrap_or_default(),
-                      payouts: payouts.unwrap_or_default(),
-                      kicks: kicks.unwrap_or_default(),
-                      chills: chills.unwrap_or_default(),
-                      over_subscribes: o,
-                    };
-                    Ok(warp::reply::json(&events))
-                  }
-              }
-          }
-          Err(_) => {
-              error!("{}", "failed to get nominated list from the cache");
-              Err(warp::reject::not_found())
+  .and_then(move |db: Repo, user_db: Repo, cache: Cache, stash: String, filters: EventFilterOptions| async move {
+      let era = current_era(&cache, &db, chain).await?;
+      let mut to_era = era;
+      if filters.to_era() > 0 {
+        to_era = filters.to_era();
+      }
+      let mut from_era = era.saturating_sub(84);
+      if filters.from_era() > 0 {
+        from_era = filters.from_era();
+      }
+      match get_nominator_events(&*db, &*user_db, &stash, from_era, to_era).await {
+          Ok(events) => Ok(warp::reply::json(&events)),
+          Err(e) => {
+              error!("failed to get the events of {}: {}", stash, e);
+              Err(db_rejection(e))
           }
       }
   })
@@ -558,19 +624,19 @@ fn get_events(
 
 fn get_ref_key(
   chain: &'static str,
-  db: Database,
-  user_db: Database
+  db: Repo,
+  user_db: Repo
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
   warp::path("api")
   .and(warp::path("v1"))
   .and(warp::path("refKey"))
-  .and(with_db(db))
-  .and(with_db(user_db))
+  .and(with_repo(db))
+  .and(with_repo(user_db))
   .and(warp::path("stash"))
   .and(warp::path::param())
   .and(warp::path(chain))
   .and(warp::path::end())
-  .and_then(move |db: Database, user_db: Database, stash: String| async move {
+  .and_then(move |_db: Repo, user_db: Repo, stash: String| async move {
     gen_ref_key(user_db, &stash).await
   })
 }
@@ -583,162 +649,542 @@ fn json_body<T: DeserializeOwned + Send>() -> impl Filter<Extract = (T,), Error
 
 fn post_nominated_records(
   chain: &'static str,
-  db: Database,
+  db: Repo,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
   warp::path("api")
   .and(warp::path("v1"))
   .and(warp::path("nominate"))
-  .and(with_db(db))
+  .and(with_repo(db))
   .and(warp::path(chain))
   .and(warp::path::end())
   .and(json_body::<NominationOptions>())
   .and(warp::post())
-  .and_then(move |db: Database, options: NominationOptions| async move {
+  .and_then(move |db: Repo, options: NominationOptions| async move {
     let result = db.insert_nomination_action(chain.to_string(), options).await;
-    if result.is_ok() {
-      let tag = result.unwrap();
-      Ok(warp::reply::with_status(
-        tag,
-        StatusCode::OK,
-      ))
-    } else {
-      let err = result.err().unwrap();
-      Err(warp::reject::custom(
-        OperationFailed::new(&err.to_string(), ErrorCode::OperationFailed)
-      ))
+    match result {
+      Ok(tag) => {
+        Ok(warp::reply::with_status(
+          tag,
+          StatusCode::OK,
+        ))
+      },
+      Err(err) => Err(db_rejection(err)),
     }
   })
 }
 
 fn post_nominated_result(
   chain: &'static str,
-  db: Database,
+  db: Repo,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
   warp::path("api")
   .and(warp::path("v1"))
   .and(warp::path("nominated"))
-  .and(with_db(db))
+  .and(with_repo(db))
   .and(warp::path(chain))
   .and(warp::path::end())
   .and(json_body::<NominationResultOptions>())
   .and(warp::query::<NominationResultParams>())
   .and(warp::post())
-  .and_then(move |db: Database, mut options: NominationResultOptions, params: NominationResultParams| async move { 
+  .and_then(move |db: Repo, mut options: NominationResultOptions, params: NominationResultParams| async move { 
     options.ref_key = params.ref_key;
-    let result = db.insert_nomination_result(options).await;
-    if result.is_ok() {
-      Ok(warp::reply::with_status(
+    match db.insert_nomination_result(options).await {
+      Ok(_) => Ok(warp::reply::with_status(
         "",
         StatusCode::OK,
-      ))
-    } else {
-      let err = result.err().unwrap();
-      Err(warp::reject::custom(
-        OperationFailed::new(&err.to_string(), ErrorCode::OperationFailed)
-      ))
+      )),
+      Err(err) => Err(db_rejection(err)),
+    }
+  })
+}
+
+fn validate_simulation(chain: &'static Chain) -> impl Filter<Extract = (SimulationOptions,), Error = Rejection> + Copy {
+  warp::filters::body::json().and_then(move |params: SimulationOptions| async move {
+    if params.amount == 0 {
+      return Err(warp::reject::custom(InvalidParam::new("amount must be positive",
+      ErrorCode::InvalidRequest)));
+    }
+    if params.validators.is_empty() || params.validators.len() > chain.max_nominations as usize {
+      return Err(warp::reject::custom(InvalidParam::new(
+        &format!("validators must list 1 to {} stashes", chain.max_nominations), ErrorCode::InvalidRequest)));
     }
+    if params.validators.iter().enumerate().any(|(i, v)| params.validators[..i].contains(v)) {
+      return Err(warp::reject::custom(InvalidParam::new("validators must not repeat a stash",
+      ErrorCode::InvalidRequest)));
+    }
+    Ok(params)
   })
 }
 
+fn post_simulation(
+  chain: &'static Chain,
+  db: Repo,
+  cache: Cache,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+  warp::path("api")
+  .and(warp::path("v1"))
+  .and(warp::path("simulate"))
+  .and(warp::path(chain.ticker.as_str()))
+  .and(warp::path::end())
+  .and(warp::post())
+  .and(with_repo(db))
+  .and(with_cache(cache))
+  .and(validate_simulation(chain))
+  .and_then(move |db: Repo, cache: Cache, options: SimulationOptions| async move {
+    let exposures = |era: u32| {
+      let mut db_options = DbAllValidatorOptions::new();
+      db_options.stashes = Some(options.validators.clone());
+      db.get_all_validator_info_of_era(era, db_options)
+    };
+    let mut era = current_era(&cache, &db, &chain.ticker).await?;
+    let mut validators = exposures(era).await.map_err(db_rejection)?;
+    if validators.is_empty() && era > 0 {
+      era -= 1;
+      validators = exposures(era).await.map_err(db_rejection)?;
+    }
+    Ok::<_, Rejection>(warp::reply::json(&simulation::simulate(chain, era, &options, &validators)))
+  })
+}
+
+fn validate_webhook(chain: &'static Chain) -> impl Filter<Extract = (WebhookOptions,), Error = Rejection> + Copy {
+  warp::filters::body::json().and_then(move |params: WebhookOptions| async move {
+    if chain::ss58_decode(&params.stash).map(|(prefix, _)| prefix) != Some(chain.ss58_prefix) {
+      return Err(warp::reject::custom(InvalidParam::new(&format!("stash must be a {} address", chain.ticker),
+      ErrorCode::InvalidAddress)));
+    }
+    if let Err(message) = webhooks::check_url(&params.url).await {
+      return Err(warp::reject::custom(InvalidParam::new(&message, ErrorCode::InvalidUrl)));
+    }
+    if params.event_types.is_empty() {
+      return Err(warp::reject::custom(InvalidParam::new("eventTypes cannot be empty",
+      ErrorCode::InvalidRequest)));
+    }
+    // only the stash subscribes to its events, with a signature made recently
+    if (chrono::Utc::now().timestamp() - params.issued_at).abs() > webhooks::SIGNATURE_MAX_AGE_SECS {
+      return Err(warp::reject::custom(InvalidParam::new(
+        &format!("issuedAt must be within {} seconds of the server time", webhooks::SIGNATURE_MAX_AGE_SECS),
+        ErrorCode::WrongMessage)));
+    }
+    let message = webhooks::subscription_message(&chain.ticker, &params.url, params.issued_at);
+    signature::verify(message.as_bytes(), &params.signature, &params.stash).map_err(signature_rejection)?;
+    Ok(params)
+  })
+}
+
+fn validate_stream(chain: &'static Chain) -> impl Filter<Extract = (Vec<String>,), Error = Rejection> + Copy {
+  warp::filters::query::query().and_then(move |params: StreamOptions| async move {
+    let stashes = params.stashes();
+    if stashes.len() > MAX_STREAM_STASHES {
+      return Err(warp::reject::custom(InvalidParam::new(
+        &format!("at most {} stashes can be streamed", MAX_STREAM_STASHES), ErrorCode::InvalidRequest)));
+    }
+    if let Some(stash) = stashes.iter().find(|s| chain::ss58_decode(s).map(|(prefix, _)| prefix) != Some(chain.ss58_prefix)) {
+      return Err(warp::reject::custom(InvalidParam::new(&format!("{} is not a {} address", stash, chain.ticker),
+      ErrorCode::InvalidAddress)));
+    }
+    Ok(stashes)
+  })
+}
+
+fn sse_event(event: &StreamEvent) -> warp::sse::Event {
+  warp::sse::Event::default().event(event.name()).json_data(event).expect("stream events serialize to JSON")
+}
+
+// The current era first, then the era transitions, the 1KV refreshes and the staking events of the stashes the
+// stream watches, as the scheduler sees them.
+fn get_stream(
+  chain: &'static Chain,
+  db: Repo,
+  cache: Cache,
+  broadcaster: Broadcaster,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+  warp::path("api")
+  .and(warp::path("v1"))
+  .and(warp::path("stream"))
+  .and(warp::path(chain.ticker.as_str()))
+  .and(warp::path::end())
+  .and(warp::get())
+  .and(with_repo(db))
+  .and(with_cache(cache))
+  .and(validate_stream(chain))
+  .and_then(move |db: Repo, cache: Cache, stashes: Vec<String>| {
+    let broadcaster = broadcaster.clone();
+    async move {
+      let era = current_era(&cache, &db, &chain.ticker).await?;
+      let current = Arc::new(StreamEvent::Era { chain: chain.ticker.clone(), era });
+      let subscription = broadcaster.subscribe(&chain.ticker, stashes)
+        .ok_or_else(|| warp::reject::custom(ApiError::unavailable("too many open streams, try again later")))?;
+      let events = stream::once(async move { current })
+        .chain(stream::unfold(subscription, |mut subscription| async move {
+          subscription.next().await.map(|event| (event, subscription))
+        }))
+        .map(|event| Ok::<_, Infallible>(sse_event(&event)));
+      Ok::<_, Rejection>(warp::sse::reply(warp::sse::keep_alive().stream(events)))
+    }
+  })
+}
+
+// the subscription starts at the current era, its events are delivered from the next one
+fn post_webhook(
+  chain: &'static Chain,
+  db: Repo,
+  chain_db: Repo,
+  cache: Cache,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+  warp::path("api")
+  .and(warp::path("v1"))
+  .and(warp::path("webhooks"))
+  .and(warp::path(chain.ticker.as_str()))
+  .and(warp::path::end())
+  .and(warp::post())
+  .and(with_repo(db))
+  .and(with_repo(chain_db))
+  .and(with_cache(cache))
+  .and(validate_webhook(chain))
+  .and_then(move |db: Repo, chain_db: Repo, cache: Cache, options: WebhookOptions| async move {
+    chain_db.get_nominator_info(&options.stash).await.map_err(db_rejection)?;
+    let era = current_era(&cache, &chain_db, &chain.ticker).await?;
+    let subscription = WebhookSubscription::new(&chain.ticker, era, options);
+    db.insert_webhook_subscription(&subscription).await.map_err(db_rejection)?;
+    Ok::<_, Rejection>(warp::reply::with_status(warp::reply::json(&subscription), StatusCode::CREATED))
+  })
+}
+
+fn delete_webhook(
+  chain: &'static str,
+  db: Repo,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+  warp::path("api")
+  .and(warp::path("v1"))
+  .and(warp::path("webhooks"))
+  .and(warp::path(chain))
+  .and(warp::path::param())
+  .and(warp::path::end())
+  .and(warp::delete())
+  .and(with_repo(db))
+  .and(warp::header::optional::<String>("x-webhook-secret"))
+  .and_then(move |id: String, db: Repo, secret: Option<String>| async move {
+    match db.delete_webhook_subscription(&id, &secret.unwrap_or_default()).await {
+      Ok(_) => Ok(warp::reply::with_status(
+        "",
+        StatusCode::NO_CONTENT,
+      )),
+      Err(err) => Err(db_rejection(err)),
+    }
+  })
+}
+
+// the subscriber is pending until the link of the confirmation email is opened, a pending address which subscribes
+// again gets a new link
 fn post_subscribe_newsletter(
-  db: Database,
+  db: Repo,
+  newsletter: Newsletter,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
   warp::path("api")
   .and(warp::path("v1"))
   .and(warp::path("newsletter"))
-  .and(with_db(db))
+  .and(with_repo(db))
   .and(warp::path::end())
   .and(warp::post())
   .and(validate_newsletter_subscription())
-  .and_then(move |db: Database, options: NewsletterSubscriberOptions| async move { 
-    let result = db.insert_newsletter_subsriber(options).await;
-    if result.is_ok() {
+  .and_then(move |db: Repo, options: NewsletterSubscriberOptions| {
+    let newsletter = newsletter.clone();
+    async move {
+      let email = options.email.clone();
+      let previous = db.insert_newsletter_subsriber(options).await.map_err(db_rejection)?;
+      if let Err(e) = newsletter.send_confirmation(&email).await {
+        error!("failed to send the confirmation to {}: {}", email, e);
+        // the address is left as it was, it can subscribe again once the mail server is back
+        let restored = match previous {
+          Some(status) => db.set_newsletter_status(&email, status).await,
+          None => db.delete_newsletter_subscriber(&email).await,
+        };
+        if let Err(e) = restored {
+          error!("{}", e);
+        }
+        return Err(warp::reject::custom(ApiError::unavailable("Email error: the confirmation cannot be sent")));
+      }
       Ok(warp::reply::with_status(
         "",
         StatusCode::OK,
       ))
-    } else {
-      let err = result.err().unwrap();
-      error!("{}", err);
-      Err(warp::reject::custom(
-        OperationFailed::new(&err.to_string(), ErrorCode::OperationFailed)
+    }
+  })
+}
+
+fn confirm_newsletter(
+  db: Repo,
+  newsletter: Newsletter,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+  warp::path("api")
+  .and(warp::path("v1"))
+  .and(warp::path("newsletter"))
+  .and(warp::path("confirm"))
+  .and(warp::path::end())
+  .and(warp::get())
+  .and(with_repo(db))
+  .and(warp::query::<NewsletterTokenParams>())
+  .and_then(move |db: Repo, params: NewsletterTokenParams| {
+    let newsletter = newsletter.clone();
+    async move {
+      let email = newsletter.verify(TokenPurpose::Confirm, &params.token).map_err(token_rejection)?;
+      let subscriber = db.get_newsletter_subscriber(&email).await.map_err(db_rejection)?;
+      match subscriber.status {
+        NewsletterStatus::Confirmed => {},
+        NewsletterStatus::Pending => db.set_newsletter_status(&email, NewsletterStatus::Confirmed).await
+          .map_err(db_rejection)?,
+        // an old confirmation link cannot undo an unsubscription
+        NewsletterStatus::Unsubscribed => return Err(warp::reject::custom(InvalidParam::new(
+          "the subscription was cancelled, subscribe again", ErrorCode::InvalidToken))),
+      }
+      Ok::<_, Rejection>(warp::reply::with_status(
+        "Your subscription is confirmed.",
+        StatusCode::OK,
       ))
     }
   })
 }
 
+// GET for the link of the emails, POST for the one-click unsubscription of mail clients (RFC 8058)
+fn unsubscribe_newsletter(
+  db: Repo,
+  newsletter: Newsletter,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+  warp::path("api")
+  .and(warp::path("v1"))
+  .and(warp::path("newsletter"))
+  .and(warp::path("unsubscribe"))
+  .and(warp::path::end())
+  .and(warp::get().or(warp::post()).unify())
+  .and(with_repo(db))
+  .and(warp::query::<NewsletterTokenParams>())
+  .and_then(move |db: Repo, params: NewsletterTokenParams| {
+    let newsletter = newsletter.clone();
+    async move {
+      let email = newsletter.verify(TokenPurpose::Unsubscribe, &params.token).map_err(token_rejection)?;
+      db.set_newsletter_status(&email, NewsletterStatus::Unsubscribed).await.map_err(db_rejection)?;
+      Ok::<_, Rejection>(warp::reply::with_status(
+        "You are unsubscribed.",
+        StatusCode::OK,
+      ))
+    }
+  })
+}
+
+// The data link is only sent to the address, so that nobody else can read or delete its data. It is sent after the
+// response: unknown addresses are answered the same way and as fast, the response does not tell whether an address
+// is subscribed.
+fn request_newsletter_data(
+  db: Repo,
+  newsletter: Newsletter,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+  warp::path("api")
+  .and(warp::path("v1"))
+  .and(warp::path("newsletter"))
+  .and(warp::path("data"))
+  .and(warp::path::end())
+  .and(warp::post())
+  .and(with_repo(db))
+  .and(validate_newsletter_data_request())
+  .map(move |db: Repo, request: NewsletterDataRequest| {
+    let newsletter = newsletter.clone();
+    tokio::spawn(async move {
+      match db.get_newsletter_subscriber(&request.email).await {
+        Ok(_) => {
+          if let Err(e) = newsletter.send_data_link(&request.email).await {
+            error!("failed to send the data link to {}: {}", request.email, e);
+          }
+        },
+        Err(DatabaseError::NotFound { .. }) => {},
+        Err(e) => error!("failed to look up the newsletter subscriber {}: {}", request.email, e),
+      }
+    });
+    warp::reply::with_status(
+      "",
+      StatusCode::ACCEPTED,
+    )
+  })
+}
+
+fn get_newsletter_data(
+  db: Repo,
+  newsletter: Newsletter,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+  warp::path("api")
+  .and(warp::path("v1"))
+  .and(warp::path("newsletter"))
+  .and(warp::path("data"))
+  .and(warp::path::end())
+  .and(warp::get())
+  .and(with_repo(db))
+  .and(warp::query::<NewsletterTokenParams>())
+  .and_then(move |db: Repo, params: NewsletterTokenParams| {
+    let newsletter = newsletter.clone();
+    async move {
+      let email = newsletter.verify(TokenPurpose::Data, &params.token).map_err(token_rejection)?;
+      let subscriber = db.get_newsletter_subscriber(&email).await.map_err(db_rejection)?;
+      Ok::<_, Rejection>(warp::reply::json(&subscriber))
+    }
+  })
+}
+
+fn delete_newsletter_data(
+  db: Repo,
+  newsletter: Newsletter,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+  warp::path("api")
+  .and(warp::path("v1"))
+  .and(warp::path("newsletter"))
+  .and(warp::path("data"))
+  .and(warp::path::end())
+  .and(warp::delete())
+  .and(with_repo(db))
+  .and(warp::query::<NewsletterTokenParams>())
+  .and_then(move |db: Repo, params: NewsletterTokenParams| {
+    let newsletter = newsletter.clone();
+    async move {
+      let email = newsletter.verify(TokenPurpose::Data, &params.token).map_err(token_rejection)?;
+      db.delete_newsletter_subscriber(&email).await.map_err(db_rejection)?;
+      info!("deleted the newsletter data of {}", email);
+      Ok::<_, Rejection>(warp::reply::with_status(
+        "",
+        StatusCode::NO_CONTENT,
+      ))
+    }
+  })
+}
+
+// the admin token of the config, sent as `Authorization: Bearer <token>`
+fn with_admin() -> impl Filter<Extract = (), Error = Rejection> + Copy {
+  warp::header::optional::<String>("authorization").and_then(|authorization: Option<String>| async move {
+    let token = Config::current().admin_token.clone().unwrap_or_default();
+    let sent = authorization.as_deref().and_then(|a| a.strip_prefix("Bearer ")).unwrap_or_default();
+    // compared in constant time, the time of a wrong guess does not tell how much of it matched
+    if token.is_empty() || !bool::from(sent.as_bytes().ct_eq(token.as_bytes())) {
+      return Err(warp::reject::custom(ApiError::new(StatusCode::UNAUTHORIZED, ErrorCode::Unauthorized,
+        "a valid admin token is required")));
+    }
+    Ok(())
+  }).untuple_one()
+}
+
+fn get_newsletter_subscribers(
+  db: Repo,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+  warp::path("api")
+  .and(warp::path("v1"))
+  .and(warp::path("admin"))
+  .and(warp::path("newsletter"))
+  .and(warp::path::end())
+  .and(warp::get())
+  .and(with_admin())
+  .and(with_repo(db))
+  .and(warp::query::<NewsletterAdminParams>())
+  .and_then(move |db: Repo, params: NewsletterAdminParams| async move {
+    let mut counts = NewsletterCounts::default();
+    let mut subscribers = vec![];
+    for (status, count) in [(NewsletterStatus::Pending, &mut counts.pending),
+      (NewsletterStatus::Confirmed, &mut counts.confirmed), (NewsletterStatus::Unsubscribed, &mut counts.unsubscribed)] {
+      *count = db.count_newsletter_subscribers(status).await.map_err(db_rejection)?;
+      if params.status.is_none_or(|s| s == status) {
+        subscribers.extend(db.get_newsletter_subscribers(status).await.map_err(db_rejection)?);
+      }
+    }
+    Ok::<_, Rejection>(warp::reply::json(&NewsletterSubscriberList { counts, subscribers }))
+  })
+}
+
+fn get_log_level() -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+  warp::path("api")
+  .and(warp::path("v1"))
+  .and(warp::path("admin"))
+  .and(warp::path("logLevel"))
+  .and(warp::path::end())
+  .and(warp::get())
+  .and(with_admin())
+  .map(|| warp::reply::json(&LogLevel { level: logging::level() }))
+}
+
+// takes effect at once, until the next restart
+fn put_log_level() -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+  warp::path("api")
+  .and(warp::path("v1"))
+  .and(warp::path("admin"))
+  .and(warp::path("logLevel"))
+  .and(warp::path::end())
+  .and(warp::put())
+  .and(with_admin())
+  .and(warp::filters::body::json())
+  .and_then(|request: LogLevel| async move {
+    logging::set_level(&request.level).map_err(|e| warp::reject::custom(InvalidParam::new(&e.to_string(),
+      ErrorCode::InvalidLogLevel)))?;
+    info!("log level set to {}", request.level);
+    Ok::<_, Rejection>(warp::reply::json(&LogLevel { level: logging::level() }))
+  })
+}
+
 fn verify_ref_key(
   chain: &'static str,
-  db: Database,
+  db: Repo,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
   warp::path("api")
   .and(warp::path("v1"))
   .and(warp::path("refKey"))
-  .and(with_db(db))
+  .and(with_repo(db))
   .and(warp::path("stash"))
   .and(warp::path::param())
   .and(warp::path(chain))
   .and(warp::path("verify"))
   .and(warp::path::end())
   .and(validate_ref_key_options())
-  .and_then(move |db: Database, stash: String, options: RefKeyOptions| async move {
-    let buf = Path::new(Config::current().signature_verifier.as_str()).join("src").join("index.js").to_path_buf();
-    let path  = buf.to_str().unwrap();
-    let cmd = Command::new("node")
-    .args([path, "--msg", &options.ref_key,
-      "--signature", &options.encoded.unwrap(), "--address", &stash])
-    .output()
-    .expect("failed to execute process");
-    let output = String::from_utf8(cmd.stdout);
-    info!("{:?}", output);
-    match output {
-        Ok(output) => {
-          if output.contains("true") {
-            let ref_key_options = referer::decrypt_ref_key(&options.ref_key);
-            match ref_key_options {
-                Ok(ref_key_options) => {
-                  db.insert_validator_ref_key(ref_key_options).await;
-                  Ok(warp::reply::with_status(
-                    "true",
-                    StatusCode::OK,
-                  ))
-                },
-                Err(_) => {
-                  Err(warp::reject::custom(
-                    OperationFailed::new("", ErrorCode::OperationFailed)
-                  ))
-                },
-            }
-          } else {
-            Ok(warp::reply::with_status(
-              "false",
-              StatusCode::OK,
-            ))
-          }
-        },
-        Err(err) => {
-          error!("{}", err);
-          Err(warp::reject::custom(
-            OperationFailed::new(&err.to_string(), ErrorCode::OperationFailed)
-          ))
-        },
+  .and_then(move |db: Repo, stash: String, options: RefKeyOptions| async move {
+    let ref_key_options = match referer::decrypt_ref_key(&options.ref_key) {
+      Ok(ref_key_options) => ref_key_options,
+      Err(err) => {
+        return Err(warp::reject::custom(InvalidParam::new(&err.to_string(), ErrorCode::InvalidRefKey)));
+      },
+    };
+    // the signature of the stash over the ref key, as sent or wrapped in <Bytes>
+    let encoded = options.encoded.unwrap_or_default();
+    let verified = signature::verify(options.ref_key.as_bytes(), &encoded, &stash).map_err(signature_rejection)?;
+    // signed by the stash, but the ref key of another one
+    if ref_key_options.stash != stash {
+      return Err(warp::reject::custom(InvalidParam::new("ref_key was generated for another stash",
+        ErrorCode::WrongMessage)));
     }
-    
+    info!("ref key of {} verified: {:?}", stash, verified);
+    if let Err(e) = db.insert_validator_ref_key(ref_key_options).await {
+      error!("{}", e);
+    }
+    Ok::<_, Rejection>(warp::reply::with_status(
+      "true",
+      StatusCode::OK,
+    ))
   })
 }
 
+// a signature which verifies with neither wrapping of the message was not made by the address
+fn signature_rejection(err: SignatureError) -> Rejection {
+  let code = match err {
+    SignatureError::MalformedSignature => ErrorCode::MalformedSignature,
+    SignatureError::InvalidAddress => ErrorCode::InvalidAddress,
+    SignatureError::BadSignature => ErrorCode::WrongAddress,
+  };
+  warp::reject::custom(InvalidParam::new(&err.to_string(), code))
+}
+
 fn decode_ref_key(
-  chain: &'static str,
-  db: Database,
+  _chain: &'static str,
+  db: Repo,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
   warp::path("api")
   .and(warp::path("v1"))
   .and(warp::path("refKey"))
-  .and(with_db(db))
+  .and(with_repo(db))
   .and(warp::path("decode"))
   .and(warp::path::end())
   .and(validate_ref_key_options())
-  .and_then(move |db: Database, options: RefKeyOptions| async move {
+  .and_then(move |db: Repo, options: RefKeyOptions| async move {
     match db.decode_validator_ref_key(&options.ref_key).await {
         Ok(c) => {
           Ok(warp::reply::with_status(
@@ -746,46 +1192,622 @@ fn decode_ref_key(
             StatusCode::OK,
           ))
         },
-        Err(err) => {
-          Err(warp::reject::custom(
-            OperationFailed::new(&err.to_string(), ErrorCode::OperationFailed)
-          ))
-        },
+        Err(err) => Err(db_rejection(err)),
     }
   })
 }
 
+pub fn common_routes(
+    jobs: RewardsJobQueue,
+    users_db: Database,
+) -> impl Filter<Extract = (impl Reply,), Error = warp::Rejection> + Clone {
+    warp::get().and(get_stash_rewards_collector(jobs.clone())
+    .or(get_stash_rewards_job(jobs.clone()))
+    .or(get_stash_rewards_collector_report(users_db.clone(), "csv"))
+    .or(get_stash_rewards_collector_report(users_db, "json")))
+    .or(warp::post().and(create_stash_rewards_job(jobs)))
+}
+
+// served without compression, which would hold the events back
+pub fn stream_routes(
+    chain: &'static Chain,
+    db: Repo,
+    cache: Cache,
+    broadcaster: Broadcaster,
+) -> impl Filter<Extract = (impl Reply,), Error = warp::Rejection> + Clone {
+    get_stream(chain, db, cache, broadcaster)
+}
+
+pub fn admin_routes() -> impl Filter<Extract = (impl Reply,), Error = warp::Rejection> + Clone {
+    get_log_level().or(put_log_level())
+}
+
+// the newsletter is not bound to a chain
+pub fn newsletter_routes(
+    db: Repo,
+    newsletter: Newsletter,
+) -> impl Filter<Extract = (impl Reply,), Error = warp::Rejection> + Clone {
+    post_subscribe_newsletter(db.clone(), newsletter.clone())
+    .or(confirm_newsletter(db.clone(), newsletter.clone()))
+    .or(unsubscribe_newsletter(db.clone(), newsletter.clone()))
+    .or(request_newsletter_data(db.clone(), newsletter.clone()))
+    .or(get_newsletter_data(db.clone(), newsletter.clone()))
+    .or(delete_newsletter_data(db.clone(), newsletter))
+    .or(get_newsletter_subscribers(db))
+}
+
 pub fn get_routes(
-    chain: &'static str,
-    db: Database,
-    user_db: Database,
+    chain: &'static Chain,
+    db: Repo,
+    user_db: Repo,
     cache: Cache,
-    src_path: String
 ) -> impl Filter<Extract = (impl Reply,), Error = warp::Rejection> + Clone {
+    let ticker = chain.ticker.as_str();
     warp::get().and(get_all_validators(chain, db.clone(), cache.clone())
-    .or(get_nominator_info(chain, db.clone()))
-    .or(get_all_nominators(chain, cache.clone()))
-    .or(get_nominated_validators(chain, db.clone(), cache.clone()))
-    .or(get_validator_history(chain, db.clone()))
-    .or(get_1kv_validators(chain, cache.clone()))
-    .or(get_1kv_nominators(chain, cache.clone()))
-    .or(get_stash_rewards_collector(src_path.clone()))
-    .or(get_stash_rewards_collector_csv(src_path.clone()))
-    .or(get_stash_rewards_collector_json(src_path))
-    .or(get_validator_unclaimed_eras(chain, db.clone()))
-    .or(get_validator_slashes(chain, db.clone())))
-    .or(get_events(chain, db, user_db, cache))
+    .or(get_nominator_info(ticker, db.clone()))
+    .or(get_all_nominators(ticker, cache.clone()))
+    .or(get_nominated_validators(ticker, db.clone(), cache.clone()))
+    .or(get_validator_history(ticker, db.clone()))
+    .or(get_1kv_validators(ticker, cache.clone()))
+    .or(get_1kv_nominators(ticker, cache.clone()))
+    .or(get_validator_unclaimed_eras(ticker, db.clone()))
+    .or(get_validator_slashes(ticker, db.clone()))
+    .or(get_validator_risk(ticker, db.clone()))
+    .or(get_nomination_strategy(chain, db.clone(), cache.clone())))
+    .or(get_events(ticker, db, user_db, cache))
 }
 
 pub fn post_routes(
-  chain: &'static str,
-  db: Database,
-  chain_db: Database,
-) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
-  post_nominated_records(chain, db.clone())
-  .or(post_subscribe_newsletter(db.clone()))
-  .or(post_nominated_result(chain, db.clone()))
-  .or(verify_ref_key(chain, db.clone()))
-  .or(get_ref_key(chain, chain_db, db.clone()))
-  .or(decode_ref_key(chain, db))
+  chain: &'static Chain,
+  db: Repo,
+  chain_db: Repo,
+  cache: Cache,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+  let ticker = chain.ticker.as_str();
+  post_nominated_records(ticker, db.clone())
+  .or(post_nominated_result(ticker, db.clone()))
+  .or(verify_ref_key(ticker, db.clone()))
+  .or(get_ref_key(ticker, chain_db.clone(), db.clone()))
+  .or(decode_ref_key(ticker, db.clone()))
+  .or(post_simulation(chain, chain_db.clone(), cache.clone()))
+  .or(post_webhook(chain, db.clone(), chain_db, cache))
+  .or(delete_webhook(ticker, db))
+}
+
+// the v1 routes of KSM over the fixtures, the rewards jobs run against a database which is not connected
+#[cfg(test)]
+pub(super) fn test_routes() -> impl Filter<Extract = (impl Reply,), Error = Infallible> + Clone {
+  test_routes_with(Newsletter::new(crate::email::Mailer::memory(), "https://cryptolab.network", "secret"))
+}
+
+// the emails are kept by the mailer of `newsletter`
+#[cfg(test)]
+fn test_routes_with(newsletter: Newsletter) -> impl Filter<Extract = (impl Reply,), Error = Infallible> + Clone {
+  test_routes_on(test_repo(), newsletter)
+}
+
+#[cfg(test)]
+fn test_routes_on(repo: Repo, newsletter: Newsletter) -> impl Filter<Extract = (impl Reply,), Error = Infallible> + Clone {
+  let chain = test_chain();
+  let cache: Cache = std::sync::Arc::new(crate::cache_memory::MemoryCache::new());
+  let users_db = Database::new("127.0.0.1".to_string(), 27017, "users");
+  let jobs = RewardsJobQueue::start(users_db.clone(), std::collections::HashMap::new());
+  get_routes(chain, repo.clone(), repo.clone(), cache.clone())
+    .or(post_routes(chain, repo.clone(), repo.clone(), cache.clone()))
+    .or(newsletter_routes(repo.clone(), newsletter))
+    .or(admin_routes())
+    .or(stream_routes(chain, repo, cache, Broadcaster::new(crate::stream::DEFAULT_MAX_STREAMS)))
+    .or(common_routes(jobs, users_db))
+    .or(super::openapi::routes())
+    .recover(super::error::handle_rejection)
+}
+
+#[cfg(test)]
+fn test_chain() -> &'static Chain {
+  Box::leak(Box::new(Chain {
+    one_kv: false,
+    // low enough for the fixtures to have an oversubscribed validator
+    max_nominators_rewarded: 1,
+    cache_folder: None,
+    ..crate::db::memory::kusama_chain()
+  }))
+}
+
+#[cfg(test)]
+fn test_repo() -> Repo {
+  std::sync::Arc::new(crate::db::memory::MemoryRepo::kusama())
+}
+
+#[cfg(test)]
+async fn request_json(request: warp::test::RequestBuilder) -> (StatusCode, serde_json::Value) {
+  let response = request.reply(&test_routes()).await;
+  (response.status(), serde_json::from_slice(response.body()).unwrap_or_default())
+}
+
+#[tokio::test]
+async fn test_validator_routes() {
+  let (status, body) = request_json(warp::test::request().path("/api/v1/validators/KSM?size=10&page=0")).await;
+  assert_eq!(StatusCode::OK, status);
+  assert_eq!(3001, body["era"]);
+  assert_eq!(2, body["totalCount"]);
+  assert!(body["next"].is_null());
+  let ids: Vec<&str> = body["validators"].as_array().unwrap().iter().map(|v| v["id"].as_str().unwrap()).collect();
+  assert_eq!(vec!["FcjmeNzPk3vgdENm1rHeiMCxFK96beUoi2kb59FmCoZtkGF", "GLVeryFRbg5hEKvQZcAnLvXZEXhQsiswr9CMnw1ZhQiAbBe"], ids);
+
+  let path = "/api/v1/validators/KSM?size=10&page=0&has_verified_identity=true&commission_max=0.05";
+  let (_, body) = request_json(warp::test::request().path(path)).await;
+  assert_eq!(1, body["validators"].as_array().unwrap().len());
+  assert_eq!(1, body["totalCount"]);
+
+  let (status, body) = request_json(warp::test::request().path("/api/v1/validators/KSM?size=10&page=0&apy_max=2")).await;
+  assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, status);
+  assert_eq!(ErrorCode::InvalidApy.to_int(), body["code"]);
+
+  let (status, body) = request_json(warp::test::request()
+    .path("/api/v1/validator/GLVeryFRbg5hEKvQZcAnLvXZEXhQsiswr9CMnw1ZhQiAbBe/KSM")).await;
+  assert_eq!(StatusCode::OK, status, "{}", body);
+  assert_eq!(2, body[0]["info"].as_array().unwrap().len());
+
+  let (status, body) = request_json(warp::test::request().path("/api/v1/validator/unknown/KSM")).await;
+  assert_eq!(StatusCode::NOT_FOUND, status);
+  assert_eq!(ErrorCode::NotFound.to_int(), body["code"]);
+  assert!(body["requestId"].is_string());
+
+  let (_, body) = request_json(warp::test::request()
+    .path("/api/v1/validator/GLVeryFRbg5hEKvQZcAnLvXZEXhQsiswr9CMnw1ZhQiAbBe/unclaimedEras/KSM")).await;
+  assert_eq!(serde_json::json!([3000]), body);
+}
+
+#[tokio::test]
+async fn test_validator_filters() {
+  let glv = "GLVeryFRbg5hEKvQZcAnLvXZEXhQsiswr9CMnw1ZhQiAbBe";
+  let fcj = "FcjmeNzPk3vgdENm1rHeiMCxFK96beUoi2kb59FmCoZtkGF";
+  let cases = [
+    ("total_min=5000", vec![glv]),
+    ("total_max=5000", vec![fcj]),
+    ("self_stake_min=1000&self_stake_max=1000", vec![fcj, glv]),
+    ("self_stake_max=500", vec![]),
+    ("not_oversubscribed=true", vec![fcj]),
+    ("no_slashes_in_eras=10", vec![glv]),
+    ("no_slashes_in_eras=5", vec![fcj, glv]),
+    ("exclude_blocked=true", vec![glv]),
+    ("has_parent_identity=true", vec![glv]),
+    ("era_points_percentile=0.5", vec![fcj, glv]),
+    ("era_points_percentile=1", vec![glv]),
+  ];
+  for (query, expected) in cases {
+    let (status, body) = request_json(warp::test::request().path(&format!("/api/v1/validators/KSM?{}", query))).await;
+    assert_eq!(StatusCode::OK, status, "{}", query);
+    let ids: Vec<&str> = body["validators"].as_array().unwrap().iter().map(|v| v["id"].as_str().unwrap()).collect();
+    assert_eq!(expected, ids, "{}", query);
+    assert_eq!(expected.len() as u64, body["totalCount"].as_u64().unwrap(), "{}", query);
+  }
+
+  for query in ["era_points_percentile=1.5", "total_min=-1", "self_stake_min=10&self_stake_max=1", "size=0", "size=5001",
+    "page=4294967295", "size=5000&page=858994"] {
+    let (status, body) = request_json(warp::test::request().path(&format!("/api/v1/validators/KSM?{}", query))).await;
+    assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, status, "{}", query);
+    assert_eq!(ErrorCode::InvalidRequest.to_int(), body["code"]);
+  }
+}
+
+#[tokio::test]
+async fn test_validator_risk_routes() {
+  let fcj = "FcjmeNzPk3vgdENm1rHeiMCxFK96beUoi2kb59FmCoZtkGF";
+  let (status, body) = request_json(warp::test::request().path(&format!("/api/v1/validator/{}/risk/KSM", fcj))).await;
+  assert_eq!(StatusCode::OK, status, "{}", body);
+  assert_eq!(25, body["score"]);
+  assert_eq!(15, body["components"]["slashes"]);
+  assert_eq!(0, body["components"]["kicks"]);
+
+  let (status, _) = request_json(warp::test::request()
+    .path("/api/v1/validator/GLVeryFRbg5hEKvQZcAnLvXZEXhQsiswr9CMnw1ZhQiAbBe/risk/KSM")).await;
+  assert_eq!(StatusCode::NOT_FOUND, status);
+
+  // the score is also part of the listing
+  let (_, body) = request_json(warp::test::request().path("/api/v1/validators/KSM")).await;
+  let validators = body["validators"].as_array().unwrap();
+  assert_eq!(25, validators[0]["risk"]["score"]);
+  assert!(validators[1]["risk"].is_null());
+}
+
+#[tokio::test]
+async fn test_simulation_routes() {
+  let glv = "GLVeryFRbg5hEKvQZcAnLvXZEXhQsiswr9CMnw1ZhQiAbBe";
+  let fcj = "FcjmeNzPk3vgdENm1rHeiMCxFK96beUoi2kb59FmCoZtkGF";
+  let simulate = |body: serde_json::Value| request_json(warp::test::request().method("POST")
+    .path("/api/v1/simulate/KSM").json(&body));
+
+  // 1000 KSM on each validator, below the single rewarded nominator of the first one
+  let (status, body) = simulate(serde_json::json!({"amount": 2_000_000_000_000_000u64, "validators": [glv, fcj, "unknown"]})).await;
+  assert_eq!(StatusCode::OK, status, "{}", body);
+  assert_eq!(3001, body["era"]);
+  assert_eq!(serde_json::json!(["unknown"]), body["unknown"]);
+  assert_eq!(true, body["oversubscribed"]);
+  assert_eq!(true, body["validators"][0]["oversubscribed"]);
+  assert_eq!(0.0, body["validators"][0]["eraReward"].as_f64().unwrap());
+  // the stake doubles the exposure of the second validator and 10% goes to commission
+  assert!((body["validators"][1]["effectiveApy"].as_f64().unwrap() - 0.08 * 0.9 / 2.0).abs() < 1e-6);
+  assert!((body["eraReward"].as_f64().unwrap() - 1000.0 * 0.036 / 1460.0).abs() < 1e-9);
+  assert!((body["annualReward"].as_f64().unwrap() - 36.0).abs() < 1e-6);
+  assert!((body["effectiveApy"].as_f64().unwrap() - 0.018).abs() < 1e-6);
+
+  let (_, body) = simulate(serde_json::json!({"amount": 20_000_000_000_000_000u64, "validators": [glv, fcj]})).await;
+  assert_eq!(false, body["oversubscribed"]);
+
+  for body in [serde_json::json!({"amount": 0, "validators": [glv]}), serde_json::json!({"amount": 1, "validators": []}),
+    serde_json::json!({"amount": 1, "validators": [glv, glv]})] {
+    let (status, response) = simulate(body).await;
+    assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, status);
+    assert_eq!(ErrorCode::InvalidRequest.to_int(), response["code"]);
+  }
+}
+
+// ed25519 keys of the seeds [7; 32], a nominator of the fixtures, and [8; 32], which does not nominate
+#[cfg(test)]
+const TEST_ACCOUNTS: [(u8, &str); 2] = [
+  (7, "HsWqAKdsQ4CrhP9xXabwR5gQaZScVJL6BpLY8wN7mSBJWx4"),
+  (8, "D21sDnTQCt2hESxhFSnVZgKL7i5j8jUZrS7ubdmzC9kkLJt"),
+];
+
+#[cfg(test)]
+fn sign_with(seed: u8, message: &[u8]) -> String {
+  use ed25519_dalek::Signer;
+  let key = ed25519_dalek::SigningKey::from_bytes(&[seed; 32]);
+  format!("0x{}", hex::encode(key.sign(message).to_bytes()))
+}
+
+#[cfg(test)]
+fn sign_webhook(seed: u8, url: &str, issued_at: i64) -> String {
+  sign_with(seed, webhooks::subscription_message("KSM", url, issued_at).as_bytes())
+}
+
+#[tokio::test]
+async fn test_webhook_routes() {
+  let routes = test_routes();
+  let [(seed, nominator), (other_seed, not_nominating)] = TEST_ACCOUNTS;
+  let url = "https://93.184.216.34/hook";
+  let now = chrono::Utc::now().timestamp();
+  let sign_webhook = |seed: u8, url: &str| sign_webhook(seed, url, now);
+  let subscribe_at = move |stash: &str, url: &str, event_types: serde_json::Value, issued_at: i64, signature: String| {
+    warp::test::request().method("POST").path("/api/v1/webhooks/KSM").json(&serde_json::json!({
+      "stash": stash, "url": url, "eventTypes": event_types, "issuedAt": issued_at, "signature": signature,
+    }))
+  };
+  let subscribe = |stash: &str, url: &str, event_types: serde_json::Value, signature: String| {
+    subscribe_at(stash, url, event_types, now, signature)
+  };
+  let response = subscribe(nominator, url, serde_json::json!(["commission", "slash"]), sign_webhook(seed, url))
+    .reply(&routes).await;
+  assert_eq!(StatusCode::CREATED, response.status());
+  let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+  assert_eq!(3001, body["lastEra"]);
+  assert_eq!(serde_json::json!(["commission", "slash"]), body["eventTypes"]);
+  let (id, secret) = (body["id"].as_str().unwrap(), body["secret"].as_str().unwrap());
+
+  let rejected = |request: warp::test::RequestBuilder| async {
+    let response = request.reply(&routes).await;
+    let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+    (response.status(), body["code"].clone())
+  };
+  let payout = || serde_json::json!(["payout"]);
+  for url in ["ftp://example.com", "http://127.0.0.1:3030/hook", "http://169.254.169.254/latest/meta-data",
+    "http://10.0.0.5/hook", "http://[::1]/hook"] {
+    assert_eq!((StatusCode::UNPROCESSABLE_ENTITY, serde_json::json!(-1040)),
+      rejected(subscribe(nominator, url, payout(), sign_webhook(seed, url))).await, "{}", url);
+  }
+  assert_eq!((StatusCode::UNPROCESSABLE_ENTITY, serde_json::json!(-1012)),
+    rejected(subscribe("1ZGzjQ", url, payout(), sign_webhook(seed, url))).await);
+  assert_eq!((StatusCode::UNPROCESSABLE_ENTITY, serde_json::json!(-1004)),
+    rejected(subscribe(nominator, url, serde_json::json!([]), sign_webhook(seed, url))).await);
+  assert_eq!((StatusCode::UNPROCESSABLE_ENTITY, serde_json::json!(-1004)),
+    rejected(subscribe(nominator, url, serde_json::json!(["reward"]), sign_webhook(seed, url))).await);
+  // the signature of another account, of another URL, and none
+  assert_eq!((StatusCode::UNPROCESSABLE_ENTITY, serde_json::json!(-1013)),
+    rejected(subscribe("HNZata7iMYWmk5RvZRTiAsSDhV8366zq2YGb3tLH5Upf74F", url, payout(), sign_webhook(seed, url))).await);
+  assert_eq!((StatusCode::UNPROCESSABLE_ENTITY, serde_json::json!(-1013)),
+    rejected(subscribe(nominator, url, payout(), sign_webhook(seed, "https://93.184.216.34/other"))).await);
+  assert_eq!((StatusCode::UNPROCESSABLE_ENTITY, serde_json::json!(-1011)),
+    rejected(subscribe(nominator, url, payout(), String::new())).await);
+  // a signature made too long ago, and one of another time
+  let stale = now - webhooks::SIGNATURE_MAX_AGE_SECS - 1;
+  assert_eq!((StatusCode::UNPROCESSABLE_ENTITY, serde_json::json!(-1014)),
+    rejected(subscribe_at(nominator, url, payout(), stale, self::sign_webhook(seed, url, stale))).await);
+  assert_eq!((StatusCode::UNPROCESSABLE_ENTITY, serde_json::json!(-1013)),
+    rejected(subscribe_at(nominator, url, payout(), now - 1, sign_webhook(seed, url))).await);
+  // a stash which does not nominate
+  assert_eq!((StatusCode::NOT_FOUND, serde_json::json!(-2002)),
+    rejected(subscribe(not_nominating, url, payout(), sign_webhook(other_seed, url))).await);
+
+  let delete = |secret: &str| warp::test::request().method("DELETE").path(&format!("/api/v1/webhooks/KSM/{}", id))
+    .header("x-webhook-secret", secret);
+  assert_eq!(StatusCode::NOT_FOUND, delete("wrong").reply(&routes).await.status());
+  assert_eq!(StatusCode::NO_CONTENT, delete(secret).reply(&routes).await.status());
+  assert_eq!(StatusCode::NOT_FOUND, delete(secret).reply(&routes).await.status());
+}
+
+#[tokio::test]
+async fn test_strategy_routes() {
+  let (status, body) = request_json(warp::test::request().path("/api/v1/strategy/KSM/highApy")).await;
+  assert_eq!(StatusCode::OK, status, "{}", body);
+  assert_eq!(3001, body["era"]);
+  assert_eq!("highApy", body["strategy"]);
+  // one validator blocks nominations and the test chain rewards a single nominator per validator
+  assert_eq!(0, body["validators"].as_array().unwrap().len());
+
+  let (status, body) = request_json(warp::test::request().path("/api/v1/strategy/KSM/1")).await;
+  assert_eq!(StatusCode::OK, status);
+  assert_eq!("lowRisk", body["strategy"]);
+
+  let (status, body) = request_json(warp::test::request().path("/api/v1/strategy/KSM/yolo")).await;
+  assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, status);
+  assert_eq!(ErrorCode::UnsupportedStrategy.to_int(), body["code"]);
+}
+
+#[tokio::test]
+async fn test_validator_pagination() {
+  let mut path = "/api/v1/validators/KSM?size=1&sort=apy&order=desc".to_string();
+  let mut ids = vec![];
+  loop {
+    let (status, body) = request_json(warp::test::request().path(&path)).await;
+    assert_eq!(StatusCode::OK, status, "{}", body);
+    assert_eq!(2, body["totalCount"]);
+    for v in body["validators"].as_array().unwrap() {
+      ids.push(v["id"].as_str().unwrap().to_string());
+    }
+    match body["next"].as_str() {
+      Some(next) => path = format!("/api/v1/validators/KSM?size=1&sort=apy&order=desc&next={}", next),
+      None => break,
+    }
+  }
+  assert_eq!(vec!["GLVeryFRbg5hEKvQZcAnLvXZEXhQsiswr9CMnw1ZhQiAbBe", "FcjmeNzPk3vgdENm1rHeiMCxFK96beUoi2kb59FmCoZtkGF"], ids);
+
+  let (_, body) = request_json(warp::test::request().path("/api/v1/validators/KSM?size=1&sort=nominatorCount")).await;
+  assert_eq!("FcjmeNzPk3vgdENm1rHeiMCxFK96beUoi2kb59FmCoZtkGF", body["validators"][0]["id"]);
+  let next = body["next"].as_str().unwrap().to_string();
+
+  // the cursor only continues the listing it was issued for
+  let (status, body) = request_json(warp::test::request()
+    .path(&format!("/api/v1/validators/KSM?size=1&sort=apy&next={}", next))).await;
+  assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, status);
+  assert_eq!(ErrorCode::InvalidRequest.to_int(), body["code"]);
+
+  let (status, _) = request_json(warp::test::request().path("/api/v1/validators/KSM?next=0OIl")).await;
+  assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, status);
+
+  let (status, _) = request_json(warp::test::request().path("/api/v1/validators/KSM?sort=stake")).await;
+  assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, status);
+}
+
+#[tokio::test]
+async fn test_nominator_routes() {
+  let nominator = "HNZata7iMYWmk5RvZRTiAsSDhV8366zq2YGb3tLH5Upf74F";
+  let (status, body) = request_json(warp::test::request().path(&format!("/api/v1/nominator/id/{}/KSM", nominator))).await;
+  assert_eq!(StatusCode::OK, status);
+  assert_eq!(2, body["rewards"]["eraRewards"].as_array().unwrap().len());
+  assert_eq!(0.5 * 400.0 + 0.25 * 420.0, body["rewards"]["totalInFiat"].as_f64().unwrap());
+
+  let (status, body) = request_json(warp::test::request().path(&format!("/api/v1/events/stash/{}/KSM", nominator))).await;
+  assert_eq!(StatusCode::OK, status);
+  assert_eq!(1, body["commissions"].as_array().unwrap().len());
+  assert_eq!(serde_json::json!([2999]), body["inactive"]);
+  assert_eq!(2, body["payouts"].as_array().unwrap().len());
+
+  let path = format!("/api/v1/events/stash/{}/KSM?from_era=3001&to_era=3000", nominator);
+  let (status, _) = request_json(warp::test::request().path(&path)).await;
+  assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, status);
+}
+
+#[tokio::test]
+async fn test_newsletter_routes() {
+  let newsletter = Newsletter::new(crate::email::Mailer::memory(), "https://cryptolab.network", "secret");
+  let routes = test_routes_with(newsletter.clone());
+  let email = "watcher@cryptolab.network";
+  let subscribe = |stashes: Vec<&str>| warp::test::request().method("POST").path("/api/v1/newsletter")
+    .json(&serde_json::json!({"email": email, "stashes": stashes}));
+  let link = |body: &str, path: &str| body.lines().find(|l| l.contains(path)).unwrap()
+    .trim_start_matches("https://cryptolab.network").to_string();
+  assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, subscribe(vec!["not a stash"]).reply(&routes).await.status());
+  assert_eq!(StatusCode::OK, subscribe(vec!["HNZata7iMYWmk5RvZRTiAsSDhV8366zq2YGb3tLH5Upf74F"]).reply(&routes).await.status());
+  // a pending address gets a new confirmation
+  assert_eq!(StatusCode::OK, subscribe(vec!["HNZata7iMYWmk5RvZRTiAsSDhV8366zq2YGb3tLH5Upf74F"]).reply(&routes).await.status());
+  let sent = newsletter.mailer().sent();
+  assert_eq!(2, sent.len());
+  assert_eq!(email, sent[1].to);
+  let confirm = link(&sent[1].body, "/api/v1/newsletter/confirm?token=");
+
+  let (status, body) = request_json(warp::test::request().path("/api/v1/newsletter/confirm?token=forged")).await;
+  assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, status);
+  assert_eq!(ErrorCode::InvalidToken.to_int(), body["code"]);
+  let response = warp::test::request().path(&confirm).reply(&routes).await;
+  assert_eq!(StatusCode::OK, response.status());
+  // confirming twice changes nothing, and a confirmed address cannot subscribe again
+  assert_eq!(StatusCode::OK, warp::test::request().path(&confirm).reply(&routes).await.status());
+  let response = subscribe(vec![]).reply(&routes).await;
+  assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, response.status());
+  let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+  assert_eq!(ErrorCode::Duplicated.to_int(), body["code"]);
+
+  let unsubscribe = format!("/api/v1/newsletter/unsubscribe?token={}",
+    newsletter.token(TokenPurpose::Unsubscribe, email));
+  let response = warp::test::request().path(&confirm.replace("confirm?", "unsubscribe?")).reply(&routes).await;
+  assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, response.status());
+  let response = warp::test::request().method("POST").path(&unsubscribe)
+    .body("List-Unsubscribe=One-Click").reply(&routes).await;
+  assert_eq!(StatusCode::OK, response.status());
+  assert_eq!(StatusCode::OK, warp::test::request().path(&unsubscribe).reply(&routes).await.status());
+  // an old confirmation link cannot subscribe the address again
+  assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, warp::test::request().path(&confirm).reply(&routes).await.status());
+
+  // subscribing again after unsubscribing asks for a new confirmation
+  assert_eq!(StatusCode::OK, subscribe(vec![]).reply(&routes).await.status());
+  assert_eq!(3, newsletter.mailer().sent().len());
+  let unknown = format!("/api/v1/newsletter/unsubscribe?token={}",
+    newsletter.token(TokenPurpose::Unsubscribe, "unknown@cryptolab.network"));
+  assert_eq!(StatusCode::NOT_FOUND, warp::test::request().path(&unknown).reply(&routes).await.status());
+}
+
+#[tokio::test]
+async fn test_newsletter_email_failure() {
+  // the file transport cannot write to a missing folder
+  let mailer = crate::email::Mailer::new(&crate::config::EmailConfig {
+    transport: "file".to_string(),
+    file_folder: Some("/nonexistent/cryptolab-mail".to_string()),
+    from: "CryptoLab <newsletter@cryptolab.network>".to_string(),
+    ..Default::default()
+  }).unwrap();
+  let repo = test_repo();
+  let routes = test_routes_on(repo.clone(), Newsletter::new(mailer, "https://cryptolab.network", "secret"));
+  let subscribe = |email: &str| warp::test::request().method("POST").path("/api/v1/newsletter")
+    .json(&serde_json::json!({"email": email, "stashes": []}));
+
+  // a new address is removed, one which unsubscribed is unsubscribed again
+  assert_eq!(StatusCode::SERVICE_UNAVAILABLE, subscribe("new@cryptolab.network").reply(&routes).await.status());
+  assert!(repo.get_newsletter_subscriber("new@cryptolab.network").await.is_err());
+  let email = "subscriber@cryptolab.network";
+  repo.set_newsletter_status(email, NewsletterStatus::Unsubscribed).await.unwrap();
+  assert_eq!(StatusCode::SERVICE_UNAVAILABLE, subscribe(email).reply(&routes).await.status());
+  assert_eq!(NewsletterStatus::Unsubscribed, repo.get_newsletter_subscriber(email).await.unwrap().status);
+}
+
+#[tokio::test]
+async fn test_newsletter_data_routes() {
+  let newsletter = Newsletter::new(crate::email::Mailer::memory(), "https://cryptolab.network", "secret");
+  let routes = test_routes_with(newsletter.clone());
+  let email = "subscriber@cryptolab.network";
+  let request_data = |email: &str| warp::test::request().method("POST").path("/api/v1/newsletter/data")
+    .json(&serde_json::json!({"email": email}));
+  // unknown addresses are answered the same way, without an email
+  assert_eq!(StatusCode::ACCEPTED, request_data("unknown@cryptolab.network").reply(&routes).await.status());
+  // the link is sent by a task of its own
+  tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+  assert!(newsletter.mailer().sent().is_empty());
+  assert_eq!(StatusCode::ACCEPTED, request_data(email).reply(&routes).await.status());
+  tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+  let sent = newsletter.mailer().sent();
+  assert_eq!(1, sent.len());
+  let data = sent[0].body.lines().find(|l| l.contains("/api/v1/newsletter/data?token=")).unwrap()
+    .trim_start_matches("https://cryptolab.network").to_string();
+
+  let unsubscribe = format!("/api/v1/newsletter/data?token={}", newsletter.token(TokenPurpose::Unsubscribe, email));
+  assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, warp::test::request().path(&unsubscribe).reply(&routes).await.status());
+  let response = warp::test::request().path(&data).reply(&routes).await;
+  assert_eq!(StatusCode::OK, response.status());
+  let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+  assert_eq!(email, body["email"]);
+  assert_eq!("confirmed", body["status"]);
+  assert_eq!("HNZata7iMYWmk5RvZRTiAsSDhV8366zq2YGb3tLH5Upf74F", body["stashes"][0]);
+
+  let admin = |token: &str| warp::test::request().path("/api/v1/admin/newsletter?status=confirmed")
+    .header("authorization", format!("Bearer {}", token));
+  crate::config::Config { admin_token: Some("admin".to_string()), ..Default::default() }.make_current();
+  assert_eq!(StatusCode::UNAUTHORIZED, admin("guess").reply(&routes).await.status());
+  assert_eq!(StatusCode::UNAUTHORIZED, warp::test::request().path("/api/v1/admin/newsletter").reply(&routes).await.status());
+  assert_eq!(StatusCode::OK, warp::test::request().method("POST").path("/api/v1/newsletter")
+    .json(&serde_json::json!({"email": "pending@cryptolab.network"})).reply(&routes).await.status());
+  let response = admin("admin").reply(&routes).await;
+  assert_eq!(StatusCode::OK, response.status());
+  let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+  assert_eq!(serde_json::json!({"pending": 1, "confirmed": 1, "unsubscribed": 0}), body["counts"]);
+  assert_eq!(1, body["subscribers"].as_array().unwrap().len());
+  assert_eq!(email, body["subscribers"][0]["email"]);
+
+  let response = warp::test::request().method("DELETE").path(&data).reply(&routes).await;
+  assert_eq!(StatusCode::NO_CONTENT, response.status());
+  assert_eq!(StatusCode::NOT_FOUND, warp::test::request().path(&data).reply(&routes).await.status());
+  assert_eq!(StatusCode::NOT_FOUND, warp::test::request().method("DELETE").path(&data).reply(&routes).await.status());
+}
+
+#[tokio::test]
+async fn test_stream_routes() {
+  use warp::hyper::body::HttpBody;
+  let (status, body) = request_json(warp::test::request().path("/api/v1/stream/KSM?stashes=not-a-stash")).await;
+  assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, status);
+  assert_eq!(ErrorCode::InvalidAddress.to_int(), body["code"]);
+  let polkadot = "15Uv8ppUZVb8dM2uDf8rLnNPo4QdK9mHrJSUn6fqAhAtDZKu";
+  let (status, _) = request_json(warp::test::request().path(&format!("/api/v1/stream/KSM?stashes={}", polkadot))).await;
+  assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, status);
+
+  let broadcaster = Broadcaster::new(1);
+  let cache: Cache = std::sync::Arc::new(crate::cache_memory::MemoryCache::new());
+  let routes = stream_routes(test_chain(), test_repo(), cache, broadcaster.clone());
+  let stash = "HNZata7iMYWmk5RvZRTiAsSDhV8366zq2YGb3tLH5Upf74F";
+  let reply = warp::test::request().path(&format!("/api/v1/stream/KSM?stashes={}", stash)).filter(&routes).await;
+  let response = reply.unwrap().into_response();
+  assert_eq!(StatusCode::OK, response.status());
+  assert_eq!("text/event-stream", response.headers()["content-type"]);
+  assert_eq!(vec![stash.to_string()], broadcaster.watched("KSM"));
+  // one stream at most
+  let full = warp::test::request().path(&format!("/api/v1/stream/KSM?stashes={}", stash))
+    .reply(&routes.clone().recover(super::error::handle_rejection)).await;
+  assert_eq!(StatusCode::SERVICE_UNAVAILABLE, full.status());
+  let mut body = response.into_body();
+  let chunk = body.data().await.unwrap().unwrap();
+  assert_eq!("event:era\ndata:{\"type\":\"era\",\"chain\":\"KSM\",\"era\":3001}\n\n", chunk);
+
+  broadcaster.publish(StreamEvent::OneKv { chain: "KSM".to_string(), timestamp: "1000".to_string() });
+  let chunk = body.data().await.unwrap().unwrap();
+  assert!(String::from_utf8_lossy(&chunk).starts_with("event:onekv\n"));
+  // the stream stops watching once the client is gone
+  drop(body);
+  assert!(broadcaster.watched("KSM").is_empty());
+}
+
+#[tokio::test]
+async fn test_user_action_routes() {
+  let routes = test_routes();
+  let subscribe = |email: &str| warp::test::request().method("POST").path("/api/v1/newsletter")
+    .json(&serde_json::json!({"email": email}));
+  assert_eq!(StatusCode::OK, subscribe("new@cryptolab.network").reply(&routes).await.status());
+  // a pending address subscribes again to get a new confirmation, a confirmed one is a duplicate
+  assert_eq!(StatusCode::OK, subscribe("new@cryptolab.network").reply(&routes).await.status());
+  assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, subscribe("subscriber@cryptolab.network").reply(&routes).await.status());
+  assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, subscribe("not an email").reply(&routes).await.status());
+
+  let stash = "GLVeryFRbg5hEKvQZcAnLvXZEXhQsiswr9CMnw1ZhQiAbBe";
+  let response = warp::test::request().path(&format!("/api/v1/refKey/stash/{}/KSM", stash)).reply(&routes).await;
+  let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+  let ref_key = body["refKey"].as_str().unwrap().to_string();
+  assert_eq!(stash, referer::decrypt_ref_key(&ref_key).unwrap().stash);
+
+  let response = warp::test::request().method("POST").path("/api/v1/refKey/decode")
+    .json(&serde_json::json!({"refKey": ref_key})).reply(&routes).await;
+  assert_eq!(StatusCode::OK, response.status());
+  assert_eq!(stash, response.body());
+  let response = warp::test::request().method("POST").path("/api/v1/refKey/decode")
+    .json(&serde_json::json!({"refKey": "unknown"})).reply(&routes).await;
+  assert_eq!(StatusCode::NOT_FOUND, response.status());
+}
+
+#[tokio::test]
+async fn test_verify_ref_key() {
+  let routes = test_routes();
+  let [(seed, stash), (other_seed, _)] = TEST_ACCOUNTS;
+  let verify = |ref_key: &str, signature: &str| warp::test::request().method("POST")
+    .path(&format!("/api/v1/refKey/stash/{}/KSM/verify", stash))
+    .json(&serde_json::json!({"refKey": ref_key, "encoded": signature}));
+  let ref_key = referer::gen_ref_key(stash);
+  let wrapped = format!("<Bytes>{}</Bytes>", ref_key);
+  for signature in [sign_with(seed, wrapped.as_bytes()), sign_with(seed, ref_key.as_bytes())] {
+    let response = verify(&ref_key, &signature).reply(&routes).await;
+    assert_eq!(StatusCode::OK, response.status());
+    assert_eq!("true", response.body());
+  }
+
+  let rejected = |request: warp::test::RequestBuilder| async {
+    let response = request.reply(&routes).await;
+    let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+    (response.status(), body["code"].clone())
+  };
+  // signed by another account
+  assert_eq!((StatusCode::UNPROCESSABLE_ENTITY, serde_json::json!(-1013)),
+    rejected(verify(&ref_key, &sign_with(other_seed, wrapped.as_bytes()))).await);
+  // signed by the stash, but the ref key of another validator
+  let other = referer::gen_ref_key("GLVeryFRbg5hEKvQZcAnLvXZEXhQsiswr9CMnw1ZhQiAbBe");
+  assert_eq!((StatusCode::UNPROCESSABLE_ENTITY, serde_json::json!(-1014)),
+    rejected(verify(&other, &sign_with(seed, other.as_bytes()))).await);
+  assert_eq!((StatusCode::UNPROCESSABLE_ENTITY, serde_json::json!(-1011)),
+    rejected(verify(&ref_key, "0x1234")).await);
+  assert_eq!((StatusCode::UNPROCESSABLE_ENTITY, serde_json::json!(-1015)),
+    rejected(verify("unknown", &sign_with(seed, b"unknown"))).await);
 }
@@ -0,0 +1,213 @@
+use std::convert::Infallible;
+
+use tracing::{error, warn};
+use serde::Serialize;
+use schemars::JsonSchema;
+use warp::http::header::{self, HeaderValue};
+use warp::hyper::StatusCode;
+use warp::{Rejection, Reply, reject};
+
+use crate::cache::CacheError;
+use crate::db::DatabaseError;
+use crate::logging;
+use crate::staking_rewards_collector::SRCError;
+
+use super::Invalid;
+use super::params::{ErrorCode, InvalidParam};
+use super::rate_limit::Throttled;
+
+// Every failed API request is answered with this body, whatever layer the error comes from.
+#[derive(Serialize, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ErrorEnvelope {
+  pub code: i32,
+  pub message: String,
+  pub request_id: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct ApiError {
+  pub status: StatusCode,
+  pub code: i32,
+  pub message: String,
+}
+
+impl reject::Reject for ApiError {}
+
+impl ApiError {
+  pub fn new(status: StatusCode, code: ErrorCode, message: impl Into<String>) -> Self {
+    ApiError {
+      status,
+      code: code.to_int(),
+      message: message.into(),
+    }
+  }
+
+  pub fn not_found(message: impl Into<String>) -> Self {
+    ApiError::new(StatusCode::NOT_FOUND, ErrorCode::NotFound, message)
+  }
+
+  pub fn unavailable(message: impl Into<String>) -> Self {
+    ApiError::new(StatusCode::SERVICE_UNAVAILABLE, ErrorCode::ServiceUnavailable, message)
+  }
+
+  pub fn internal(message: impl Into<String>) -> Self {
+    ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, ErrorCode::InternalError, message)
+  }
+}
+
+impl From<DatabaseError> for ApiError {
+  fn from(err: DatabaseError) -> Self {
+    match err {
+      DatabaseError::NotFound { .. } => ApiError::not_found(err.to_string()),
+      DatabaseError::Duplicated { .. } =>
+        ApiError::new(StatusCode::UNPROCESSABLE_ENTITY, ErrorCode::Duplicated, err.to_string()),
+      // driver messages may contain hosts and credentials, only the collection is exposed
+      DatabaseError::Mongo { ref collection, .. } =>
+        ApiError::unavailable(format!("Database error: query on {} failed", collection)),
+      DatabaseError::Connection(_) | DatabaseError::Disconnected =>
+        ApiError::unavailable("Database error: not connected"),
+      DatabaseError::WriteFailed { .. } =>
+        ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, ErrorCode::OperationFailed, err.to_string()),
+      DatabaseError::Decode { .. } | DatabaseError::CacheMissed => ApiError::internal(err.to_string()),
+    }
+  }
+}
+
+impl From<CacheError> for ApiError {
+  fn from(err: CacheError) -> Self {
+    match err {
+      CacheError::NotFound(_) => ApiError::not_found(err.to_string()),
+      CacheError::Unavailable(_) => ApiError::unavailable(err.to_string()),
+      CacheError::InvalidData(_) => ApiError::internal(err.to_string()),
+    }
+  }
+}
+
+impl From<SRCError> for ApiError {
+  fn from(err: SRCError) -> Self {
+    let unprocessable = |code| ApiError::new(StatusCode::UNPROCESSABLE_ENTITY, code, err.message.clone());
+    match err.err_code {
+      -2 | -20 => ApiError::not_found(err.message),
+      -6..=-3 => unprocessable(ErrorCode::InvalidDate),
+      -7 => unprocessable(ErrorCode::UnsupportedCurrency),
+      -8 => unprocessable(ErrorCode::UnsupportedNetwork),
+      // -10 is a database error, -13 a report which is still being computed
+      -10 | -13 => ApiError::unavailable(err.message),
+      _ => ApiError::internal(err.message),
+    }
+  }
+}
+
+pub fn db_rejection(err: DatabaseError) -> Rejection {
+  reject::custom(ApiError::from(err))
+}
+
+// a missing cache entry is answered with 404, an unreachable cache with 503 and a corrupted one with 500
+pub fn cache_rejection(err: CacheError) -> Rejection {
+  reject::custom(ApiError::from(err))
+}
+
+pub fn src_rejection(err: SRCError) -> Rejection {
+  reject::custom(ApiError::from(err))
+}
+
+fn to_api_error(err: &Rejection) -> ApiError {
+  if let Some(e) = err.find::<ApiError>() {
+    e.clone()
+  } else if let Some(e) = err.find::<InvalidParam>() {
+    ApiError {
+      status: StatusCode::UNPROCESSABLE_ENTITY,
+      code: e.err_code,
+      message: e.message.clone(),
+    }
+  } else if err.find::<Invalid>().is_some() {
+    ApiError::new(StatusCode::UNPROCESSABLE_ENTITY, ErrorCode::InvalidAddress, "stash is invalid")
+  } else if let Some(e) = err.find::<Throttled>() {
+    let message = format!("too many {} requests, retry in {} seconds", e.class.name(), e.retry_after_secs);
+    ApiError::new(StatusCode::TOO_MANY_REQUESTS, ErrorCode::TooManyRequests, message)
+  } else if let Some(e) = err.find::<SRCError>() {
+    ApiError::from(e.clone())
+  } else if err.find::<reject::InvalidQuery>().is_some() {
+    ApiError::new(StatusCode::UNPROCESSABLE_ENTITY, ErrorCode::InvalidRequest, "query string is invalid")
+  } else if let Some(e) = err.find::<warp::body::BodyDeserializeError>() {
+    ApiError::new(StatusCode::UNPROCESSABLE_ENTITY, ErrorCode::InvalidRequest, e.to_string())
+  } else if err.find::<reject::PayloadTooLarge>().is_some() {
+    ApiError::new(StatusCode::PAYLOAD_TOO_LARGE, ErrorCode::InvalidRequest, "request body is too large")
+  } else if err.is_not_found() || err.find::<reject::MethodNotAllowed>().is_some() {
+    // a route which only rejects the method is reported as missing, like any other unmatched path
+    ApiError::not_found("not found")
+  } else {
+    ApiError::internal(format!("unhandled rejection: {:?}", err))
+  }
+}
+
+pub fn envelope(err: &ApiError, request_id: &str) -> ErrorEnvelope {
+  ErrorEnvelope {
+    code: err.code,
+    message: err.message.clone(),
+    request_id: request_id.to_string(),
+  }
+}
+
+pub async fn handle_rejection(err: Rejection) -> Result<impl Reply, Infallible> {
+  let api_error = to_api_error(&err);
+  // the id of the request span, so that the envelope and the logs of the request match
+  let request_id = logging::request_id().unwrap_or_else(logging::new_request_id);
+  if api_error.status.is_server_error() {
+    error!("request {} failed: {}", request_id, api_error.message);
+  } else if api_error.status != StatusCode::NOT_FOUND {
+    warn!("request {} rejected: {}", request_id, api_error.message);
+  }
+  let reply = warp::reply::with_status(warp::reply::json(&envelope(&api_error, &request_id)), api_error.status);
+  let mut response = warp::reply::with_header(reply, "x-request-id", request_id).into_response();
+  if let Some(throttled) = err.find::<Throttled>() {
+    response.headers_mut().insert(header::RETRY_AFTER, HeaderValue::from(throttled.retry_after_secs));
+  }
+  Ok(response)
+}
+
+#[test]
+fn test_status_mapping() {
+  let status = |e: ApiError| (e.status, e.code);
+  assert_eq!((StatusCode::NOT_FOUND, -2002), status(DatabaseError::not_found("validator", "stash 1ZGz").into()));
+  assert_eq!((StatusCode::SERVICE_UNAVAILABLE, -2001), status(DatabaseError::Disconnected.into()));
+  assert_eq!((StatusCode::INTERNAL_SERVER_ERROR, -2003), status(DatabaseError::missing_field("nomination", "data").into()));
+  assert_eq!((StatusCode::UNPROCESSABLE_ENTITY, -1005),
+    status(DatabaseError::Duplicated { collection: "newsletter".to_string(), key: "a@b.c".to_string() }.into()));
+  assert_eq!((StatusCode::NOT_FOUND, -2002), status(CacheError::NotFound("KSMEra".to_string()).into()));
+  assert_eq!((StatusCode::SERVICE_UNAVAILABLE, -2001), status(CacheError::Unavailable("refused".to_string()).into()));
+  assert_eq!((StatusCode::INTERNAL_SERVER_ERROR, -2003), status(CacheError::InvalidData("eof".to_string()).into()));
+  let src = |err_code| SRCError { message: "".to_string(), err_code };
+  assert_eq!((StatusCode::NOT_FOUND, -2002), status(src(-2).into()));
+  assert_eq!((StatusCode::UNPROCESSABLE_ENTITY, -1020), status(src(-5).into()));
+  assert_eq!((StatusCode::UNPROCESSABLE_ENTITY, -1021), status(src(-7).into()));
+  assert_eq!((StatusCode::SERVICE_UNAVAILABLE, -2001), status(src(-13).into()));
+}
+
+#[tokio::test]
+async fn test_error_envelope() {
+  let error = DatabaseError::not_found("validator", "stash 1ZGz");
+  let response = handle_rejection(db_rejection(error)).await.unwrap().into_response();
+  assert_eq!(StatusCode::NOT_FOUND, response.status());
+  let request_id = response.headers()["x-request-id"].to_str().unwrap().to_string();
+  let body = warp::hyper::body::to_bytes(response.into_body()).await.unwrap();
+  let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+  assert_eq!(serde_json::json!({
+    "code": -2002,
+    "message": "Database error: stash 1ZGz is not found in validator",
+    "requestId": request_id,
+  }), body);
+
+  let response = handle_rejection(reject::custom(InvalidParam::new("apy_max must be between 0 ~ 1.", ErrorCode::InvalidApy)))
+    .await.unwrap().into_response();
+  assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, response.status());
+  let response = handle_rejection(reject::not_found()).await.unwrap().into_response();
+  assert_eq!(StatusCode::NOT_FOUND, response.status());
+
+  // the id of the request span is reused
+  let _subscriber = tracing::subscriber::set_default(logging::test_subscriber());
+  let _span = tracing::info_span!("request", request_id = %"span-id").entered();
+  let response = handle_rejection(reject::not_found()).await.unwrap().into_response();
+  assert_eq!("span-id", response.headers()["x-request-id"]);
+}
@@ -1,91 +1,183 @@
-use std::convert::Infallible;
+use std::collections::HashMap;
+use std::sync::Arc;
 
-use crate::cache_redis::Cache;
+use crate::cache::Cache;
+use crate::chain;
+use crate::health::{Dependencies, HealthReport};
+use crate::logging;
+use crate::metrics;
+use crate::newsletter::Newsletter;
+use crate::stream::Broadcaster;
+use crate::rewards_jobs::RewardsJobQueue;
 use crate::staking_rewards_collector::SRCError;
-use self::params::{InvalidParam, OperationFailed};
+use self::error::handle_rejection;
 
 use super::db::Database;
+use super::db::repo::Repo;
 
-use serde_json::json;
-use warp::hyper::StatusCode;
-use warp::{Filter, Rejection};
+use warp::filters::BoxedFilter;
+use warp::http::StatusCode;
+use warp::{Filter, Rejection, Reply};
 use warp::reject::Reject;
 mod kusama;
 mod polkadot;
 mod cryptolab_api;
+mod cors;
+mod error;
+mod openapi;
 mod params;
+mod rate_limit;
 use super::config::Config;
 
+// the routes of kusama.rs, served under /api, and of polkadot.rs, under /api/dot
+const LEGACY_ROUTES: &[&str] = &[
+    "/validators",
+    "/valid",
+    "/validDetail",
+    "/nominators",
+    "/1kv/nominators",
+    "/allValidators",
+    "/validator/{stash}/trend",
+    "/validator/{stash}/unclaimedEras",
+    "/nominated/stash/{stash}",
+    "/stash/{stash}/rewards",
+    "/stash/{stash}/rewards/collector",
+    "/stash/{stash}/rewards/collector/csv",
+    "/stash/{stash}/rewards/collector/json",
+];
+
+// the paths of the API routes with their parameters as placeholders, e.g. /api/v1/validators/{chain}
+pub fn route_templates() -> Vec<String> {
+    let mut routes = openapi::paths();
+    for prefix in ["/api", "/api/dot"] {
+        routes.extend(LEGACY_ROUTES.iter().map(|route| format!("{}{}", prefix, route)));
+    }
+    routes
+}
+
 impl Reject for SRCError {}
 #[derive(Debug)]
 struct Invalid;
 impl Reject for Invalid {}
 
 pub struct WebServerOptions {
-    pub kusama_db: Database,
-    pub polkadot_db: Database,
-    pub westend_db: Option<Database>,
+    pub chain_dbs: HashMap<&'static str, Database>,
     pub users_db: Database,
     pub cache: Cache,
+    pub rewards_jobs: RewardsJobQueue,
+    pub newsletter: Newsletter,
+    pub broadcaster: Broadcaster,
 }
 
 pub struct WebServer {
     port: u16,
-    kusama_db: Database,
-    polkadot_db: Database,
-    westend_db: Option<Database>,
+    chain_dbs: HashMap<&'static str, Database>,
     pub users_db: Database,
     cache: Cache,
+    rewards_jobs: RewardsJobQueue,
+    newsletter: Newsletter,
+    broadcaster: Broadcaster,
+}
+
+type ApiFilter = BoxedFilter<(Box<dyn Reply>,)>;
+
+fn merge_routes(routes: Vec<ApiFilter>) -> ApiFilter {
+    routes.into_iter().reduce(|acc, r| acc.or(r).unify().boxed()).unwrap_or_else(|| {
+        warp::any()
+            .and_then(|| async { Err::<Box<dyn Reply>, Rejection>(warp::reject::not_found()) })
+            .boxed()
+    })
+}
+
+// GET /metrics in the Prometheus text format
+fn metrics_route() -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path("metrics").and(warp::path::end()).and(warp::get()).map(|| {
+        warp::reply::with_header(metrics::render(), "content-type", "text/plain; version=0.0.4")
+    })
+}
+
+// GET /healthz checks the in-process parts and GET /readyz the dependencies too, both return 503 when a check fails
+fn health_routes(dependencies: Dependencies) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let healthz = warp::path("healthz").and(warp::path::end()).and(warp::get()).map({
+        let dependencies = dependencies.clone();
+        move || health_reply(dependencies.liveness())
+    });
+    let readyz = warp::path("readyz").and(warp::path::end()).and(warp::get()).and_then(move || {
+        let dependencies = dependencies.clone();
+        async move { Ok::<_, Rejection>(health_reply(dependencies.readiness().await)) }
+    });
+    healthz.or(readyz).unify()
+}
+
+fn health_reply(report: HealthReport) -> warp::reply::WithStatus<warp::reply::Json> {
+    let status = if report.is_ok() { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    warp::reply::with_status(warp::reply::json(&report), status)
 }
 
 impl WebServer {
     pub fn new(port: u16, options: WebServerOptions) -> Self {
         WebServer {
             port,
-            kusama_db: options.kusama_db,
-            polkadot_db: options.polkadot_db,
-            westend_db: options.westend_db,
+            chain_dbs: options.chain_dbs,
             users_db: options.users_db,
             cache: options.cache,
+            rewards_jobs: options.rewards_jobs,
+            newsletter: options.newsletter,
+            broadcaster: options.broadcaster,
         }
     }
 
-    fn initialize_legacy_routes(
-        &self,
-    ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
-        kusama::routes(self.kusama_db.clone(), self.cache.clone())
-        .or(polkadot::routes(self.polkadot_db.clone(), self.cache.clone()))
+    fn initialize_legacy_routes(&self) -> ApiFilter {
+        let mut routes: Vec<ApiFilter> = vec![];
+        if let Some(db) = self.chain_dbs.get("KSM") {
+            routes.push(kusama::routes(db.clone(), self.cache.clone(), self.users_db.clone(), self.rewards_jobs.clone())
+                .map(|r| Box::new(r) as Box<dyn Reply>).boxed());
+        }
+        if let Some(db) = self.chain_dbs.get("DOT") {
+            routes.push(polkadot::routes(db.clone(), self.cache.clone(), self.users_db.clone(), self.rewards_jobs.clone())
+                .map(|r| Box::new(r) as Box<dyn Reply>).boxed());
+        }
+        merge_routes(routes)
     }
 
-    fn initialize_routes(
-        &self,
-    ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
-        cryptolab_api::get_routes("KSM", self.kusama_db.clone(), self.users_db.clone(), self.cache.clone(),
-        Config::current().staking_rewards_collector_dir.to_string())
-        .or(cryptolab_api::get_routes("DOT", self.polkadot_db.clone(), self.users_db.clone(),self.cache.clone(),
-        Config::current().staking_rewards_collector_dir.to_string()))
-        .or(cryptolab_api::get_routes("WND", self.westend_db.clone().unwrap(), self.users_db.clone(), self.cache.clone(),
-        Config::current().staking_rewards_collector_dir.to_string()))
-        .or(cryptolab_api::post_routes("KSM", self.users_db.clone(), self.kusama_db.clone()))
-        .or(cryptolab_api::post_routes("DOT", self.users_db.clone(), self.polkadot_db.clone()))
-        .or(cryptolab_api::post_routes("WND", self.users_db.clone(), self.westend_db.clone().unwrap()))
+    fn initialize_routes(&self) -> ApiFilter {
+        let mut routes: Vec<ApiFilter> = vec![
+            cryptolab_api::common_routes(self.rewards_jobs.clone(), self.users_db.clone())
+                .map(|r| Box::new(r) as Box<dyn Reply>).boxed(),
+            openapi::routes().map(|r| Box::new(r) as Box<dyn Reply>).boxed(),
+            cryptolab_api::admin_routes().map(|r| Box::new(r) as Box<dyn Reply>).boxed(),
+            metrics_route().map(|r| Box::new(r) as Box<dyn Reply>).boxed(),
+            health_routes(Dependencies {
+                chain_dbs: self.chain_dbs.clone(),
+                users_db: self.users_db.clone(),
+                cache: self.cache.clone(),
+                rewards_jobs: self.rewards_jobs.clone(),
+            }).map(|r| Box::new(r) as Box<dyn Reply>).boxed(),
+        ];
+        let users_repo: Repo = Arc::new(self.users_db.clone());
+        for chain in chain::all() {
+            let repo: Repo = Arc::new(self.chain_dbs[chain.ticker.as_str()].clone());
+            routes.push(cryptolab_api::get_routes(chain, repo.clone(), users_repo.clone(), self.cache.clone())
+                .map(|r| Box::new(r) as Box<dyn Reply>).boxed());
+            routes.push(cryptolab_api::post_routes(chain, users_repo.clone(), repo, self.cache.clone())
+                .map(|r| Box::new(r) as Box<dyn Reply>).boxed());
+        }
+        routes.push(cryptolab_api::newsletter_routes(users_repo, self.newsletter.clone())
+            .map(|r| Box::new(r) as Box<dyn Reply>).boxed());
+        merge_routes(routes)
+    }
+
+    fn initialize_stream_routes(&self) -> ApiFilter {
+        let mut routes: Vec<ApiFilter> = vec![];
+        for chain in chain::all() {
+            let repo: Repo = Arc::new(self.chain_dbs[chain.ticker.as_str()].clone());
+            routes.push(cryptolab_api::stream_routes(chain, repo, self.cache.clone(), self.broadcaster.clone())
+                .map(|r| Box::new(r) as Box<dyn Reply>).boxed());
+        }
+        merge_routes(routes)
     }
 
     pub async fn start(&self) {
-        let config = Config::current();
-        let origins: Vec<&str> = config.cors_url.iter().map(|s| &**s).collect();
-        let cors = warp::cors()
-            .allow_origins(origins)
-            .allow_headers(vec![
-                "User-Agent",
-                "Sec-Fetch-Mode",
-                "Referer",
-                "Origin",
-                "Access-Control-Request-Method",
-                "Access-Control-Request-Headers",
-                "Content-Type",
-            ])
-            .allow_methods(&[warp::http::Method::GET, warp::http::Method::POST, warp::http::Method::OPTIONS]);
         let routes = warp::fs::dir("./www/static");
         let tool_routes = warp::path("tools").and(warp::fs::dir("./www/static"));
         let validator_status_routes = warp::path("tools").and(warp::path("validatorStatus")).and(warp::fs::dir("./www/static"));
@@ -96,13 +188,19 @@ impl WebServer {
         let onekv_dot_routes = warp::path("tools").and(warp::path("oneKValidatorsDot")).and(warp::fs::dir("./www/static"));
         let contact_routes = warp::path("contact").and(warp::fs::dir("./www/static"));
 
-        let api_routes = 
-            self.initialize_routes()
-            .or(self.initialize_legacy_routes())
-            .recover(handle_rejection)
-            .with(cors)
-            .with(warp::compression::gzip())
-            .with(warp::log("warp_request"));
+        // the event streams are not compressed, the encoder would hold the events back
+        let api_routes = rate_limit::limit(self.cache.clone(), rate_limit::config_limits)
+            .and(self.initialize_stream_routes()
+                .or(self.initialize_routes()
+                    .or(self.initialize_legacy_routes())
+                    .with(warp::compression::gzip())))
+            .recover(handle_rejection);
+        let api_routes = cors::with_cors(api_routes, cors::is_config_origin)
+            .with(warp::log::custom(logging::log_request))
+            .with(warp::log::custom(|info| {
+                metrics::record_request(info.path(), info.method().as_str(), info.status().as_u16(), info.elapsed())
+            }))
+            .with(warp::trace(logging::request_span));
         if Config::current().serve_www.unwrap_or_default() {
             warp::serve(api_routes.or(routes).or(tool_routes).or(validator_status_routes)
             .or(ksmvn_routes).or(dotvn_routes).or(dotsr_routes)
@@ -113,33 +211,3 @@ impl WebServer {
         }
     }
 }
-
-async fn handle_rejection(err: Rejection) -> Result<warp::reply::WithStatus<warp::reply::Json>, Infallible> {
-    if err.is_not_found() {
-        Ok(warp::reply::with_status(
-        warp::reply::json(&""),
-        StatusCode::NOT_FOUND,
-        ))
-    } else if let Some(e) = err.find::<InvalidParam>() {
-        Ok(warp::reply::with_status(
-        warp::reply::json(&json! ({
-            "message": e.message,
-            "code": e.err_code
-        })),
-        StatusCode::BAD_REQUEST,
-        ))
-    } else if let Some(e) = err.find::<OperationFailed>() {
-        Ok(warp::reply::with_status(
-        warp::reply::json(&json! ({
-            "message": e.message,
-            "code": e.err_code
-        })),
-        StatusCode::BAD_REQUEST,
-        ))
-    } else {
-        Ok(warp::reply::with_status(
-        warp::reply::json(&""),
-        StatusCode::BAD_REQUEST,
-        ))
-    }
-}
@@ -0,0 +1,465 @@
+use std::sync::OnceLock;
+
+use schemars::JsonSchema;
+use schemars::gen::{SchemaGenerator, SchemaSettings};
+use serde_json::{Map, Value, json};
+use warp::Filter;
+
+use crate::db::rewards_jobs::RewardsJob;
+use crate::risk::ValidatorRisk;
+use crate::simulation::{SimulationOptions, SimulationResult};
+use crate::strategy::StrategyResult;
+use crate::webhooks::{WebhookOptions, WebhookSubscription};
+use crate::types::{LogLevel, NewsletterAdminParams, NewsletterDataRequest, NewsletterSubscriber, NewsletterSubscriberList,
+  NewsletterSubscriberOptions, NewsletterTokenParams, NominationOptions, NominationResultOptions, NominationResultParams,
+  NominatorNomination, OneKvNominators, RefKey, RefKeyOptions, StakingEvents, StashRewards, ValidatorDetail1kv,
+  ValidatorList, ValidatorNominationInfo, ValidatorNominationTrend, ValidatorSlash};
+
+use super::cryptolab_api::StakingRewardsOptions;
+use super::error::ErrorEnvelope;
+use super::params::{AllValidatorOptions, EventFilterOptions, StreamOptions};
+
+static DOCUMENT: OnceLock<Value> = OnceLock::new();
+
+// Builds the operations of the document. Schemas are generated from the request and response types,
+// so only the paths, their parameters and the type of each body are listed by hand.
+struct Spec {
+  gen: SchemaGenerator,
+  paths: Map<String, Value>,
+}
+
+enum Body {
+  Json(Value),
+  Text(&'static str),
+  // server-sent events
+  EventStream(&'static str),
+  Empty,
+}
+
+struct Op {
+  method: &'static str,
+  path: &'static str,
+  summary: &'static str,
+  query: Vec<Value>,
+  request: Option<Value>,
+  status: u16,
+  response: Body,
+}
+
+impl Op {
+  fn new(method: &'static str, path: &'static str, summary: &'static str) -> Self {
+    Op {
+      method,
+      path,
+      summary,
+      query: vec![],
+      request: None,
+      status: 200,
+      response: Body::Empty,
+    }
+  }
+
+  fn get(path: &'static str, summary: &'static str) -> Self {
+    Op::new("get", path, summary)
+  }
+
+  fn post(path: &'static str, summary: &'static str) -> Self {
+    Op::new("post", path, summary)
+  }
+
+  fn put(path: &'static str, summary: &'static str) -> Self {
+    Op::new("put", path, summary)
+  }
+
+  fn delete(path: &'static str, summary: &'static str) -> Self {
+    Op::new("delete", path, summary)
+  }
+
+  fn query(mut self, query: Vec<Value>) -> Self {
+    self.query = query;
+    self
+  }
+
+  fn request(mut self, schema: Value) -> Self {
+    self.request = Some(schema);
+    self
+  }
+
+  fn status(mut self, status: u16) -> Self {
+    self.status = status;
+    self
+  }
+
+  fn returns(mut self, response: Body) -> Self {
+    self.response = response;
+    self
+  }
+}
+
+impl Spec {
+  fn new() -> Self {
+    Spec {
+      gen: SchemaSettings::openapi3().into_generator(),
+      paths: Map::new(),
+    }
+  }
+
+  fn schema<T: JsonSchema>(&mut self) -> Value {
+    serde_json::to_value(self.gen.subschema_for::<T>()).unwrap_or_default()
+  }
+
+  fn json<T: JsonSchema>(&mut self) -> Body {
+    Body::Json(self.schema::<T>())
+  }
+
+  // the fields of a query string struct, one parameter each
+  fn query<T: JsonSchema>(&mut self) -> Vec<Value> {
+    let root = self.gen.clone().into_root_schema_for::<T>();
+    let object = match root.schema.object {
+      Some(object) => object,
+      None => return vec![],
+    };
+    let required = object.required;
+    object.properties.into_iter().map(|(name, schema)| json!({
+      "name": name,
+      "in": "query",
+      "required": required.contains(&name),
+      "schema": schema,
+    })).collect()
+  }
+
+  fn add(&mut self, op: Op) {
+    let mut parameters: Vec<Value> = op.path.split('/')
+      .filter_map(|segment| segment.strip_prefix('{').and_then(|s| s.strip_suffix('}')))
+      .map(|name| json!({
+        "name": name,
+        "in": "path",
+        "required": true,
+        "description": path_parameter_description(name),
+        "schema": {"type": "string"},
+      }))
+      .collect();
+    parameters.extend(op.query);
+    let mut operation = json!({
+      "summary": op.summary,
+      "operationId": operation_id(op.method, op.path),
+      "responses": {
+        op.status.to_string(): response_of(op.response),
+        "default": {
+          "description": "Error",
+          "content": {"application/json": {"schema": self.schema::<ErrorEnvelope>()}},
+        },
+      },
+    });
+    if !parameters.is_empty() {
+      operation["parameters"] = Value::Array(parameters);
+    }
+    if let Some(schema) = op.request {
+      operation["requestBody"] = json!({
+        "required": true,
+        "content": {"application/json": {"schema": schema}},
+      });
+    }
+    let item = self.paths.entry(op.path.to_string()).or_insert_with(|| json!({}));
+    item[op.method] = operation;
+  }
+
+  fn into_document(self) -> Value {
+    json!({
+      "openapi": "3.0.3",
+      "info": {
+        "title": "CryptoLab API",
+        "version": env!("CARGO_PKG_VERSION"),
+      },
+      "paths": self.paths,
+      "components": {"schemas": self.gen.definitions()},
+    })
+  }
+}
+
+fn path_parameter_description(name: &str) -> &'static str {
+  match name {
+    "chain" => "Ticker of the chain, e.g. KSM or DOT",
+    "stash" => "Stash address",
+    "jobId" => "Id of a rewards job",
+    "id" => "Id of a webhook subscription",
+    _ => "",
+  }
+}
+
+// e.g. getValidatorUnclaimedErasChain for GET /api/v1/validator/{stash}/unclaimedEras/{chain}
+fn operation_id(method: &str, path: &str) -> String {
+  let words = path.trim_start_matches("/api/v1/").split(|c: char| !c.is_alphanumeric())
+    .filter(|w| !w.is_empty() && *w != "stash" && *w != "id");
+  let mut id = method.to_string();
+  for word in words {
+    let mut chars = word.chars();
+    if let Some(first) = chars.next() {
+      id.push(first.to_ascii_uppercase());
+      id.extend(chars);
+    }
+  }
+  id
+}
+
+fn response_of(body: Body) -> Value {
+  match body {
+    Body::Json(schema) => json!({
+      "description": "OK",
+      "content": {"application/json": {"schema": schema}},
+    }),
+    Body::Text(description) => json!({
+      "description": description,
+      "content": {"text/plain": {"schema": {"type": "string"}}},
+    }),
+    Body::EventStream(description) => json!({
+      "description": description,
+      "content": {"text/event-stream": {"schema": {"type": "string"}}},
+    }),
+    Body::Empty => json!({"description": "OK"}),
+  }
+}
+
+// the documented paths, e.g. /api/v1/validators/{chain}
+pub fn paths() -> Vec<String> {
+  build()["paths"].as_object().map(|paths| paths.keys().cloned().collect()).unwrap_or_default()
+}
+
+pub fn build() -> Value {
+  let mut spec = Spec::new();
+
+  let query = spec.query::<AllValidatorOptions>();
+  let body = spec.json::<ValidatorList>();
+  spec.add(Op::get("/api/v1/validators/{chain}", "Validators of the current era, a page at a time").query(query).returns(body));
+  let body = spec.json::<StrategyResult>();
+  spec.add(Op::get("/api/v1/strategy/{chain}/{strategy}", "Validators to nominate picked by a strategy").returns(body));
+  let body = spec.json::<ValidatorRisk>();
+  spec.add(Op::get("/api/v1/validator/{stash}/risk/{chain}", "Latest risk score of a validator").returns(body));
+  let body = spec.json::<NominatorNomination>();
+  spec.add(Op::get("/api/v1/nominator/id/{stash}/{chain}", "Nominator with its rewards").returns(body));
+  let body = spec.json::<Vec<ValidatorNominationTrend>>();
+  spec.add(Op::get("/api/v1/validator/{stash}/{chain}", "Validator with its nomination info of every era").returns(body));
+  let body = spec.json::<Vec<i32>>();
+  spec.add(Op::get("/api/v1/validator/{stash}/unclaimedEras/{chain}", "Eras whose rewards are not claimed").returns(body));
+  let body = spec.json::<Vec<ValidatorSlash>>();
+  spec.add(Op::get("/api/v1/validator/{stash}/slashes/{chain}", "Slashes of a validator").returns(body));
+  let body = spec.json::<Vec<NominatorNomination>>();
+  spec.add(Op::get("/api/v1/nominators/{chain}", "All nominators").returns(body));
+  let body = spec.json::<ValidatorDetail1kv>();
+  spec.add(Op::get("/api/v1/1kv/validators/{chain}", "Validators of the Thousand Validators Programme").returns(body));
+  let body = spec.json::<OneKvNominators>();
+  spec.add(Op::get("/api/v1/1kv/nominators/{chain}", "Nominators of the Thousand Validators Programme").returns(body));
+  let body = spec.json::<Vec<ValidatorNominationInfo>>();
+  spec.add(Op::get("/api/v1/nominated/stash/{stash}/{chain}", "Validators nominated by a stash").returns(body));
+  let query = spec.query::<EventFilterOptions>();
+  let body = spec.json::<StakingEvents>();
+  spec.add(Op::get("/api/v1/events/stash/{stash}/{chain}", "Staking events of a nominator, the last 84 eras by default")
+    .query(query).returns(body));
+
+  let body = spec.json::<RefKey>();
+  let query = spec.query::<StreamOptions>();
+  spec.add(Op::get("/api/v1/stream/{chain}",
+    "Server-sent events: era transitions, 1KV refreshes and the staking events of the given stashes")
+    .query(query).returns(Body::EventStream("`era`, `onekv` and `events` events, starting with the current era")));
+  spec.add(Op::get("/api/v1/refKey/stash/{stash}/{chain}", "Ref key of a validator, a new one if it has none").returns(body));
+  let request = spec.schema::<RefKeyOptions>();
+  spec.add(Op::post("/api/v1/refKey/stash/{stash}/{chain}/verify", "Verifies the signed ref key of a validator and stores it")
+    .request(request.clone()).returns(Body::Text("true")));
+  spec.add(Op::post("/api/v1/refKey/decode", "Stash of a stored ref key").request(request).returns(Body::Text("The stash")));
+  let request = spec.schema::<SimulationOptions>();
+  let body = spec.json::<SimulationResult>();
+  spec.add(Op::post("/api/v1/simulate/{chain}", "Expected rewards of nominating validators with an amount")
+    .request(request).returns(body));
+  let request = spec.schema::<WebhookOptions>();
+  let body = spec.json::<WebhookSubscription>();
+  spec.add(Op::post("/api/v1/webhooks/{chain}", "Subscribes a webhook to the staking events of a nominator")
+    .request(request).status(201).returns(body));
+  spec.add(Op::delete("/api/v1/webhooks/{chain}/{id}",
+    "Deletes a webhook subscription, its secret is sent in the X-Webhook-Secret header").status(204));
+  let request = spec.schema::<NominationOptions>();
+  spec.add(Op::post("/api/v1/nominate/{chain}", "Records a nomination").request(request)
+    .returns(Body::Text("Tag of the nomination record")));
+  let query = spec.query::<NominationResultParams>();
+  let request = spec.schema::<NominationResultOptions>();
+  spec.add(Op::post("/api/v1/nominated/{chain}", "Records the extrinsic of a nomination").query(query).request(request));
+  let request = spec.schema::<NewsletterSubscriberOptions>();
+  spec.add(Op::post("/api/v1/newsletter", "Subscribes to the newsletter, the address is sent a confirmation link")
+    .request(request));
+  let query = spec.query::<NewsletterTokenParams>();
+  spec.add(Op::get("/api/v1/newsletter/confirm", "Confirms a subscription with the token of the confirmation email")
+    .query(query.clone()).returns(Body::Text("Confirmation message")));
+  spec.add(Op::get("/api/v1/newsletter/unsubscribe", "Unsubscribes with the token of a digest email")
+    .query(query.clone()).returns(Body::Text("Unsubscription message")));
+  spec.add(Op::post("/api/v1/newsletter/unsubscribe", "One-click unsubscription of mail clients (RFC 8058)")
+    .query(query.clone()).returns(Body::Text("Unsubscription message")));
+  let request = spec.schema::<NewsletterDataRequest>();
+  spec.add(Op::post("/api/v1/newsletter/data", "Sends the address a link to the data held about it")
+    .request(request).status(202));
+  let body = spec.json::<NewsletterSubscriber>();
+  spec.add(Op::get("/api/v1/newsletter/data", "Data held about an address, with the token of the data link")
+    .query(query.clone()).returns(body));
+  spec.add(Op::delete("/api/v1/newsletter/data", "Deletes the data held about an address, with the token of the data link")
+    .query(query).status(204));
+  let query = spec.query::<NewsletterAdminParams>();
+  let body = spec.json::<NewsletterSubscriberList>();
+  spec.add(Op::get("/api/v1/admin/newsletter",
+    "Subscribers and their counts by status, the admin token is sent as a bearer token").query(query).returns(body));
+  let request = spec.schema::<LogLevel>();
+  let body = spec.json::<LogLevel>();
+  spec.add(Op::get("/api/v1/admin/logLevel", "The log filter, with the admin token as a bearer token").returns(body));
+  let body = spec.json::<LogLevel>();
+  spec.add(Op::put("/api/v1/admin/logLevel", "Replaces the log filter until the next restart")
+    .request(request).returns(body));
+
+  let query = spec.query::<StakingRewardsOptions>();
+  let body = spec.json::<StashRewards>();
+  spec.add(Op::get("/api/v1/stash/{stash}/rewards/collector", "Staking rewards report once it is computed, its job with 202 until then")
+    .query(query.clone()).returns(body));
+  let body = spec.json::<RewardsJob>();
+  spec.add(Op::post("/api/v1/stash/{stash}/rewards/collector", "Queues a staking rewards report")
+    .query(query).status(202).returns(body));
+  let body = spec.json::<RewardsJob>();
+  spec.add(Op::get("/api/v1/rewards/jobs/{jobId}", "Status of a rewards job").returns(body));
+  spec.add(Op::get("/api/v1/stash/{stash}/rewards/collector/csv", "Latest finished report of a stash")
+    .returns(Body::Text("The report in CSV")));
+  spec.add(Op::get("/api/v1/stash/{stash}/rewards/collector/json", "Latest finished report of a stash")
+    .returns(Body::Text("The report in JSON")));
+
+  spec.add(Op::get("/api/v1/openapi.json", "This document").returns(Body::Json(json!({"type": "object"}))));
+  spec.into_document()
+}
+
+pub fn document() -> &'static Value {
+  DOCUMENT.get_or_init(build)
+}
+
+pub fn routes() -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+  warp::get()
+  .and(warp::path("api"))
+  .and(warp::path("v1"))
+  .and(warp::path("openapi.json"))
+  .and(warp::path::end())
+  .map(|| warp::reply::json(document()))
+}
+
+#[cfg(test)]
+fn resolve<'a>(schema: &'a Value, document: &'a Value) -> &'a Value {
+  match schema["$ref"].as_str().and_then(|r| r.strip_prefix("#/components/schemas/")) {
+    Some(name) => resolve(&document["components"]["schemas"][name], document),
+    None => schema,
+  }
+}
+
+// checks a response body against its schema, undocumented fields are reported as well
+#[cfg(test)]
+fn conform(value: &Value, schema: &Value, document: &Value, path: &str) -> Result<(), String> {
+  // nullable may sit next to a $ref
+  if value.is_null() && schema["nullable"] == true {
+    return Ok(());
+  }
+  let schema = resolve(schema, document);
+  if let Some(all) = schema["allOf"].as_array() {
+    return all.iter().try_for_each(|s| conform(value, s, document, path));
+  }
+  if let Some(any) = schema["anyOf"].as_array().or_else(|| schema["oneOf"].as_array()) {
+    return match any.iter().any(|s| conform(value, s, document, path).is_ok()) {
+      true => Ok(()),
+      false => Err(format!("{} matches none of its schemas", path)),
+    };
+  }
+  if let Some(values) = schema["enum"].as_array() {
+    if !values.contains(value) {
+      return Err(format!("{} is not one of {:?}", path, values));
+    }
+  }
+  let matched = match schema["type"].as_str() {
+    Some("object") => {
+      let object = value.as_object().ok_or_else(|| format!("{} is not an object", path))?;
+      for required in schema["required"].as_array().into_iter().flatten() {
+        if !object.contains_key(required.as_str().unwrap_or_default()) {
+          return Err(format!("{}.{} is missing", path, required));
+        }
+      }
+      for (key, field) in object {
+        let field_path = format!("{}.{}", path, key);
+        match schema["properties"].get(key) {
+          Some(field_schema) => conform(field, field_schema, document, &field_path)?,
+          None if schema["additionalProperties"].is_object() =>
+            conform(field, &schema["additionalProperties"], document, &field_path)?,
+          None if schema["properties"].is_object() => return Err(format!("{} is not in the spec", field_path)),
+          None => {},
+        }
+      }
+      true
+    },
+    Some("array") => {
+      let items = value.as_array().ok_or_else(|| format!("{} is not an array", path))?;
+      for (i, item) in items.iter().enumerate() {
+        conform(item, &schema["items"], document, &format!("{}[{}]", path, i))?;
+      }
+      true
+    },
+    Some("integer") => value.is_i64() || value.is_u64(),
+    Some("number") => value.is_number(),
+    Some("string") => value.is_string(),
+    Some("boolean") => value.is_boolean(),
+    _ => true,
+  };
+  match matched {
+    true => Ok(()),
+    false => Err(format!("{} is not {}", path, schema["type"])),
+  }
+}
+
+#[test]
+fn test_openapi_document() {
+  // the committed copy of the document, which clients are generated from
+  let file = concat!(env!("CARGO_MANIFEST_DIR"), "/docs/openapi.json");
+  let generated = serde_json::to_string_pretty(&build()).unwrap() + "\n";
+  if std::env::var("UPDATE_OPENAPI").is_ok() {
+    std::fs::write(file, &generated).unwrap();
+  }
+  let committed = std::fs::read_to_string(file).unwrap_or_default();
+  assert!(committed == generated,
+    "docs/openapi.json is out of date, regenerate it with `UPDATE_OPENAPI=1 cargo test test_openapi_document`");
+}
+
+// every operation of the document is served, and the bodies of the fixtures match their schemas
+#[tokio::test]
+async fn test_openapi_routes() {
+  let document = build();
+  let routes = super::cryptolab_api::test_routes();
+  let mut operations = 0;
+  for (path, item) in document["paths"].as_object().unwrap() {
+    for (method, operation) in item.as_object().unwrap() {
+      let stash = if path.starts_with("/api/v1/validator/") || path.starts_with("/api/v1/refKey/") {
+        "GLVeryFRbg5hEKvQZcAnLvXZEXhQsiswr9CMnw1ZhQiAbBe"
+      } else {
+        "HNZata7iMYWmk5RvZRTiAsSDhV8366zq2YGb3tLH5Upf74F"
+      };
+      let uri = path.replace("{chain}", "KSM").replace("{stash}", stash).replace("{jobId}", "job")
+        .replace("{strategy}", "default").replace("{id}", "webhook");
+      if !operation["responses"]["200"]["content"]["text/event-stream"].is_null() {
+        // the stream does not end, only its start is checked
+        let response = warp::Reply::into_response(warp::test::request().path(&uri).filter(&routes).await.unwrap());
+        assert_eq!(200, response.status(), "{} {} is not served", method, uri);
+        operations += 1;
+        continue;
+      }
+      let response = warp::test::request().method(&method.to_uppercase()).path(&uri).json(&json!({}))
+        .reply(&routes).await;
+      let body: Value = serde_json::from_slice(response.body()).unwrap_or_default();
+      assert!(!(response.status() == 404 && body["message"] == "not found"), "{} {} is not served", method, uri);
+      let responses = &operation["responses"];
+      let documented = responses.get(response.status().as_str()).unwrap_or(&responses["default"]);
+      let schema = &documented["content"]["application/json"]["schema"];
+      if !schema.is_null() {
+        if let Err(e) = conform(&body, schema, &document, "body") {
+          panic!("{} {} does not match the spec: {}", method, uri, e);
+        }
+      }
+      operations += 1;
+    }
+  }
+  assert_eq!(37, operations);
+}
@@ -1,15 +1,38 @@
 use std::fmt;
 use serde_json::json;
 use serde::Deserialize;
+use schemars::JsonSchema;
 use warp::reject;
 
-#[derive(Copy, Clone)]
+use crate::chain::Chain;
+use crate::db::params::{ValidatorCursor, ValidatorSort};
+
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum ErrorCode {
     InvalidApy = -1000,
     InvalidCommission = -1001,
     InvalidEmailAddress = -1002,
+    UnsupportedNetwork = -1003,
+    InvalidRequest = -1004,
+    Duplicated = -1005,
     EmptyRefKey = -1010,
+    MalformedSignature = -1011,
+    InvalidAddress = -1012,
+    WrongAddress = -1013,
+    WrongMessage = -1014,
+    InvalidRefKey = -1015,
+    InvalidDate = -1020,
+    UnsupportedCurrency = -1021,
+    UnsupportedStrategy = -1030,
+    InvalidUrl = -1040,
+    InvalidToken = -1041,
+    InvalidLogLevel = -1050,
     OperationFailed = -2000,
+    ServiceUnavailable = -2001,
+    NotFound = -2002,
+    InternalError = -2003,
+    Unauthorized = -2004,
+    TooManyRequests = -2005,
 }
 
 impl ErrorCode {
@@ -30,7 +53,7 @@ impl fmt::Display for InvalidParam {
             "message": self.message,
             "err_code": self.err_code,
         });
-        write!(f, "{}", msg.to_string())
+        write!(f, "{}", msg)
     }
 }
 
@@ -43,41 +66,19 @@ impl InvalidParam {
     }
 }
 
-#[derive(Debug)]
-pub struct OperationFailed {
-    pub message: String,
-    pub err_code: i32,
-}
-
-impl fmt::Display for OperationFailed {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let msg = json!({
-            "message": self.message,
-            "err_code": self.err_code,
-        });
-        write!(f, "{}", msg.to_string())
-    }
-}
-
-impl OperationFailed {
-    pub fn new(message: &str, err_code: ErrorCode) -> Self {
-        OperationFailed {
-            message: message.to_string(),
-            err_code: err_code.to_int(),
-        }
-    }
-}
-
 impl reject::Reject for InvalidParam {}
-impl reject::Reject for OperationFailed {}
 
 #[derive(Deserialize)]
 pub struct ValidDetailOptions {
     pub option: String,
 }
 
-#[derive(Deserialize)]
+// validators of a page, the default size too so that a request without it still gets the whole era
+pub const MAX_PAGE_SIZE: u32 = 5000;
+
+#[derive(Deserialize, JsonSchema)]
 pub struct AllValidatorOptions {
+    // 1 to 5000, 5000 by default
     size: Option<u32>,
     page: Option<u32>,
     apy_min: Option<f32>,
@@ -86,30 +87,46 @@ pub struct AllValidatorOptions {
     commission_max: Option<f32>,
     has_verified_identity: Option<bool>,
     has_joined_1kv: Option<bool>,
+    // stake bounds in tokens
+    total_min: Option<f64>,
+    total_max: Option<f64>,
+    self_stake_min: Option<f64>,
+    self_stake_max: Option<f64>,
+    // only validators a new nomination would not oversubscribe
+    not_oversubscribed: Option<bool>,
+    // only validators without slashes in the last n eras
+    no_slashes_in_eras: Option<u32>,
+    exclude_blocked: Option<bool>,
+    has_parent_identity: Option<bool>,
+    // only validators whose average era points reach this percentile, between 0 and 1
+    era_points_percentile: Option<f32>,
+    sort: Option<ValidatorSort>,
+    order: Option<SortOrder>,
+    // cursor returned as `next` by the previous page
+    next: Option<String>,
 }
 
-impl AllValidatorOptions {
-    pub fn new() -> Self {
-        AllValidatorOptions{
-            size: Some(5000),
-            page: Some(0),
-            apy_min: Some(0.0),
-            apy_max: Some(1.0),
-            commission_min: Some(0.0),
-            commission_max: Some(1.0),
-            has_verified_identity: Some(false),
-            has_joined_1kv: Some(false),
-        }
-    }
+#[derive(Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    Asc,
+    Desc,
+}
 
+impl AllValidatorOptions {
     pub fn size(&self) -> u32 {
-        self.size.unwrap_or(5000)
+        self.size.unwrap_or(MAX_PAGE_SIZE)
     }
 
     pub fn page(&self) -> u32 {
         self.page.unwrap_or(0)
     }
 
+    // validators before the page, None when `page * size` overflows
+    pub fn skip(&self) -> Option<u32> {
+        self.page().checked_mul(self.size())
+    }
+
     pub fn apy_min(&self) -> f32 {
         self.apy_min.unwrap_or(0.0)
     }
@@ -134,7 +151,67 @@ impl AllValidatorOptions {
         self.has_joined_1kv.unwrap_or(false)
     }
 
-    pub fn to_db_all_validator_options(&self) -> super::super::db::params::AllValidatorOptions {
+    pub fn total_min(&self) -> Option<f64> {
+        self.total_min
+    }
+
+    pub fn total_max(&self) -> Option<f64> {
+        self.total_max
+    }
+
+    pub fn self_stake_min(&self) -> Option<f64> {
+        self.self_stake_min
+    }
+
+    pub fn self_stake_max(&self) -> Option<f64> {
+        self.self_stake_max
+    }
+
+    pub fn not_oversubscribed(&self) -> bool {
+        self.not_oversubscribed.unwrap_or(false)
+    }
+
+    pub fn no_slashes_in_eras(&self) -> Option<u32> {
+        self.no_slashes_in_eras
+    }
+
+    pub fn exclude_blocked(&self) -> bool {
+        self.exclude_blocked.unwrap_or(false)
+    }
+
+    pub fn has_parent_identity(&self) -> bool {
+        self.has_parent_identity.unwrap_or(false)
+    }
+
+    pub fn era_points_percentile(&self) -> Option<f32> {
+        self.era_points_percentile
+    }
+
+    pub fn sort(&self) -> Option<ValidatorSort> {
+        self.sort
+    }
+
+    pub fn descending(&self) -> bool {
+        self.order == Some(SortOrder::Desc)
+    }
+
+    // the cursor of `next`, which must have been issued for the same sort and order
+    pub fn cursor(&self) -> Result<Option<ValidatorCursor>, InvalidParam> {
+        let next = match &self.next {
+            Some(next) => next,
+            None => return Ok(None),
+        };
+        let cursor: ValidatorCursor = bs58::decode(next).into_vec().ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .ok_or_else(|| InvalidParam::new("next is not a valid cursor", ErrorCode::InvalidRequest))?;
+        if cursor.sort != self.sort() || cursor.descending != self.descending() {
+            return Err(InvalidParam::new("next was issued for another sort or order", ErrorCode::InvalidRequest));
+        }
+        Ok(Some(cursor))
+    }
+
+    pub fn to_db_all_validator_options(&self, chain: &Chain) -> super::super::db::params::AllValidatorOptions {
+        let planck = |tokens: f64| (tokens * 10f64.powi(chain.decimals as i32)) as u128;
         super::super::db::params::AllValidatorOptions {
             size: self.size(),
             page: self.page(),
@@ -143,17 +220,48 @@ impl AllValidatorOptions {
             commission_min: self.commission_min(),
             commission_max: self.commission_max(),
             has_verified_identity: self.has_verified_identity(),
+            total_min: self.total_min().map(planck),
+            total_max: self.total_max().map(planck),
+            self_stake_min: self.self_stake_min().map(planck),
+            self_stake_max: self.self_stake_max().map(planck),
+            nominator_limit: if self.not_oversubscribed() { Some(chain.max_nominators_rewarded) } else { None },
+            slash_free_eras: self.no_slashes_in_eras(),
+            exclude_blocked: self.exclude_blocked(),
+            has_parent_identity: self.has_parent_identity(),
+            era_points_percentile: self.era_points_percentile(),
+            stashes: None,
+            sort: self.sort(),
+            descending: self.descending(),
+            after: self.cursor().ok().flatten(),
         }
     }
 }
 
-#[derive(Deserialize)]
+pub fn encode_cursor(cursor: &ValidatorCursor) -> String {
+    bs58::encode(serde_json::to_vec(cursor).unwrap_or_default()).into_string()
+}
+
+#[derive(Deserialize, JsonSchema)]
 pub struct EventFilterOptions {
     from_era: Option<u32>,
     to_era: Option<u32>
 }
 
+#[derive(Deserialize, JsonSchema)]
+pub struct StreamOptions {
+    // comma separated stashes whose staking events are streamed
+    stashes: Option<String>,
+}
+
+impl StreamOptions {
+    pub fn stashes(&self) -> Vec<String> {
+        self.stashes.as_deref().unwrap_or_default().split(',').map(|s| s.trim()).filter(|s| !s.is_empty())
+            .map(|s| s.to_string()).collect()
+    }
+}
+
 impl EventFilterOptions {
+    #[allow(clippy::wrong_self_convention)]
     pub fn from_era(&self) -> u32 {
         self.from_era.unwrap_or(0)
     }
@@ -1,12 +1,14 @@
-use log::error;
+use tracing::error;
 use serde::Deserialize;
-use std::{collections::HashMap, convert::Infallible};
-use super::params::AllValidatorOptions;
-use super::params::{ValidDetailOptions};
+use std::collections::HashMap;
+use super::error::{cache_rejection, db_rejection, src_rejection};
+use super::super::db::params::AllValidatorOptions;
+use super::params::{ErrorCode, InvalidParam, ValidDetailOptions};
 use warp::http::StatusCode;
-use warp::Filter;
-use crate::cache_redis::Cache;
-use crate::config::Config;
+use warp::{Filter, Rejection};
+use crate::cache::Cache;
+use crate::rewards_jobs::RewardsJobQueue;
+use super::cryptolab_api::rewards_report_reply;
 use crate::staking_rewards_collector::StakingRewardsReport;
 use crate::web::Invalid;
 
@@ -26,14 +28,20 @@ struct StakingRewardsOptions {
 fn get_validators(cache: Cache) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::path("validators")
     .and(warp::path::end())
-    .map(move || warp::reply::json(&cache.get_validators("DOT")))
+    .and(with_cache(cache))
+    .and_then(|cache: Cache| async move {
+        cache.get_validators("DOT").await.map(|v| warp::reply::json(&v)).map_err(cache_rejection)
+    })
 }
 
 fn get_1kv_validators(cache: Cache) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone
 {
     warp::path("valid")
     .and(warp::path::end())
-    .map(move || warp::reply::json(&cache.get_1kv_info_detail("DOT")))
+    .and(with_cache(cache))
+    .and_then(|cache: Cache| async move {
+        cache.get_1kv_info_detail("DOT").await.map(|v| warp::reply::json(&v)).map_err(cache_rejection)
+    })
 }
 
 fn get_validator_trend(
@@ -48,7 +56,7 @@ fn get_validator_trend(
             let validator = db.get_validator(stash).await;
             match validator {
                 Ok(v) => Ok(warp::reply::json(&[v])),
-                Err(_) => Err(warp::reject::not_found()),
+                Err(e) => Err(db_rejection(e)),
             }
         })
 }
@@ -65,7 +73,7 @@ fn get_validator_unclaimed_eras(
             let validator = db.get_validator_unclaimed_eras(stash).await;
             match validator {
                 Ok(v) => Ok(warp::reply::json(&v)),
-                Err(_) => Err(warp::reject::not_found()),
+                Err(e) => Err(db_rejection(e)),
             }
         })
 }
@@ -76,26 +84,27 @@ fn with_db(
     warp::any().map(move || db.clone())
 }
 
+fn with_jobs(
+    jobs: RewardsJobQueue,
+) -> impl Filter<Extract = (RewardsJobQueue,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || jobs.clone())
+}
+
 fn with_cache(
     cache: Cache,
 ) -> impl Filter<Extract = (Cache,), Error = std::convert::Infallible> + Clone {
     warp::any().map(move || cache.clone())
 }
 
-fn with_string(
-    s: String
-) -> impl Filter<Extract = (String,), Error = std::convert::Infallible> + Clone {
-    warp::any().map(move || s.clone())
-}
-
 async fn get_data_from_db(
     db: Database,
     era: u32,
     options: AllValidatorOptions,
-) -> Result<warp::reply::WithStatus<warp::reply::Json>, Infallible> {
-    let result = db.get_all_validator_info_of_era(era, options.to_db_all_validator_options()).await;
+) -> Result<warp::reply::WithStatus<warp::reply::Json>, Rejection> {
+    let result = db.get_all_validator_info_of_era(era, options).await
+        .map_err(db_rejection)?;
     Ok(warp::reply::with_status(
-        warp::reply::json(&result.unwrap()),
+        warp::reply::json(&result),
         StatusCode::OK,
     ))
 }
@@ -104,19 +113,24 @@ fn get_validator_detail(cache: Cache) -> impl Filter<Extract = impl warp::Reply,
 {
     warp::path("validDetail")
     .and(warp::path::end())
-    .and(warp::query().map(move |opt: ValidDetailOptions| {
+    .and(warp::query())
+    .and(with_cache(cache))
+    .and_then(|opt: ValidDetailOptions, cache: Cache| async move {
         if opt.option == "1kv" {
-            warp::reply::json(&cache.get_1kv_info_simple("DOT"))
+            cache.get_1kv_info_simple("DOT").await.map(|v| warp::reply::json(&v)).map_err(cache_rejection)
         } else {
-            warp::reply::json(&cache.get_validators("DOT"))
+            cache.get_validators("DOT").await.map(|v| warp::reply::json(&v)).map_err(cache_rejection)
         }
-    }))
+    })
 }
 
 fn get_nominators(cache: Cache) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::path("nominators")
     .and(warp::path::end())
-    .map(move || warp::reply::json(&cache.get_nominators("DOT")))
+    .and(with_cache(cache))
+    .and_then(|cache: Cache| async move {
+        cache.get_nominators("DOT").await.map(|v| warp::reply::json(&v)).map_err(cache_rejection)
+    })
 }
 
 fn get_1kv_nominators(cache: Cache) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone
@@ -124,7 +138,10 @@ fn get_1kv_nominators(cache: Cache) -> impl Filter<Extract = impl warp::Reply, E
     warp::path("1kv")
     .and(warp::path("nominators"))
     .and(warp::path::end())
-    .map(move || warp::reply::json(&cache.get_1kv_nominators("DOT")))
+    .and(with_cache(cache))
+    .and_then(|cache: Cache| async move {
+        cache.get_1kv_nominators("DOT").await.map(|v| warp::reply::json(&v)).map_err(cache_rejection)
+    })
 }
 
 fn get_nominated_validators(
@@ -138,7 +155,7 @@ fn get_nominated_validators(
     .and(warp::path::param())
     .and(warp::path::end())
     .and_then(|db: Database, cache: Cache, stash: String| async move {
-        let result = cache.get_nominator("DOT", stash);
+        let result = cache.get_nominator("DOT", stash).await;
         match result {
             Ok(nominator) => {
                 let chain_info = db.get_chain_info().await;
@@ -149,17 +166,17 @@ fn get_nominated_validators(
                         .await;
                         match result {
                             Ok(validators) => Ok(warp::reply::json(&validators)),
-                            Err(_) => Err(warp::reject::not_found()),
+                            Err(e) => Err(db_rejection(e)),
                         }
                     },
-                    Err(_) => {
-                        Err(warp::reject::not_found())
+                    Err(e) => {
+                        Err(db_rejection(e))
                     },
                 }
             }
-            Err(_) => {
-                error!("{}", "failed to get nominated list from the cache");
-                Err(warp::reject::not_found())
+            Err(e) => {
+                error!("failed to get nominated list from the cache: {}", e);
+                Err(cache_rejection(e))
             }
         }
     })
@@ -177,20 +194,20 @@ fn get_stash_rewards(
         let validator = db.get_stash_reward(&stash).await;
         match validator {
             Ok(v) => Ok(warp::reply::json(&v)),
-            Err(_) => Err(warp::reject::not_found()),
+            Err(e) => Err(db_rejection(e)),
         }
     })
 }
 
-fn get_stash_rewards_collector(src_path: String) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+fn get_stash_rewards_collector(jobs: RewardsJobQueue) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::path("stash")
     .and(warp::path::param())
-    .and(with_string(src_path))
+    .and(with_jobs(jobs))
     .and(warp::path("rewards"))
     .and(warp::path("collector"))
     .and(warp::path::end())
     .and(warp::query::<StakingRewardsOptions>())
-    .and_then(|stash: String, src_path: String, p: StakingRewardsOptions| async move {
+    .and_then(|stash: String, jobs: RewardsJobQueue, p: StakingRewardsOptions| async move {
         let start = "2020-01-01".to_string();
         let end = chrono::Utc::now().format("%Y-%m-%d").to_string();
         let currency = "USD".to_string();
@@ -199,51 +216,44 @@ fn get_stash_rewards_collector(src_path: String) -> impl Filter<Extract = impl w
         vec![StakingRewardsAddress::new("".to_string(), stash.clone(), p.start_balance.unwrap_or(0.0), "Polkadot".to_string())]);
         match src {
             Ok(src) => {
-                let result = src.call_exe(src_path.to_string());
+                let result = jobs.report(&src).await;
                 match result {
-                    Ok(v) => {
-                        Ok(warp::reply::json(&v))
-                    },
+                    Ok(report) => Ok(rewards_report_reply(report)),
                     Err(e) => {
                         error!("{}", e);
-                        if e.err_code == -2 {
-                            Err(warp::reject::not_found())
-                        } else {
-                            Err(warp::reject::custom(e))
-                        }
+                        Err(src_rejection(e))
                     },
                 }
             },
             Err(e) => {
-                Err(warp::reject::custom(e))
+                Err(src_rejection(e))
             },
         }
     })
 }
 
-fn get_stash_rewards_collector_csv(src_path: String) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+fn get_stash_rewards_collector_csv(users_db: Database) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::path("stash")
         .and(warp::path::param())
-        .and(with_string(src_path))
+        .and(with_db(users_db))
         .and(warp::path("rewards"))
         .and(warp::path("collector"))
         .and(warp::path("csv"))
         .and(warp::path::end())
-        .and_then(|stash: String, src_path: String| async move{
+        .and_then(|stash: String, users_db: Database| async move{
             // validate stash
             if !stash.chars().all(char::is_alphanumeric) {
                 error!("{}", stash);
                 Err(warp::reject::custom(Invalid))
             } else {
-                // get file from src path
-                let srr = StakingRewardsReport::new(src_path, stash, "csv".to_string());
-                let file = srr.get_report();
+                let srr = StakingRewardsReport::new(stash, "csv".to_string());
+                let file = srr.get_report(&users_db).await;
                 match file {
                     Ok(data) => {
                         Ok(data)
                     },
                     Err(err) => {
-                        Err(warp::reject::custom(err))
+                        Err(src_rejection(err))
                     },
                 }
             }
@@ -251,29 +261,28 @@ fn get_stash_rewards_collector_csv(src_path: String) -> impl Filter<Extract = im
         })
 }
 
-fn get_stash_rewards_collector_json(src_path: String) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+fn get_stash_rewards_collector_json(users_db: Database) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::path("stash")
         .and(warp::path::param())
-        .and(with_string(src_path))
+        .and(with_db(users_db))
         .and(warp::path("rewards"))
         .and(warp::path("collector"))
         .and(warp::path("json"))
         .and(warp::path::end())
-        .and_then(|stash: String, src_path: String| async move{
+        .and_then(|stash: String, users_db: Database| async move{
             // validate stash
             if !stash.chars().all(char::is_alphanumeric) {
                 error!("{}", stash);
                 Err(warp::reject::custom(Invalid))
             } else {
-                // get file from src path
-                let srr = StakingRewardsReport::new(src_path, stash, "json".to_string());
-                let file = srr.get_report();
+                let srr = StakingRewardsReport::new(stash, "json".to_string());
+                let file = srr.get_report(&users_db).await;
                 match file {
                     Ok(data) => {
                         Ok(data)
                     },
                     Err(err) => {
-                        Err(warp::reject::custom(err))
+                        Err(src_rejection(err))
                     },
                 }
             }
@@ -282,17 +291,16 @@ fn get_stash_rewards_collector_json(src_path: String) -> impl Filter<Extract = i
 }
 
 async fn handle_query_parameter_err(
-) -> Result<warp::reply::WithStatus<warp::reply::Json>, Infallible> {
-    Ok(warp::reply::with_status(
-        warp::reply::json(&""),
-        StatusCode::BAD_REQUEST,
-    ))
+) -> Result<warp::reply::WithStatus<warp::reply::Json>, Rejection> {
+    Err(warp::reject::custom(InvalidParam::new("size is required", ErrorCode::InvalidRequest)))
 }
 
 pub fn routes(
     db: Database,
     cache: Cache,
-) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    users_db: Database,
+    jobs: RewardsJobQueue,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
     // let routes_v2 = get_all_validators_formal(db.clone());
     warp::get()
     .and(warp::path("api"))
@@ -305,9 +313,9 @@ pub fn routes(
             .or(get_nominated_validators(cache.clone(), db.clone()))
             .or(get_validator_unclaimed_eras(db.clone()))
             .or(get_stash_rewards(db.clone()))
-            .or(get_stash_rewards_collector(Config::current().staking_rewards_collector_dir.to_string()))
-            .or(get_stash_rewards_collector_csv(Config::current().staking_rewards_collector_dir.to_string()))
-            .or(get_stash_rewards_collector_json(Config::current().staking_rewards_collector_dir.to_string()))
+            .or(get_stash_rewards_collector(jobs))
+            .or(get_stash_rewards_collector_csv(users_db.clone()))
+            .or(get_stash_rewards_collector_json(users_db))
             .or(get_1kv_validators(cache.clone()))
             .or(get_1kv_nominators(cache))
             .or(warp::path("allValidators")
@@ -317,7 +325,7 @@ pub fn routes(
                 .and_then(|db: Database, p: HashMap<String, String>| async move {
                     match p.get("size") {
                         Some(_) => {
-                            let chain_info = db.get_chain_info().await.unwrap();
+                            let chain_info = db.get_chain_info().await.map_err(db_rejection)?;
                             get_data_from_db(db, chain_info.active_era, AllValidatorOptions::new()).await
                         }
                         None => handle_query_parameter_err().await,
@@ -0,0 +1,107 @@
+use std::net::SocketAddr;
+
+use chrono::Utc;
+use tracing::warn;
+use warp::filters::BoxedFilter;
+use warp::filters::path::FullPath;
+use warp::http::Method;
+use warp::reject::{self, Reject};
+use warp::Filter;
+
+use crate::cache::Cache;
+use crate::config::{Config, RateLimitConfig};
+use crate::metrics;
+use crate::rate_limit::{self, RouteClass};
+
+// A request over the limit of its class, answered with 429 and `Retry-After`
+#[derive(Debug)]
+pub struct Throttled {
+  pub class: RouteClass,
+  pub retry_after_secs: u64,
+}
+
+impl Reject for Throttled {}
+
+// the `rate_limit` of the current config, so that a reloaded one applies to the next request
+pub fn config_limits() -> RateLimitConfig {
+  Config::current().rate_limit.clone().unwrap_or_default()
+}
+
+// Rejects the requests of a client over the limit of their route class with `Throttled`. The requests are let
+// through when the cache backend fails, an outage of Redis should not close the API.
+pub fn limit(cache: Cache, limits: fn() -> RateLimitConfig) -> BoxedFilter<()> {
+  warp::method()
+    .and(warp::path::full())
+    .and(warp::addr::remote())
+    .and(warp::header::optional::<String>("x-forwarded-for"))
+    .and_then(move |method: Method, path: FullPath, remote: Option<SocketAddr>, forwarded_for: Option<String>| {
+      let cache = cache.clone();
+      async move {
+        let limits = limits();
+        if !limits.is_enabled() {
+          return Ok(());
+        }
+        let class = match rate_limit::classify(method.as_str(), path.as_str()) {
+          Some(class) => class,
+          None => return Ok(()),
+        };
+        let ip = rate_limit::client_ip(remote.map(|addr| addr.ip()), forwarded_for.as_deref(), &limits.trusted_proxies);
+        let (limit, ip) = match (limits.limit(class), ip) {
+          (Some(limit), Some(ip)) => (limit, ip),
+          _ => return Ok(()),
+        };
+        let now_ms = Utc::now().timestamp_millis().max(0) as u64;
+        match cache.take_token(&rate_limit::bucket_key(class, ip), &limit, now_ms).await {
+          Ok(None) => Ok(()),
+          Ok(Some(wait_ms)) => {
+            metrics::record_rate_limited(class.name());
+            Err(reject::custom(Throttled { class, retry_after_secs: wait_ms.div_ceil(1000).max(1) }))
+          },
+          Err(e) => {
+            warn!("rate limit of {} not checked: {}", ip, e);
+            Ok(())
+          },
+        }
+      }
+    })
+    .untuple_one()
+    .boxed()
+}
+
+#[cfg(test)]
+fn test_limits() -> RateLimitConfig {
+  let limit = crate::config::RateLimit { burst: 1, per_minute: 1 };
+  RateLimitConfig { trusted_proxies: vec!["10.0.0.1".parse().unwrap()], read: Some(limit), ..Default::default() }
+}
+
+#[tokio::test]
+async fn test_limit() {
+  use std::sync::Arc;
+  use warp::hyper::StatusCode;
+
+  let cache: Cache = Arc::new(crate::cache_memory::MemoryCache::new());
+  let filter = limit(cache, test_limits)
+    .and(warp::any().map(|| "ok"))
+    .recover(super::error::handle_rejection);
+  let request = |path: &str, remote: &str| warp::test::request().path(path).remote_addr(remote.parse().unwrap());
+
+  assert_eq!(StatusCode::OK, request("/api/v1/validators/KSM", "1.2.3.4:5000").reply(&filter).await.status());
+  let response = request("/api/v1/validators/KSM", "1.2.3.4:5001").reply(&filter).await;
+  assert_eq!(StatusCode::TOO_MANY_REQUESTS, response.status());
+  assert_eq!("60", response.headers()["retry-after"]);
+  // the classes and the clients have their own buckets
+  assert_eq!(StatusCode::OK, request("/api/v1/nominate", "1.2.3.4:5000").method("POST").reply(&filter).await.status());
+  assert_eq!(StatusCode::OK, request("/api/v1/validators/KSM", "5.6.7.8:5000").reply(&filter).await.status());
+  assert_eq!(StatusCode::OK, request("/healthz", "1.2.3.4:5000").reply(&filter).await.status());
+
+  // behind a trusted proxy the client is the forwarded address
+  let forwarded = |client: &'static str| {
+    request("/api/v1/nominators/KSM", "10.0.0.1:5000").header("x-forwarded-for", client)
+  };
+  assert_eq!(StatusCode::OK, forwarded("9.9.9.9").reply(&filter).await.status());
+  assert_eq!(StatusCode::OK, forwarded("9.9.9.8").reply(&filter).await.status());
+  assert_eq!(StatusCode::TOO_MANY_REQUESTS, forwarded("9.9.9.8").reply(&filter).await.status());
+  // an untrusted peer cannot choose its address
+  let spoofed = request("/api/v1/nominators/KSM", "1.2.3.4:5000").header("x-forwarded-for", "7.7.7.7");
+  assert_eq!(StatusCode::TOO_MANY_REQUESTS, spoofed.reply(&filter).await.status());
+}
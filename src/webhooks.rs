@@ -0,0 +1,470 @@
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use hmac::{Hmac, Mac};
+use hyper::client::connect::{HttpConnector, dns::Name};
+use hyper::service::Service;
+use hyper_rustls::HttpsConnector;
+use tracing::{error, warn};
+use rand::{Rng, distributions::Alphanumeric, thread_rng};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::db::DatabaseError;
+use crate::db::repo::{self, Repository};
+use crate::types::StakingEvents;
+
+// `sha256=` followed by the hex HMAC-SHA256 of the body, keyed by the secret of the subscription
+pub const SIGNATURE_HEADER: &str = "x-cryptolab-signature";
+pub const SUBSCRIPTION_HEADER: &str = "x-cryptolab-webhook";
+// how far `issued_at` of a subscription may be from the time of the server
+pub const SIGNATURE_MAX_AGE_SECS: i64 = 600;
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum WebhookEventType {
+  Commission,
+  Slash,
+  Inactive,
+  StalePayout,
+  Payout,
+  Kick,
+  Chill,
+  OverSubscribe,
+}
+
+#[derive(Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct WebhookOptions {
+  pub stash: String,
+  pub url: String,
+  pub event_types: Vec<WebhookEventType>,
+  // unix time in seconds at which the message was signed
+  pub issued_at: i64,
+  // signature of `subscription_message` by the stash, hex encoded as polkadot-js returns it
+  pub signature: String,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct WebhookSubscription {
+  pub id: String,
+  pub chain: String,
+  pub stash: String,
+  pub url: String,
+  pub event_types: Vec<WebhookEventType>,
+  // key of the payload signatures, only returned when the subscription is created
+  pub secret: String,
+  // the events up to this era were delivered, the era the subscription was created at to begin with
+  pub last_era: u32,
+  pub created_at: i64,
+}
+
+fn random_string(len: usize) -> String {
+  thread_rng()
+    .sample_iter(&Alphanumeric)
+    .take(len)
+    .map(char::from)
+    .collect()
+}
+
+impl WebhookSubscription {
+  pub fn new(chain: &str, era: u32, options: WebhookOptions) -> Self {
+    WebhookSubscription {
+      id: random_string(16),
+      chain: chain.to_string(),
+      stash: options.stash,
+      url: options.url,
+      event_types: options.event_types,
+      secret: random_string(32),
+      last_era: era,
+      created_at: chrono::Utc::now().timestamp(),
+    }
+  }
+}
+
+// what the stash signs to subscribe `url` to its events, the time keeps a captured signature from being replayed later
+pub fn subscription_message(chain: &str, url: &str, issued_at: i64) -> String {
+  format!("cryptolab webhook {} {} {}", chain, url, issued_at)
+}
+
+// whether the address is reachable from the internet, so not one of the network of the server, e.g. its loopback,
+// a private range or the link-local 169.254.169.254 of the cloud metadata
+pub fn is_public(ip: IpAddr) -> bool {
+  match ip {
+    IpAddr::V4(ip) => {
+      let [first, second, ..] = ip.octets();
+      !(ip.is_private() || ip.is_loopback() || ip.is_link_local() || ip.is_unspecified() || ip.is_broadcast()
+        || ip.is_multicast() || ip.is_documentation() || first == 0
+        // shared address space of carrier-grade NAT, 100.64.0.0/10
+        || (first == 100 && second & 0xc0 == 64))
+    },
+    IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+      Some(ip) => is_public(IpAddr::V4(ip)),
+      // unique local fc00::/7 and link-local fe80::/10
+      None => !(ip.is_loopback() || ip.is_unspecified() || ip.is_multicast()
+        || ip.segments()[0] & 0xfe00 == 0xfc00 || ip.segments()[0] & 0xffc0 == 0xfe80),
+    },
+  }
+}
+
+// the addresses the host of `url` resolves to, with the port of the URL
+async fn resolve_url(url: &str) -> Result<Vec<SocketAddr>, String> {
+  let url = match url::Url::parse(url) {
+    Ok(url) if url.scheme() == "https" || url.scheme() == "http" => url,
+    _ => return Err("url must be an http or https URL".to_string()),
+  };
+  let host = url.host_str().ok_or_else(|| "url has no host".to_string())?;
+  let port = url.port_or_known_default().unwrap_or(80);
+  let addresses: Vec<SocketAddr> = match host.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>() {
+    Ok(ip) => vec![SocketAddr::new(ip, port)],
+    Err(_) => tokio::net::lookup_host((host, port)).await
+      .map_err(|e| format!("{} cannot be resolved: {}", host, e))?
+      .collect(),
+  };
+  if addresses.is_empty() {
+    return Err(format!("{} cannot be resolved", host));
+  }
+  Ok(addresses)
+}
+
+// Checks that `url` is http(s) and that every address of its host is public, and returns them. The delivery connects
+// to these addresses only, so a name which resolves to the network of the server afterwards is not followed there.
+pub async fn check_url(url: &str) -> Result<Vec<SocketAddr>, String> {
+  let addresses = resolve_url(url).await?;
+  match addresses.iter().find(|address| !is_public(address.ip())) {
+    Some(address) => Err(format!("{} resolves to {}, which is not a public address", url, address.ip())),
+    None => Ok(addresses),
+  }
+}
+
+// Resolves every name to the addresses given by `check_url`
+#[derive(Clone)]
+struct PinnedResolver(Arc<Vec<SocketAddr>>);
+
+impl Service<Name> for PinnedResolver {
+  type Response = std::vec::IntoIter<SocketAddr>;
+  type Error = std::io::Error;
+  type Future = futures::future::Ready<Result<Self::Response, Self::Error>>;
+
+  fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+    Poll::Ready(Ok(()))
+  }
+
+  fn call(&mut self, _: Name) -> Self::Future {
+    futures::future::ready(Ok(Vec::clone(&self.0).into_iter()))
+  }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct WebhookPayload<'a> {
+  subscription: &'a str,
+  chain: &'a str,
+  stash: &'a str,
+  from_era: u32,
+  to_era: u32,
+  events: &'a StakingEvents,
+}
+
+#[derive(Debug)]
+pub enum WebhookError {
+  Database(DatabaseError),
+  // the endpoint could not be reached or did not answer with a 2xx status, after every attempt
+  Delivery { url: String, attempts: u32, message: String },
+  // the URL no longer passes `check_url`, nothing was sent
+  Refused { url: String, message: String },
+}
+
+impl fmt::Display for WebhookError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      WebhookError::Database(e) => write!(f, "{}", e),
+      WebhookError::Delivery { url, attempts, message } =>
+        write!(f, "Webhook error: {} failed after {} attempts: {}", url, attempts, message),
+      WebhookError::Refused { url, message } => write!(f, "Webhook error: {} refused: {}", url, message),
+    }
+  }
+}
+
+impl From<DatabaseError> for WebhookError {
+  fn from(err: DatabaseError) -> Self {
+    WebhookError::Database(err)
+  }
+}
+
+// keeps the events of the chosen types
+pub fn select(events: StakingEvents, event_types: &[WebhookEventType]) -> StakingEvents {
+  fn keep<T>(wanted: bool, events: Vec<T>) -> Vec<T> {
+    if wanted { events } else { vec![] }
+  }
+  let wanted = |event_type: WebhookEventType| event_types.contains(&event_type);
+  StakingEvents {
+    commissions: keep(wanted(WebhookEventType::Commission), events.commissions),
+    slashes: keep(wanted(WebhookEventType::Slash), events.slashes),
+    inactive: keep(wanted(WebhookEventType::Inactive), events.inactive),
+    stale_payouts: keep(wanted(WebhookEventType::StalePayout), events.stale_payouts),
+    payouts: keep(wanted(WebhookEventType::Payout), events.payouts),
+    kicks: keep(wanted(WebhookEventType::Kick), events.kicks),
+    chills: keep(wanted(WebhookEventType::Chill), events.chills),
+    over_subscribes: keep(wanted(WebhookEventType::OverSubscribe), events.over_subscribes),
+  }
+}
+
+pub fn sign(secret: &str, body: &[u8]) -> String {
+  let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any size");
+  mac.update(body);
+  format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+#[derive(Clone)]
+pub struct WebhookSender {
+  tls: Arc<rustls::ClientConfig>,
+  attempts: u32,
+  // wait before the second attempt, doubled before each following one
+  backoff: Duration,
+  // skips the public address check of `check_url`, for the stand-ins of the tests on the loopback
+  allow_private: bool,
+}
+
+impl WebhookSender {
+  pub fn new(attempts: u32, backoff: Duration) -> Self {
+    let mut tls = rustls::ClientConfig::new();
+    tls.root_store.add_server_trust_anchors(&webpki_roots::TLS_SERVER_ROOTS);
+    WebhookSender {
+      tls: Arc::new(tls),
+      attempts: attempts.max(1),
+      backoff,
+      allow_private: false,
+    }
+  }
+
+  // a client which connects to `addresses` whatever the host of the request, and does not follow redirects, which
+  // could point to the network of the server
+  fn client(&self, addresses: Vec<SocketAddr>) -> hyper::Client<HttpsConnector<HttpConnector<PinnedResolver>>> {
+    let mut http = HttpConnector::new_with_resolver(PinnedResolver(Arc::new(addresses)));
+    http.enforce_http(false);
+    http.set_connect_timeout(Some(DELIVERY_TIMEOUT));
+    hyper::Client::builder().build(HttpsConnector::from((http, self.tls.clone())))
+  }
+
+  pub async fn send(&self, subscription: &WebhookSubscription, body: Vec<u8>) -> Result<(), WebhookError> {
+    let addresses = if self.allow_private { resolve_url(&subscription.url).await } else { check_url(&subscription.url).await }
+      .map_err(|message| WebhookError::Refused { url: subscription.url.clone(), message })?;
+    let client = self.client(addresses);
+    let signature = sign(&subscription.secret, &body);
+    let mut message = String::new();
+    for attempt in 0..self.attempts {
+      if attempt > 0 {
+        tokio::time::sleep(self.backoff.saturating_mul(2u32.saturating_pow(attempt - 1))).await;
+      }
+      let request = hyper::Request::post(subscription.url.as_str())
+        .header(hyper::header::CONTENT_TYPE, "application/json")
+        .header(SIGNATURE_HEADER, &signature)
+        .header(SUBSCRIPTION_HEADER, &subscription.id)
+        .body(hyper::Body::from(body.clone()))
+        .map_err(|e| WebhookError::Refused { url: subscription.url.clone(), message: e.to_string() })?;
+      message = match tokio::time::timeout(DELIVERY_TIMEOUT, client.request(request)).await {
+        Ok(Ok(response)) if response.status().is_success() => return Ok(()),
+        Ok(Ok(response)) => format!("status {}", response.status()),
+        Ok(Err(e)) => e.to_string(),
+        Err(_) => "timed out".to_string(),
+      };
+      warn!("attempt {} to deliver webhook {} failed: {}", attempt + 1, subscription.id, message);
+    }
+    Err(WebhookError::Delivery { url: subscription.url.clone(), attempts: self.attempts, message })
+  }
+}
+
+// Delivers the events of the eras after `last_era` up to `era`. The subscription is moved on to `era` before the
+// delivery, so that one instance of the server delivers it, and moved back when the delivery fails, so that it is
+// retried with the next run. Returns whether a payload was sent.
+pub async fn notify(db: &dyn Repository, user_db: &dyn Repository, sender: &WebhookSender,
+  subscription: &WebhookSubscription, era: u32) -> Result<bool, WebhookError> {
+  if era <= subscription.last_era {
+    return Ok(false);
+  }
+  if !user_db.swap_webhook_last_era(&subscription.id, subscription.last_era, era).await? {
+    return Ok(false);
+  }
+  let sent = deliver(db, user_db, sender, subscription, era).await;
+  if sent.is_err() {
+    if let Err(e) = user_db.swap_webhook_last_era(&subscription.id, era, subscription.last_era).await {
+      error!("failed to move webhook {} back to era {}: {}", subscription.id, subscription.last_era, e);
+    }
+  }
+  sent
+}
+
+async fn deliver(db: &dyn Repository, user_db: &dyn Repository, sender: &WebhookSender,
+  subscription: &WebhookSubscription, era: u32) -> Result<bool, WebhookError> {
+  let from_era = subscription.last_era + 1;
+  let events = match repo::get_nominator_events(db, user_db, &subscription.stash, from_era, era).await {
+    Ok(events) => select(events, &subscription.event_types),
+    // the stash no longer nominates
+    Err(DatabaseError::NotFound { .. }) => StakingEvents::default(),
+    Err(e) => return Err(e.into()),
+  };
+  if events.is_empty() {
+    return Ok(false);
+  }
+  let body = serde_json::to_vec(&WebhookPayload {
+    subscription: &subscription.id,
+    chain: &subscription.chain,
+    stash: &subscription.stash,
+    from_era,
+    to_era: era,
+    events: &events,
+  }).expect("staking events serialize to JSON");
+  sender.send(subscription, body).await?;
+  Ok(true)
+}
+
+// signature, subscription id and body of each request the stand-in received
+#[cfg(test)]
+type Received = std::sync::Arc<std::sync::Mutex<Vec<(String, String, warp::hyper::body::Bytes)>>>;
+
+#[cfg(test)]
+fn stand_in(statuses: Vec<u16>) -> (String, Received) {
+  use std::sync::{Arc, Mutex};
+  use warp::Filter;
+  // answers with the given statuses in turn, then with 200
+  let received = Arc::new(Mutex::new(Vec::new()));
+  let statuses = Arc::new(Mutex::new(statuses.into_iter()));
+  let log = received.clone();
+  let route = warp::post()
+    .and(warp::header::<String>(SIGNATURE_HEADER))
+    .and(warp::header::<String>(SUBSCRIPTION_HEADER))
+    .and(warp::body::bytes())
+    .map(move |signature: String, id: String, body: warp::hyper::body::Bytes| {
+      log.lock().unwrap().push((signature, id, body));
+      let status = statuses.lock().unwrap().next().unwrap_or(200);
+      warp::reply::with_status("", warp::http::StatusCode::from_u16(status).unwrap())
+    });
+  let (address, server) = warp::serve(route).bind_ephemeral(([127, 0, 0, 1], 0));
+  tokio::spawn(server);
+  (format!("http://{}/hook", address), received)
+}
+
+#[cfg(test)]
+fn new_subscription(url: &str, last_era: u32, event_types: Vec<WebhookEventType>) -> WebhookSubscription {
+  WebhookSubscription::new("KSM", last_era, WebhookOptions {
+    stash: "HNZata7iMYWmk5RvZRTiAsSDhV8366zq2YGb3tLH5Upf74F".to_string(),
+    url: url.to_string(),
+    event_types,
+    issued_at: 0,
+    signature: String::new(),
+  })
+}
+
+#[cfg(test)]
+fn test_sender(attempts: u32) -> WebhookSender {
+  WebhookSender { allow_private: true, ..WebhookSender::new(attempts, Duration::from_millis(10)) }
+}
+
+#[test]
+fn test_sign() {
+  // RFC 4231 test case 2
+  assert_eq!("sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843",
+    sign("Jefe", b"what do ya want for nothing?"));
+}
+
+#[tokio::test]
+async fn test_check_url() {
+  for url in ["https://93.184.216.34/hook", "http://[2606:2800:220:1:248:1893:25c8:1946]:8080/hook"] {
+    assert!(check_url(url).await.is_ok(), "{}", url);
+  }
+  for url in ["http://127.0.0.1/hook", "http://localhost:3030/hook", "http://10.0.0.5/hook", "http://192.168.1.1/",
+    "http://172.16.0.1/", "http://169.254.169.254/latest/meta-data", "http://[::1]/hook", "http://[fe80::1]/",
+    "http://[fd00::1]/", "http://[::ffff:127.0.0.1]/", "http://0.0.0.0/", "http://100.64.0.1/"] {
+    assert!(check_url(url).await.unwrap_err().contains("not a public address"), "{}", url);
+  }
+  assert!(check_url("ftp://93.184.216.34/").await.is_err());
+
+  // the delivery checks the URL again
+  let sender = WebhookSender::new(1, Duration::from_millis(10));
+  let (url, received) = stand_in(vec![]);
+  match sender.send(&new_subscription(&url, 3000, vec![]), b"{}".to_vec()).await {
+    Err(WebhookError::Refused { message, .. }) => assert!(message.contains("not a public address")),
+    result => panic!("unexpected {:?}", result),
+  }
+  assert!(received.lock().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_send_retries() {
+  let (url, received) = stand_in(vec![500, 503]);
+  let sender = test_sender(3);
+  let subscription = new_subscription(&url, 3000, vec![WebhookEventType::Payout]);
+  sender.send(&subscription, b"{}".to_vec()).await.unwrap();
+  let requests = received.lock().unwrap().clone();
+  assert_eq!(3, requests.len());
+  for (signature, id, body) in requests.iter() {
+    assert_eq!(&sign(&subscription.secret, body), signature);
+    assert_eq!(&subscription.id, id);
+  }
+
+  let (url, received) = stand_in(vec![500, 500]);
+  let sender = test_sender(2);
+  match sender.send(&new_subscription(&url, 3000, vec![]), b"{}".to_vec()).await {
+    Err(WebhookError::Delivery { attempts, message, .. }) => {
+      assert_eq!(2, attempts);
+      assert_eq!("status 500 Internal Server Error", message);
+    },
+    result => panic!("unexpected {:?}", result),
+  }
+  assert_eq!(2, received.lock().unwrap().len());
+}
+
+#[tokio::test]
+async fn test_pinned_addresses() {
+  // the name is not resolved again, the request goes to the checked address
+  let (url, received) = stand_in(vec![]);
+  let address: SocketAddr = url.trim_start_matches("http://").trim_end_matches("/hook").parse().unwrap();
+  let client = test_sender(1).client(vec![address]);
+  let request = hyper::Request::post(format!("http://webhook.invalid:{}/hook", address.port()))
+    .header(SIGNATURE_HEADER, "sha256=0").header(SUBSCRIPTION_HEADER, "id").body(hyper::Body::empty()).unwrap();
+  assert!(client.request(request).await.unwrap().status().is_success());
+  assert_eq!(1, received.lock().unwrap().len());
+}
+
+#[tokio::test]
+async fn test_notify() {
+  use crate::db::repo::WebhookRepo;
+  let repo = crate::db::memory::MemoryRepo::kusama();
+  let sender = test_sender(2);
+  let (url, received) = stand_in(vec![]);
+
+  let commissions = new_subscription(&url, 3000, vec![WebhookEventType::Commission]);
+  repo.insert_webhook_subscription(&commissions).await.unwrap();
+  assert!(notify(&repo, &repo, &sender, &commissions, 3001).await.unwrap());
+  let body: serde_json::Value = serde_json::from_slice(&received.lock().unwrap()[0].2).unwrap();
+  assert_eq!(3001, body["fromEra"]);
+  assert_eq!(1, body["events"]["commissions"].as_array().unwrap().len());
+  assert_eq!(0, body["events"]["payouts"].as_array().unwrap().len());
+  let stored = repo.get_webhook_subscriptions("KSM").await.unwrap();
+  assert_eq!(3001, stored[0].last_era);
+
+  // nothing new, the era is delivered without a request
+  let chills = new_subscription(&url, 3000, vec![WebhookEventType::Chill]);
+  repo.insert_webhook_subscription(&chills).await.unwrap();
+  assert!(!notify(&repo, &repo, &sender, &chills, 3001).await.unwrap());
+  assert!(!notify(&repo, &repo, &sender, &commissions, 3000).await.unwrap());
+  assert_eq!(1, received.lock().unwrap().len());
+  // another instance which read the subscription before it moved on does not deliver it again
+  assert!(!notify(&repo, &repo, &sender, &commissions, 3001).await.unwrap());
+  assert_eq!(1, received.lock().unwrap().len());
+
+  // a failed delivery leaves the subscription at its era
+  let (url, _) = stand_in(vec![500, 500]);
+  let payouts = new_subscription(&url, 2999, vec![WebhookEventType::Payout]);
+  repo.insert_webhook_subscription(&payouts).await.unwrap();
+  assert!(notify(&repo, &repo, &sender, &payouts, 3001).await.is_err());
+  let stored = repo.get_webhook_subscriptions("KSM").await.unwrap();
+  assert_eq!(2999, stored.iter().find(|s| s.id == payouts.id).unwrap().last_era);
+}